@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS "articles" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "slug" TEXT NOT NULL UNIQUE,
+    "title" TEXT NOT NULL,
+    "description" TEXT NOT NULL,
+    "body" TEXT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updatedAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "author" BIGINT NOT NULL,
+    FOREIGN KEY ("author") REFERENCES "users"("id")
+)
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS "taglist" (
+    "article" BIGINT NOT NULL,
+    "tag" BIGINT NOT NULL,
+    FOREIGN KEY ("article") REFERENCES "articles"("id"),
+    FOREIGN KEY ("tag") REFERENCES "tags"("id")
+)
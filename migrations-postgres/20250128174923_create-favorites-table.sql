@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS "favorites" (
+    "source" BIGINT NOT NULL,
+    "target" BIGINT NOT NULL,
+    FOREIGN KEY ("source") REFERENCES "users"("id"),
+    FOREIGN KEY ("target") REFERENCES "articles"("id")
+)
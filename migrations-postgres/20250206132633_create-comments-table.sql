@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS "comments" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "article" BIGINT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updatedAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "body" TEXT NOT NULL,
+    "author" BIGINT NOT NULL,
+    FOREIGN KEY ("author") REFERENCES "users"("id"),
+    FOREIGN KEY ("article") REFERENCES "articles"("id")
+)
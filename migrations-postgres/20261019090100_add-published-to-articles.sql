@@ -0,0 +1 @@
+ALTER TABLE "articles" ADD COLUMN "published" BOOLEAN NOT NULL DEFAULT TRUE
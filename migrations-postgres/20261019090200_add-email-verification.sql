@@ -0,0 +1,13 @@
+ALTER TABLE "users" ADD COLUMN "verified" BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Everyone who registered before verification existed keeps full access
+UPDATE "users" SET "verified" = TRUE;
+
+CREATE TABLE IF NOT EXISTS "one_time_tokens" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "user" BIGINT NOT NULL,
+    "purpose" TEXT NOT NULL,
+    "hash" TEXT NOT NULL UNIQUE,
+    "expiresAt" BIGINT NOT NULL,
+    FOREIGN KEY ("user") REFERENCES "users"("id")
+);
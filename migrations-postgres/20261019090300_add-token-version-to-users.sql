@@ -0,0 +1 @@
+ALTER TABLE "users" ADD COLUMN "tokenVersion" BIGINT NOT NULL DEFAULT 0
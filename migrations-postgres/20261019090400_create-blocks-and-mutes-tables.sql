@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS "blocks" (
+    "source" BIGINT NOT NULL,
+    "target" BIGINT NOT NULL,
+    PRIMARY KEY ("source", "target"),
+    FOREIGN KEY ("source") REFERENCES "users"("id"),
+    FOREIGN KEY ("target") REFERENCES "users"("id")
+);
+
+CREATE TABLE IF NOT EXISTS "mutes" (
+    "source" BIGINT NOT NULL,
+    "target" BIGINT NOT NULL,
+    PRIMARY KEY ("source", "target"),
+    FOREIGN KEY ("source") REFERENCES "users"("id"),
+    FOREIGN KEY ("target") REFERENCES "users"("id")
+);
@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS "notifications" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "user" BIGINT NOT NULL,
+    "kind" TEXT NOT NULL,
+    "actor" BIGINT NOT NULL,
+    "article" BIGINT,
+    "comment" BIGINT,
+    "read" BOOLEAN NOT NULL DEFAULT FALSE,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY ("user") REFERENCES "users"("id"),
+    FOREIGN KEY ("actor") REFERENCES "users"("id"),
+    FOREIGN KEY ("article") REFERENCES "articles"("id"),
+    FOREIGN KEY ("comment") REFERENCES "comments"("id")
+);
+
+-- Only kinds a user changed have a row, everything else is enabled
+CREATE TABLE IF NOT EXISTS "notification_preferences" (
+    "user" BIGINT NOT NULL,
+    "kind" TEXT NOT NULL,
+    "enabled" BOOLEAN NOT NULL,
+    PRIMARY KEY ("user", "kind"),
+    FOREIGN KEY ("user") REFERENCES "users"("id")
+);
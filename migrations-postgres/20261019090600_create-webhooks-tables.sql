@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS "webhooks" (
+    "id" BIGSERIAL PRIMARY KEY,
+    -- Global webhooks registered by admins have no owner
+    "owner" BIGINT,
+    "url" TEXT NOT NULL,
+    "secret" TEXT NOT NULL,
+    -- Comma-separated, e.g. `article.created,comment.created`
+    "events" TEXT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY ("owner") REFERENCES "users"("id")
+);
+
+CREATE TABLE IF NOT EXISTS "webhook_deliveries" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "webhook" BIGINT NOT NULL,
+    "event" TEXT NOT NULL,
+    "payload" TEXT NOT NULL,
+    "status" TEXT NOT NULL DEFAULT 'pending',
+    "attempts" BIGINT NOT NULL DEFAULT 0,
+    -- Seconds since the epoch
+    "nextAttemptAt" BIGINT,
+    "responseStatus" BIGINT,
+    "error" TEXT,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY ("webhook") REFERENCES "webhooks"("id")
+);
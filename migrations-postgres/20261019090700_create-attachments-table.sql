@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS "attachments" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "article" BIGINT NOT NULL,
+    -- Who uploaded it, counted against their quota
+    "owner" BIGINT NOT NULL,
+    -- Where the media store keeps the file
+    "key" TEXT NOT NULL UNIQUE,
+    "filename" TEXT NOT NULL,
+    "contentType" TEXT NOT NULL,
+    "size" BIGINT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY ("article") REFERENCES "articles"("id"),
+    FOREIGN KEY ("owner") REFERENCES "users"("id")
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS "sessions" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "user" BIGINT NOT NULL,
+    -- Only the hash of the current refresh token is stored
+    "refreshHash" TEXT NOT NULL UNIQUE,
+    -- Seconds since the epoch, pushed back whenever the session is refreshed
+    "expiresAt" BIGINT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY ("user") REFERENCES "users"("id")
+);
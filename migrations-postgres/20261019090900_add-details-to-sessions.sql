@@ -0,0 +1,6 @@
+-- Seconds since the epoch, updated at most once a minute
+ALTER TABLE "sessions" ADD COLUMN "lastUsedAt" BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE "sessions" ADD COLUMN "userAgent" TEXT;
+ALTER TABLE "sessions" ADD COLUMN "ip" TEXT;
+
+UPDATE "sessions" SET "lastUsedAt" = CAST(EXTRACT(EPOCH FROM "createdAt") AS BIGINT);
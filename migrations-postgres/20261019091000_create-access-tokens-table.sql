@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS "access_tokens" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "user" BIGINT NOT NULL,
+    "name" TEXT NOT NULL,
+    -- Only the hash of the token is stored
+    "hash" TEXT NOT NULL UNIQUE,
+    -- Comma-separated, e.g. `read,comments:write`
+    "scopes" TEXT NOT NULL,
+    -- Seconds since the epoch
+    "expiresAt" BIGINT NOT NULL,
+    -- Seconds since the epoch, updated at most once a minute
+    "lastUsedAt" BIGINT,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY ("user") REFERENCES "users"("id")
+);
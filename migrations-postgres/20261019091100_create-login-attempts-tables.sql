@@ -0,0 +1,20 @@
+-- Failed logins counted against an account (`account:<email>`) or an
+-- address (`ip:<address>`) since the last successful login
+CREATE TABLE IF NOT EXISTS "login_throttles" (
+    "key" TEXT PRIMARY KEY NOT NULL,
+    "failures" BIGINT NOT NULL,
+    -- Seconds since the epoch
+    "lastFailureAt" BIGINT NOT NULL
+);
+
+-- Audit trail of failed logins
+CREATE TABLE IF NOT EXISTS "login_failures" (
+    "id" BIGSERIAL PRIMARY KEY,
+    -- As entered, the account may not exist
+    "email" TEXT NOT NULL,
+    "user" BIGINT,
+    "ip" TEXT,
+    "userAgent" TEXT,
+    "reason" TEXT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS "two_factor" (
+    "user" BIGINT PRIMARY KEY NOT NULL,
+    -- Shared with the authenticator app, so it cannot be hashed
+    "secret" TEXT NOT NULL,
+    -- Only once a code was confirmed
+    "enabled" BOOLEAN NOT NULL DEFAULT FALSE,
+    -- The time step of the last code used, which cannot be used again
+    "lastStep" BIGINT,
+    FOREIGN KEY ("user") REFERENCES "users"("id")
+);
+
+CREATE TABLE IF NOT EXISTS "recovery_codes" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "user" BIGINT NOT NULL,
+    "hash" TEXT NOT NULL UNIQUE,
+    FOREIGN KEY ("user") REFERENCES "users"("id")
+);
@@ -0,0 +1,24 @@
+-- Accounts at external identity providers which users log in with
+CREATE TABLE IF NOT EXISTS "identities" (
+    "id" BIGSERIAL PRIMARY KEY,
+    "user" BIGINT NOT NULL,
+    -- The name of the provider in the configuration
+    "provider" TEXT NOT NULL,
+    -- The `sub` claim of the provider
+    "subject" TEXT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE ("provider", "subject"),
+    FOREIGN KEY ("user") REFERENCES "users"("id")
+);
+
+-- Logins which were sent to a provider and have not come back yet
+CREATE TABLE IF NOT EXISTS "oidc_logins" (
+    -- Only the hash of the `state` parameter is stored
+    "hash" TEXT PRIMARY KEY NOT NULL,
+    "provider" TEXT NOT NULL,
+    -- The PKCE code verifier, which is sent to the provider later
+    "verifier" TEXT NOT NULL,
+    "nonce" TEXT NOT NULL,
+    -- Seconds since the epoch
+    "expiresAt" BIGINT NOT NULL
+);
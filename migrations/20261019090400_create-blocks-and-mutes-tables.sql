@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS `blocks` (
+    `source` INTEGER NOT NULL,
+    `target` INTEGER NOT NULL,
+    PRIMARY KEY (`source`, `target`),
+    FOREIGN KEY (`source`) REFERENCES `users`(`id`),
+    FOREIGN KEY (`target`) REFERENCES `users`(`id`)
+);
+
+CREATE TABLE IF NOT EXISTS `mutes` (
+    `source` INTEGER NOT NULL,
+    `target` INTEGER NOT NULL,
+    PRIMARY KEY (`source`, `target`),
+    FOREIGN KEY (`source`) REFERENCES `users`(`id`),
+    FOREIGN KEY (`target`) REFERENCES `users`(`id`)
+);
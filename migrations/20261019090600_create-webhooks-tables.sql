@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS `webhooks` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+    -- Global webhooks registered by admins have no owner
+    `owner` INTEGER,
+    `url` TEXT NOT NULL,
+    `secret` TEXT NOT NULL,
+    -- Comma-separated, e.g. `article.created,comment.created`
+    `events` TEXT NOT NULL,
+    `createdAt` TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`owner`) REFERENCES `users`(`id`)
+);
+
+CREATE TABLE IF NOT EXISTS `webhook_deliveries` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+    `webhook` INTEGER NOT NULL,
+    `event` TEXT NOT NULL,
+    `payload` TEXT NOT NULL,
+    `status` TEXT NOT NULL DEFAULT 'pending',
+    `attempts` INTEGER NOT NULL DEFAULT 0,
+    -- Seconds since the epoch
+    `nextAttemptAt` INTEGER,
+    `responseStatus` INTEGER,
+    `error` TEXT,
+    `createdAt` TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`webhook`) REFERENCES `webhooks`(`id`)
+);
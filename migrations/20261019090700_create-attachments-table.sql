@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS `attachments` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+    `article` INTEGER NOT NULL,
+    -- Who uploaded it, counted against their quota
+    `owner` INTEGER NOT NULL,
+    -- Where the media store keeps the file
+    `key` TEXT NOT NULL UNIQUE,
+    `filename` TEXT NOT NULL,
+    `contentType` TEXT NOT NULL,
+    `size` INTEGER NOT NULL,
+    `createdAt` TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`article`) REFERENCES `articles`(`id`),
+    FOREIGN KEY (`owner`) REFERENCES `users`(`id`)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS `sessions` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+    `user` INTEGER NOT NULL,
+    -- Only the hash of the current refresh token is stored
+    `refreshHash` TEXT NOT NULL UNIQUE,
+    -- Seconds since the epoch, pushed back whenever the session is refreshed
+    `expiresAt` INTEGER NOT NULL,
+    `createdAt` TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`user`) REFERENCES `users`(`id`)
+);
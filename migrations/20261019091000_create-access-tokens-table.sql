@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS `access_tokens` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+    `user` INTEGER NOT NULL,
+    `name` TEXT NOT NULL,
+    -- Only the hash of the token is stored
+    `hash` TEXT NOT NULL UNIQUE,
+    -- Comma-separated, e.g. `read,comments:write`
+    `scopes` TEXT NOT NULL,
+    -- Seconds since the epoch
+    `expiresAt` INTEGER NOT NULL,
+    -- Seconds since the epoch, updated at most once a minute
+    `lastUsedAt` INTEGER,
+    `createdAt` TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (`user`) REFERENCES `users`(`id`)
+);
@@ -0,0 +1,20 @@
+-- Failed logins counted against an account (`account:<email>`) or an
+-- address (`ip:<address>`) since the last successful login
+CREATE TABLE IF NOT EXISTS `login_throttles` (
+    `key` TEXT PRIMARY KEY NOT NULL,
+    `failures` INTEGER NOT NULL,
+    -- Seconds since the epoch
+    `lastFailureAt` INTEGER NOT NULL
+);
+
+-- Audit trail of failed logins
+CREATE TABLE IF NOT EXISTS `login_failures` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+    -- As entered, the account may not exist
+    `email` TEXT NOT NULL,
+    `user` INTEGER,
+    `ip` TEXT,
+    `userAgent` TEXT,
+    `reason` TEXT NOT NULL,
+    `createdAt` TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
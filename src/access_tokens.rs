@@ -0,0 +1,291 @@
+//! Personal access tokens, which let scripts use the API without a password
+//!
+//! Users create tokens with a name, an expiry and the scopes they need. A
+//! token works in the `Authorization` header like an access token, but only
+//! for the routes one of its scopes covers, see [`required_scope`]. Everything
+//! else, such as changing the account or creating more tokens, takes logging
+//! in. Only the hashes of the tokens are stored.
+
+use crate::{
+    auth::Auth,
+    errors,
+    repo::{self, AccessToken, NewAccessToken, Scope},
+    sessions::TOUCH_INTERVAL,
+    token::{hash_token, now, random_token},
+    AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Every personal access token starts with this, which tells them apart from
+/// the JWTs of sessions
+pub(crate) const PREFIX: &str = "rwpat_";
+
+/// Names are cut off after this many characters
+const MAX_NAME: usize = 100;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The name is empty
+    InvalidName,
+    /// A token has to have at least one scope
+    NoScopes,
+    /// The expiry is not in the future
+    InvalidExpiry,
+    /// The user has no token with this `id`
+    NotFound,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidName => write!(f, "the name must not be empty"),
+            Error::NoScopes => write!(f, "no scopes to grant"),
+            Error::InvalidExpiry => write!(f, "the expiry has to be in the future"),
+            Error::NotFound => write!(f, "no such token"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::InvalidName | Error::NoScopes | Error::InvalidExpiry => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// The scope a personal access token needs for the route matching `path`,
+/// `None` if only the tokens of sessions are accepted there
+pub(crate) fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    match (method.as_str(), path) {
+        (
+            "GET",
+            "/api/user"
+            | "/api/profiles/{username}"
+            | "/api/articles"
+            | "/api/articles/feed"
+            | "/api/articles/{slug}"
+            | "/api/articles/{slug}/attachments"
+            | "/api/articles/{slug}/comments"
+            | "/api/articles/{slug}/comments/stream"
+            | "/api/tags",
+        ) => Some(Scope::Read),
+        ("POST", "/api/articles")
+        | ("PUT" | "DELETE", "/api/articles/{slug}")
+        | ("POST", "/api/articles/{slug}/attachments")
+        | ("DELETE", "/api/articles/{slug}/attachments/{id}")
+        | ("POST" | "DELETE", "/api/articles/{slug}/favorite") => Some(Scope::ArticlesWrite),
+        ("POST", "/api/articles/{slug}/comments")
+        | ("DELETE", "/api/articles/{slug}/comments/{id}") => Some(Scope::CommentsWrite),
+        _ => None,
+    }
+}
+
+/// Record that `token` was used at `now`, unless that was done recently
+pub(crate) async fn touch(app: &AppState, token: &AccessToken, now: i64) -> repo::Result<()> {
+    if token
+        .last_used_at
+        .is_none_or(|last_used_at| now - last_used_at >= TOUCH_INTERVAL)
+    {
+        app.repos.access_tokens.touch(token.id, now).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseAccessTokenBody {
+    id: i64,
+    name: String,
+    scopes: Vec<Scope>,
+    /// Seconds since the epoch
+    expires_at: i64,
+    /// Seconds since the epoch, updated at most once a minute
+    last_used_at: Option<i64>,
+    /// Only returned when the token is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    created_at: String,
+}
+
+impl From<AccessToken> for ResponseAccessTokenBody {
+    fn from(token: AccessToken) -> Self {
+        ResponseAccessTokenBody {
+            id: token.id,
+            name: token.name,
+            scopes: token.scopes,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+            token: None,
+            created_at: token.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseAccessToken {
+    access_token: ResponseAccessTokenBody,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseMultipleAccessTokens {
+    access_tokens: Vec<ResponseAccessTokenBody>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewToken {
+    /// What the token is for, e.g. the script using it
+    name: String,
+    scopes: Vec<Scope>,
+    /// Seconds since the epoch
+    expires_at: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestAccessToken {
+    access_token: NewToken,
+}
+
+/// Create a personal access token for the authenticated user
+///
+/// The token is only returned in this response. It has to be sent in the
+/// `Authorization` header like the token of a session, and only works for
+/// routes its scopes cover.
+#[utoipa::path(
+    post,
+    path = "/api/user/tokens",
+    tag = "users",
+    request_body = RequestAccessToken,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The token, including its secret", body = ResponseAccessToken),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 422, description = "Empty name, no scopes or an expiry in the past"),
+    ),
+)]
+pub async fn create_access_token(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Json(RequestAccessToken { access_token }): Json<RequestAccessToken>,
+) -> Result<Json<ResponseAccessToken>, Error> {
+    let name: String = access_token.name.trim().chars().take(MAX_NAME).collect();
+
+    if name.is_empty() {
+        return Err(Error::InvalidName);
+    }
+
+    let mut scopes = access_token.scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    if scopes.is_empty() {
+        return Err(Error::NoScopes);
+    }
+
+    if access_token.expires_at <= now() {
+        return Err(Error::InvalidExpiry);
+    }
+
+    let secret = format!("{}{}", PREFIX, random_token());
+    let token = app
+        .repos
+        .access_tokens
+        .create(NewAccessToken {
+            user: user_id,
+            name,
+            hash: hash_token(&secret),
+            scopes,
+            expires_at: access_token.expires_at,
+        })
+        .await?;
+
+    Ok(Json(ResponseAccessToken {
+        access_token: ResponseAccessTokenBody {
+            token: Some(secret),
+            ..token.into()
+        },
+    }))
+}
+
+/// The personal access tokens of the authenticated user, newest first
+#[utoipa::path(
+    get,
+    path = "/api/user/tokens",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The tokens, without their secrets", body = ResponseMultipleAccessTokens),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn list_access_tokens(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<Json<ResponseMultipleAccessTokens>, Error> {
+    let tokens = app.repos.access_tokens.list(user_id).await?;
+
+    Ok(Json(ResponseMultipleAccessTokens {
+        access_tokens: tokens.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Revoke a personal access token of the authenticated user
+#[utoipa::path(
+    delete,
+    path = "/api/user/tokens/{id}",
+    tag = "users",
+    params(("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The token was revoked"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The user has no such token"),
+    ),
+)]
+pub async fn delete_access_token(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(id): Path<i64>,
+) -> Result<(), Error> {
+    let token = app
+        .repos
+        .access_tokens
+        .find(id)
+        .await?
+        .filter(|token| token.user == user_id)
+        .ok_or(Error::NotFound)?;
+
+    app.repos.access_tokens.delete(token.id).await?;
+
+    Ok(())
+}
@@ -0,0 +1,299 @@
+//! Users leaving and taking their data with them
+
+use crate::{
+    attachments,
+    auth::{Auth, AuthenticationFailure},
+    errors, media,
+    oidc::{self, Callback},
+    repo::{self, Relation},
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The password given to confirm the deletion is not the user's
+    WrongPassword,
+    /// Someone else logged in at the provider to confirm the deletion
+    WrongIdentity,
+    /// The user of the token is gone by now
+    Auth(AuthenticationFailure),
+    Oidc(oidc::Error),
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::WrongPassword => write!(f, "wrong password"),
+            Error::WrongIdentity => write!(f, "the provider authenticated someone else"),
+            Error::Auth(error) => write!(f, "{}", error),
+            Error::Oidc(error) => write!(f, "{}", error),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl From<AuthenticationFailure> for Error {
+    fn from(error: AuthenticationFailure) -> Self {
+        Error::Auth(error)
+    }
+}
+
+impl From<oidc::Error> for Error {
+    fn from(error: oidc::Error) -> Self {
+        Error::Oidc(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::Auth(error) => return error.into_response(),
+            Error::Oidc(error) => return error.into_response(),
+            Error::WrongPassword | Error::WrongIdentity => StatusCode::FORBIDDEN,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// What happens to the articles and comments of a deleted user
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthoredContent {
+    /// Remove them together with the account
+    Delete,
+    /// Keep them, attributed to a placeholder name
+    Anonymize,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AccountDeletion {
+    /// The current password of the user
+    #[serde(default)]
+    password: Option<String>,
+    /// Instead of the password, from the redirect back after logging in
+    /// again at an identity provider the user is linked to, which is how
+    /// users created by logging in there confirm
+    #[serde(default)]
+    oidc: Option<Callback>,
+    content: AuthoredContent,
+}
+
+/// Delete the authenticated user
+#[utoipa::path(
+    delete,
+    path = "/api/user",
+    tag = "users",
+    security(("token" = [])),
+    request_body = AccountDeletion,
+    responses(
+        (status = 200, description = "The account is gone"),
+        (status = 400, description = "The state of the login at the provider is invalid or expired"),
+        (status = 401, description = "Missing or invalid token, or ID token of the provider"),
+        (status = 403, description = "Wrong password, or someone else logged in at the provider"),
+        (status = 502, description = "The provider could not be reached or rejected the code"),
+    ),
+)]
+pub async fn delete_account(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    headers: HeaderMap,
+    Json(deletion): Json<AccountDeletion>,
+) -> Result<(), Error> {
+    let user = app
+        .repos
+        .users
+        .find(user_id)
+        .await?
+        .ok_or(AuthenticationFailure::UnknownUser)?;
+
+    match (&deletion.oidc, &deletion.password) {
+        (Some(callback), _) => {
+            if !oidc::reauthenticate(&app, user_id, &headers, callback).await? {
+                return Err(Error::WrongIdentity);
+            }
+        }
+        (None, Some(password)) if *password == user.password => {}
+        (None, _) => return Err(Error::WrongPassword),
+    }
+
+    match deletion.content {
+        AuthoredContent::Delete => {
+            let attachments = app.repos.attachments.owned(user_id).await?;
+            app.repos.users.delete(user_id).await?;
+            attachments::delete_files(app.media_store.as_ref(), &attachments).await;
+        }
+        AuthoredContent::Anonymize => app.repos.users.anonymize(user_id).await?,
+    }
+
+    // The files go once the account is gone, those left behind do no harm
+    if let Err(error) = media::delete_avatar(
+        &app.media,
+        app.media_store.as_ref(),
+        user_id,
+        user.image.as_deref(),
+    )
+    .await
+    {
+        eprintln!("Deleting the avatar failed: {}", error);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExportedProfile {
+    username: String,
+    email: String,
+    bio: Option<String>,
+    image: Option<String>,
+    verified: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedArticle {
+    slug: String,
+    title: String,
+    description: String,
+    body: String,
+    tag_list: Vec<String>,
+    created_at: String,
+    updated_at: String,
+    published: bool,
+    favorites_count: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedComment {
+    /// Slug of the article the comment is on
+    article: String,
+    body: String,
+    created_at: String,
+    updated_at: String,
+}
+
+/// Everything stored about a user
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AccountExport {
+    profile: ExportedProfile,
+    articles: Vec<ExportedArticle>,
+    comments: Vec<ExportedComment>,
+    /// Usernames of the users the user follows
+    following: Vec<String>,
+    /// Usernames of the users following the user
+    followers: Vec<String>,
+    /// Slugs of the articles the user favorited
+    favorites: Vec<String>,
+    /// Usernames of the users the user blocked
+    blocking: Vec<String>,
+    /// Usernames of the users the user muted
+    muting: Vec<String>,
+}
+
+/// Download all data of the authenticated user
+#[utoipa::path(
+    get,
+    path = "/api/user/export",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The data as a JSON attachment", body = AccountExport),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn export_account(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<Response, Error> {
+    let repos = &app.repos;
+    let user = repos
+        .users
+        .find(user_id)
+        .await?
+        .ok_or(AuthenticationFailure::UnknownUser)?;
+
+    let articles = repos
+        .articles
+        .list_by_author(user_id)
+        .await?
+        .into_iter()
+        .map(|article| ExportedArticle {
+            slug: article.slug,
+            title: article.title,
+            description: article.description,
+            body: article.body,
+            tag_list: article.tag_list,
+            created_at: article.created_at,
+            updated_at: article.updated_at,
+            published: article.published,
+            favorites_count: article.favorites_count,
+        })
+        .collect();
+
+    let comments = repos
+        .comments
+        .list_by_author(user_id)
+        .await?
+        .into_iter()
+        .map(|(article, comment)| ExportedComment {
+            article,
+            body: comment.body,
+            created_at: comment.created_at,
+            updated_at: comment.updated_at,
+        })
+        .collect();
+
+    let export = AccountExport {
+        profile: ExportedProfile {
+            username: user.username,
+            email: user.email,
+            bio: user.bio,
+            image: user.image,
+            verified: user.verified,
+        },
+        articles,
+        comments,
+        following: repos.follows.following(user_id).await?,
+        followers: repos.follows.followers(user_id).await?,
+        favorites: repos.articles.favorites(user_id).await?,
+        blocking: repos
+            .relations
+            .target_names(Relation::Block, user_id)
+            .await?,
+        muting: repos
+            .relations
+            .target_names(Relation::Mute, user_id)
+            .await?,
+    };
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"realworld-export.json\"",
+        )],
+        Json(export),
+    )
+        .into_response())
+}
@@ -1,28 +1,89 @@
-use crate::{auth::Auth, database::Profile, AppState};
+use crate::{
+    attachments::delete_files,
+    auth::{Auth, Verified},
+    database::Profile,
+    errors,
+    notifications::notify,
+    profile::load_profile_by_id,
+    repo::{
+        self, ArticleFilter, ArticleUpdate, NewArticle, NewNotification, NotificationKind,
+        WebhookEvent,
+    },
+    webhooks::dispatch,
+    AppState,
+};
 use axum::{
     extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, QueryBuilder, Sqlite};
-use std::iter;
+use serde_json::json;
+use std::fmt;
 use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+/// Articles take at most this many tags
+const MAX_TAGS: usize = 999;
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    /// Only the author may change the article
+    Forbidden,
+    /// Another article has the slug of the title
+    SlugTaken,
+    TooManyTags,
+    OffsetWithoutLimit,
+    Repo(repo::Error),
+}
 
-#[derive(Debug, FromRow)]
-#[sqlx(rename_all = "camelCase")]
-pub struct SimpleNoBodyArticle {
-    id: i64,
-    slug: String,
-    title: String,
-    description: String,
-    created_at: String,
-    updated_at: String,
-    author: i64,
-    favorited: bool,
-    favorites_count: i64,
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "no such article"),
+            Error::Forbidden => write!(f, "only the author may change the article"),
+            Error::SlugTaken => write!(f, "an article with this title already exists"),
+            Error::TooManyTags => write!(f, "articles take at most {} tags", MAX_TAGS),
+            Error::OffsetWithoutLimit => write!(f, "offset must be used with limit"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::SlugTaken | Error::TooManyTags | Error::OffsetWithoutLimit => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// Saving an article conflicts only with the slug of another
+fn slug_taken(error: repo::Error) -> Error {
+    match error {
+        repo::Error::Conflict => Error::SlugTaken,
+        error => Error::Repo(error),
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NoBodyArticle {
     slug: String,
@@ -36,367 +97,141 @@ pub struct NoBodyArticle {
     author: Profile,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ResponseMultipleArticles {
     articles: Vec<NoBodyArticle>,
     articles_count: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ListArticlesConstraints {
     tag: Option<String>,
+    /// Username of the author
     author: Option<String>,
+    /// Username of a user who favorited the article
     favorited: Option<String>,
     limit: Option<i64>,
+    /// Requires `limit`
     offset: Option<i64>,
 }
 
+/// Published articles, newest first
+#[utoipa::path(
+    get,
+    path = "/api/articles",
+    tag = "articles",
+    params(ListArticlesConstraints),
+    security((), ("token" = [])),
+    responses((status = 200, description = "The matching articles", body = ResponseMultipleArticles)),
+)]
 pub async fn list_articles(
     State(app): State<Arc<AppState>>,
     authentication: Option<Auth>,
     Query(query): Query<ListArticlesConstraints>,
-) -> Json<ResponseMultipleArticles> {
+) -> Result<Json<ResponseMultipleArticles>, Error> {
     if query.offset.is_some() && query.limit.is_none() {
-        panic!("Offset must be used with limit");
+        return Err(Error::OffsetWithoutLimit);
     };
 
-    let sql = format!(
-        "
-        SELECT `articles`.`id`, `slug`, `title`, `description`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `createdAt`) AS `createdAt`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `updatedAt`) AS `updatedAt`,
-        `author`, (
-            SELECT COUNT(*)
-            FROM `favorites`
-            WHERE `favorites`.`source`=? AND `favorites`.`target`=`articles`.`id`
-        ) AS `favorited`, {}, (
-            SELECT COUNT(*)
-            FROM `favorites`
-            WHERE `target`=`articles`.`id`
-        ) AS `favoritesCount`
-        FROM `articles`
-        {}
-        {}
-        WHERE
-        {}
-        AND {}
-        AND `favoritedByTarget`=TRUE
-        ORDER BY `updatedAt` DESC
-        {}
-        {}
-    ",
-        query
-            .favorited
-            .as_ref()
-            .map(|_| "(
-            SELECT COUNT(*)
-            FROM `favorites`
-            WHERE `favorites`.`source`=? AND `favorites`.`target`=`articles`.`id`
-        ) AS `favoritedByTarget`")
-            .unwrap_or("TRUE AS `favoritedByTarget`"),
-        query
-            .tag
-            .as_ref()
-            .map(|_| "
-                JOIN `taglist` ON `taglist`.`article`=`articles`.`id`
-                JOIN `tags` ON `tags`.`id`=`taglist`.`tag`
-            ")
-            .unwrap_or(""),
-        query
-            .author
-            .as_ref()
-            .map(|_| "
-                JOIN `users` ON `users`.`id`=`articles`.`author`
-            ")
-            .unwrap_or(""),
-        query
-            .tag
-            .as_ref()
-            .map(|_| "
-                `tags`.`name`=?
-            ")
-            .unwrap_or("TRUE"),
-        query
-            .author
-            .as_ref()
-            .map(|_| "
-                `users`.`username`=?
-            ")
-            .unwrap_or("TRUE"),
-        query.limit.map(|_| "LIMIT ?").unwrap_or(""),
-        query.offset.map(|_| "OFFSET ?").unwrap_or("")
-    );
-
-    // Get the list of article attributes first
-    let statement = sqlx::query_as::<_, SimpleNoBodyArticle>(&sql)
-        // Use an `id` which never exists if the user is not authenticated
-        .bind(authentication.as_ref().map(|auth| auth.0).unwrap_or(-1));
-
-    let statement = if let Some(name) = query.favorited {
-        let id: i64 = sqlx::query_scalar(
-            "
-            SELECT `id`
-            FROM `users`
-            WHERE `username`=?
-        ",
+    let viewer = authentication.map(|auth| auth.0);
+    let list = app
+        .repos
+        .articles
+        .list(
+            viewer,
+            ArticleFilter {
+                tag: query.tag,
+                author: query.author,
+                favorited: query.favorited,
+                limit: query.limit,
+                offset: query.offset,
+                muted_by: viewer,
+                ..ArticleFilter::default()
+            },
         )
-        .bind(name)
-        .fetch_one(&app.db)
-        .await
-        .unwrap();
-        println!("has id {}", id);
-
-        statement.bind(id)
-    } else {
-        statement
-    };
-
-    let statement = if let Some(name) = query.tag {
-        statement.bind(name)
-    } else {
-        statement
-    };
-
-    let statement = if let Some(name) = query.author {
-        statement.bind(name)
-    } else {
-        statement
-    };
-
-    let statement = if let Some(limit) = query.limit {
-        statement.bind(limit)
-    } else {
-        statement
-    };
+        .await?;
 
-    let statement = if let Some(offset) = query.offset {
-        statement.bind(offset)
-    } else {
-        statement
-    };
-
-    let list = statement.fetch_all(&app.db).await.unwrap();
-
-    // Then fetch the taglist
-    let mut tag_list = Vec::with_capacity(list.len());
-
-    for i in &list {
-        let tags: Vec<String> = sqlx::query_scalar(
-            "
-                SELECT `name`
-                FROM `taglist` INNER JOIN `tags` ON `taglist`.`tag`=`tags`.`id`
-                WHERE `article`=?
-            ",
-        )
-        .bind(i.id)
-        .fetch_all(&app.db)
-        .await
-        .unwrap();
-        tag_list.push(tags);
-    }
-
-    // Then the authors
-    let mut author_list = Vec::with_capacity(list.len());
-
-    for i in &list {
-        let profile = if let Some(ref authentication) = authentication {
-            let user_id = authentication.0;
-
-            sqlx::query_as::<_, crate::database::Profile>(
-                "
-                SELECT `username`, `bio`, `image`, (
-                    SELECT COUNT(*)
-                    FROM `follows`
-                    WHERE `follows`.`source`=? AND `follows`.`target`=`users`.`id`
-                ) AS `following`
-                FROM `users`
-                WHERE `users`.`id`=?
-            ",
-            )
-            .bind(user_id)
-            .bind(i.author)
-        } else {
-            sqlx::query_as::<_, crate::database::Profile>(
-                "
-                    SELECT `username`, `bio`, `image`, FALSE AS `following`
-                    FROM `users`
-                    WHERE `users`.`id`=?
-                ",
-            )
-            .bind(i.author)
-        }
-        .fetch_one(&app.db)
-        .await
-        .unwrap();
-
-        author_list.push(profile);
-    }
-
-    // Combine the query results
-    let mut articles = Vec::with_capacity(list.len());
-
-    for ((article, tag_list), author) in iter::zip(iter::zip(list, tag_list), author_list) {
-        articles.push(NoBodyArticle {
-            slug: article.slug,
-            title: article.title,
-            description: article.description,
-            tag_list,
-            created_at: article.created_at,
-            updated_at: article.updated_at,
-            favorited: article.favorited,
-            favorites_count: article.favorites_count,
-            author,
-        });
-    }
-
-    Json(ResponseMultipleArticles {
-        articles_count: articles.len(),
-        articles,
-    })
+    Ok(Json(multiple_articles(&app, viewer, list).await?))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct FeedArticlesConstraints {
     limit: Option<i64>,
+    /// Requires `limit`
     offset: Option<i64>,
 }
 
+/// Articles by followed users, newest first
+#[utoipa::path(
+    get,
+    path = "/api/articles/feed",
+    tag = "articles",
+    params(FeedArticlesConstraints),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The matching articles", body = ResponseMultipleArticles),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
 pub async fn feed_articles(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
     Query(query): Query<FeedArticlesConstraints>,
-) -> Json<ResponseMultipleArticles> {
+) -> Result<Json<ResponseMultipleArticles>, Error> {
     if query.offset.is_some() && query.limit.is_none() {
-        panic!("Offset must be used with limit");
-    };
-
-    let sql = format!(
-        "
-        SELECT `articles`.`id`, `slug`, `title`, `description`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `createdAt`) AS `createdAt`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `updatedAt`) AS `updatedAt`,
-        `author`, (
-            SELECT COUNT(*)
-            FROM `favorites`
-            WHERE `favorites`.`source`=? AND `favorites`.`target`=`articles`.`id`
-        ) AS `favorited`, (
-            SELECT COUNT(*)
-            FROM `favorites`
-            WHERE `target`=`articles`.`id`
-        ) AS `favoritesCount`
-        FROM `articles`
-        JOIN `follows` ON `follows`.`target`=`articles`.`author`
-        WHERE `follows`.`source`=?
-        ORDER BY `updatedAt` DESC
-        {}
-        {}
-    ",
-        query.limit.map(|_| "LIMIT ?").unwrap_or(""),
-        query.offset.map(|_| "OFFSET ?").unwrap_or("")
-    );
-
-    // Get the list of article attributes first
-    let statement = sqlx::query_as::<_, SimpleNoBodyArticle>(&sql)
-        // Use an `id` which never exists if the user is not authenticated
-        .bind(user_id)
-        .bind(user_id);
-
-    let statement = if let Some(limit) = query.limit {
-        statement.bind(limit)
-    } else {
-        statement
+        return Err(Error::OffsetWithoutLimit);
     };
 
-    let statement = if let Some(offset) = query.offset {
-        statement.bind(offset)
-    } else {
-        statement
-    };
-
-    let list = statement.fetch_all(&app.db).await.unwrap();
-
-    // Then fetch the taglist
-    let mut tag_list = Vec::with_capacity(list.len());
-
-    for i in &list {
-        let tags: Vec<String> = sqlx::query_scalar(
-            "
-                SELECT `name`
-                FROM `taglist` INNER JOIN `tags` ON `taglist`.`tag`=`tags`.`id`
-                WHERE `article`=?
-            ",
-        )
-        .bind(i.id)
-        .fetch_all(&app.db)
-        .await
-        .unwrap();
-        tag_list.push(tags);
-    }
-
-    // Then the authors
-    let mut author_list = Vec::with_capacity(list.len());
-
-    for i in &list {
-        let profile = sqlx::query_as::<_, crate::database::Profile>(
-            "
-            SELECT `username`, `bio`, `image`, (
-                SELECT COUNT(*)
-                FROM `follows`
-                WHERE `follows`.`source`=? AND `follows`.`target`=`users`.`id`
-            ) AS `following`
-            FROM `users`
-            WHERE `users`.`id`=?
-        ",
+    let list = app
+        .repos
+        .articles
+        .list(
+            Some(user_id),
+            ArticleFilter {
+                followed_by: Some(user_id),
+                muted_by: Some(user_id),
+                limit: query.limit,
+                offset: query.offset,
+                ..ArticleFilter::default()
+            },
         )
-        .bind(user_id)
-        .bind(i.author)
-        .fetch_one(&app.db)
-        .await
-        .unwrap();
+        .await?;
 
-        author_list.push(profile);
-    }
+    Ok(Json(multiple_articles(&app, Some(user_id), list).await?))
+}
 
-    // Combine the query results
+/// Add the authors to a list of articles
+async fn multiple_articles(
+    app: &AppState,
+    viewer: Option<i64>,
+    list: Vec<repo::Article>,
+) -> repo::Result<ResponseMultipleArticles> {
     let mut articles = Vec::with_capacity(list.len());
 
-    for ((article, tag_list), author) in iter::zip(iter::zip(list, tag_list), author_list) {
+    for article in list {
         articles.push(NoBodyArticle {
+            author: load_profile_by_id(app, viewer, article.author).await?,
             slug: article.slug,
             title: article.title,
             description: article.description,
-            tag_list,
+            tag_list: article.tag_list,
             created_at: article.created_at,
             updated_at: article.updated_at,
             favorited: article.favorited,
             favorites_count: article.favorites_count,
-            author,
         });
     }
 
-    Json(ResponseMultipleArticles {
+    Ok(ResponseMultipleArticles {
         articles_count: articles.len(),
         articles,
     })
 }
 
-#[derive(Debug, FromRow)]
-#[sqlx(rename_all = "camelCase")]
-pub struct SimpleBodyArticle {
-    id: i64,
-    slug: String,
-    title: String,
-    description: String,
-    body: String,
-    created_at: String,
-    updated_at: String,
-    author: i64,
-    favorited: bool,
-    favorites_count: i64,
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct BodyArticle {
     slug: String,
@@ -411,108 +246,57 @@ pub struct BodyArticle {
     author: Profile,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ResponseSingleArticle {
     article: BodyArticle,
 }
 
+/// A single article
+#[utoipa::path(
+    get,
+    path = "/api/articles/{slug}",
+    tag = "articles",
+    params(("slug" = String, Path)),
+    security((), ("token" = [])),
+    responses(
+        (status = 200, description = "The article", body = ResponseSingleArticle),
+        (status = 404, description = "There is no such article"),
+    ),
+)]
 pub async fn get_article(
     State(app): State<Arc<AppState>>,
     authentication: Option<Auth>,
     Path(slug): Path<String>,
-) -> Json<ResponseSingleArticle> {
-    let article = sqlx::query_as::<_, SimpleBodyArticle>(
-        "
-        SELECT `id`, `slug`, `title`, `description`, `body`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `createdAt`) AS `createdAt`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `updatedAt`) AS `updatedAt`,
-            `author`, (
-                SELECT COUNT(*)
-                FROM `favorites`
-                WHERE `favorites`.`source`=? AND `favorites`.`target`=`articles`.`id`
-            ) AS `favorited`, (
-                SELECT COUNT(*)
-                FROM `favorites`
-                WHERE `target`=`articles`.`id`
-            ) AS `favoritesCount`
-        FROM `articles`
-        WHERE `slug`=?
-        ",
-    )
-    .bind(authentication.as_ref().map(|auth| auth.0).unwrap_or(-1))
-    .bind(slug)
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    // Fetch the taglist
-    let tags: Vec<String> = sqlx::query_scalar(
-        "
-            SELECT `name`
-            FROM `taglist` INNER JOIN `tags` ON `taglist`.`tag`=`tags`.`id`
-            WHERE `article`=?
-        ",
-    )
-    .bind(article.id)
-    .fetch_all(&app.db)
-    .await
-    .unwrap();
-
-    // Then the authors
-    let profile = if let Some(ref authentication) = authentication {
-        let user_id = authentication.0;
-
-        sqlx::query_as::<_, crate::database::Profile>(
-            "
-                SELECT `username`, `bio`, `image`, (
-                    SELECT COUNT(*)
-                    FROM `follows`
-                    WHERE `follows`.`source`=? AND `follows`.`target`=`users`.`id`
-                ) AS `following`
-                FROM `users`
-                WHERE `users`.`id`=?
-            ",
-        )
-        .bind(user_id)
-        .bind(article.author)
-    } else {
-        sqlx::query_as::<_, crate::database::Profile>(
-            "
-                    SELECT `username`, `bio`, `image`, FALSE AS `following`
-                    FROM `users`
-                    WHERE `users`.`id`=?
-                ",
-        )
-        .bind(article.author)
-    }
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
+) -> Result<Json<ResponseSingleArticle>, Error> {
+    let viewer = authentication.map(|auth| auth.0);
+    let article = find_visible_article(&app, viewer, &slug)
+        .await?
+        .ok_or(Error::NotFound)?;
 
-    Json(ResponseSingleArticle {
+    Ok(Json(ResponseSingleArticle {
         article: BodyArticle {
+            author: load_profile_by_id(&app, viewer, article.author).await?,
             slug: article.slug,
             title: article.title,
             description: article.description,
             body: article.body,
-            tag_list: tags,
+            tag_list: article.tag_list,
             created_at: article.created_at,
             updated_at: article.updated_at,
             favorited: article.favorited,
             favorites_count: article.favorites_count,
-            author: profile,
         },
-    })
+    }))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateArticleRequest {
     article: CreateArticle,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateArticle {
     title: String,
@@ -521,264 +305,250 @@ pub struct CreateArticle {
     tag_list: Option<Vec<String>>,
 }
 
+/// Write a new article
+#[utoipa::path(
+    post,
+    path = "/api/articles",
+    tag = "articles",
+    security(("token" = [])),
+    request_body = CreateArticleRequest,
+    responses(
+        (status = 200, description = "The new article", body = ResponseSingleArticle),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The email address has to be verified first"),
+        (status = 422, description = "An article with the title exists or there are too many tags"),
+    ),
+)]
 pub async fn create_article(
     State(app): State<Arc<AppState>>,
-    Auth(user_id): Auth,
+    Verified(user_id): Verified,
     Json(article): Json<CreateArticleRequest>,
-) -> Json<ResponseSingleArticle> {
+) -> Result<Json<ResponseSingleArticle>, Error> {
     let article = article.article;
     let slug = create_slug(&article.title);
+    let tag_list = article.tag_list.unwrap_or_default();
 
-    let id: i64 = sqlx::query_scalar(
-        "
-            INSERT INTO `articles`
-            (`slug`, `title`, `description`, `body`, `author`)
-            VALUES
-            (?, ?, ?, ?, ?)
-            RETURNING `id`
-        ",
-    )
-    .bind(&slug)
-    .bind(article.title)
-    .bind(article.description)
-    .bind(article.body)
-    .bind(user_id)
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    if let Some(mut tag_list) = article.tag_list {
-        if tag_list.len() > 999 {
-            panic!("Too many tags");
-        };
-
-        if !tag_list.is_empty() {
-            // We only need each tag once
-            tag_list.sort();
-            tag_list.dedup();
-
-            let tags = sqlx::query_as::<_, (String, i64)>(
-                "
-                SELECT `name`, `id`
-                FROM `tags`
-            ",
-            )
-            .fetch_all(&app.db)
-            .await
-            .unwrap();
-
-            let mut query: QueryBuilder<Sqlite> =
-                QueryBuilder::new("INSERT INTO `taglist` (`article`, `tag`) ");
-
-            query.push_values(tag_list, |mut query, tag| {
-                query
-                    .push_bind(id)
-                    .push_bind(
-                        tags.iter()
-                            .find_map(|(name, id)| if name == &tag { Some(id) } else { None }),
-                    );
-            });
-
-            query.build().execute(&app.db).await.unwrap();
-        };
+    if tag_list.len() > MAX_TAGS {
+        return Err(Error::TooManyTags);
     };
 
-    get_article(State(app), Some(Auth(user_id)), Path(slug)).await
+    app.repos
+        .articles
+        .create(
+            user_id,
+            NewArticle {
+                slug: slug.clone(),
+                title: article.title,
+                description: article.description,
+                body: article.body,
+                tag_list,
+            },
+        )
+        .await
+        .map_err(slug_taken)?;
+
+    let response = get_article(State(app.clone()), Some(Auth(user_id)), Path(slug)).await?;
+    dispatch(&app, WebhookEvent::ArticleCreated, user_id, &response.0).await?;
+
+    Ok(response)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateArticleRequest {
     article: UpdateArticle,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateArticle {
     title: Option<String>,
     description: Option<String>,
     body: Option<String>,
 }
 
+/// Change an article of the authenticated user
+#[utoipa::path(
+    put,
+    path = "/api/articles/{slug}",
+    tag = "articles",
+    params(("slug" = String, Path)),
+    security(("token" = [])),
+    request_body = UpdateArticleRequest,
+    responses(
+        (status = 200, description = "The updated article", body = ResponseSingleArticle),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The article is by someone else"),
+        (status = 404, description = "There is no such article"),
+        (status = 422, description = "An article with the new title exists"),
+    ),
+)]
 pub async fn update_article(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
     Path(slug): Path<String>,
     Json(article): Json<UpdateArticleRequest>,
-) -> Json<ResponseSingleArticle> {
+) -> Result<Json<ResponseSingleArticle>, Error> {
     let article = article.article;
-
-    let article_id: i64 = sqlx::query_scalar(
-        "
-            SELECT `id`
-            FROM `articles`
-            WHERE `slug`=? AND `author`=?
-        ",
-    )
-    .bind(&slug)
-    .bind(user_id) // Make sure only the owner can edit an article
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
+    let article_id = find_own_article(&app, user_id, &slug).await?;
 
     if article.title.is_none() && article.description.is_none() && article.body.is_none() {
         return get_article(State(app), Some(Auth(user_id)), Path(slug)).await;
     };
 
-    let attributes = vec![
-        article.title.as_ref().map(|_| "`slug`=?"), // Update the slug if the title changes
-        article.title.as_ref().map(|_| "`title`=?"),
-        article.description.as_ref().map(|_| "`description`=?"),
-        article.body.as_ref().map(|_| "`body`=?"),
-    ];
-
     let new_slug = article
         .title
         .as_ref()
-        .map(|slug| create_slug(&slug))
+        .map(|slug| create_slug(slug))
         .unwrap_or_else(|| slug);
 
-    let sql = format!(
-        "
-            UPDATE `articles`
-            SET
-            {}, `updatedAt`=CURRENT_TIMESTAMP
-            WHERE `id`=?
-        ",
-        attributes
-            .into_iter()
-            .filter_map(|i| i)
-            .collect::<Vec<&'static str>>()
-            .join(", ")
-    );
-
-    let query = sqlx::query(&sql);
-
-    let query = if let Some(_) = article.title {
-        query.bind(&new_slug)
-    } else {
-        query
-    };
-
-    let query = if let Some(ref title) = article.title {
-        query.bind(title)
-    } else {
-        query
-    };
-
-    let query = if let Some(description) = article.description {
-        query.bind(description)
-    } else {
-        query
-    };
-
-    let query = if let Some(body) = article.body {
-        query.bind(body)
-    } else {
-        query
-    };
+    app.repos
+        .articles
+        .update(
+            article_id,
+            ArticleUpdate {
+                // Update the slug if the title changes
+                slug: article.title.as_ref().map(|_| new_slug.clone()),
+                title: article.title,
+                description: article.description,
+                body: article.body,
+            },
+        )
+        .await
+        .map_err(slug_taken)?;
 
-    query.bind(article_id).execute(&app.db).await.unwrap();
+    let response = get_article(State(app.clone()), Some(Auth(user_id)), Path(new_slug)).await?;
+    dispatch(&app, WebhookEvent::ArticleUpdated, user_id, &response.0).await?;
 
-    get_article(State(app), Some(Auth(user_id)), Path(new_slug)).await
+    Ok(response)
 }
 
+/// Delete an article of the authenticated user
+#[utoipa::path(
+    delete,
+    path = "/api/articles/{slug}",
+    tag = "articles",
+    params(("slug" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The article was deleted"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The article is by someone else"),
+        (status = 404, description = "There is no such article"),
+    ),
+)]
 pub async fn delete_article(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
     Path(slug): Path<String>,
-) {
-    let article_id: i64 = sqlx::query_scalar(
-        "
-            SELECT `id`
-            FROM `articles`
-            WHERE `slug`=? AND `author`=?
-        ",
-    )
-    .bind(slug)
-    .bind(user_id) // Make sure only owners can delete their articles
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    // First, delete the favorites
-    sqlx::query(
-        "
-            DELETE FROM `favorites`
-            WHERE `target`=?
-        ",
-    )
-    .bind(article_id)
-    .execute(&app.db)
-    .await
-    .unwrap();
-
-    // Then, delete the tags
-    sqlx::query(
-        "
-        DELETE FROM `taglist`
-        WHERE `article`=?
-    ",
-    )
-    .bind(article_id)
-    .execute(&app.db)
-    .await
-    .unwrap();
-
-    // Finally, delete the articles
-    sqlx::query(
-        "
-            DELETE FROM `articles`
-            WHERE `id`=?
-        ",
+) -> Result<(), Error> {
+    let article_id = find_own_article(&app, user_id, &slug).await?;
+    let attachments = app.repos.attachments.list(article_id).await?;
+
+    app.repos.articles.delete(article_id).await?;
+    delete_files(app.media_store.as_ref(), &attachments).await;
+
+    dispatch(
+        &app,
+        WebhookEvent::ArticleDeleted,
+        user_id,
+        json!({ "article": { "slug": slug } }),
     )
-    .bind(article_id)
-    .execute(&app.db)
-    .await
-    .unwrap();
+    .await?;
+
+    Ok(())
+}
+
+/// The article with `slug` as `viewer` sees it
+///
+/// Unpublished articles are only visible to their author, everyone else
+/// gets `None` as if there were no such article.
+pub(crate) async fn find_visible_article(
+    app: &AppState,
+    viewer: Option<i64>,
+    slug: &str,
+) -> repo::Result<Option<repo::Article>> {
+    let article = app.repos.articles.find_by_slug(viewer, slug).await?;
+
+    Ok(article.filter(|article| article.published || Some(article.author) == viewer))
 }
 
+/// The `id` of the article, which only its author may change
+async fn find_own_article(app: &AppState, user_id: i64, slug: &str) -> Result<i64, Error> {
+    let article = app
+        .repos
+        .articles
+        .find_by_slug(Some(user_id), slug)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    if article.author != user_id {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(article.id)
+}
+
+/// Favorite an article
+#[utoipa::path(
+    post,
+    path = "/api/articles/{slug}/favorite",
+    tag = "articles",
+    params(("slug" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The favorited article", body = ResponseSingleArticle),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such article"),
+    ),
+)]
 pub async fn favorite_article(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
     Path(slug): Path<String>,
-) -> Json<ResponseSingleArticle> {
-    sqlx::query(
-        "
-            INSERT INTO `favorites`
-            (`source`, `target`)
-            SELECT ?, `id`
-            FROM `articles`
-            WHERE `articles`.`slug`=?
-        ",
-    )
-    .bind(user_id)
-    .bind(&slug)
-    .execute(&app.db)
-    .await
-    .unwrap();
+) -> Result<Json<ResponseSingleArticle>, Error> {
+    let article = find_visible_article(&app, Some(user_id), &slug)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    if !article.favorited {
+        app.repos.articles.favorite(user_id, article.id).await?;
+        notify(
+            &app,
+            NewNotification {
+                user: article.author,
+                kind: NotificationKind::Favorite,
+                actor: user_id,
+                article: Some(article.id),
+                comment: None,
+            },
+        )
+        .await?;
+    }
 
     get_article(State(app), Some(Auth(user_id)), Path(slug)).await
 }
 
+/// Remove an article from the favorites
+#[utoipa::path(
+    delete,
+    path = "/api/articles/{slug}/favorite",
+    tag = "articles",
+    params(("slug" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The unfavorited article", body = ResponseSingleArticle),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such article"),
+    ),
+)]
 pub async fn unfavorite_article(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
     Path(slug): Path<String>,
-) -> Json<ResponseSingleArticle> {
-    sqlx::query(
-        "
-            DELETE FROM `favorites`
-            WHERE `source`=? AND `target` IN (
-                SELECT `id`
-                FROM `articles`
-                WHERE `slug`=?
-            )
-        ",
-    )
-    .bind(user_id)
-    .bind(&slug)
-    .execute(&app.db)
-    .await
-    .unwrap();
+) -> Result<Json<ResponseSingleArticle>, Error> {
+    let article = find_visible_article(&app, Some(user_id), &slug)
+        .await?
+        .ok_or(Error::NotFound)?;
+    app.repos.articles.unfavorite(user_id, article.id).await?;
 
     get_article(State(app), Some(Auth(user_id)), Path(slug)).await
 }
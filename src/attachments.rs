@@ -0,0 +1,402 @@
+//! Files authors upload to link to from the body of their articles
+//!
+//! Attachments are kept in the [`crate::media::MediaStore`] next to the
+//! avatars and belong to their article, deleting the article deletes them.
+//! What a user uploads counts against their `--attachment-quota`.
+
+use crate::{
+    articles::find_visible_article,
+    auth::Auth,
+    errors,
+    media::{self, MediaStore, TYPES},
+    repo::{self, Attachment, NewAttachment},
+    token::random_token,
+    AppState,
+};
+use axum::{
+    extract::{multipart::MultipartError, Multipart, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::{fmt, io};
+use utoipa::ToSchema;
+
+/// Uploaded file names are cut off after this many characters
+const MAX_FILENAME: usize = 255;
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "attachments")]
+pub struct Config {
+    /// Largest attachment accepted, in bytes
+    #[arg(
+        id = "attachment_max_size",
+        value_name = "BYTES",
+        long = "attachment-max-size",
+        env = "ATTACHMENT_MAX_SIZE",
+        default_value_t = 10 * 1024 * 1024
+    )]
+    pub max_size: i64,
+
+    /// Bytes of attachments each user may upload in total
+    #[arg(
+        id = "attachment_quota",
+        value_name = "BYTES",
+        long = "attachment-quota",
+        env = "ATTACHMENT_QUOTA",
+        default_value_t = 100 * 1024 * 1024
+    )]
+    pub quota: i64,
+
+    /// Content types attachments may have, separated by commas
+    #[arg(
+        id = "attachment_types",
+        value_name = "TYPES",
+        long = "attachment-types",
+        env = "ATTACHMENT_TYPES",
+        value_delimiter = ',',
+        value_parser = TYPES.map(|(content_type, _)| content_type),
+        default_values_t = TYPES.map(|(content_type, _)| content_type.to_owned())
+    )]
+    pub types: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_size: 10 * 1024 * 1024,
+            quota: 100 * 1024 * 1024,
+            types: TYPES
+                .map(|(content_type, _)| content_type.to_owned())
+                .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The user has no article with this slug
+    ArticleNotFound,
+    /// The article has no attachment with this `id`
+    NotFound,
+    Multipart(MultipartError),
+    /// The form has no `file` field
+    MissingFile,
+    /// The file is larger than `--attachment-max-size`
+    TooLarge,
+    /// The file does not fit into what is left of the quota of the user
+    QuotaExceeded,
+    /// The content type is not one of `--attachment-types`
+    UnsupportedType,
+    /// The content of the file does not match its content type
+    Mismatch,
+    Io(io::Error),
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ArticleNotFound => write!(f, "no such article"),
+            Error::NotFound => write!(f, "no such attachment"),
+            Error::Multipart(error) => write!(f, "{}", error.body_text()),
+            Error::MissingFile => write!(f, "the form has no file field"),
+            Error::TooLarge => write!(f, "the file is too large"),
+            Error::QuotaExceeded => write!(f, "the file exceeds the attachment quota"),
+            Error::UnsupportedType => write!(f, "files of this type cannot be attached"),
+            Error::Mismatch => write!(f, "the file does not match its content type"),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<MultipartError> for Error {
+    fn from(error: MultipartError) -> Self {
+        Error::Multipart(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            Error::ArticleNotFound | Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Multipart(error) => error.status(),
+            Error::MissingFile | Error::Mismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::TooLarge | Error::QuotaExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::UnsupportedType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::Io(_) | Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// Whether `bytes` are what `content_type` claims, so nothing is served as
+/// something else
+fn matches(content_type: &str, bytes: &[u8]) -> bool {
+    if let Some(format) = media::format(content_type) {
+        return image::guess_format(bytes).is_ok_and(|guessed| guessed == format);
+    }
+
+    match content_type {
+        "application/pdf" => bytes.starts_with(b"%PDF-"),
+        "text/plain" => std::str::from_utf8(bytes).is_ok(),
+        _ => false,
+    }
+}
+
+/// The name of an uploaded file without any directories
+fn filename(name: Option<&str>) -> String {
+    let name = name
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("attachment");
+
+    name.chars().take(MAX_FILENAME).collect()
+}
+
+/// Remove the stored files of attachments whose rows are gone
+///
+/// Files left behind do no harm, so failures are only logged.
+pub(crate) async fn delete_files(store: &dyn MediaStore, attachments: &[Attachment]) {
+    for attachment in attachments {
+        if let Err(error) = store.delete(&attachment.key).await {
+            eprintln!("Deleting {} failed: {}", attachment.key, error);
+        }
+    }
+}
+
+/// The `id` of the article, if it is one of the user
+async fn find_own_article(app: &AppState, user_id: i64, slug: &str) -> Result<i64, Error> {
+    app.repos
+        .articles
+        .find_by_slug(Some(user_id), slug)
+        .await?
+        .filter(|article| article.author == user_id)
+        .map(|article| article.id)
+        .ok_or(Error::ArticleNotFound)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseAttachmentBody {
+    id: i64,
+    /// Where the file is served, to be linked to from the body
+    url: String,
+    filename: String,
+    content_type: String,
+    /// In bytes
+    size: i64,
+    created_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseAttachment {
+    attachment: ResponseAttachmentBody,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseMultipleAttachments {
+    attachments: Vec<ResponseAttachmentBody>,
+}
+
+fn response_attachment(app: &AppState, attachment: Attachment) -> ResponseAttachmentBody {
+    ResponseAttachmentBody {
+        id: attachment.id,
+        url: app.media.url(&attachment.key),
+        filename: attachment.filename,
+        content_type: attachment.content_type,
+        size: attachment.size,
+        created_at: attachment.created_at,
+    }
+}
+
+/// Attach a file to an article of the authenticated user
+///
+/// The file goes into the `file` field of a `multipart/form-data` body.
+#[utoipa::path(
+    post,
+    path = "/api/articles/{slug}/attachments",
+    tag = "articles",
+    params(("slug" = String, Path)),
+    security(("token" = [])),
+    request_body(content_type = "multipart/form-data", description = "The file in a `file` field"),
+    responses(
+        (status = 200, description = "The new attachment", body = ResponseAttachment),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The user has no such article"),
+        (status = 413, description = "The file is too large or exceeds the quota"),
+        (status = 415, description = "Files of this type cannot be attached"),
+        (status = 422, description = "No file or one which does not match its content type"),
+    ),
+)]
+pub async fn upload_attachment(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(slug): Path<String>,
+    mut multipart: Multipart,
+) -> Result<Json<ResponseAttachment>, Error> {
+    let article_id = find_own_article(&app, user_id, &slug).await?;
+    let config = &app.attachments;
+    let left = config.quota - app.repos.attachments.usage(user_id).await?;
+    let mut upload = None;
+
+    while let Some(mut field) = multipart.next_field().await? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let content_type = field
+            .content_type()
+            .filter(|&content_type| config.types.iter().any(|allowed| allowed == content_type))
+            .ok_or(Error::UnsupportedType)?
+            .to_owned();
+        let filename = filename(field.file_name());
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = field.chunk().await? {
+            let size = (bytes.len() + chunk.len()) as i64;
+
+            if size > config.max_size {
+                return Err(Error::TooLarge);
+            }
+
+            if size > left {
+                return Err(Error::QuotaExceeded);
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        upload = Some((content_type, filename, bytes));
+        break;
+    }
+
+    let (content_type, filename, bytes) = upload.ok_or(Error::MissingFile)?;
+
+    if !matches(&content_type, &bytes) {
+        return Err(Error::Mismatch);
+    }
+
+    let key = format!(
+        "attachments/{}/{}.{}",
+        article_id,
+        &random_token()[..16],
+        // Only known types are allowed
+        media::extension(&content_type).unwrap()
+    );
+    let size = bytes.len() as i64;
+    app.media_store.put(&key, bytes).await?;
+
+    // Other uploads may have used up the quota in the meantime
+    let created = app
+        .repos
+        .attachments
+        .create(
+            NewAttachment {
+                article: article_id,
+                owner: user_id,
+                key: key.clone(),
+                filename,
+                content_type,
+                size,
+            },
+            config.quota,
+        )
+        .await;
+    let attachment = match created {
+        Ok(Some(attachment)) => attachment,
+        failed => {
+            // Nothing refers to the file
+            app.media_store.delete(&key).await?;
+
+            return Err(failed.err().map_or(Error::QuotaExceeded, Error::from));
+        }
+    };
+
+    Ok(Json(ResponseAttachment {
+        attachment: response_attachment(&app, attachment),
+    }))
+}
+
+/// The attachments of an article, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/articles/{slug}/attachments",
+    tag = "articles",
+    params(("slug" = String, Path)),
+    security((), ("token" = [])),
+    responses(
+        (status = 200, description = "The attachments", body = ResponseMultipleAttachments),
+        (status = 404, description = "No such article"),
+    ),
+)]
+pub async fn list_attachments(
+    State(app): State<Arc<AppState>>,
+    authentication: Option<Auth>,
+    Path(slug): Path<String>,
+) -> Result<Json<ResponseMultipleAttachments>, Error> {
+    let viewer = authentication.map(|auth| auth.0);
+    let article = find_visible_article(&app, viewer, &slug)
+        .await?
+        .ok_or(Error::ArticleNotFound)?;
+    let attachments = app.repos.attachments.list(article.id).await?;
+
+    Ok(Json(ResponseMultipleAttachments {
+        attachments: attachments
+            .into_iter()
+            .map(|attachment| response_attachment(&app, attachment))
+            .collect(),
+    }))
+}
+
+/// Remove an attachment from an article of the authenticated user
+#[utoipa::path(
+    delete,
+    path = "/api/articles/{slug}/attachments/{id}",
+    tag = "articles",
+    params(("slug" = String, Path), ("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The attachment is gone"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The user has no such article or attachment"),
+    ),
+)]
+pub async fn delete_attachment(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path((slug, id)): Path<(String, i64)>,
+) -> Result<(), Error> {
+    let article_id = find_own_article(&app, user_id, &slug).await?;
+    let attachment = app
+        .repos
+        .attachments
+        .find(id)
+        .await?
+        .filter(|attachment| attachment.article == article_id)
+        .ok_or(Error::NotFound)?;
+
+    app.repos.attachments.delete(attachment.id).await?;
+    delete_files(app.media_store.as_ref(), &[attachment]).await;
+
+    Ok(())
+}
@@ -1,209 +1,602 @@
-use crate::token::{authenticate, create_token};
+use crate::access_tokens::{self, required_scope};
+use crate::database::{self, Role};
+use crate::errors;
+use crate::lockout;
+use crate::repo::{self, Scope, UserUpdate};
+use crate::sessions::{self, Client, Cookies};
+use crate::token::{authenticate, hash_token, now, TokenError};
+use crate::two_factor::{self, ResponseChallenge};
+use crate::verification::try_send_token;
 use crate::AppState;
-use axum::body::Body;
-use axum::extract::{FromRequestParts, OptionalFromRequestParts, State};
+use axum::extract::{FromRequestParts, MatchedPath, OptionalFromRequestParts, State};
 use axum::http::request::Parts;
-use axum::http::HeaderMap;
-use axum::response::{IntoResponse, Response};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{AppendHeaders, IntoResponse, Response};
 use axum::Json;
+use ring::constant_time::verify_slices_are_equal;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone)]
+/// Realm of the challenges in `WWW-Authenticate`
+const REALM: &str = "realworld";
+
+#[derive(Debug)]
 pub enum AuthenticationFailure {
     MissingToken,
-    InvalidToken,
+    /// The `Authorization` header holds no `Token` or `Bearer` credentials
+    InvalidScheme,
+    InvalidToken(TokenError),
+    /// The token is for a user who no longer exists
+    UnknownUser,
+    /// The token was revoked or its session ended
+    Revoked,
+    /// The user is known but lacks the required role
+    Forbidden,
+    /// A personal access token was used without the scope the route needs,
+    /// or where only the tokens of sessions are accepted
+    InsufficientScope(Option<Scope>),
+    /// The token came from the session cookie of a request which changes
+    /// something, without the matching `X-CSRF-Token` header
+    InvalidCsrfToken,
+    /// Looking up the user or session of the token failed
+    Repo(repo::Error),
+}
+
+impl fmt::Display for AuthenticationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthenticationFailure::MissingToken => write!(f, "no token was sent"),
+            AuthenticationFailure::InvalidScheme => {
+                write!(f, "the authorization scheme has to be Token or Bearer")
+            }
+            AuthenticationFailure::InvalidToken(error) => write!(f, "{}", error),
+            AuthenticationFailure::UnknownUser => write!(f, "the user of the token does not exist"),
+            AuthenticationFailure::Revoked => {
+                write!(f, "the token was revoked or its session ended")
+            }
+            AuthenticationFailure::Forbidden => write!(f, "the user may not do this"),
+            AuthenticationFailure::InsufficientScope(Some(scope)) => {
+                write!(f, "the token lacks the {} scope", scope)
+            }
+            AuthenticationFailure::InsufficientScope(None) => {
+                write!(f, "personal access tokens are not accepted here")
+            }
+            AuthenticationFailure::InvalidCsrfToken => {
+                write!(f, "the CSRF token is missing or wrong")
+            }
+            AuthenticationFailure::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for AuthenticationFailure {}
+
+impl From<repo::Error> for AuthenticationFailure {
+    fn from(error: repo::Error) -> Self {
+        AuthenticationFailure::Repo(error)
+    }
 }
 
 impl IntoResponse for AuthenticationFailure {
     fn into_response(self) -> Response {
-        Response::builder()
-            .status(401)
-            .body(Body::new(String::from("401 Unauthorized")))
-            .unwrap()
+        let challenge = match self {
+            // See RFC 6750, section 3.1
+            AuthenticationFailure::InsufficientScope(scope) => {
+                let scope = scope.map_or(String::new(), |scope| format!(r#", scope="{}""#, scope));
+
+                let challenge = format!(
+                    r#"Bearer realm="{}", error="insufficient_scope"{}"#,
+                    REALM, scope
+                );
+
+                return (
+                    StatusCode::FORBIDDEN,
+                    [(header::WWW_AUTHENTICATE, challenge)],
+                    errors::body(&self),
+                )
+                    .into_response();
+            }
+            AuthenticationFailure::Forbidden | AuthenticationFailure::InvalidCsrfToken => {
+                return (StatusCode::FORBIDDEN, errors::body(&self)).into_response();
+            }
+            AuthenticationFailure::Repo(_) => return errors::internal(&self),
+            // Clients which sent nothing are not told about an error, see
+            // RFC 6750, section 3.1
+            AuthenticationFailure::MissingToken => format!(r#"Bearer realm="{}""#, REALM),
+            AuthenticationFailure::InvalidScheme => {
+                format!(r#"Bearer realm="{}", error="invalid_request""#, REALM)
+            }
+            _ => format!(
+                r#"Bearer realm="{}", error="invalid_token", error_description="{}""#,
+                REALM, self
+            ),
+        };
+
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, challenge)],
+            errors::body(&self),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Another user has the username or email address
+    Taken,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Taken => write!(f, "the username or email address is already taken"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        match error {
+            repo::Error::Conflict => Error::Taken,
+            error => Error::Repo(error),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::Taken => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// The token in an `Authorization` header, either as `Token <jwt>` as the
+/// RealWorld spec has it or as the standard `Bearer <jwt>`
+fn credentials(value: &str) -> Option<&str> {
+    let (scheme, token) = value.split_once(' ')?;
+
+    if scheme.eq_ignore_ascii_case("Token") || scheme.eq_ignore_ascii_case("Bearer") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// Who a request was authenticated as
+struct Authorized {
+    user_id: i64,
+    /// `None` for personal access tokens
+    session: Option<i64>,
+}
+
+/// The access token in the session cookie, if the request may use it
+fn cookie_token<'a>(parts: &'a Parts, state: &AppState) -> Result<&'a str, AuthenticationFailure> {
+    let token = sessions::cookie(&parts.headers, sessions::TOKEN_COOKIE)
+        .filter(|_| state.sessions.cookies)
+        .ok_or(AuthenticationFailure::MissingToken)?;
+
+    // Other sites can make browsers send the cookie but cannot read the CSRF
+    // token to send along
+    if !parts.method.is_safe()
+        && parts
+            .headers
+            .get(sessions::CSRF_HEADER)
+            .is_none_or(|value| {
+                verify_slices_are_equal(value.as_bytes(), sessions::csrf_token(token).as_bytes())
+                    .is_err()
+            })
+    {
+        return Err(AuthenticationFailure::InvalidCsrfToken);
     }
+
+    Ok(token)
+}
+
+/// The user of a valid access token or personal access token in the
+/// `Authorization` header, or of the access token in the session cookie
+async fn authorize(parts: &Parts, state: &AppState) -> Result<Authorized, AuthenticationFailure> {
+    let mut values = parts.headers.get_all(header::AUTHORIZATION).iter();
+
+    let token = match values.next() {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(credentials)
+            .ok_or(AuthenticationFailure::InvalidScheme)?,
+        None => cookie_token(parts, state)?,
+    };
+
+    if token.starts_with(access_tokens::PREFIX) {
+        return authorize_access_token(parts, state, token).await;
+    }
+
+    let claims = authenticate(&state.keys, token).map_err(AuthenticationFailure::InvalidToken)?;
+    let user = state
+        .repos
+        .users
+        .find(claims.user_id)
+        .await?
+        .ok_or(AuthenticationFailure::UnknownUser)?;
+
+    // Bumping the version of a user revokes all their tokens
+    if user.token_version != claims.version {
+        return Err(AuthenticationFailure::Revoked);
+    }
+
+    // Logging out ends the session and with it its tokens
+    let now = now();
+    let session = state
+        .repos
+        .sessions
+        .find(claims.session, now)
+        .await?
+        .filter(|session| session.user == claims.user_id)
+        .ok_or(AuthenticationFailure::Revoked)?;
+    sessions::touch(state, &session, now).await?;
+
+    Ok(Authorized {
+        user_id: claims.user_id,
+        session: Some(session.id),
+    })
+}
+
+/// The user of a personal access token, if it has the scope the route needs
+async fn authorize_access_token(
+    parts: &Parts,
+    state: &AppState,
+    token: &str,
+) -> Result<Authorized, AuthenticationFailure> {
+    let token = state
+        .repos
+        .access_tokens
+        .find_by_hash(&hash_token(token))
+        .await?
+        .ok_or(AuthenticationFailure::Revoked)?;
+    let now = now();
+
+    if token.expires_at < now {
+        return Err(AuthenticationFailure::InvalidToken(TokenError::Expired));
+    }
+
+    let required = parts
+        .extensions
+        .get::<MatchedPath>()
+        .and_then(|path| required_scope(&parts.method, path.as_str()));
+
+    if !required.is_some_and(|scope| token.scopes.contains(&scope)) {
+        return Err(AuthenticationFailure::InsufficientScope(required));
+    }
+
+    access_tokens::touch(state, &token, now).await?;
+
+    Ok(Authorized {
+        user_id: token.user,
+        session: None,
+    })
 }
 
 #[derive(Debug, Clone)]
 pub struct Auth(pub i64);
-impl<S> FromRequestParts<S> for Auth
-where
-    S: Sync + Send,
-{
+impl FromRequestParts<Arc<AppState>> for Auth {
     type Rejection = AuthenticationFailure;
 
-    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        let mut values = parts.headers.get_all("authorization").iter();
-
-        if let Some(value) = values.next() {
-            if let Ok(string) = value.to_str() {
-                if string.starts_with("Token ") {
-                    let token = &string[6..];
-                    let user_id = authenticate(token);
-
-                    Ok(Auth(user_id))
-                } else {
-                    Err(AuthenticationFailure::InvalidToken)
-                }
-            } else {
-                Err(AuthenticationFailure::InvalidToken)
-            }
-        } else {
-            // There is no token header
-            Err(AuthenticationFailure::MissingToken)
-        }
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let authorized = authorize(parts, state).await?;
+
+        Ok(Auth(authorized.user_id))
     }
 }
 
-impl<S> OptionalFromRequestParts<S> for Auth
-where
-    S: Sync + Send,
-{
+impl OptionalFromRequestParts<Arc<AppState>> for Auth {
     type Rejection = AuthenticationFailure;
 
     async fn from_request_parts(
         parts: &mut Parts,
-        _state: &S,
+        state: &Arc<AppState>,
     ) -> Result<Option<Self>, Self::Rejection> {
-        match <Auth as FromRequestParts<S>>::from_request_parts(parts, _state).await {
+        match <Auth as FromRequestParts<_>>::from_request_parts(parts, state).await {
             Ok(auth) => Ok(Some(auth)),
             Err(AuthenticationFailure::MissingToken) => Ok(None),
-            Err(AuthenticationFailure::InvalidToken) => Err(AuthenticationFailure::InvalidToken),
+            Err(failure) => Err(failure),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Like [`Auth`], together with the session the token was issued for
+#[derive(Debug, Clone)]
+pub struct CurrentSession {
+    pub user_id: i64,
+    pub session_id: i64,
+}
+
+impl FromRequestParts<Arc<AppState>> for CurrentSession {
+    type Rejection = AuthenticationFailure;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let authorized = authorize(parts, state).await?;
+
+        Ok(CurrentSession {
+            user_id: authorized.user_id,
+            session_id: authorized
+                .session
+                .ok_or(AuthenticationFailure::InsufficientScope(None))?,
+        })
+    }
+}
+
+/// An authenticated user with the `admin` role
+#[derive(Debug, Clone)]
+pub struct Admin(pub i64);
+
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = AuthenticationFailure;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Auth(user_id) = <Auth as FromRequestParts<_>>::from_request_parts(parts, state).await?;
+
+        let user = state.repos.users.find(user_id).await?;
+
+        if user.is_some_and(|user| user.role == Role::Admin) {
+            Ok(Admin(user_id))
+        } else {
+            Err(AuthenticationFailure::Forbidden)
+        }
+    }
+}
+
+/// An authenticated user who may write articles and comments, which takes a
+/// verified email address if `verification.required` is set
+#[derive(Debug, Clone)]
+pub struct Verified(pub i64);
+
+impl FromRequestParts<Arc<AppState>> for Verified {
+    type Rejection = AuthenticationFailure;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Auth(user_id) = <Auth as FromRequestParts<_>>::from_request_parts(parts, state).await?;
+
+        if !state.verification.required {
+            return Ok(Verified(user_id));
+        }
+
+        let user = state.repos.users.find(user_id).await?;
+
+        if user.is_some_and(|user| user.verified) {
+            Ok(Verified(user_id))
+        } else {
+            Err(AuthenticationFailure::Forbidden)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
 struct User {
     email: String,
     token: String,
     username: String,
     bio: Option<String>,
     image: Option<String>,
+    /// Only included when a session starts or is refreshed
+    #[serde(rename = "refreshToken", skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    /// Only included for `GET /api/user`
+    #[serde(
+        rename = "unreadNotifications",
+        skip_serializing_if = "Option::is_none"
+    )]
+    unread_notifications: Option<i64>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ResponseUser {
     user: User,
 }
 
-#[derive(Debug, Deserialize)]
+/// The response to logging in, which is a challenge if the user has to enter
+/// a code of their second factor as well
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum ResponseLogin {
+    User(ResponseUser),
+    Challenge(ResponseChallenge),
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct Authentication {
     user: AuthenticationUser,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AuthenticationUser {
     email: String,
     password: String,
 }
 
+/// Log in
+///
+/// With two-factor authentication enabled, this returns a challenge token
+/// for `POST /api/users/login/2fa` instead of the user.
+#[utoipa::path(
+    post,
+    path = "/api/users/login",
+    tag = "users",
+    request_body = Authentication,
+    responses(
+        (status = 200, description = "The user with the tokens of a new session, or a challenge for the second factor", body = ResponseLogin),
+        (status = 401, description = "Invalid email or password"),
+        (status = 429, description = "Too many failed logins, see `Retry-After`"),
+    ),
+)]
 pub async fn authentication(
     State(state): State<Arc<AppState>>,
+    client: Client,
     Json(authenticate): Json<Authentication>,
-) -> Json<ResponseUser> {
-    let user = sqlx::query_as::<_, crate::database::User>(
-        "
-            SELECT * FROM `users` WHERE `email`=? AND `password`=?
-    ",
+) -> Result<(Cookies, Json<ResponseLogin>), lockout::Error> {
+    let user = lockout::login(
+        &state,
+        &client,
+        &authenticate.user.email,
+        &authenticate.user.password,
     )
-    .bind(&authenticate.user.email)
-    .bind(&authenticate.user.password)
-    .fetch_one(&state.db)
-    .await
-    .unwrap();
+    .await?;
+
+    if two_factor::enabled(&state, user.id).await? {
+        let challenge = two_factor::challenge(&state, user.id).await?;
+
+        return Ok((
+            AppendHeaders(Vec::new()),
+            Json(ResponseLogin::Challenge(challenge)),
+        ));
+    }
+
+    lockout::succeeded(&state, &user).await?;
+    let (token, refresh_token) = sessions::start(&state, &user, client).await?;
+    let (cookies, response) = sessions::issue(&state, user, token, refresh_token);
+
+    Ok((cookies, Json(ResponseLogin::User(response))))
+}
 
-    Json(ResponseUser {
+/// The response for `user` with a token issued to them
+pub(crate) fn logged_in(
+    user: database::User,
+    token: String,
+    refresh_token: Option<String>,
+) -> ResponseUser {
+    ResponseUser {
         user: User {
-            email: user.email.clone(),
-            token: create_token(user.id),
+            email: user.email,
+            token,
             username: user.username,
             bio: user.bio,
             image: user.image,
+            refresh_token,
+            unread_notifications: None,
         },
-    })
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct Registration {
     user: RegistrationUser,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RegistrationUser {
     username: String,
     email: String,
     password: String,
 }
 
+/// Register a new user
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "users",
+    request_body = Registration,
+    responses(
+        (status = 200, description = "The new user with a token", body = ResponseUser),
+        (status = 422, description = "The username or email address is taken"),
+    ),
+)]
 pub async fn registration(
     State(state): State<Arc<AppState>>,
+    client: Client,
     Json(registration): Json<Registration>,
-) -> Json<ResponseUser> {
-    sqlx::query(
-        "
-            INSERT INTO `users`
-            (`email`, `password`, `username`)
-            VALUES
-            (?, ?, ?)
-    ",
-    )
-    .bind(&registration.user.email)
-    .bind(&registration.user.password)
-    .bind(&registration.user.username)
-    .execute(&state.db)
-    .await
-    .unwrap();
-
-    authentication(
-        State(state),
-        Json(Authentication {
-            user: AuthenticationUser {
-                email: registration.user.email,
-                password: registration.user.password,
-            },
-        }),
-    )
-    .await
+) -> Result<(Cookies, Json<ResponseUser>), Error> {
+    let user_id = state
+        .repos
+        .users
+        .create(
+            &registration.user.email,
+            &registration.user.password,
+            &registration.user.username,
+        )
+        .await?;
+    let user = state.repos.users.find(user_id).await?.unwrap();
+    try_send_token(&state, &user).await;
+
+    let (token, refresh_token) = sessions::start(&state, &user, client).await?;
+    let (cookies, response) = sessions::issue(&state, user, token, refresh_token);
+
+    Ok((cookies, Json(response)))
 }
 
+/// The authenticated user
+#[utoipa::path(
+    get,
+    path = "/api/user",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The authenticated user", body = ResponseUser),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
 pub async fn get_current_user(
     State(state): State<Arc<AppState>>,
     Auth(user_id): Auth,
     headers: HeaderMap,
-) -> Json<ResponseUser> {
-    let user = sqlx::query_as::<_, crate::database::User>(
-        "
-            SELECT * FROM `users` WHERE `id`=?
-    ",
-    )
-    .bind(user_id)
-    .fetch_one(&state.db)
-    .await
-    .unwrap();
+) -> Result<Json<ResponseUser>, Error> {
+    Ok(Json(current_user(&state, user_id, &headers).await?))
+}
 
-    Json(ResponseUser {
+/// The response for the authenticated user, with the token of the request
+pub(crate) async fn current_user(
+    state: &AppState,
+    user_id: i64,
+    headers: &HeaderMap,
+) -> repo::Result<ResponseUser> {
+    let user = state.repos.users.find(user_id).await?.unwrap();
+    let unread_notifications = state.repos.notifications.unread_count(user_id).await?;
+
+    Ok(ResponseUser {
         user: User {
             email: user.email,
             token: headers
-                .get("authorization")
-                .unwrap() // Both this and the `to_str()`
-                .to_str() // call will not panic because the `Auth`
-                .unwrap() // extractor ensures that a header is present
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(credentials)
+                // Tokens in cookies are not for scripts to see
+                .unwrap_or_default()
                 .to_owned(),
             username: user.username,
             bio: user.bio,
             image: user.image,
+            refresh_token: None,
+            unread_notifications: Some(unread_notifications),
         },
     })
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct Update {
     user: UpdateUser,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateUser {
     email: Option<String>,
     password: Option<String>,
@@ -212,35 +605,53 @@ pub struct UpdateUser {
     image: Option<String>,
 }
 
+/// Change the authenticated user
+#[utoipa::path(
+    put,
+    path = "/api/user",
+    tag = "users",
+    security(("token" = [])),
+    request_body = Update,
+    responses(
+        (status = 200, description = "The updated user", body = ResponseUser),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 422, description = "The username or email address is taken"),
+    ),
+)]
 pub async fn update_user(
     State(state): State<Arc<AppState>>,
     Auth(user_id): Auth,
     headers: HeaderMap,
     Json(update): Json<Update>,
-) -> Json<ResponseUser> {
-    async fn update_field(state: &AppState, user_id: i64, name: &str, value: &Option<String>) {
-        if let Some(value) = value {
-            sqlx::query(&format!(
-                "
-                    UPDATE `users`
-                    SET {}=?
-                    WHERE `users`.`id`=?
-                ",
-                name
-            ))
-            .bind(&value)
-            .bind(&user_id)
-            .execute(&state.db)
-            .await
-            .unwrap();
-        }
-    }
+) -> Result<Json<ResponseUser>, Error> {
+    let current = state.repos.users.find(user_id).await?.unwrap();
+    // A new address has to be verified again
+    let email_changed = update
+        .user
+        .email
+        .as_ref()
+        .is_some_and(|email| *email != current.email);
+
+    state
+        .repos
+        .users
+        .update(
+            user_id,
+            UserUpdate {
+                email: update.user.email,
+                password: update.user.password,
+                username: update.user.username,
+                bio: update.user.bio,
+                image: update.user.image,
+            },
+        )
+        .await?;
 
-    update_field(&state, user_id, "email", &update.user.email).await;
-    update_field(&state, user_id, "password", &update.user.password).await;
-    update_field(&state, user_id, "username", &update.user.username).await;
-    update_field(&state, user_id, "bio", &update.user.bio).await;
-    update_field(&state, user_id, "image", &update.user.image).await;
+    if email_changed {
+        state.repos.users.set_verified(user_id, false).await?;
+        let user = state.repos.users.find(user_id).await?.unwrap();
+        try_send_token(&state, &user).await;
+    }
 
     get_current_user(State(state), Auth(user_id), headers).await
 }
@@ -0,0 +1,271 @@
+//! Consistent copies of the live database
+//!
+//! Backups are written with `VACUUM INTO`, which reads the database inside a
+//! transaction, so the copy is never torn even while the server keeps
+//! writing. PostgreSQL has its own tools for this, such as `pg_dump`.
+
+use crate::{auth::Admin, database::Pool, errors, AppState};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fmt, fs, time::Duration};
+use utoipa::ToSchema;
+
+const PREFIX: &str = "realworld-";
+const SUFFIX: &str = ".db";
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "backup")]
+pub struct Config {
+    /// Directory backups are written to
+    #[arg(
+        id = "backup_dir",
+        value_name = "DIR",
+        long = "backup-dir",
+        env = "BACKUP_DIR",
+        default_value = "backups"
+    )]
+    pub dir: PathBuf,
+
+    /// Number of backups to keep, older ones are deleted after each backup
+    #[arg(
+        id = "backup_keep",
+        value_name = "KEEP",
+        long = "backup-keep",
+        env = "BACKUP_KEEP",
+        default_value_t = 7
+    )]
+    pub keep: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    pub file: String,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Database(sqlx::Error),
+    Io(std::io::Error),
+    /// The backup was written but could not be opened again
+    Verify(String),
+    /// The storage behind the application cannot be backed up this way
+    Unsupported,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Database(error) => write!(f, "database error: {}", error),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Verify(message) => write!(f, "backup is unusable: {}", message),
+            Error::Unsupported => write!(f, "backups are only supported for SQLite databases"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        Error::Database(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::Unsupported => StatusCode::NOT_IMPLEMENTED,
+            _ => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// Write a new backup, make sure it can be opened and rotate old backups
+#[cfg(not(feature = "postgres"))]
+pub async fn create(db: &Pool, config: &Config) -> Result<Backup, Error> {
+    fs::create_dir_all(&config.dir)?;
+
+    // Millisecond timestamps keep the names unique and in chronological order
+    let timestamp: String = sqlx::query_scalar("SELECT strftime('%Y%m%dT%H%M%fZ', 'now')")
+        .fetch_one(db)
+        .await?;
+    let path = config
+        .dir
+        .join(format!("{}{}{}", PREFIX, timestamp, SUFFIX));
+
+    sqlx::query("VACUUM INTO ?")
+        .bind(path.to_string_lossy())
+        .execute(db)
+        .await?;
+
+    if let Err(error) = verify(&path).await {
+        let _ = fs::remove_file(&path);
+        return Err(error);
+    }
+
+    rotate(config)?;
+
+    Ok(Backup {
+        file: path.to_string_lossy().into_owned(),
+        size: fs::metadata(&path)?.len(),
+    })
+}
+
+#[cfg(feature = "postgres")]
+pub async fn create(_: &Pool, _: &Config) -> Result<Backup, Error> {
+    Err(Error::Unsupported)
+}
+
+/// Open a backup read-only and check it for corruption
+pub async fn verify(path: &Path) -> Result<(), Error> {
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .map_err(|error| Error::Verify(error.to_string()))?;
+
+    let result: String = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_one(&pool)
+        .await
+        .map_err(|error| Error::Verify(error.to_string()))?;
+
+    pool.close().await;
+
+    if result == "ok" {
+        Ok(())
+    } else {
+        Err(Error::Verify(result))
+    }
+}
+
+/// All backups in the backup directory, oldest first
+pub fn list(config: &Config) -> Result<Vec<Backup>, Error> {
+    let mut backups = Vec::new();
+
+    if !config.dir.exists() {
+        return Ok(backups);
+    }
+
+    for entry in fs::read_dir(&config.dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        if name.starts_with(PREFIX) && name.ends_with(SUFFIX) {
+            backups.push(Backup {
+                file: entry.path().to_string_lossy().into_owned(),
+                size: entry.metadata()?.len(),
+            });
+        }
+    }
+
+    backups.sort_by(|a, b| a.file.cmp(&b.file));
+
+    Ok(backups)
+}
+
+/// Delete all but the newest `config.keep` backups
+#[cfg(not(feature = "postgres"))]
+fn rotate(config: &Config) -> Result<(), Error> {
+    let backups = list(config)?;
+    let obsolete = backups.len().saturating_sub(config.keep);
+
+    for backup in &backups[..obsolete] {
+        fs::remove_file(&backup.file)?;
+    }
+
+    Ok(())
+}
+
+/// Keep backing up the database every `interval` for as long as the server
+/// runs
+pub fn schedule(db: Pool, config: Config, interval: Duration) {
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(interval);
+        // The first tick completes immediately
+        timer.tick().await;
+
+        loop {
+            timer.tick().await;
+
+            match create(&db, &config).await {
+                Ok(backup) => println!("Wrote backup {}", backup.file),
+                Err(error) => eprintln!("Scheduled backup failed: {}", error),
+            }
+        }
+    });
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseBackup {
+    backup: Backup,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseMultipleBackups {
+    backups: Vec<Backup>,
+}
+
+/// Back up the database now
+#[utoipa::path(
+    post,
+    path = "/api/admin/backups",
+    tag = "admin",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The new backup", body = ResponseBackup),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The user is not an admin"),
+        (status = 501, description = "The database cannot be backed up this way"),
+    ),
+)]
+pub async fn create_backup(
+    State(app): State<Arc<AppState>>,
+    _: Admin,
+) -> Result<Json<ResponseBackup>, Error> {
+    let db = app.db.as_ref().ok_or(Error::Unsupported)?;
+
+    Ok(Json(ResponseBackup {
+        backup: create(db, &app.backups).await?,
+    }))
+}
+
+/// All backups, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/admin/backups",
+    tag = "admin",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The backups", body = ResponseMultipleBackups),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The user is not an admin"),
+    ),
+)]
+pub async fn list_backups(
+    State(app): State<Arc<AppState>>,
+    _: Admin,
+) -> Result<Json<ResponseMultipleBackups>, Error> {
+    Ok(Json(ResponseMultipleBackups {
+        backups: list(&app.backups)?,
+    }))
+}
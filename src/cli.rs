@@ -0,0 +1,620 @@
+use crate::{
+    attachments, backup,
+    database::{self, Pool, Role},
+    dump, keys, lockout, mail, media, oidc,
+    repo::{self, Repos, UserUpdate},
+    router, sessions, verification, webhooks, AppState,
+};
+use clap::{Args, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::{fmt, sync::Arc, time::Duration};
+
+/// Backend of the RealWorld example app
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Database to operate on
+    #[arg(long, global = true, env = "DATABASE_URL", default_value = database::DEFAULT_URL)]
+    database: String,
+
+    /// How the results of administrative commands are printed
+    #[arg(long, global = true, value_enum, default_value_t = Format::Human)]
+    format: Format,
+
+    /// Defaults to `serve`
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Args)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, env = "BIND", default_value = "0.0.0.0:3000")]
+    bind: String,
+
+    /// Seconds between automatic backups, none are made if this is not set
+    #[arg(long, env = "BACKUP_INTERVAL")]
+    backup_interval: Option<u64>,
+
+    #[command(flatten)]
+    backups: backup::Config,
+
+    #[command(flatten)]
+    mail: mail::Config,
+
+    #[command(flatten)]
+    media: media::Config,
+
+    #[command(flatten)]
+    attachments: attachments::Config,
+
+    #[command(flatten)]
+    sessions: sessions::Config,
+
+    #[command(flatten)]
+    keys: keys::Config,
+
+    #[command(flatten)]
+    lockout: lockout::Config,
+
+    #[command(flatten)]
+    verification: verification::Config,
+
+    #[command(flatten)]
+    webhooks: webhooks::Config,
+
+    #[command(flatten)]
+    oidc: oidc::Config,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the HTTP server
+    Serve(Box<ServeArgs>),
+    /// Manage users
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Moderate articles
+    #[command(subcommand)]
+    Article(ArticleCommand),
+    /// Clean up tags
+    #[command(subcommand)]
+    Tag(TagCommand),
+    /// Write the whole database as newline-delimited JSON
+    Export {
+        /// File to write to instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Load a dump written by `export` into an empty database
+    Import {
+        /// File to read from, `-` for standard input
+        input: PathBuf,
+    },
+    /// Write a consistent copy of the database while it may be in use
+    Backup(backup::Config),
+}
+
+#[derive(Debug, Subcommand)]
+enum UserCommand {
+    /// Register a new user
+    Create {
+        username: String,
+        email: String,
+        password: String,
+        #[arg(long, value_enum, default_value_t = Role::User)]
+        role: Role,
+    },
+    /// List all users
+    List,
+    /// Delete a user together with their articles, comments, follows and
+    /// favorites, and the files they uploaded
+    Delete {
+        username: String,
+        #[command(flatten)]
+        media: media::Config,
+    },
+    /// Replace the password of a user and log them out everywhere
+    ResetPassword { username: String, password: String },
+    /// Change the role of a user
+    SetRole {
+        username: String,
+        #[arg(value_enum)]
+        role: Role,
+    },
+    /// Let a user log in again right away after too many failed logins
+    Unlock {
+        username: String,
+        /// Also unlock this client address
+        #[arg(long)]
+        ip: Option<String>,
+    },
+    /// Let a user who lost their authenticator and recovery codes log in with
+    /// the password only
+    DisableTwoFactor { username: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum ArticleCommand {
+    /// Hide an article from everyone but its author
+    Unpublish { slug: String },
+    /// Make an unpublished article visible again
+    Publish { slug: String },
+    /// Delete an article regardless of its author, with its attachments
+    Delete {
+        slug: String,
+        #[command(flatten)]
+        media: media::Config,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum TagCommand {
+    /// Give a tag a new name
+    Rename { from: String, to: String },
+    /// Retag all articles of `source` with `target` and delete `source`
+    Merge { source: String, target: String },
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Database(sqlx::Error),
+    Io(std::io::Error),
+    NotFound(&'static str, String),
+    Conflict(String),
+    Repo(repo::Error),
+    Dump(dump::Error),
+    Backup(backup::Error),
+    Mail(mail::Error),
+    Keys(keys::Error),
+    Oidc(oidc::ConfigError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Database(error) => write!(f, "database error: {}", error),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::NotFound(kind, name) => write!(f, "{} `{}` does not exist", kind, name),
+            Error::Conflict(message) => write!(f, "{}", message),
+            Error::Repo(error) => write!(f, "{}", error),
+            Error::Dump(error) => write!(f, "{}", error),
+            Error::Backup(error) => write!(f, "{}", error),
+            Error::Mail(error) => write!(f, "{}", error),
+            Error::Keys(error) => write!(f, "{}", error),
+            Error::Oidc(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        Error::Database(error)
+    }
+}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl From<backup::Error> for Error {
+    fn from(error: backup::Error) -> Self {
+        Error::Backup(error)
+    }
+}
+
+impl From<mail::Error> for Error {
+    fn from(error: mail::Error) -> Self {
+        Error::Mail(error)
+    }
+}
+
+impl From<keys::Error> for Error {
+    fn from(error: keys::Error) -> Self {
+        Error::Keys(error)
+    }
+}
+
+impl From<oidc::ConfigError> for Error {
+    fn from(error: oidc::ConfigError) -> Self {
+        Error::Oidc(error)
+    }
+}
+
+impl From<dump::Error> for Error {
+    fn from(error: dump::Error) -> Self {
+        Error::Dump(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct UserSummary {
+    id: i64,
+    username: String,
+    email: String,
+    role: Role,
+}
+
+impl From<database::User> for UserSummary {
+    fn from(user: database::User) -> Self {
+        UserSummary {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            role: user.role,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ArticleSummary {
+    id: i64,
+    slug: String,
+    published: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct TagSummary {
+    id: i64,
+    name: String,
+}
+
+pub async fn run(cli: Cli) -> Result<(), Error> {
+    let db = database::connect(&cli.database).await?;
+    let repos = Repos::sql(db.clone());
+    let format = cli.format;
+
+    let command = cli.command.unwrap_or_else(|| {
+        // Without a subcommand, behave like `serve` without arguments
+        let matches =
+            ServeArgs::augment_args(clap::Command::new("serve")).get_matches_from(["serve"]);
+        Command::Serve(Box::new(ServeArgs::from_arg_matches(&matches).unwrap()))
+    });
+
+    match command {
+        Command::Serve(args) => serve(db, repos, *args).await,
+        Command::User(command) => user(&repos, format, command).await,
+        Command::Article(command) => article(&repos, format, command).await,
+        Command::Tag(command) => tag(&repos, format, command).await,
+        Command::Export { output } => export(&db, format, output).await,
+        Command::Import { input } => import(&db, format, input).await,
+        Command::Backup(config) => {
+            let backup = backup::create(&db, &config).await?;
+
+            report(format, &backup, |backup| {
+                format!("Wrote backup {} ({} bytes)", backup.file, backup.size)
+            });
+
+            Ok(())
+        }
+    }
+}
+
+async fn serve(db: Pool, repos: Repos, args: ServeArgs) -> Result<(), Error> {
+    if let Some(seconds) = args.backup_interval {
+        backup::schedule(
+            db.clone(),
+            args.backups.clone(),
+            Duration::from_secs(seconds),
+        );
+    }
+
+    let state = Arc::new(AppState {
+        repos,
+        db: Some(db),
+        backups: args.backups,
+        mailer: args.mail.mailer()?,
+        media_store: args.media.store(),
+        media: args.media,
+        attachments: args.attachments,
+        sessions: args.sessions,
+        keys: args.keys.key_ring()?,
+        lockout: args.lockout,
+        verification: args.verification,
+        events: Default::default(),
+        webhooks: args.webhooks,
+        oidc: args.oidc.providers()?,
+    });
+    webhooks::spawn(state.clone());
+    let app = router(state);
+
+    let listener = tokio::net::TcpListener::bind(&args.bind).await?;
+    // Sessions record the address of clients
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn user(repos: &Repos, format: Format, command: UserCommand) -> Result<(), Error> {
+    match command {
+        UserCommand::Create {
+            username,
+            email,
+            password,
+            role,
+        } => {
+            if repos.users.find_by_username(&username).await?.is_some() {
+                return Err(Error::Conflict(format!(
+                    "user `{}` already exists",
+                    username
+                )));
+            }
+
+            let id = repos.users.create(&email, &password, &username).await?;
+            repos.users.set_role(id, role).await?;
+            // Whoever runs this vouches for the address
+            repos.users.set_verified(id, true).await?;
+
+            let user = UserSummary {
+                id,
+                username,
+                email,
+                role,
+            };
+            report(format, &user, |user| {
+                format!("Created user {}", user.username)
+            });
+        }
+        UserCommand::List => {
+            let users: Vec<UserSummary> = repos
+                .users
+                .list()
+                .await?
+                .into_iter()
+                .map(UserSummary::from)
+                .collect();
+
+            report(format, &users, |users| {
+                let mut table = format!(
+                    "{:>6}  {:<32}  {:<32}  {}",
+                    "ID", "USERNAME", "EMAIL", "ROLE"
+                );
+
+                for user in users {
+                    table.push_str(&format!(
+                        "\n{:>6}  {:<32}  {:<32}  {}",
+                        user.id,
+                        user.username,
+                        user.email,
+                        role_name(user.role)
+                    ));
+                }
+
+                table
+            });
+        }
+        UserCommand::Delete { username, media } => {
+            let user = require_user(repos, &username).await?;
+            let image = repos.users.find(user.id).await?.and_then(|user| user.image);
+            let attachments = repos.attachments.owned(user.id).await?;
+            repos.users.delete(user.id).await?;
+
+            let store = media.store();
+            attachments::delete_files(store.as_ref(), &attachments).await;
+            // Files left behind do no harm
+            if let Err(error) =
+                media::delete_avatar(&media, store.as_ref(), user.id, image.as_deref()).await
+            {
+                eprintln!("Deleting the avatar failed: {}", error);
+            }
+
+            report(format, &user, |user| {
+                format!("Deleted user {}", user.username)
+            });
+        }
+        UserCommand::ResetPassword { username, password } => {
+            let user = require_user(repos, &username).await?;
+            repos
+                .users
+                .update(
+                    user.id,
+                    UserUpdate {
+                        password: Some(password),
+                        ..UserUpdate::default()
+                    },
+                )
+                .await?;
+            repos.users.revoke_tokens(user.id).await?;
+
+            report(format, &user, |user| {
+                format!("Changed the password of {}", user.username)
+            });
+        }
+        UserCommand::SetRole { username, role } => {
+            let mut user = require_user(repos, &username).await?;
+            repos.users.set_role(user.id, role).await?;
+            user.role = role;
+
+            report(format, &user, |user| {
+                format!("{} is now {}", user.username, role_name(user.role))
+            });
+        }
+        UserCommand::Unlock { username, ip } => {
+            let user = require_user(repos, &username).await?;
+            repos
+                .login_attempts
+                .reset(&lockout::account_key(&user.email))
+                .await?;
+
+            if let Some(ip) = ip {
+                repos.login_attempts.reset(&lockout::ip_key(&ip)).await?;
+            }
+
+            report(format, &user, |user| format!("Unlocked {}", user.username));
+        }
+        UserCommand::DisableTwoFactor { username } => {
+            let user = require_user(repos, &username).await?;
+            repos.two_factor.disable(user.id).await?;
+
+            report(format, &user, |user| {
+                format!("Disabled two-factor authentication of {}", user.username)
+            });
+        }
+    }
+
+    Ok(())
+}
+
+async fn article(repos: &Repos, format: Format, command: ArticleCommand) -> Result<(), Error> {
+    let (slug, published, media) = match command {
+        ArticleCommand::Unpublish { slug } => (slug, Some(false), None),
+        ArticleCommand::Publish { slug } => (slug, Some(true), None),
+        ArticleCommand::Delete { slug, media } => (slug, None, Some(media)),
+    };
+
+    let id = repos
+        .articles
+        .find_by_slug(None, &slug)
+        .await?
+        .ok_or_else(|| Error::NotFound("article", slug.clone()))?
+        .id;
+
+    if let Some(published) = published {
+        repos.articles.set_published(id, published).await?;
+    } else if let Some(media) = media {
+        let attachments = repos.attachments.list(id).await?;
+        repos.articles.delete(id).await?;
+        attachments::delete_files(media.store().as_ref(), &attachments).await;
+    }
+
+    let article = ArticleSummary {
+        id,
+        slug,
+        published,
+    };
+    report(format, &article, |article| match article.published {
+        Some(true) => format!("Published {}", article.slug),
+        Some(false) => format!("Unpublished {}", article.slug),
+        None => format!("Deleted {}", article.slug),
+    });
+
+    Ok(())
+}
+
+async fn tag(repos: &Repos, format: Format, command: TagCommand) -> Result<(), Error> {
+    let (tag, message) = match command {
+        TagCommand::Rename { from, to } => {
+            let id = require_tag(repos, &from).await?;
+
+            if repos.tags.find(&to).await?.is_some() {
+                return Err(Error::Conflict(format!(
+                    "tag `{}` already exists, use `tag merge` instead",
+                    to
+                )));
+            }
+
+            repos.tags.rename(id, &to).await?;
+
+            let message = format!("Renamed {} to {}", from, to);
+            (TagSummary { id, name: to }, message)
+        }
+        TagCommand::Merge { source, target } => {
+            let source_id = require_tag(repos, &source).await?;
+            let target_id = require_tag(repos, &target).await?;
+
+            if source_id == target_id {
+                return Err(Error::Conflict(String::from(
+                    "cannot merge a tag into itself",
+                )));
+            }
+
+            repos.tags.merge(source_id, target_id).await?;
+
+            let message = format!("Merged {} into {}", source, target);
+            (
+                TagSummary {
+                    id: target_id,
+                    name: target,
+                },
+                message,
+            )
+        }
+    };
+
+    report(format, &tag, |_| message);
+
+    Ok(())
+}
+
+async fn export(db: &Pool, format: Format, output: Option<PathBuf>) -> Result<(), Error> {
+    if let Some(path) = output {
+        let summary = dump::export(db, BufWriter::new(File::create(&path)?)).await?;
+
+        report(format, &summary, |summary| {
+            format!("Exported {} to {}", summary, path.display())
+        });
+    } else {
+        // The dump itself is the output
+        dump::export(db, BufWriter::new(io::stdout().lock())).await?;
+    }
+
+    Ok(())
+}
+
+async fn import(db: &Pool, format: Format, input: PathBuf) -> Result<(), Error> {
+    let summary = if input.as_os_str() == "-" {
+        dump::import(db, io::stdin().lock()).await?
+    } else {
+        dump::import(db, BufReader::new(File::open(&input)?)).await?
+    };
+
+    report(format, &summary, |summary| format!("Imported {}", summary));
+
+    Ok(())
+}
+
+async fn require_user(repos: &Repos, username: &str) -> Result<UserSummary, Error> {
+    repos
+        .users
+        .find_by_username(username)
+        .await?
+        .map(UserSummary::from)
+        .ok_or_else(|| Error::NotFound("user", username.to_owned()))
+}
+
+async fn require_tag(repos: &Repos, name: &str) -> Result<i64, Error> {
+    repos
+        .tags
+        .find(name)
+        .await?
+        .ok_or_else(|| Error::NotFound("tag", name.to_owned()))
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::User => "user",
+        Role::Admin => "admin",
+    }
+}
+
+/// Print `value` as JSON or as the text produced by `human`
+fn report<T: Serialize>(format: Format, value: &T, human: impl FnOnce(&T) -> String) {
+    match format {
+        Format::Human => println!("{}", human(value)),
+        Format::Json => println!("{}", serde_json::to_string_pretty(value).unwrap()),
+    }
+}
@@ -1,23 +1,77 @@
-use crate::{auth::Auth, database::Profile, AppState};
+use crate::{
+    articles::find_visible_article,
+    auth::{Auth, Verified},
+    database::Profile,
+    errors,
+    events::{self, Topic},
+    notifications::notify,
+    profile::{is_blocked_by, load_profile_by_id},
+    repo::{self, NewNotification, NotificationKind, Relation, WebhookEvent},
+    webhooks::dispatch,
+    AppState,
+};
 use axum::{
     extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
+use futures_util::{future, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use serde_json::json;
+use std::convert::Infallible;
+use std::fmt;
 use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Debug, FromRow)]
-#[sqlx(rename_all = "camelCase")]
-pub struct Comment {
-    id: i64,
-    created_at: String,
-    updated_at: String,
-    body: String,
-    author: i64,
+#[derive(Debug)]
+pub enum Error {
+    ArticleNotFound,
+    /// The article has no comment with this id
+    NotFound,
+    /// Only the author may delete the comment
+    Forbidden,
+    /// The author of the article blocked the user
+    Blocked,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ArticleNotFound => write!(f, "no such article"),
+            Error::NotFound => write!(f, "no such comment"),
+            Error::Forbidden => write!(f, "only the author may delete the comment"),
+            Error::Blocked => write!(f, "blocked by the user"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
 }
 
-#[derive(Serialize)]
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::ArticleNotFound | Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Forbidden | Error::Blocked => StatusCode::FORBIDDEN,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ResponseComment {
     id: i64,
@@ -27,189 +81,260 @@ pub struct ResponseComment {
     author: Profile,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ResponseSingleComment {
     comment: ResponseComment,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct AddComment {
     body: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct RequestAddComment {
     comment: AddComment,
 }
 
+/// Comment on an article
+#[utoipa::path(
+    post,
+    path = "/api/articles/{slug}/comments",
+    tag = "comments",
+    params(("slug" = String, Path)),
+    security(("token" = [])),
+    request_body = RequestAddComment,
+    responses(
+        (status = 200, description = "The new comment", body = ResponseSingleComment),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The email address has to be verified first or the author blocked the user"),
+        (status = 404, description = "There is no such article"),
+    ),
+)]
 pub async fn add_comment(
     State(app): State<Arc<AppState>>,
-    Auth(user_id): Auth,
+    Verified(user_id): Verified,
     Path(slug): Path<String>,
     Json(comment): Json<RequestAddComment>,
-) -> Json<ResponseSingleComment> {
-    let comment = comment.comment;
-
-    let article_id: i64 = sqlx::query_scalar(
-        "
-            SELECT `id`
-            FROM `articles`
-            WHERE `slug`=?
-        ",
-    )
-    .bind(slug)
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    let comment = sqlx::query_as::<_, Comment>(
-        "
-            INSERT INTO `comments` (`article`, `body`, `author`)
-            VALUES (?, ?, ?)
-            RETURNING `id`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `createdAt`) AS `createdAt`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `updatedAt`) AS `updatedAt`,
-            `body`, `author`
-        ",
-    )
-    .bind(article_id)
-    .bind(comment.body)
-    .bind(user_id)
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    let author = sqlx::query_as::<_, crate::database::Profile>(
-        "
-        SELECT `username`, `bio`, `image`, (
-            SELECT COUNT(*)
-            FROM `follows`
-            WHERE `follows`.`source`=? AND `follows`.`target`=`users`.`id`
-        ) AS `following`
-        FROM `users`
-        WHERE `users`.`id`=?
-    ",
+) -> Result<Json<ResponseSingleComment>, Error> {
+    let article = find_visible_article(&app, Some(user_id), &slug)
+        .await?
+        .ok_or(Error::ArticleNotFound)?;
+
+    if is_blocked_by(&app, user_id, article.author).await? {
+        return Err(Error::Blocked);
+    }
+
+    let article_id = article.id;
+
+    let comment = app
+        .repos
+        .comments
+        .create(article_id, user_id, &comment.comment.body)
+        .await?;
+    notify(
+        &app,
+        NewNotification {
+            user: article.author,
+            kind: NotificationKind::Comment,
+            actor: user_id,
+            article: Some(article_id),
+            comment: Some(comment.id),
+        },
     )
-    .bind(user_id)
-    .bind(comment.author)
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    Json(ResponseSingleComment {
-        comment: ResponseComment {
-            id: comment.id,
-            created_at: comment.created_at,
-            updated_at: comment.updated_at,
-            body: comment.body,
-            author,
+    .await?;
+
+    // Subscribers all see the author as strangers do
+    app.events.publish(
+        Topic::Article(article_id),
+        "comment",
+        Some(user_id),
+        ResponseSingleComment {
+            comment: response_comment(&app, None, comment.clone()).await?,
         },
-    })
-}
+    );
 
-#[derive(Debug, FromRow)]
-#[sqlx(rename_all = "camelCase")]
-pub struct CommentWithAuthor {
-    id: i64,
-    created_at: String,
-    updated_at: String,
-    body: String,
-    username: String,
-    bio: Option<String>,
-    image: Option<String>,
-    following: bool,
+    dispatch(
+        &app,
+        WebhookEvent::CommentCreated,
+        article.author,
+        json!({
+            "article": { "slug": article.slug },
+            "comment": response_comment(&app, None, comment.clone()).await?,
+        }),
+    )
+    .await?;
+
+    Ok(Json(ResponseSingleComment {
+        comment: response_comment(&app, Some(user_id), comment).await?,
+    }))
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ResponseMultipleComments {
     comments: Vec<ResponseComment>,
 }
 
+/// All comments on an article, oldest first
+///
+/// Comments by users the authenticated user muted are left out.
+#[utoipa::path(
+    get,
+    path = "/api/articles/{slug}/comments",
+    tag = "comments",
+    params(("slug" = String, Path)),
+    security((), ("token" = [])),
+    responses(
+        (status = 200, description = "The comments", body = ResponseMultipleComments),
+        (status = 404, description = "There is no such article"),
+    ),
+)]
 pub async fn get_comments(
     State(app): State<Arc<AppState>>,
     authentication: Option<Auth>,
     Path(slug): Path<String>,
-) -> Json<ResponseMultipleComments> {
-    let article_id: i64 = sqlx::query_scalar(
-        "
-            SELECT `id`
-            FROM `articles`
-            WHERE `slug`=?
-        ",
-    )
-    .bind(slug)
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    let comments = sqlx::query_as::<_, CommentWithAuthor>(
-        "
-            SELECT `comments`.`id`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `createdAt`) AS `createdAt`,
-            strftime('%Y-%m-%dT%H:%M:%fZ', `updatedAt`) AS `updatedAt`,
-            `body`, `username`, `bio`, `image`, (
-                SELECT COUNT(*)
-                FROM `follows`
-                WHERE `follows`.`source`=? AND `follows`.`target`=`users`.`id`
-            ) AS `following`
-            FROM `comments`
-            JOIN `users` ON `users`.`id`=`comments`.`author`
-            WHERE `article`=?
-        ",
-    )
-    .bind(authentication.as_ref().map(|auth| auth.0).unwrap_or(-1))
-    .bind(article_id)
-    .fetch_all(&app.db)
-    .await
-    .unwrap();
-
-    Json(ResponseMultipleComments {
-        comments: comments
-            .into_iter()
-            .map(|comment| ResponseComment {
-                id: comment.id,
-                created_at: comment.created_at,
-                updated_at: comment.updated_at,
-                body: comment.body,
-                author: Profile {
-                    username: comment.username,
-                    bio: comment.bio,
-                    image: comment.image,
-                    following: comment.following,
-                },
-            })
-            .collect(),
-    })
+) -> Result<Json<ResponseMultipleComments>, Error> {
+    let viewer = authentication.map(|auth| auth.0);
+    let article_id = find_article(&app, viewer, &slug).await?;
+
+    let list = app.repos.comments.list(article_id).await?;
+    let muted = match viewer {
+        Some(viewer) => app.repos.relations.targets(Relation::Mute, viewer).await?,
+        None => Vec::new(),
+    };
+    let mut comments = Vec::with_capacity(list.len());
+
+    for comment in list
+        .into_iter()
+        .filter(|comment| !muted.contains(&comment.author))
+    {
+        comments.push(response_comment(&app, viewer, comment).await?);
+    }
+
+    Ok(Json(ResponseMultipleComments { comments }))
+}
+
+/// New and deleted comments on an article as they happen
+///
+/// A `comment` event carries the new comment like `POST` returns it, a
+/// `comment-deleted` event the `id` of the removed one. Comments by users the
+/// authenticated user muted are left out. Clients which reconnect with
+/// `Last-Event-ID` receive the events they missed.
+#[utoipa::path(
+    get,
+    path = "/api/articles/{slug}/comments/stream",
+    tag = "comments",
+    params(("slug" = String, Path)),
+    security((), ("token" = [])),
+    responses(
+        (status = 200, description = "A stream of `comment` and `comment-deleted` events", content_type = "text/event-stream", body = ResponseSingleComment),
+        (status = 404, description = "There is no such article"),
+    ),
+)]
+pub async fn stream_comments(
+    State(app): State<Arc<AppState>>,
+    authentication: Option<Auth>,
+    Path(slug): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+    let viewer = authentication.map(|auth| auth.0);
+    let article_id = find_article(&app, viewer, &slug).await?;
+    let muted = match viewer {
+        Some(viewer) => app.repos.relations.targets(Relation::Mute, viewer).await?,
+        None => Vec::new(),
+    };
+
+    let events = app
+        .events
+        .subscribe(Topic::Article(article_id), events::last_event_id(&headers))
+        .filter(move |event| {
+            future::ready(!event.actor.is_some_and(|actor| muted.contains(&actor)))
+        });
+
+    Ok(events::respond(events))
 }
 
+/// Delete a comment of the authenticated user
+#[utoipa::path(
+    delete,
+    path = "/api/articles/{slug}/comments/{id}",
+    tag = "comments",
+    params(("slug" = String, Path), ("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The comment was deleted"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The comment is by someone else"),
+        (status = 404, description = "There is no such article or comment"),
+    ),
+)]
 pub async fn delete_comment(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
-    Path((slug, comment_id)): Path<(String, String)>,
-) {
-    let comment_id: i64 = sqlx::query_scalar(
-        "
-            SELECT `comments`.`id`
-            FROM `comments`
-            JOIN `articles` ON `articles`.`id`=`comments`.`article`
-            WHERE `articles`.`slug`=? AND `comments`.`id`=? AND `comments`.`author`=?
-        ",
-    )
-    .bind(slug) // Not necessary but required in the specification
-    .bind(comment_id)
-    .bind(user_id) // Make sure only owners can delete their comments
-    .fetch_one(&app.db)
-    .await
-    .unwrap();
-
-    sqlx::query(
-        "
-            DELETE FROM `comments`
-            WHERE `id`=?
-        ",
+    Path((slug, comment_id)): Path<(String, i64)>,
+) -> Result<(), Error> {
+    // Not necessary but required in the specification
+    let article_id = find_article(&app, Some(user_id), &slug).await?;
+
+    let comment = app
+        .repos
+        .comments
+        .find(comment_id)
+        .await?
+        .filter(|comment| comment.article == article_id)
+        .ok_or(Error::NotFound)?;
+
+    // Make sure only owners can delete their comments
+    if comment.author != user_id {
+        return Err(Error::Forbidden);
+    }
+
+    app.repos.comments.delete(comment.id).await?;
+
+    app.events.publish(
+        Topic::Article(article_id),
+        "comment-deleted",
+        Some(user_id),
+        json!({ "id": comment.id }),
+    );
+
+    let author = app
+        .repos
+        .articles
+        .find(article_id)
+        .await?
+        .ok_or(Error::ArticleNotFound)?
+        .author;
+    dispatch(
+        &app,
+        WebhookEvent::CommentDeleted,
+        author,
+        json!({ "article": { "slug": slug }, "comment": { "id": comment.id } }),
     )
-    .bind(comment_id)
-    .execute(&app.db)
-    .await
-    .unwrap();
+    .await?;
+
+    Ok(())
+}
+
+/// The `id` of the article with `slug`
+async fn find_article(app: &AppState, viewer: Option<i64>, slug: &str) -> Result<i64, Error> {
+    let article = find_visible_article(app, viewer, slug)
+        .await?
+        .ok_or(Error::ArticleNotFound)?;
+
+    Ok(article.id)
+}
+
+async fn response_comment(
+    app: &AppState,
+    viewer: Option<i64>,
+    comment: repo::Comment,
+) -> repo::Result<ResponseComment> {
+    Ok(ResponseComment {
+        author: load_profile_by_id(app, viewer, comment.author).await?,
+        id: comment.id,
+        created_at: comment.created_at,
+        updated_at: comment.updated_at,
+        body: comment.body,
+    })
 }
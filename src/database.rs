@@ -1,17 +1,87 @@
-use serde::Serialize;
-use sqlx::{sqlite::SqlitePoolOptions, FromRow};
+//! Connection to the database backend selected at compile time
+//!
+//! SQLite is used by default, the `postgres` feature switches to PostgreSQL.
+//! Queries are written so that both understand them: identifiers are quoted
+//! with double quotes, parameters are numbered (`$1`) and anything which
+//! differs between the two, like formatting timestamps, goes through the
+//! helpers in this module.
 
-pub use sqlx::sqlite::SqlitePool as Pool;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[cfg(not(feature = "postgres"))]
+pub use sqlx::Sqlite as Db;
+
+#[cfg(feature = "postgres")]
+pub use sqlx::Postgres as Db;
+
+pub type Pool = sqlx::Pool<Db>;
+pub type Row = <Db as sqlx::Database>::Row;
+
+#[cfg(not(feature = "postgres"))]
+pub const DEFAULT_URL: &str = "sqlite:realworld.db";
+
+#[cfg(feature = "postgres")]
+pub const DEFAULT_URL: &str = "postgres://localhost/realworld";
+
+/// Open the database at `url`, creating it if necessary, and bring its
+/// schema up to date
+#[cfg(not(feature = "postgres"))]
+pub async fn connect(url: &str) -> Result<Pool, sqlx::Error> {
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
 
-pub async fn connect() -> Result<Pool, sqlx::Error> {
     let pool = SqlitePoolOptions::new()
         .max_connections(1)
-        .connect("sqlite:realworld.db")
+        .connect_with(options)
         .await?;
 
+    sqlx::migrate!("./migrations").run(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Open the database at `url` and bring its schema up to date
+#[cfg(feature = "postgres")]
+pub async fn connect(url: &str) -> Result<Pool, sqlx::Error> {
+    use sqlx::postgres::PgPoolOptions;
+
+    let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
+
+    sqlx::migrate!("./migrations-postgres").run(&pool).await?;
+
     Ok(pool)
 }
 
+/// SQL expression which formats the timestamp in `column` as required by the
+/// specification, e.g. `2025-01-28T17:43:41.000Z`
+#[cfg(not(feature = "postgres"))]
+pub fn timestamp(column: &str) -> String {
+    format!("strftime('%Y-%m-%dT%H:%M:%fZ', {})", column)
+}
+
+#[cfg(feature = "postgres")]
+pub fn timestamp(column: &str) -> String {
+    format!(
+        r#"to_char({} AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"')"#,
+        column
+    )
+}
+
+/// SQL expression which turns the parameter `parameter`, formatted like the
+/// output of [`timestamp`], back into a value for a timestamp column
+#[cfg(not(feature = "postgres"))]
+pub fn parse_timestamp(parameter: &str) -> String {
+    format!("datetime({})", parameter)
+}
+
+#[cfg(feature = "postgres")]
+pub fn parse_timestamp(parameter: &str) -> String {
+    format!("CAST({} AS TIMESTAMPTZ)", parameter)
+}
+
 #[derive(Debug, Clone, FromRow)]
 pub struct User {
     pub id: i64,
@@ -20,9 +90,26 @@ pub struct User {
     pub username: String,
     pub bio: Option<String>,
     pub image: Option<String>,
+    pub role: Role,
+    /// Whether the user has confirmed that they own `email`
+    pub verified: bool,
+    /// Only tokens issued with this version are accepted
+    #[sqlx(rename = "tokenVersion")]
+    pub token_version: i64,
+}
+
+/// What a user is allowed to do beyond managing their own content
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
 }
 
-#[derive(Debug, Clone, FromRow, Serialize)]
+#[derive(Debug, Clone, FromRow, Serialize, utoipa::ToSchema)]
 pub struct Profile {
     pub username: String,
     pub bio: Option<String>,
@@ -0,0 +1,531 @@
+//! Portable copy of the whole database
+//!
+//! A dump is a stream of newline-delimited JSON objects. The first line is a
+//! header naming the format version, every following line is one row tagged
+//! with its `type`. Rows may only refer to rows which appear before them, so
+//! an import never has to look ahead.
+
+use crate::database::{parse_timestamp, timestamp, Pool, Role, Row};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+const FORMAT: &str = "realworld";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record {
+    User(User),
+    Follow(Follow),
+    Block(Block),
+    Mute(Mute),
+    Tag(Tag),
+    Article(Article),
+    Taglist(Taglist),
+    Favorite(Favorite),
+    Comment(Comment),
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+struct User {
+    id: i64,
+    email: String,
+    password: String,
+    username: String,
+    bio: Option<String>,
+    image: Option<String>,
+    role: Role,
+    /// Users in dumps from before verification existed were all trusted
+    #[serde(default = "verified")]
+    verified: bool,
+}
+
+fn verified() -> bool {
+    true
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+struct Follow {
+    source: i64,
+    target: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+struct Block {
+    source: i64,
+    target: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+struct Mute {
+    source: i64,
+    target: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+struct Tag {
+    id: i64,
+    name: String,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[sqlx(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+struct Article {
+    id: i64,
+    slug: String,
+    title: String,
+    description: String,
+    body: String,
+    created_at: String,
+    updated_at: String,
+    author: i64,
+    published: bool,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+struct Taglist {
+    article: i64,
+    tag: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+struct Favorite {
+    source: i64,
+    target: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[sqlx(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+struct Comment {
+    id: i64,
+    article: i64,
+    created_at: String,
+    updated_at: String,
+    body: String,
+    author: i64,
+}
+
+/// Number of rows per table which were exported or imported
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub users: usize,
+    pub follows: usize,
+    pub blocks: usize,
+    pub mutes: usize,
+    pub tags: usize,
+    pub articles: usize,
+    pub taglist: usize,
+    pub favorites: usize,
+    pub comments: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} users, {} follows, {} blocks, {} mutes, {} tags, {} articles, {} taglist entries, \
+            {} favorites, {} comments",
+            self.users,
+            self.follows,
+            self.blocks,
+            self.mutes,
+            self.tags,
+            self.articles,
+            self.taglist,
+            self.favorites,
+            self.comments
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Database(sqlx::Error),
+    Io(std::io::Error),
+    NotEmpty,
+    Header(String),
+    /// A line of the dump could not be read or does not fit the rest
+    Line(usize, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Database(error) => write!(f, "database error: {}", error),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::NotEmpty => write!(f, "dumps can only be imported into an empty database"),
+            Error::Header(message) => write!(f, "invalid header: {}", message),
+            Error::Line(line, message) => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        Error::Database(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Write every row of the database to `out` without loading whole tables
+/// into memory
+pub async fn export(db: &Pool, mut out: impl Write) -> Result<Summary, Error> {
+    serde_json::to_writer(
+        &mut out,
+        &Header {
+            format: String::from(FORMAT),
+            version: VERSION,
+        },
+    )
+    .map_err(std::io::Error::from)?;
+    writeln!(out)?;
+
+    let summary = Summary {
+        users: export_table(
+            db,
+            &mut out,
+            r#"SELECT "id", "email", "password", "username", "bio", "image", "role", "verified"
+            FROM "users" ORDER BY "id""#,
+            Record::User,
+        )
+        .await?,
+        follows: export_table(
+            db,
+            &mut out,
+            r#"SELECT "source", "target" FROM "follows""#,
+            Record::Follow,
+        )
+        .await?,
+        blocks: export_table(
+            db,
+            &mut out,
+            r#"SELECT "source", "target" FROM "blocks""#,
+            Record::Block,
+        )
+        .await?,
+        mutes: export_table(
+            db,
+            &mut out,
+            r#"SELECT "source", "target" FROM "mutes""#,
+            Record::Mute,
+        )
+        .await?,
+        tags: export_table(
+            db,
+            &mut out,
+            r#"SELECT "id", "name" FROM "tags" ORDER BY "id""#,
+            Record::Tag,
+        )
+        .await?,
+        articles: export_table(
+            db,
+            &mut out,
+            &format!(
+                r#"SELECT "id", "slug", "title", "description", "body", {} AS "createdAt",
+                    {} AS "updatedAt", "author", "published"
+                FROM "articles" ORDER BY "id""#,
+                timestamp(r#""createdAt""#),
+                timestamp(r#""updatedAt""#)
+            ),
+            Record::Article,
+        )
+        .await?,
+        taglist: export_table(
+            db,
+            &mut out,
+            r#"SELECT "article", "tag" FROM "taglist""#,
+            Record::Taglist,
+        )
+        .await?,
+        favorites: export_table(
+            db,
+            &mut out,
+            r#"SELECT "source", "target" FROM "favorites""#,
+            Record::Favorite,
+        )
+        .await?,
+        comments: export_table(
+            db,
+            &mut out,
+            &format!(
+                r#"SELECT "id", "article", {} AS "createdAt", {} AS "updatedAt", "body",
+                    "author"
+                FROM "comments" ORDER BY "id""#,
+                timestamp(r#""createdAt""#),
+                timestamp(r#""updatedAt""#)
+            ),
+            Record::Comment,
+        )
+        .await?,
+    };
+
+    out.flush()?;
+
+    Ok(summary)
+}
+
+async fn export_table<T>(
+    db: &Pool,
+    out: &mut impl Write,
+    sql: &str,
+    record: fn(T) -> Record,
+) -> Result<usize, Error>
+where
+    T: for<'r> FromRow<'r, Row> + Send + Unpin,
+{
+    let mut rows = sqlx::query_as::<_, T>(sql).fetch(db);
+    let mut count = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        serde_json::to_writer(&mut *out, &record(row)).map_err(std::io::Error::from)?;
+        writeln!(out)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Load a dump into an empty database
+///
+/// Rows receive new ids and all references are rewritten accordingly. The
+/// import happens in a single transaction, so nothing is written unless the
+/// whole dump is valid.
+pub async fn import(db: &Pool, input: impl BufRead) -> Result<Summary, Error> {
+    let mut tx = db.begin().await?;
+
+    for table in [
+        "users",
+        "tags",
+        "articles",
+        "comments",
+        "follows",
+        "blocks",
+        "mutes",
+        "favorites",
+        "taglist",
+    ] {
+        let rows: i64 = sqlx::query_scalar(&format!(r#"SELECT COUNT(*) FROM "{}""#, table))
+            .fetch_one(&mut *tx)
+            .await?;
+
+        if rows > 0 {
+            return Err(Error::NotEmpty);
+        }
+    }
+
+    let mut lines = input.lines();
+
+    let header = lines
+        .next()
+        .ok_or_else(|| Error::Header(String::from("the dump is empty")))??;
+    let header: Header =
+        serde_json::from_str(&header).map_err(|error| Error::Header(error.to_string()))?;
+
+    if header.format != FORMAT || header.version != VERSION {
+        return Err(Error::Header(format!(
+            "expected {} version {}, found {} version {}",
+            FORMAT, VERSION, header.format, header.version
+        )));
+    }
+
+    // Map the ids in the dump to the ids in this database
+    let mut users = HashMap::new();
+    let mut tags = HashMap::new();
+    let mut articles = HashMap::new();
+    let mut comments = HashMap::new();
+    let mut summary = Summary::default();
+
+    for (i, line) in lines.enumerate() {
+        // The header is line 1
+        let number = i + 2;
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: Record =
+            serde_json::from_str(&line).map_err(|error| Error::Line(number, error.to_string()))?;
+
+        let lookup = |map: &HashMap<i64, i64>, kind: &str, id: i64| {
+            map.get(&id)
+                .copied()
+                .ok_or_else(|| Error::Line(number, format!("unknown {} {}", kind, id)))
+        };
+        let duplicate = |map: &HashMap<i64, i64>, kind: &str, id: i64| {
+            if map.contains_key(&id) {
+                Err(Error::Line(number, format!("duplicate {} {}", kind, id)))
+            } else {
+                Ok(())
+            }
+        };
+
+        match record {
+            Record::User(user) => {
+                duplicate(&users, "user", user.id)?;
+
+                let id: i64 = sqlx::query_scalar(
+                    r#"
+                        INSERT INTO "users"
+                        ("email", "password", "username", "bio", "image", "role", "verified")
+                        VALUES ($1, $2, $3, $4, $5, $6, $7)
+                        RETURNING "id"
+                    "#,
+                )
+                .bind(user.email)
+                .bind(user.password)
+                .bind(user.username)
+                .bind(user.bio)
+                .bind(user.image)
+                .bind(user.role)
+                .bind(user.verified)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                users.insert(user.id, id);
+                summary.users += 1;
+            }
+            Record::Follow(follow) => {
+                sqlx::query(r#"INSERT INTO "follows" ("source", "target") VALUES ($1, $2)"#)
+                    .bind(lookup(&users, "user", follow.source)?)
+                    .bind(lookup(&users, "user", follow.target)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                summary.follows += 1;
+            }
+            Record::Block(block) => {
+                sqlx::query(r#"INSERT INTO "blocks" ("source", "target") VALUES ($1, $2)"#)
+                    .bind(lookup(&users, "user", block.source)?)
+                    .bind(lookup(&users, "user", block.target)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                summary.blocks += 1;
+            }
+            Record::Mute(mute) => {
+                sqlx::query(r#"INSERT INTO "mutes" ("source", "target") VALUES ($1, $2)"#)
+                    .bind(lookup(&users, "user", mute.source)?)
+                    .bind(lookup(&users, "user", mute.target)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                summary.mutes += 1;
+            }
+            Record::Tag(tag) => {
+                duplicate(&tags, "tag", tag.id)?;
+
+                let id: i64 =
+                    sqlx::query_scalar(r#"INSERT INTO "tags" ("name") VALUES ($1) RETURNING "id""#)
+                        .bind(tag.name)
+                        .fetch_one(&mut *tx)
+                        .await
+                        .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                tags.insert(tag.id, id);
+                summary.tags += 1;
+            }
+            Record::Article(article) => {
+                duplicate(&articles, "article", article.id)?;
+
+                let id: i64 = sqlx::query_scalar(&format!(
+                    r#"
+                        INSERT INTO "articles"
+                        ("slug", "title", "description", "body", "createdAt", "updatedAt",
+                            "author", "published")
+                        VALUES ($1, $2, $3, $4, {}, {}, $7, $8)
+                        RETURNING "id"
+                    "#,
+                    parse_timestamp("$5"),
+                    parse_timestamp("$6")
+                ))
+                .bind(article.slug)
+                .bind(article.title)
+                .bind(article.description)
+                .bind(article.body)
+                .bind(article.created_at)
+                .bind(article.updated_at)
+                .bind(lookup(&users, "user", article.author)?)
+                .bind(article.published)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                articles.insert(article.id, id);
+                summary.articles += 1;
+            }
+            Record::Taglist(entry) => {
+                sqlx::query(r#"INSERT INTO "taglist" ("article", "tag") VALUES ($1, $2)"#)
+                    .bind(lookup(&articles, "article", entry.article)?)
+                    .bind(lookup(&tags, "tag", entry.tag)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                summary.taglist += 1;
+            }
+            Record::Favorite(favorite) => {
+                sqlx::query(r#"INSERT INTO "favorites" ("source", "target") VALUES ($1, $2)"#)
+                    .bind(lookup(&users, "user", favorite.source)?)
+                    .bind(lookup(&articles, "article", favorite.target)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                summary.favorites += 1;
+            }
+            Record::Comment(comment) => {
+                duplicate(&comments, "comment", comment.id)?;
+
+                let id: i64 = sqlx::query_scalar(&format!(
+                    r#"
+                        INSERT INTO "comments"
+                        ("article", "createdAt", "updatedAt", "body", "author")
+                        VALUES ($1, {}, {}, $4, $5)
+                        RETURNING "id"
+                    "#,
+                    parse_timestamp("$2"),
+                    parse_timestamp("$3")
+                ))
+                .bind(lookup(&articles, "article", comment.article)?)
+                .bind(comment.created_at)
+                .bind(comment.updated_at)
+                .bind(comment.body)
+                .bind(lookup(&users, "user", comment.author)?)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|error| Error::Line(number, error.to_string()))?;
+
+                comments.insert(comment.id, id);
+                summary.comments += 1;
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(summary)
+}
@@ -0,0 +1,24 @@
+//! Bodies of error responses in the shape of the RealWorld spec,
+//! `{"errors": {"body": ["..."]}}`
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+use std::fmt::Display;
+
+/// The body of a response for `error`
+pub(crate) fn body(error: impl Display) -> Json<Value> {
+    Json(json!({ "errors": { "body": [error.to_string()] } }))
+}
+
+/// A 500 for `error`, which is logged rather than shown to the client since
+/// the messages of the database and the file system tell more than they
+/// should
+pub(crate) fn internal(error: impl Display) -> Response {
+    eprintln!("Internal error: {}", error);
+
+    (StatusCode::INTERNAL_SERVER_ERROR, body("internal error")).into_response()
+}
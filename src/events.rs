@@ -0,0 +1,152 @@
+//! Pushing changes to clients as Server-Sent Events
+//!
+//! Handlers publish on the [`Bus`] of the [`crate::AppState`], streaming
+//! endpoints subscribe to one [`Topic`] of it. Event ids grow across all
+//! topics, and the bus keeps the last [`HISTORY`] events around, so a client
+//! which reconnects with `Last-Event-ID` receives what it missed in between.
+//! The bus lives in the process, other instances of the server never see its
+//! events.
+
+use axum::http::HeaderMap;
+use axum::response::sse;
+use futures_util::{stream, Stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Number of past events kept for resuming streams
+pub const HISTORY: usize = 1024;
+
+/// What an event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    /// The comments of an article
+    Article(i64),
+    /// The notifications of a user
+    User(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub id: u64,
+    pub topic: Topic,
+    /// The `event:` field, e.g. `comment`
+    pub name: &'static str,
+    /// The user who caused the event, so subscribers can leave out users
+    /// they muted
+    pub actor: Option<i64>,
+    pub data: Value,
+}
+
+impl From<Event> for sse::Event {
+    fn from(event: Event) -> Self {
+        sse::Event::default()
+            .id(event.id.to_string())
+            .event(event.name)
+            .data(event.data.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub struct Bus {
+    sender: broadcast::Sender<Event>,
+    /// The last id handed out and the most recent events, oldest first
+    history: Mutex<(u64, VecDeque<Event>)>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus {
+            sender: broadcast::channel(HISTORY).0,
+            history: Mutex::new((0, VecDeque::with_capacity(HISTORY))),
+        }
+    }
+}
+
+impl Bus {
+    pub fn publish(
+        &self,
+        topic: Topic,
+        name: &'static str,
+        actor: Option<i64>,
+        data: impl Serialize,
+    ) {
+        let mut history = self.history.lock().unwrap();
+        history.0 += 1;
+
+        let event = Event {
+            id: history.0,
+            topic,
+            name,
+            actor,
+            data: serde_json::to_value(data).unwrap(),
+        };
+
+        if history.1.len() == HISTORY {
+            history.1.pop_front();
+        }
+        history.1.push_back(event.clone());
+
+        // Sent under the lock, so subscribers see the events in order
+        let _ = self.sender.send(event);
+    }
+
+    /// Events on `topic` after `last_event_id`, as far as they are still
+    /// known, followed by every new one
+    ///
+    /// The stream ends if the subscriber falls too far behind, clients are
+    /// expected to reconnect with `Last-Event-ID` then.
+    pub fn subscribe(
+        &self,
+        topic: Topic,
+        last_event_id: Option<u64>,
+    ) -> impl Stream<Item = Event> + Send + 'static {
+        let history = self.history.lock().unwrap();
+        // Subscribing under the lock, so no event falls between the
+        // history and the receiver
+        let receiver = self.sender.subscribe();
+        let missed: Vec<_> = match last_event_id {
+            Some(last) => history
+                .1
+                .iter()
+                .filter(|event| event.id > last && event.topic == topic)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        drop(history);
+
+        let live = stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if event.topic == topic => return Some((event, receiver)),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_) | RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        stream::iter(missed).chain(live)
+    }
+}
+
+/// The id in the `Last-Event-ID` header of a reconnecting client
+pub fn last_event_id(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get("last-event-id")?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Turn events into a response which keeps the connection open
+pub fn respond(
+    events: impl Stream<Item = Event> + Send + 'static,
+) -> sse::Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    sse::Sse::new(events.map(|event| Ok(event.into()))).keep_alive(sse::KeepAlive::default())
+}
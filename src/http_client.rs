@@ -0,0 +1,135 @@
+//! A minimal HTTP/1.1 client for calling other services, such as the
+//! receivers of webhooks and identity providers
+//!
+//! Every request opens a connection of its own, over TLS with the Mozilla
+//! roots for `https` URLs.
+
+use axum::{
+    body::Bytes,
+    http::{header, request, uri::Scheme, Request, Response, Uri},
+};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, pki_types::ServerName, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+/// Bodies of responses are cut off after this many bytes
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Send `request` with `body` to `url`, which sets its URI and `Host`
+pub(crate) async fn send(
+    url: &str,
+    request: request::Builder,
+    body: Bytes,
+) -> Result<Response<Incoming>, String> {
+    send_to(url, None, request, body).await
+}
+
+/// Like [`send`], but connect to `address` if given rather than to whatever
+/// the host of `url` resolves to now
+pub(crate) async fn send_to(
+    url: &str,
+    address: Option<SocketAddr>,
+    request: request::Builder,
+    body: Bytes,
+) -> Result<Response<Incoming>, String> {
+    let uri: Uri = url.parse().map_err(|error| format!("{}", error))?;
+    let host = uri.host().ok_or("the URL has no host")?.to_owned();
+    let https = uri.scheme() == Some(&Scheme::HTTPS);
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let request = request
+        .uri(uri.path_and_query().map_or("/", |path| path.as_str()))
+        .header(header::HOST, uri.authority().unwrap().as_str())
+        .body(Full::new(body))
+        .map_err(|error| error.to_string())?;
+
+    let tcp = match address {
+        Some(address) => TcpStream::connect(address).await,
+        None => TcpStream::connect((host.as_str(), port)).await,
+    }
+    .map_err(|error| error.to_string())?;
+
+    if https {
+        let name = ServerName::try_from(host).map_err(|error| error.to_string())?;
+        let tls = TlsConnector::from(tls_config())
+            .connect(name, tcp)
+            .await
+            .map_err(|error| error.to_string())?;
+
+        exchange(TokioIo::new(tls), request).await
+    } else {
+        exchange(TokioIo::new(tcp), request).await
+    }
+}
+
+/// The body of `response`, unless it is too large
+pub(crate) async fn read_body(response: Response<Incoming>) -> Result<Bytes, String> {
+    Limited::new(response.into_body(), MAX_BODY)
+        .collect()
+        .await
+        .map(|body| body.to_bytes())
+        .map_err(|error| error.to_string())
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986
+pub(crate) fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// `pairs` as a query string or `application/x-www-form-urlencoded` body
+pub(crate) fn form(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+async fn exchange<T>(io: T, request: Request<Full<Bytes>>) -> Result<Response<Incoming>, String>
+where
+    T: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(io)
+        .await
+        .map_err(|error| error.to_string())?;
+    tokio::spawn(connection);
+
+    sender
+        .send_request(request)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+            Arc::new(
+                ClientConfig::builder_with_provider(provider)
+                    .with_safe_default_protocol_versions()
+                    .unwrap()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
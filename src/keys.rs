@@ -0,0 +1,322 @@
+//! Keys which sign and verify the access tokens
+//!
+//! Every key has an id, which tokens carry in their `kid` header. New tokens
+//! are signed with `--jwt-signing-key`, every other `--jwt-key` still
+//! verifies the tokens it signed, so keys can be rotated without logging
+//! everyone out. The public halves are published at `/.well-known/jwks.json`
+//! for other services to verify our tokens with.
+//!
+//! Without any `--jwt-key`, tokens are signed with a built-in HS256 secret,
+//! which is only fit for development.
+
+use crate::AppState;
+use axum::{extract::State, Json};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use pkcs8::{ObjectIdentifier, PrivateKeyInfo};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fmt, io};
+
+/// The secret tokens were signed with before keys could be configured
+const DEVELOPMENT_SECRET: &[u8] = b"nuclear launch codes";
+
+const RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+#[derive(Debug, Clone, Default, clap::Args)]
+#[group(id = "keys")]
+pub struct Config {
+    /// Key for tokens as `<kid>=<path>`, where the file holds an RSA or
+    /// Ed25519 private key in PEM, or only the public key of one which no
+    /// longer signs. May be repeated.
+    #[arg(
+        id = "jwt_keys",
+        long = "jwt-key",
+        env = "JWT_KEYS",
+        value_name = "KID=PATH",
+        value_delimiter = ',',
+        value_parser = parse_key_file
+    )]
+    pub keys: Vec<(String, PathBuf)>,
+
+    /// The `kid` of the key which signs new tokens, the first `--jwt-key` if
+    /// not set
+    #[arg(long = "jwt-signing-key", env = "JWT_SIGNING_KEY", value_name = "KID")]
+    pub signing_key: Option<String>,
+}
+
+fn parse_key_file(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((kid, path)) if !kid.is_empty() && !path.is_empty() => {
+            Ok((kid.to_owned(), PathBuf::from(path)))
+        }
+        _ => Err(String::from("expected <kid>=<path>")),
+    }
+}
+
+impl Config {
+    /// Read the keys this configuration names
+    pub fn key_ring(&self) -> Result<KeyRing, Error> {
+        if self.keys.is_empty() {
+            return match &self.signing_key {
+                Some(kid) => Err(Error::UnknownSigningKey(kid.clone())),
+                None => Ok(KeyRing::default()),
+            };
+        }
+
+        let mut keys: Vec<Key> = Vec::new();
+
+        for (kid, path) in &self.keys {
+            if keys.iter().any(|key| key.kid == *kid) {
+                return Err(Error::DuplicateKid(kid.clone()));
+            }
+
+            keys.push(Key::load(kid, path)?);
+        }
+
+        let signing = match &self.signing_key {
+            Some(kid) => keys
+                .iter()
+                .position(|key| key.kid == *kid)
+                .ok_or_else(|| Error::UnknownSigningKey(kid.clone()))?,
+            None => 0,
+        };
+
+        if keys[signing].encoding.is_none() {
+            return Err(Error::PublicSigningKey(keys[signing].kid.clone()));
+        }
+
+        Ok(KeyRing { keys, signing })
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    /// The file is not a PEM encoded RSA or Ed25519 key
+    InvalidKey(PathBuf),
+    DuplicateKid(String),
+    /// `--jwt-signing-key` is none of the `--jwt-key`s
+    UnknownSigningKey(String),
+    /// The signing key is only a public key
+    PublicSigningKey(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, error) => write!(f, "could not read {}: {}", path.display(), error),
+            Error::InvalidKey(path) => write!(
+                f,
+                "{} holds no RSA or Ed25519 key in PEM format",
+                path.display()
+            ),
+            Error::DuplicateKid(kid) => write!(f, "there is more than one key `{}`", kid),
+            Error::UnknownSigningKey(kid) => write!(f, "there is no key `{}` to sign with", kid),
+            Error::PublicSigningKey(kid) => {
+                write!(f, "key `{}` is a public key and cannot sign", kid)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// One key of a [`KeyRing`]
+pub(crate) struct Key {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    /// `None` for keys which only verify
+    pub encoding: Option<EncodingKey>,
+    pub decoding: DecodingKey,
+    /// The public key, `None` for secrets
+    jwk: Option<Jwk>,
+}
+
+impl Key {
+    fn load(kid: &str, path: &Path) -> Result<Self, Error> {
+        let pem = std::fs::read(path).map_err(|error| Error::Io(path.to_owned(), error))?;
+
+        Key::from_pem(kid, &pem).ok_or_else(|| Error::InvalidKey(path.to_owned()))
+    }
+
+    fn from_pem(kid: &str, pem: &[u8]) -> Option<Self> {
+        let (label, der) = pem_rfc7468::decode_vec(pem).ok()?;
+
+        match label {
+            // PKCS #1
+            "RSA PRIVATE KEY" => Key::rsa(kid, &der),
+            // PKCS #8
+            "PRIVATE KEY" => {
+                let info = PrivateKeyInfo::try_from(der.as_slice()).ok()?;
+
+                match info.algorithm.oid {
+                    RSA => Key::rsa(kid, info.private_key),
+                    ED25519 => {
+                        let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).ok()?;
+                        let x = pair.public_key().as_ref().to_vec();
+                        let mut key = Key::public_ed25519(kid, &x)?;
+                        key.encoding = Some(EncodingKey::from_ed_der(&der));
+
+                        Some(key)
+                    }
+                    _ => None,
+                }
+            }
+            // X.509 SubjectPublicKeyInfo, for keys which no longer sign
+            "PUBLIC KEY" => {
+                let info = pkcs8::SubjectPublicKeyInfoRef::try_from(der.as_slice()).ok()?;
+                let bytes = info.subject_public_key.as_bytes()?;
+
+                match info.algorithm.oid {
+                    RSA => {
+                        let public = pkcs1::RsaPublicKey::try_from(bytes).ok()?;
+
+                        Key::public_rsa(
+                            kid,
+                            public.modulus.as_bytes(),
+                            public.public_exponent.as_bytes(),
+                        )
+                    }
+                    ED25519 => Key::public_ed25519(kid, bytes),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// A key from the PKCS #1 encoding of its private key
+    fn rsa(kid: &str, der: &[u8]) -> Option<Self> {
+        let private = pkcs1::RsaPrivateKey::try_from(der).ok()?;
+        let mut key = Key::public_rsa(
+            kid,
+            private.modulus.as_bytes(),
+            private.public_exponent.as_bytes(),
+        )?;
+        key.encoding = Some(EncodingKey::from_rsa_der(der));
+
+        Some(key)
+    }
+
+    fn public_rsa(kid: &str, modulus: &[u8], exponent: &[u8]) -> Option<Self> {
+        Key::public(
+            kid,
+            Algorithm::RS256,
+            KeyAlgorithm::RS256,
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(modulus),
+                e: URL_SAFE_NO_PAD.encode(exponent),
+            }),
+        )
+    }
+
+    fn public_ed25519(kid: &str, x: &[u8]) -> Option<Self> {
+        Key::public(
+            kid,
+            Algorithm::EdDSA,
+            KeyAlgorithm::EdDSA,
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(x),
+            }),
+        )
+    }
+
+    /// A key which only verifies, described by `parameters`
+    fn public(
+        kid: &str,
+        algorithm: Algorithm,
+        key_algorithm: KeyAlgorithm,
+        parameters: AlgorithmParameters,
+    ) -> Option<Self> {
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(key_algorithm),
+                key_id: Some(kid.to_owned()),
+                ..CommonParameters::default()
+            },
+            algorithm: parameters,
+        };
+
+        Some(Key {
+            kid: kid.to_owned(),
+            algorithm,
+            encoding: None,
+            decoding: DecodingKey::from_jwk(&jwk).ok()?,
+            jwk: Some(jwk),
+        })
+    }
+}
+
+/// The keys tokens are signed and verified with
+pub struct KeyRing {
+    keys: Vec<Key>,
+    /// Index of the key new tokens are signed with
+    signing: usize,
+}
+
+impl KeyRing {
+    /// The key new tokens are signed with
+    pub(crate) fn signing_key(&self) -> &Key {
+        &self.keys[self.signing]
+    }
+
+    /// The key with the `kid`. Tokens without one were signed with the
+    /// development secret, which is only accepted as long as it is in use.
+    pub(crate) fn find(&self, kid: Option<&str>) -> Option<&Key> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|key| key.kid == kid),
+            None => self.keys.iter().find(|key| key.jwk.is_none()),
+        }
+    }
+
+    /// The public keys, for others to verify tokens with
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+impl Default for KeyRing {
+    /// Only the development secret
+    fn default() -> Self {
+        KeyRing {
+            keys: vec![Key {
+                kid: String::from("development"),
+                algorithm: Algorithm::HS256,
+                encoding: Some(EncodingKey::from_secret(DEVELOPMENT_SECRET)),
+                decoding: DecodingKey::from_secret(DEVELOPMENT_SECRET),
+                jwk: None,
+            }],
+            signing: 0,
+        }
+    }
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kids: Vec<&str> = self.keys.iter().map(|key| key.kid.as_str()).collect();
+
+        f.debug_struct("KeyRing")
+            .field("keys", &kids)
+            .field("signing", &self.signing_key().kid)
+            .finish()
+    }
+}
+
+/// The public keys which verify our tokens, as a JSON Web Key Set
+pub async fn jwks(State(app): State<Arc<AppState>>) -> Json<JwkSet> {
+    Json(app.keys.jwks())
+}
@@ -0,0 +1,198 @@
+mod access_tokens;
+mod account;
+mod articles;
+mod attachments;
+mod auth;
+pub mod backup;
+pub mod cli;
+mod comments;
+pub mod database;
+mod dump;
+mod errors;
+pub mod events;
+mod http_client;
+pub mod keys;
+pub mod lockout;
+pub mod mail;
+pub mod media;
+mod notifications;
+pub mod oidc;
+pub mod openapi;
+mod password_reset;
+mod profile;
+pub mod repo;
+pub mod sessions;
+mod tags;
+mod token;
+mod two_factor;
+pub mod verification;
+pub mod webhooks;
+
+use access_tokens::{create_access_token, delete_access_token, list_access_tokens};
+use account::{delete_account, export_account};
+use articles::{
+    create_article, delete_article, favorite_article, feed_articles, get_article, list_articles,
+    unfavorite_article, update_article,
+};
+use attachments::{delete_attachment, list_attachments, upload_attachment};
+use auth::{authentication, get_current_user, registration, update_user};
+use axum::{
+    extract::DefaultBodyLimit,
+    routing::{delete, get, post, put},
+    Router,
+};
+use backup::{create_backup, list_backups};
+use comments::{add_comment, delete_comment, get_comments, stream_comments};
+use database::Pool;
+use keys::jwks;
+use lockout::list_login_failures;
+use mail::Mailer;
+use media::{get_media, upload_image, MediaStore};
+use notifications::{
+    get_preferences, list_notifications, mark_all_notifications_read, mark_notification_read,
+    stream_notifications, update_preferences,
+};
+use oidc::{finish_oidc_login, list_providers, start_oidc_login};
+use openapi::ApiDoc;
+use password_reset::{confirm_reset, request_reset};
+use profile::{
+    block_user, follow_user, get_profile, list_blocks, list_mutes, mute_user, unblock_user,
+    unfollow_user, unmute_user,
+};
+use repo::Repos;
+use sessions::{delete_all_sessions, delete_session, list_sessions, logout, refresh};
+use std::sync::Arc;
+use tags::get_tags;
+use two_factor::{confirm_two_factor, disable_two_factor, login_two_factor, setup_two_factor};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+use verification::{resend_verification, verify_email};
+use webhooks::{
+    create_global_webhook, create_webhook, delete_global_webhook, delete_webhook, list_deliveries,
+    list_global_deliveries, list_global_webhooks, list_webhooks,
+};
+
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/", get(|| async { "Hello, World!" }))
+        .route("/api/users/login", post(authentication))
+        .route("/api/users/login/2fa", post(login_two_factor))
+        .route("/api/users/oidc", get(list_providers))
+        .route(
+            "/api/users/oidc/{provider}/authorize",
+            get(start_oidc_login),
+        )
+        .route("/api/users/oidc/callback", post(finish_oidc_login))
+        .route("/api/users", post(registration))
+        .route("/api/users/refresh", post(refresh))
+        .route("/api/users/logout", post(logout))
+        .route("/api/users/verify", post(verify_email))
+        .route("/api/users/verify/resend", post(resend_verification))
+        .route("/api/users/password-reset", post(request_reset))
+        .route("/api/users/password-reset/confirm", post(confirm_reset))
+        .route("/api/user", get(get_current_user))
+        .route("/api/user", put(update_user))
+        .route("/api/user", delete(delete_account))
+        // The handler enforces its own limit on the size of uploads
+        .route(
+            "/api/user/image",
+            post(upload_image).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/user/export", get(export_account))
+        .route("/api/user/sessions", get(list_sessions))
+        .route("/api/user/sessions", delete(delete_all_sessions))
+        .route("/api/user/sessions/{id}", delete(delete_session))
+        .route("/api/user/tokens", post(create_access_token))
+        .route("/api/user/tokens", get(list_access_tokens))
+        .route("/api/user/tokens/{id}", delete(delete_access_token))
+        .route("/api/user/2fa/setup", post(setup_two_factor))
+        .route("/api/user/2fa/confirm", post(confirm_two_factor))
+        .route("/api/user/2fa/disable", post(disable_two_factor))
+        .route("/api/user/blocks", get(list_blocks))
+        .route("/api/user/mutes", get(list_mutes))
+        .route("/api/user/notifications", get(list_notifications))
+        .route("/api/user/notifications/stream", get(stream_notifications))
+        .route(
+            "/api/user/notifications/read",
+            post(mark_all_notifications_read),
+        )
+        .route(
+            "/api/user/notifications/{id}/read",
+            post(mark_notification_read),
+        )
+        .route("/api/user/notifications/preferences", get(get_preferences))
+        .route(
+            "/api/user/notifications/preferences",
+            put(update_preferences),
+        )
+        .route("/api/user/webhooks", post(create_webhook))
+        .route("/api/user/webhooks", get(list_webhooks))
+        .route("/api/user/webhooks/{id}", delete(delete_webhook))
+        .route("/api/user/webhooks/{id}/deliveries", get(list_deliveries))
+        .route("/api/profiles/{username}", get(get_profile))
+        .route("/api/profiles/{username}/follow", post(follow_user))
+        .route("/api/profiles/{username}/follow", delete(unfollow_user))
+        .route("/api/profiles/{username}/block", post(block_user))
+        .route("/api/profiles/{username}/block", delete(unblock_user))
+        .route("/api/profiles/{username}/mute", post(mute_user))
+        .route("/api/profiles/{username}/mute", delete(unmute_user))
+        .route("/api/articles", get(list_articles))
+        .route("/api/articles/feed", get(feed_articles))
+        .route("/api/articles/{slug}", get(get_article))
+        .route("/api/articles", post(create_article))
+        .route("/api/articles/{slug}", put(update_article))
+        .route("/api/articles/{slug}", delete(delete_article))
+        // The handler enforces its own limit on the size of uploads
+        .route(
+            "/api/articles/{slug}/attachments",
+            post(upload_attachment).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/articles/{slug}/attachments", get(list_attachments))
+        .route(
+            "/api/articles/{slug}/attachments/{id}",
+            delete(delete_attachment),
+        )
+        .route("/api/articles/{slug}/comments", post(add_comment))
+        .route("/api/articles/{slug}/comments", get(get_comments))
+        .route("/api/articles/{slug}/comments/stream", get(stream_comments))
+        .route("/api/articles/{slug}/comments/{id}", delete(delete_comment))
+        .route("/api/articles/{slug}/favorite", post(favorite_article))
+        .route("/api/articles/{slug}/favorite", delete(unfavorite_article))
+        .route("/api/tags", get(get_tags))
+        .route("/api/admin/backups", post(create_backup))
+        .route("/api/admin/backups", get(list_backups))
+        .route("/api/admin/login-failures", get(list_login_failures))
+        .route("/api/admin/webhooks", post(create_global_webhook))
+        .route("/api/admin/webhooks", get(list_global_webhooks))
+        .route("/api/admin/webhooks/{id}", delete(delete_global_webhook))
+        .route(
+            "/api/admin/webhooks/{id}/deliveries",
+            get(list_global_deliveries),
+        )
+        .route("/media/{*key}", get(get_media))
+        .route("/.well-known/jwks.json", get(jwks))
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi()))
+        .with_state(state)
+}
+
+#[derive(Debug)]
+pub struct AppState {
+    pub repos: Repos,
+    /// The database behind `repos`, if they are backed by one
+    pub db: Option<Pool>,
+    pub backups: backup::Config,
+    pub mailer: Arc<dyn Mailer>,
+    pub media: media::Config,
+    /// Where uploads are kept, see `media`
+    pub media_store: Arc<dyn MediaStore>,
+    pub attachments: attachments::Config,
+    pub sessions: sessions::Config,
+    /// Sign and verify the access tokens
+    pub keys: keys::KeyRing,
+    pub lockout: lockout::Config,
+    pub verification: verification::Config,
+    pub events: events::Bus,
+    pub webhooks: webhooks::Config,
+    /// The identity providers users may log in with
+    pub oidc: oidc::Providers,
+}
@@ -0,0 +1,413 @@
+//! Slowing down password guessing
+//!
+//! Failed logins are counted per account and per client address. After each
+//! failure, the next attempt at the account has to wait `--login-delay`
+//! seconds, doubling with every further failure. Once `--login-attempts`
+//! logins of an account failed, or `--login-ip-attempts` from an address,
+//! they are locked for `--login-lockout` seconds. Addresses are not delayed
+//! before that, as many users may share one. A successful login resets the
+//! count of the account, and counts are forgotten once the last failure is
+//! that long ago.
+//!
+//! Wrong codes of the second factor count as failed logins as well. Every
+//! failed login is kept in an audit trail for admins, and the
+//! `user unlock` command lifts a lockout early.
+
+use crate::{
+    auth::Admin,
+    database::User,
+    errors,
+    repo::{self, LoginFailure, LoginFailureReason, NewLoginFailure, Throttle},
+    sessions::Client,
+    token::now,
+    AppState,
+};
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+/// Emails in the audit trail are cut off after this many characters
+const MAX_EMAIL: usize = 255;
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "lockout")]
+pub struct Config {
+    /// Failed logins of an account before it is locked
+    #[arg(
+        id = "login_attempts",
+        long = "login-attempts",
+        env = "LOGIN_ATTEMPTS",
+        value_parser = clap::value_parser!(i64).range(1..),
+        default_value_t = 5
+    )]
+    pub attempts: i64,
+
+    /// Failed logins from an address before it is locked
+    #[arg(
+        long = "login-ip-attempts",
+        env = "LOGIN_IP_ATTEMPTS",
+        value_parser = clap::value_parser!(i64).range(1..),
+        default_value_t = 20
+    )]
+    pub ip_attempts: i64,
+
+    /// Seconds to wait after the first failed login, doubling with every
+    /// further one
+    #[arg(
+        long = "login-delay",
+        env = "LOGIN_DELAY",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(i64).range(0..),
+        default_value_t = 1
+    )]
+    pub delay: i64,
+
+    /// Seconds an account or address stays locked
+    #[arg(
+        id = "login_lockout",
+        long = "login-lockout",
+        env = "LOGIN_LOCKOUT",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(i64).range(1..),
+        default_value_t = 15 * 60
+    )]
+    pub lockout: i64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            attempts: 5,
+            ip_attempts: 20,
+            delay: 1,
+            lockout: 15 * 60,
+        }
+    }
+}
+
+impl Config {
+    /// Seconds `throttle` still holds back the next attempt at `now`, after
+    /// `threshold` failures it is locked. Before that, only `delayed`
+    /// throttles hold back attempts.
+    fn wait(&self, throttle: Throttle, threshold: i64, delayed: bool, now: i64) -> i64 {
+        let wait = if throttle.failures >= threshold {
+            self.lockout
+        } else if !delayed {
+            0
+        } else {
+            let doublings = (throttle.failures - 1).clamp(0, 32) as u32;
+
+            self.delay.saturating_mul(1 << doublings).min(self.lockout)
+        };
+
+        (throttle.last_failure_at + wait - now).max(0)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The email address or the password is wrong
+    InvalidCredentials,
+    /// Too many logins failed, the next attempt is allowed after this many
+    /// seconds
+    TooManyAttempts(i64),
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidCredentials => write!(f, "invalid email or password"),
+            Error::TooManyAttempts(seconds) => write!(
+                f,
+                "too many failed logins, try again in {} seconds",
+                seconds
+            ),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Error::InvalidCredentials => {
+                (StatusCode::UNAUTHORIZED, errors::body(&self)).into_response()
+            }
+            Error::TooManyAttempts(seconds) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, seconds.to_string())],
+                errors::body(&self),
+            )
+                .into_response(),
+            Error::Repo(_) => errors::internal(&self),
+        }
+    }
+}
+
+/// The key failed logins of the account with `email` are counted under
+pub fn account_key(email: &str) -> String {
+    format!("account:{}", email)
+}
+
+/// The key failed logins from `ip` are counted under
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// The keys failed logins of `email` from `client` are counted under, with
+/// their thresholds and whether they are delayed before those
+fn keys(config: &Config, email: &str, client: &Client) -> Vec<(String, i64, bool)> {
+    let mut keys = vec![(account_key(email), config.attempts, true)];
+    if let Some(ip) = &client.ip {
+        keys.push((ip_key(ip), config.ip_attempts, false));
+    }
+
+    keys
+}
+
+/// Count a failed login under `keys`
+async fn fail(app: &AppState, keys: &[(String, i64, bool)], now: i64) -> repo::Result<()> {
+    for (key, ..) in keys {
+        app.repos
+            .login_attempts
+            .fail(key, now, now - app.lockout.lockout)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Count an attempt under `key` before its password is checked, so that
+/// concurrent attempts cannot all get through, unless it has to wait first.
+/// Returns the seconds to wait, or 0 once the attempt is counted.
+async fn claim(
+    app: &AppState,
+    key: &str,
+    threshold: i64,
+    delayed: bool,
+    now: i64,
+) -> repo::Result<i64> {
+    let config = &app.lockout;
+    let forget_before = now - config.lockout;
+
+    loop {
+        let seen = app.repos.login_attempts.throttle(key).await?;
+
+        if let Some(throttle) = seen.filter(|throttle| throttle.last_failure_at >= forget_before) {
+            let wait = config.wait(throttle, threshold, delayed, now);
+
+            if wait > 0 {
+                return Ok(wait);
+            }
+        }
+
+        // Otherwise another attempt was counted in between, which this one
+        // may have to wait for
+        if app
+            .repos
+            .login_attempts
+            .attempt(key, seen, now, forget_before)
+            .await?
+        {
+            return Ok(0);
+        }
+    }
+}
+
+/// Take back the attempts counted under `keys`
+async fn forgive(app: &AppState, keys: &[&str]) -> repo::Result<()> {
+    for key in keys {
+        app.repos.login_attempts.forgive(key).await?;
+    }
+
+    Ok(())
+}
+
+/// Add a failed login of `email` to the audit trail
+async fn record(
+    app: &AppState,
+    client: &Client,
+    email: &str,
+    user: Option<i64>,
+    reason: LoginFailureReason,
+) -> repo::Result<()> {
+    app.repos
+        .login_attempts
+        .record(NewLoginFailure {
+            email: email.chars().take(MAX_EMAIL).collect(),
+            user,
+            ip: client.ip.clone(),
+            user_agent: client.user_agent.clone(),
+            reason,
+        })
+        .await
+}
+
+/// The user with `email` and `password`, unless the account or the address
+/// of `client` has to wait after failed logins
+///
+/// Once the user passed every factor, [`succeeded`] has to be called.
+pub(crate) async fn login(
+    app: &AppState,
+    client: &Client,
+    email: &str,
+    password: &str,
+) -> Result<User, Error> {
+    let now = now();
+    let keys = keys(&app.lockout, email, client);
+
+    // Every attempt counts as a failure until its password turned out right
+    let mut counted = Vec::new();
+    let mut wait = 0;
+    for (key, threshold, delayed) in &keys {
+        wait = claim(app, key, *threshold, *delayed, now).await?;
+
+        if wait > 0 {
+            break;
+        }
+
+        counted.push(key.as_str());
+    }
+
+    let reason = if wait > 0 {
+        // Attempts while throttled do not count, or else the wait of the
+        // rightful user would never end
+        forgive(app, &counted).await?;
+
+        LoginFailureReason::Throttled
+    } else if let Some(user) = app.repos.users.find_by_credentials(email, password).await? {
+        forgive(app, &counted).await?;
+
+        return Ok(user);
+    } else {
+        LoginFailureReason::InvalidCredentials
+    };
+
+    let known_user = app
+        .repos
+        .users
+        .find_by_email(email)
+        .await?
+        .map(|user| user.id);
+    record(app, client, email, known_user, reason).await?;
+
+    match reason {
+        LoginFailureReason::Throttled => Err(Error::TooManyAttempts(wait)),
+        _ => Err(Error::InvalidCredentials),
+    }
+}
+
+/// Reset the count of failed logins of `user`, who logged in
+///
+/// Not before the second factor, or else every right password would forgive
+/// the wrong codes before.
+pub(crate) async fn succeeded(app: &AppState, user: &User) -> repo::Result<()> {
+    app.repos
+        .login_attempts
+        .reset(&account_key(&user.email))
+        .await
+}
+
+/// Count a wrong code of the second factor of `user` like a wrong password,
+/// so that guessing codes locks the account as well
+pub(crate) async fn second_factor_failed(
+    app: &AppState,
+    client: &Client,
+    user: &User,
+) -> repo::Result<()> {
+    fail(app, &keys(&app.lockout, &user.email, client), now()).await?;
+    record(
+        app,
+        client,
+        &user.email,
+        Some(user.id),
+        LoginFailureReason::InvalidCode,
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListLoginFailuresConstraints {
+    /// At most this many, 100 by default
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseLoginFailure {
+    id: i64,
+    /// As entered
+    email: String,
+    /// Whether there is an account with the email address
+    known_user: bool,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    reason: LoginFailureReason,
+    created_at: String,
+}
+
+impl From<LoginFailure> for ResponseLoginFailure {
+    fn from(failure: LoginFailure) -> Self {
+        ResponseLoginFailure {
+            id: failure.id,
+            email: failure.email,
+            known_user: failure.user.is_some(),
+            ip: failure.ip,
+            user_agent: failure.user_agent,
+            reason: failure.reason,
+            created_at: failure.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseLoginFailures {
+    login_failures: Vec<ResponseLoginFailure>,
+}
+
+/// The audit trail of failed logins, newest first
+#[utoipa::path(
+    get,
+    path = "/api/admin/login-failures",
+    tag = "admin",
+    params(ListLoginFailuresConstraints),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The failed logins", body = ResponseLoginFailures),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The user is no admin"),
+    ),
+)]
+pub async fn list_login_failures(
+    State(app): State<Arc<AppState>>,
+    _: Admin,
+    Query(query): Query<ListLoginFailuresConstraints>,
+) -> Result<Json<ResponseLoginFailures>, Error> {
+    let failures = app
+        .repos
+        .login_attempts
+        .failures(query.limit.unwrap_or(100))
+        .await?;
+
+    Ok(Json(ResponseLoginFailures {
+        login_failures: failures.into_iter().map(Into::into).collect(),
+    }))
+}
@@ -0,0 +1,436 @@
+//! Uploaded images
+//!
+//! Handlers only see the [`MediaStore`] trait, [`LocalMediaStore`] keeps the
+//! files in a directory. Stored files are served at `/media/{key}` and linked
+//! to as `--media-url` followed by the key, so a CDN in front of the server
+//! can take over serving them.
+//!
+//! Avatars are cropped to a square and stored as PNG in every size of
+//! [`AVATAR_SIZES`], the `image` of the user links to the largest one.
+
+use crate::{
+    auth::{current_user, Auth, ResponseUser},
+    errors,
+    repo::{self, UserUpdate},
+    token::random_token,
+    AppState,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::{multipart::MultipartError, Multipart, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use image::{imageops::FilterType, ImageFormat, ImageReader, Limits};
+use std::fmt;
+use std::io::{self, Cursor};
+use std::path::{Component, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+
+/// Edge lengths in pixels avatars are stored in, largest first
+pub const AVATAR_SIZES: [u32; 3] = [256, 128, 64];
+
+/// Uploads wider or higher than this are not even decoded
+const MAX_DIMENSION: u32 = 8192;
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "media")]
+pub struct Config {
+    /// Directory uploaded files are stored in
+    #[arg(
+        id = "media_dir",
+        value_name = "DIR",
+        long = "media-dir",
+        env = "MEDIA_DIR",
+        default_value = "media"
+    )]
+    pub dir: PathBuf,
+
+    /// URL stored files are linked to with, followed by a slash and the key
+    /// of the file
+    #[arg(
+        id = "media_url",
+        value_name = "URL",
+        long = "media-url",
+        env = "MEDIA_URL",
+        default_value = "/media"
+    )]
+    pub url: String,
+
+    /// Largest upload accepted, in bytes
+    #[arg(
+        id = "max_upload_size",
+        value_name = "BYTES",
+        long = "max-upload-size",
+        env = "MAX_UPLOAD_SIZE",
+        default_value_t = 5 * 1024 * 1024
+    )]
+    pub max_size: usize,
+}
+
+impl Config {
+    /// The store this configuration describes
+    pub fn store(&self) -> Arc<dyn MediaStore> {
+        Arc::new(LocalMediaStore::new(self.dir.clone()))
+    }
+
+    /// The URL of the file stored under `key`
+    pub fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), key)
+    }
+
+    /// The key of the file `url` links to, if it is one of ours
+    fn key<'a>(&self, url: &'a str) -> Option<&'a str> {
+        url.strip_prefix(self.url.trim_end_matches('/'))?
+            .strip_prefix('/')
+    }
+}
+
+/// Keys are relative paths such as `avatars/1-4f2a-256.png`
+#[async_trait]
+pub trait MediaStore: fmt::Debug + Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> io::Result<()>;
+
+    /// The file stored under `key`, if there is one
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Remove the file stored under `key`, if there is one
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// Keeps files in a directory, with the keys as paths below it
+#[derive(Debug, Clone)]
+pub struct LocalMediaStore {
+    dir: PathBuf,
+}
+
+impl LocalMediaStore {
+    pub fn new(dir: PathBuf) -> Self {
+        LocalMediaStore { dir }
+    }
+
+    /// Where the file stored under `key` is, if `key` stays inside the
+    /// directory
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let relative = std::path::Path::new(key);
+        let inside = !key.is_empty()
+            && !key.contains('\\')
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+
+        if !inside {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid key"));
+        }
+
+        Ok(self.dir.join(relative))
+    }
+}
+
+#[async_trait]
+impl MediaStore for LocalMediaStore {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> io::Result<()> {
+        let path = self.path(key)?;
+        let partial = path.with_extension("partial");
+
+        // Readers only ever see complete files
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        fs::write(&partial, bytes).await?;
+        fs::rename(&partial, &path).await
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let Ok(path) = self.path(key) else {
+            return Ok(None);
+        };
+
+        match fs::read(path).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Multipart(MultipartError),
+    /// The form has no `image` field
+    MissingImage,
+    /// The upload is larger than `--max-upload-size`
+    TooLarge,
+    /// The upload is not a PNG, JPEG, GIF or WebP image
+    UnsupportedType,
+    /// The upload claims to be an image but cannot be decoded
+    InvalidImage(String),
+    Io(io::Error),
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Multipart(error) => write!(f, "{}", error.body_text()),
+            Error::MissingImage => write!(f, "the form has no image field"),
+            Error::TooLarge => write!(f, "the image is too large"),
+            Error::UnsupportedType => write!(f, "only PNG, JPEG, GIF and WebP images are accepted"),
+            Error::InvalidImage(message) => write!(f, "invalid image: {}", message),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<MultipartError> for Error {
+    fn from(error: MultipartError) -> Self {
+        Error::Multipart(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            Error::Multipart(error) => error.status(),
+            Error::MissingImage | Error::InvalidImage(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::UnsupportedType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::Io(_) | Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// Content types files may be stored as, with the extension of their keys
+pub(crate) const TYPES: [(&str, &str); 6] = [
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("application/pdf", "pdf"),
+    ("text/plain", "txt"),
+];
+
+/// The extension of keys of files with `content_type`
+pub(crate) fn extension(content_type: &str) -> Option<&'static str> {
+    TYPES
+        .iter()
+        .find(|&&(known, _)| known == content_type)
+        .map(|&(_, extension)| extension)
+}
+
+/// The image format behind a content type, if it is an accepted image
+pub(crate) fn format(content_type: &str) -> Option<ImageFormat> {
+    match content_type {
+        "image/png" => Some(ImageFormat::Png),
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// The content type a stored file is served with
+fn content_type(key: &str) -> &'static str {
+    let extension = key.rsplit_once('.').map(|(_, extension)| extension);
+
+    match TYPES.iter().find(|&&(_, known)| Some(known) == extension) {
+        // Text has to be UTF-8 to be stored
+        Some(("text/plain", _)) => "text/plain; charset=utf-8",
+        Some(&(content_type, _)) => content_type,
+        None => "application/octet-stream",
+    }
+}
+
+fn avatar_key(stem: &str, size: u32) -> String {
+    format!("avatars/{}-{}.png", stem, size)
+}
+
+/// The avatar in every size of [`AVATAR_SIZES`], encoded as PNG
+fn resize(bytes: &[u8], format: ImageFormat) -> Result<Vec<Vec<u8>>, Error> {
+    let invalid = |error: image::ImageError| Error::InvalidImage(error.to_string());
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(invalid)?;
+
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let mut png = Vec::new();
+            image
+                .resize_to_fill(size, size, FilterType::Lanczos3)
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .map_err(invalid)?;
+
+            Ok(png)
+        })
+        .collect()
+}
+
+/// Remove the stored files of the avatar `image` links to, if it is one
+/// `user_id` uploaded to this server
+///
+/// Users may set their `image` to any URL, including someone else's avatar,
+/// so only stems of their own uploads count.
+pub(crate) async fn delete_avatar(
+    config: &Config,
+    store: &dyn MediaStore,
+    user_id: i64,
+    image: Option<&str>,
+) -> io::Result<()> {
+    let Some(stem) = image
+        .and_then(|image| config.key(image))
+        .and_then(|key| key.strip_prefix("avatars/"))
+        .and_then(|name| name.strip_suffix(&format!("-{}.png", AVATAR_SIZES[0])))
+        .filter(|stem| {
+            stem.strip_prefix(&format!("{}-", user_id))
+                .is_some_and(|random| {
+                    random.len() == 16 && random.bytes().all(|byte| byte.is_ascii_hexdigit())
+                })
+        })
+    else {
+        return Ok(());
+    };
+
+    for size in AVATAR_SIZES {
+        store.delete(&avatar_key(stem, size)).await?;
+    }
+
+    Ok(())
+}
+
+/// Upload an avatar for the authenticated user
+///
+/// The `image` field of a `multipart/form-data` body holds a PNG, JPEG, GIF
+/// or WebP image, which replaces the `image` of the user.
+#[utoipa::path(
+    post,
+    path = "/api/user/image",
+    tag = "users",
+    security(("token" = [])),
+    request_body(content_type = "multipart/form-data", description = "The image in an `image` field"),
+    responses(
+        (status = 200, description = "The user with the new image", body = ResponseUser),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 413, description = "The image is larger than allowed"),
+        (status = 415, description = "The image is not a PNG, JPEG, GIF or WebP image"),
+        (status = 422, description = "No image or one which cannot be decoded"),
+    ),
+)]
+pub async fn upload_image(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<ResponseUser>, Error> {
+    let mut upload = None;
+
+    while let Some(mut field) = multipart.next_field().await? {
+        if field.name() != Some("image") {
+            continue;
+        }
+
+        let format = field
+            .content_type()
+            .and_then(format)
+            .ok_or(Error::UnsupportedType)?;
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = field.chunk().await? {
+            if bytes.len() + chunk.len() > app.media.max_size {
+                return Err(Error::TooLarge);
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        upload = Some((bytes, format));
+        break;
+    }
+
+    let (bytes, format) = upload.ok_or(Error::MissingImage)?;
+    // Decoding and resizing take a while, which the runtime should not wait for
+    let sizes = tokio::task::spawn_blocking(move || resize(&bytes, format))
+        .await
+        .unwrap()?;
+
+    let stem = format!("{}-{}", user_id, &random_token()[..16]);
+
+    for (size, png) in AVATAR_SIZES.into_iter().zip(sizes) {
+        app.media_store.put(&avatar_key(&stem, size), png).await?;
+    }
+
+    let previous = app.repos.users.find(user_id).await?.unwrap().image;
+    app.repos
+        .users
+        .update(
+            user_id,
+            UserUpdate {
+                image: Some(app.media.url(&avatar_key(&stem, AVATAR_SIZES[0]))),
+                ..UserUpdate::default()
+            },
+        )
+        .await?;
+
+    // An old file left behind does no harm
+    if let Err(error) = delete_avatar(
+        &app.media,
+        app.media_store.as_ref(),
+        user_id,
+        previous.as_deref(),
+    )
+    .await
+    {
+        eprintln!("Deleting the previous avatar failed: {}", error);
+    }
+
+    Ok(Json(current_user(&app, user_id, &headers).await?))
+}
+
+/// Serve a stored file
+pub async fn get_media(
+    State(app): State<Arc<AppState>>,
+    Path(key): Path<String>,
+) -> Result<Response, Error> {
+    let Some(bytes) = app.media_store.get(&key).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type(&key)),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            // Every upload is stored under a new key
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        bytes,
+    )
+        .into_response())
+}
@@ -0,0 +1,355 @@
+//! Telling users what others did to them
+//!
+//! Following a user, favoriting their article or commenting on it leaves a
+//! notification for them, unless they turned that kind off or muted or
+//! blocked whoever did it. Besides being listed, new notifications are
+//! pushed to `GET /api/user/notifications/stream`.
+
+use crate::{
+    auth::Auth,
+    database::Profile,
+    errors,
+    events::{self, Topic},
+    profile::load_profile_by_id,
+    repo::{self, NewNotification, Notification, NotificationFilter, NotificationKind, Relation},
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{sse::Sse, IntoResponse, Response},
+    Json,
+};
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The user has no notification with this `id`
+    NotFound,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "no such notification"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// Leave a notification for `notification.user`, if they want it, and push
+/// it to their stream
+pub(crate) async fn notify(app: &AppState, notification: NewNotification) -> repo::Result<()> {
+    let repos = &app.repos;
+    let (user, actor) = (notification.user, notification.actor);
+
+    if user == actor
+        || !repos
+            .notifications
+            .is_enabled(user, notification.kind)
+            .await?
+        || repos.relations.exists(Relation::Mute, user, actor).await?
+        || repos.relations.exists(Relation::Block, user, actor).await?
+    {
+        return Ok(());
+    }
+
+    let notification = repos.notifications.create(notification).await?;
+    let response = response_notification(app, notification).await?;
+    app.events.publish(
+        Topic::User(user),
+        "notification",
+        Some(actor),
+        ResponseSingleNotification {
+            notification: response,
+        },
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseNotification {
+    id: i64,
+    kind: NotificationKind,
+    /// Who followed, favorited or commented
+    actor: Profile,
+    /// Slug of the article which was favorited or commented on
+    article: Option<String>,
+    /// `id` of the new comment
+    comment: Option<i64>,
+    read: bool,
+    created_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseSingleNotification {
+    notification: ResponseNotification,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseMultipleNotifications {
+    notifications: Vec<ResponseNotification>,
+    /// Unread notifications in total, not only on this page
+    unread_count: i64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListNotificationsConstraints {
+    /// Only unread notifications
+    #[serde(default)]
+    unread: bool,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Notifications of the authenticated user, newest first
+#[utoipa::path(
+    get,
+    path = "/api/user/notifications",
+    tag = "notifications",
+    params(ListNotificationsConstraints),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The notifications", body = ResponseMultipleNotifications),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn list_notifications(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Query(query): Query<ListNotificationsConstraints>,
+) -> Result<Json<ResponseMultipleNotifications>, Error> {
+    let list = app
+        .repos
+        .notifications
+        .list(
+            user_id,
+            NotificationFilter {
+                unread: query.unread,
+                limit: query.limit,
+                offset: query.offset,
+            },
+        )
+        .await?;
+    let mut notifications = Vec::with_capacity(list.len());
+
+    for notification in list {
+        notifications.push(response_notification(&app, notification).await?);
+    }
+
+    Ok(Json(ResponseMultipleNotifications {
+        notifications,
+        unread_count: app.repos.notifications.unread_count(user_id).await?,
+    }))
+}
+
+/// New notifications of the authenticated user as they arrive
+///
+/// Every `notification` event carries the notification as `data`. Clients
+/// which reconnect with `Last-Event-ID` receive the ones they missed.
+#[utoipa::path(
+    get,
+    path = "/api/user/notifications/stream",
+    tag = "notifications",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "A stream of `notification` events", content_type = "text/event-stream", body = ResponseSingleNotification),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn stream_notifications(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<axum::response::sse::Event, Infallible>>> {
+    let last_event_id = events::last_event_id(&headers);
+
+    events::respond(app.events.subscribe(Topic::User(user_id), last_event_id))
+}
+
+/// Mark a notification as read
+#[utoipa::path(
+    post,
+    path = "/api/user/notifications/{id}/read",
+    tag = "notifications",
+    params(("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The notification is read"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The user has no such notification"),
+    ),
+)]
+pub async fn mark_notification_read(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(id): Path<i64>,
+) -> Result<(), Error> {
+    if app.repos.notifications.mark_read(user_id, id).await? {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+/// Mark every notification of the authenticated user as read
+#[utoipa::path(
+    post,
+    path = "/api/user/notifications/read",
+    tag = "notifications",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "All notifications are read"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn mark_all_notifications_read(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<(), Error> {
+    app.repos.notifications.mark_all_read(user_id).await?;
+
+    Ok(())
+}
+
+async fn response_notification(
+    app: &AppState,
+    notification: Notification,
+) -> repo::Result<ResponseNotification> {
+    let article = match notification.article {
+        Some(article) => app.repos.articles.find(article).await?.map(|a| a.slug),
+        None => None,
+    };
+
+    Ok(ResponseNotification {
+        id: notification.id,
+        kind: notification.kind,
+        actor: load_profile_by_id(app, Some(notification.user), notification.actor).await?,
+        article,
+        comment: notification.comment,
+        read: notification.read,
+        created_at: notification.created_at,
+    })
+}
+
+/// Which kinds of notifications a user receives
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Preferences {
+    follow: bool,
+    favorite: bool,
+    comment: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponsePreferences {
+    preferences: Preferences,
+}
+
+/// Kinds which are left out stay as they are
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PreferencesUpdate {
+    follow: Option<bool>,
+    favorite: Option<bool>,
+    comment: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RequestPreferences {
+    preferences: PreferencesUpdate,
+}
+
+/// The notification preferences of the authenticated user
+#[utoipa::path(
+    get,
+    path = "/api/user/notifications/preferences",
+    tag = "notifications",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The preferences", body = ResponsePreferences),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn get_preferences(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<Json<ResponsePreferences>, Error> {
+    let notifications = &app.repos.notifications;
+
+    Ok(Json(ResponsePreferences {
+        preferences: Preferences {
+            follow: notifications
+                .is_enabled(user_id, NotificationKind::Follow)
+                .await?,
+            favorite: notifications
+                .is_enabled(user_id, NotificationKind::Favorite)
+                .await?,
+            comment: notifications
+                .is_enabled(user_id, NotificationKind::Comment)
+                .await?,
+        },
+    }))
+}
+
+/// Turn kinds of notifications on or off
+#[utoipa::path(
+    put,
+    path = "/api/user/notifications/preferences",
+    tag = "notifications",
+    security(("token" = [])),
+    request_body = RequestPreferences,
+    responses(
+        (status = 200, description = "The updated preferences", body = ResponsePreferences),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn update_preferences(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Json(request): Json<RequestPreferences>,
+) -> Result<Json<ResponsePreferences>, Error> {
+    let update = request.preferences;
+
+    for (kind, enabled) in [
+        (NotificationKind::Follow, update.follow),
+        (NotificationKind::Favorite, update.favorite),
+        (NotificationKind::Comment, update.comment),
+    ] {
+        if let Some(enabled) = enabled {
+            app.repos
+                .notifications
+                .set_enabled(user_id, kind, enabled)
+                .await?;
+        }
+    }
+
+    get_preferences(State(app), Auth(user_id)).await
+}
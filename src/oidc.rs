@@ -0,0 +1,691 @@
+//! Logging in with external identity providers through OpenID Connect
+//!
+//! Providers are listed in the JSON file given as `--oidc-providers`.
+//! `GET /api/users/oidc/{provider}/authorize` sends the browser to the
+//! provider with the authorization code flow and PKCE. The provider sends it
+//! back to `--oidc-redirect-url`, a page of the frontend, which posts the
+//! `code` and `state` it got to `POST /api/users/oidc/callback`. That trades
+//! the code for an ID token, checks it against the keys the provider
+//! publishes and logs in the user linked to the account at the provider.
+//! Starting the login sets an `HttpOnly` cookie with the `state`, which the
+//! callback has to come with, so that nobody can hand the code of their own
+//! login to someone else's browser.
+//!
+//! Accounts at a provider are linked when they are first used: to the user
+//! with the same email address if both the provider and we have verified it,
+//! or else to a new user named after the account.
+
+use crate::{
+    auth::ResponseLogin,
+    database::User,
+    errors,
+    http_client::{self, form},
+    repo::{self, PendingLogin},
+    sessions::{self, Client, Cookies},
+    token::{hash_token, now, random_token},
+    two_factor, AppState,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, request, HeaderMap, HeaderName, Method, Request, StatusCode},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    Json,
+};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use ring::constant_time::verify_slices_are_equal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
+use utoipa::ToSchema;
+
+/// Seconds a login may take at the provider
+const LOGIN_LIFETIME: i64 = 10 * 60;
+
+/// The cookie with the `state` of the login the browser started
+const STATE_COOKIE: &str = "realworld_oidc_state";
+
+/// How long the provider has to answer each request
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Usernames are cut off after this many characters, leaving room for a
+/// number which makes them unique
+const MAX_USERNAME: usize = 28;
+
+/// Numbered usernames tried before giving up
+const USERNAME_ATTEMPTS: usize = 100;
+
+#[derive(Debug, Clone, Default, clap::Args)]
+#[group(id = "oidc")]
+pub struct Config {
+    /// JSON file listing the OpenID Connect providers users may log in with,
+    /// as `[{"name", "issuer", "clientId", "clientSecret", "scopes"}]`
+    #[arg(long = "oidc-providers", env = "OIDC_PROVIDERS", value_name = "PATH")]
+    pub providers: Option<PathBuf>,
+
+    /// Where providers send users back to, a page of the frontend which posts
+    /// the `code` and `state` to `POST /api/users/oidc/callback`
+    #[arg(
+        long = "oidc-redirect-url",
+        env = "OIDC_REDIRECT_URL",
+        value_name = "URL"
+    )]
+    pub redirect_url: Option<String>,
+}
+
+impl Config {
+    /// Read the providers this configuration names
+    pub fn providers(&self) -> Result<Providers, ConfigError> {
+        let Some(path) = &self.providers else {
+            return Ok(Providers::default());
+        };
+
+        let json =
+            std::fs::read_to_string(path).map_err(|error| ConfigError::Io(path.clone(), error))?;
+        let providers: Vec<Provider> = serde_json::from_str(&json)
+            .map_err(|error| ConfigError::Invalid(path.clone(), error))?;
+
+        for (index, provider) in providers.iter().enumerate() {
+            if providers[..index]
+                .iter()
+                .any(|other| other.name == provider.name)
+            {
+                return Err(ConfigError::DuplicateName(provider.name.clone()));
+            }
+        }
+
+        let redirect_url = match &self.redirect_url {
+            Some(url) => url.clone(),
+            None if providers.is_empty() => String::new(),
+            None => return Err(ConfigError::MissingRedirectUrl),
+        };
+
+        Ok(Providers {
+            redirect_url,
+            providers,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Invalid(PathBuf, serde_json::Error),
+    DuplicateName(String),
+    /// There are providers, but nowhere for them to send users back to
+    MissingRedirectUrl,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, error) => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            ConfigError::Invalid(path, error) => {
+                write!(f, "{} lists no valid providers: {}", path.display(), error)
+            }
+            ConfigError::DuplicateName(name) => {
+                write!(f, "there is more than one provider `{}`", name)
+            }
+            ConfigError::MissingRedirectUrl => write!(f, "--oidc-redirect-url is missing"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn default_scopes() -> Vec<String> {
+    ["openid", "email", "profile"].map(String::from).to_vec()
+}
+
+/// An OpenID Connect provider users may log in with
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provider {
+    /// Identifies the provider in URLs, and the accounts linked to it
+    pub name: String,
+    /// Where `/.well-known/openid-configuration` is found, and what the ID
+    /// tokens have to name as `iss`
+    pub issuer: String,
+    pub client_id: String,
+    /// Sent with HTTP Basic authentication, public clients have none
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+impl fmt::Debug for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Provider")
+            .field("name", &self.name)
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field("scopes", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The configured providers
+#[derive(Debug, Clone, Default)]
+pub struct Providers {
+    pub redirect_url: String,
+    pub providers: Vec<Provider>,
+}
+
+impl Providers {
+    fn find(&self, name: &str) -> Option<&Provider> {
+        self.providers.iter().find(|provider| provider.name == name)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownProvider,
+    /// The state is unknown, was already used or has expired
+    InvalidState,
+    /// The provider could not be reached or gave an unexpected answer
+    Provider(String),
+    /// The ID token of the provider is not valid
+    InvalidToken(String),
+    /// The provider did not vouch for an email address of the account, which
+    /// new users need
+    UnverifiedEmail,
+    /// A user who has not verified it has the email address of the account
+    EmailTaken,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownProvider => write!(f, "no such identity provider"),
+            Error::InvalidState => write!(f, "invalid or expired login, start again"),
+            Error::Provider(error) => write!(f, "the identity provider failed: {}", error),
+            Error::InvalidToken(error) => write!(f, "invalid ID token: {}", error),
+            Error::UnverifiedEmail => write!(
+                f,
+                "the identity provider has not verified an email address of the account"
+            ),
+            Error::EmailTaken => write!(
+                f,
+                "an account with this email address exists, log in with its password and verify the address first"
+            ),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::UnknownProvider => StatusCode::NOT_FOUND,
+            Error::InvalidState => StatusCode::BAD_REQUEST,
+            Error::Provider(_) => StatusCode::BAD_GATEWAY,
+            Error::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            Error::UnverifiedEmail => StatusCode::FORBIDDEN,
+            Error::EmailTaken => StatusCode::CONFLICT,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// The parts of the discovery document we need
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The claims of an ID token we look at, besides those [`Validation`] checks
+#[derive(Debug, Deserialize)]
+struct IdClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+}
+
+/// Send `request` to `url` and parse the JSON it responds with
+async fn fetch<T: DeserializeOwned>(
+    url: &str,
+    request: request::Builder,
+    body: Bytes,
+) -> Result<T, Error> {
+    let exchange = async {
+        let response = http_client::send(url, request, body).await?;
+        let status = response.status();
+        let body = http_client::read_body(response).await?;
+
+        if !status.is_success() {
+            return Err(format!("{} responded with {}", url, status));
+        }
+
+        serde_json::from_slice(&body)
+            .map_err(|error| format!("invalid response from {}: {}", url, error))
+    };
+
+    tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .unwrap_or_else(|_| Err(format!("{} timed out", url)))
+        .map_err(Error::Provider)
+}
+
+fn get() -> request::Builder {
+    Request::builder()
+        .method(Method::GET)
+        .header(header::ACCEPT, "application/json")
+}
+
+async fn discover(provider: &Provider) -> Result<Discovery, Error> {
+    let issuer = provider.issuer.trim_end_matches('/');
+    let url = format!("{}/.well-known/openid-configuration", issuer);
+    let discovery: Discovery = fetch(&url, get(), Bytes::new()).await?;
+
+    if discovery.issuer.trim_end_matches('/') != issuer {
+        return Err(Error::Provider(format!(
+            "the discovery document names `{}` as the issuer",
+            discovery.issuer
+        )));
+    }
+
+    Ok(discovery)
+}
+
+/// The PKCE code challenge for `verifier`, see RFC 7636
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// The claims of `id_token` from `provider`, if it is signed with one of
+/// the keys at `jwks_uri`, meant for us and not expired
+async fn verify(
+    provider: &Provider,
+    jwks_uri: &str,
+    id_token: &str,
+    nonce: &str,
+) -> Result<IdClaims, Error> {
+    let invalid = |error: jsonwebtoken::errors::Error| Error::InvalidToken(error.to_string());
+    let header = decode_header(id_token).map_err(invalid)?;
+
+    // Secrets are shared with the client, so anyone could have signed
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(Error::InvalidToken(String::from(
+            "the token is not signed with a public key",
+        )));
+    }
+
+    let jwks: JwkSet = fetch(jwks_uri, get(), Bytes::new()).await?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| Error::InvalidToken(String::from("the token names none of the keys")))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&provider.issuer, provider.issuer.trim_end_matches('/')]);
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let claims = decode::<IdClaims>(id_token, &key, &validation)
+        .map_err(invalid)?
+        .claims;
+
+    // The token has to be from this login and not replayed from another
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(Error::InvalidToken(String::from(
+            "the nonce does not match",
+        )));
+    }
+
+    Ok(claims)
+}
+
+/// A username for a new user with `claims`, made of the characters which
+/// are safe in URLs
+fn username(claims: &IdClaims, email: &str) -> String {
+    let name = claims
+        .preferred_username
+        .as_deref()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default());
+    let username: String = name
+        .chars()
+        .filter(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '_' | '.'))
+        .take(MAX_USERNAME)
+        .collect();
+
+    if username.is_empty() {
+        String::from("user")
+    } else {
+        username
+    }
+}
+
+/// Create a user for the account with `claims` at an identity provider, who
+/// logs in through it and has no password, see [`reauthenticate`] for what
+/// they confirm with instead
+async fn provision(app: &AppState, claims: &IdClaims, email: &str) -> Result<User, Error> {
+    let base = username(claims, email);
+
+    for attempt in 1..=USERNAME_ATTEMPTS {
+        let username = match attempt {
+            1 => base.clone(),
+            _ => format!("{}{}", base, attempt),
+        };
+
+        // Nobody knows this password
+        match app
+            .repos
+            .users
+            .create(email, &random_token(), &username)
+            .await
+        {
+            Ok(id) => {
+                // The provider vouches for the address
+                app.repos.users.set_verified(id, true).await?;
+
+                return Ok(app.repos.users.find(id).await?.unwrap());
+            }
+            Err(repo::Error::Conflict) => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Err(repo::Error::Conflict.into())
+}
+
+/// The user linked to the account with `claims` at `provider`, linking or
+/// creating one if the account is new
+async fn link(app: &AppState, provider: &Provider, claims: IdClaims) -> Result<User, Error> {
+    let identities = &app.repos.identities;
+
+    if let Some(user_id) = identities.find(&provider.name, &claims.sub).await? {
+        return Ok(app.repos.users.find(user_id).await?.unwrap());
+    }
+
+    let email = claims
+        .email
+        .as_deref()
+        .filter(|_| claims.email_verified)
+        .ok_or(Error::UnverifiedEmail)?;
+
+    // Whoever registered an address without verifying it may not own it
+    let user = match app.repos.users.find_by_email(email).await? {
+        Some(user) if user.verified => user,
+        Some(_) => return Err(Error::EmailTaken),
+        None => provision(app, &claims, email).await?,
+    };
+
+    identities
+        .link(user.id, &provider.name, &claims.sub)
+        .await?;
+
+    Ok(user)
+}
+
+/// `Set-Cookie` for the cookie with the `state` of a login, which is sent to
+/// the callback and to confirming an account deletion
+fn state_cookie(app: &AppState, state: &str, max_age: i64) -> (HeaderName, String) {
+    let mut cookie = format!(
+        "{}={}; Path=/api; Max-Age={}; SameSite=Lax; HttpOnly",
+        STATE_COOKIE, state, max_age
+    );
+    if !app.sessions.insecure_cookies {
+        cookie.push_str("; Secure");
+    }
+
+    (header::SET_COOKIE, cookie)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseProviders {
+    /// The names of the providers
+    providers: Vec<String>,
+}
+
+/// The identity providers users may log in with
+#[utoipa::path(
+    get,
+    path = "/api/users/oidc",
+    tag = "users",
+    responses((status = 200, description = "The providers", body = ResponseProviders)),
+)]
+pub async fn list_providers(State(app): State<Arc<AppState>>) -> Json<ResponseProviders> {
+    Json(ResponseProviders {
+        providers: app
+            .oidc
+            .providers
+            .iter()
+            .map(|provider| provider.name.clone())
+            .collect(),
+    })
+}
+
+/// Log in with an identity provider
+///
+/// Redirects the browser to the provider, which sends it back to the
+/// frontend with a `code` and `state` for `POST /api/users/oidc/callback`.
+#[utoipa::path(
+    get,
+    path = "/api/users/oidc/{provider}/authorize",
+    tag = "users",
+    params(("provider" = String, Path)),
+    responses(
+        (status = 303, description = "Redirect to the provider"),
+        (status = 404, description = "No such provider"),
+        (status = 502, description = "The provider could not be reached"),
+    ),
+)]
+pub async fn start_oidc_login(
+    State(app): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<(Cookies, Redirect), Error> {
+    let provider = app.oidc.find(&name).ok_or(Error::UnknownProvider)?;
+    let discovery = discover(provider).await?;
+
+    let state = random_token();
+    let verifier = random_token();
+    let nonce = random_token();
+    let now = now();
+
+    app.repos
+        .identities
+        .start_login(
+            PendingLogin {
+                hash: hash_token(&state),
+                provider: provider.name.clone(),
+                verifier: verifier.clone(),
+                nonce: nonce.clone(),
+                expires_at: now + LOGIN_LIFETIME,
+            },
+            now,
+        )
+        .await?;
+
+    let query = form(&[
+        ("response_type", "code"),
+        ("client_id", &provider.client_id),
+        ("redirect_uri", &app.oidc.redirect_url),
+        ("scope", &provider.scopes.join(" ")),
+        ("state", &state),
+        ("nonce", &nonce),
+        ("code_challenge", &code_challenge(&verifier)),
+        ("code_challenge_method", "S256"),
+    ]);
+    let separator = if discovery.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    Ok((
+        AppendHeaders(vec![state_cookie(&app, &state, LOGIN_LIFETIME)]),
+        Redirect::to(&format!(
+            "{}{}{}",
+            discovery.authorization_endpoint, separator, query
+        )),
+    ))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Callback {
+    /// From the query of the redirect
+    code: String,
+    /// From the query of the redirect
+    state: String,
+}
+
+/// The provider the login of `callback` was started for, and the claims of
+/// the account which logged in there
+async fn authenticate<'a>(
+    app: &'a AppState,
+    headers: &HeaderMap,
+    callback: &Callback,
+) -> Result<(&'a Provider, IdClaims), Error> {
+    // The login has to be the one this browser started
+    let started = sessions::cookie(headers, STATE_COOKIE).ok_or(Error::InvalidState)?;
+    if verify_slices_are_equal(started.as_bytes(), callback.state.as_bytes()).is_err() {
+        return Err(Error::InvalidState);
+    }
+
+    let login = app
+        .repos
+        .identities
+        .finish_login(&hash_token(&callback.state), now())
+        .await?
+        .ok_or(Error::InvalidState)?;
+    let provider = app
+        .oidc
+        .find(&login.provider)
+        .ok_or(Error::UnknownProvider)?;
+    let discovery = discover(provider).await?;
+
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(header::ACCEPT, "application/json");
+    if let Some(secret) = &provider.client_secret {
+        let credentials = format!(
+            "{}:{}",
+            http_client::percent_encode(&provider.client_id),
+            http_client::percent_encode(secret)
+        );
+        request = request.header(
+            header::AUTHORIZATION,
+            format!("Basic {}", STANDARD.encode(credentials)),
+        );
+    }
+    let body = form(&[
+        ("grant_type", "authorization_code"),
+        ("code", &callback.code),
+        ("redirect_uri", &app.oidc.redirect_url),
+        ("client_id", &provider.client_id),
+        ("code_verifier", &login.verifier),
+    ]);
+    let tokens: TokenResponse =
+        fetch(&discovery.token_endpoint, request, Bytes::from(body)).await?;
+
+    let claims = verify(
+        provider,
+        &discovery.jwks_uri,
+        &tokens.id_token,
+        &login.nonce,
+    )
+    .await?;
+
+    Ok((provider, claims))
+}
+
+/// Whether the login of `callback` was by an account linked to `user_id`,
+/// which confirms the user is present without a password
+pub(crate) async fn reauthenticate(
+    app: &AppState,
+    user_id: i64,
+    headers: &HeaderMap,
+    callback: &Callback,
+) -> Result<bool, Error> {
+    let (provider, claims) = authenticate(app, headers, callback).await?;
+    let linked = app
+        .repos
+        .identities
+        .find(&provider.name, &claims.sub)
+        .await?;
+
+    Ok(linked == Some(user_id))
+}
+
+/// Finish logging in with an identity provider
+///
+/// With two-factor authentication enabled, this returns a challenge token
+/// for `POST /api/users/login/2fa` instead of the user.
+#[utoipa::path(
+    post,
+    path = "/api/users/oidc/callback",
+    tag = "users",
+    request_body = Callback,
+    responses(
+        (status = 200, description = "The user with the tokens of a new session, or a challenge for the second factor", body = ResponseLogin),
+        (status = 400, description = "The state is invalid, expired or not the one of the browser's cookie"),
+        (status = 401, description = "The ID token of the provider is invalid"),
+        (status = 403, description = "The provider has not verified the email address"),
+        (status = 409, description = "An unverified user has the email address"),
+        (status = 502, description = "The provider could not be reached or rejected the code"),
+    ),
+)]
+pub async fn finish_oidc_login(
+    State(app): State<Arc<AppState>>,
+    client: Client,
+    headers: HeaderMap,
+    Json(callback): Json<Callback>,
+) -> Result<(Cookies, Json<ResponseLogin>), Error> {
+    let (provider, claims) = authenticate(&app, &headers, &callback).await?;
+    let user = link(&app, provider, claims).await?;
+    // The state is used up
+    let clear_state = state_cookie(&app, "", 0);
+
+    if two_factor::enabled(&app, user.id).await? {
+        let challenge = two_factor::challenge(&app, user.id).await?;
+
+        return Ok((
+            AppendHeaders(vec![clear_state]),
+            Json(ResponseLogin::Challenge(challenge)),
+        ));
+    }
+
+    let (token, refresh_token) = sessions::start(&app, &user, client).await?;
+    let (AppendHeaders(mut cookies), response) = sessions::issue(&app, user, token, refresh_token);
+    cookies.push(clear_state);
+
+    Ok((AppendHeaders(cookies), Json(ResponseLogin::User(response))))
+}
@@ -0,0 +1,117 @@
+//! OpenAPI description of the HTTP API
+//!
+//! The document is put together from the `#[utoipa::path]` annotations on the
+//! handlers and the schemas derived from their request and response types.
+//! It is served at `/api/openapi.json` and browsable at `/api/docs`.
+//! `tests/openapi.rs` checks it against the routes of [`crate::router`].
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "RealWorld API",
+        description = "Backend of the RealWorld example app, a Medium clone",
+        license(name = "MIT", identifier = "MIT"),
+    ),
+    paths(
+        crate::auth::authentication,
+        crate::auth::registration,
+        crate::sessions::refresh,
+        crate::sessions::logout,
+        crate::sessions::list_sessions,
+        crate::sessions::delete_session,
+        crate::sessions::delete_all_sessions,
+        crate::access_tokens::create_access_token,
+        crate::access_tokens::list_access_tokens,
+        crate::access_tokens::delete_access_token,
+        crate::two_factor::setup_two_factor,
+        crate::two_factor::confirm_two_factor,
+        crate::two_factor::disable_two_factor,
+        crate::two_factor::login_two_factor,
+        crate::oidc::list_providers,
+        crate::oidc::start_oidc_login,
+        crate::oidc::finish_oidc_login,
+        crate::verification::verify_email,
+        crate::verification::resend_verification,
+        crate::password_reset::request_reset,
+        crate::password_reset::confirm_reset,
+        crate::auth::get_current_user,
+        crate::auth::update_user,
+        crate::account::delete_account,
+        crate::media::upload_image,
+        crate::account::export_account,
+        crate::profile::list_blocks,
+        crate::profile::list_mutes,
+        crate::notifications::list_notifications,
+        crate::notifications::stream_notifications,
+        crate::notifications::mark_all_notifications_read,
+        crate::notifications::mark_notification_read,
+        crate::notifications::get_preferences,
+        crate::notifications::update_preferences,
+        crate::webhooks::create_webhook,
+        crate::webhooks::list_webhooks,
+        crate::webhooks::delete_webhook,
+        crate::webhooks::list_deliveries,
+        crate::profile::get_profile,
+        crate::profile::follow_user,
+        crate::profile::unfollow_user,
+        crate::profile::block_user,
+        crate::profile::unblock_user,
+        crate::profile::mute_user,
+        crate::profile::unmute_user,
+        crate::articles::list_articles,
+        crate::articles::feed_articles,
+        crate::articles::get_article,
+        crate::articles::create_article,
+        crate::articles::update_article,
+        crate::articles::delete_article,
+        crate::attachments::upload_attachment,
+        crate::attachments::list_attachments,
+        crate::attachments::delete_attachment,
+        crate::comments::add_comment,
+        crate::comments::get_comments,
+        crate::comments::stream_comments,
+        crate::comments::delete_comment,
+        crate::articles::favorite_article,
+        crate::articles::unfavorite_article,
+        crate::tags::get_tags,
+        crate::backup::create_backup,
+        crate::backup::list_backups,
+        crate::lockout::list_login_failures,
+        crate::webhooks::create_global_webhook,
+        crate::webhooks::list_global_webhooks,
+        crate::webhooks::delete_global_webhook,
+        crate::webhooks::list_global_deliveries,
+    ),
+    modifiers(&TokenSecurity),
+    tags(
+        (name = "users", description = "Registration, login and the current user"),
+        (name = "profiles", description = "Public profiles, following, blocking and muting"),
+        (name = "articles", description = "Articles, the feed and favorites"),
+        (name = "comments", description = "Comments on articles"),
+        (name = "tags", description = "Tags of articles"),
+        (name = "notifications", description = "Follows, favorites and comments by others"),
+        (name = "webhooks", description = "Outgoing webhooks for articles and comments"),
+        (name = "admin", description = "Administration, requires the `admin` role"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Adds the `token` security scheme the handlers refer to
+struct TokenSecurity;
+
+impl Modify for TokenSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "`Token <jwt>` or `Bearer <jwt>` with the access token returned on login, registration or refresh",
+            ))),
+        );
+    }
+}
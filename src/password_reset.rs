@@ -0,0 +1,166 @@
+//! Recovering an account through its email address
+//!
+//! `POST /api/users/password-reset` mails a token to the address, which
+//! `POST /api/users/password-reset/confirm` takes together with the new
+//! password. Setting it revokes every token issued to the user before, so
+//! whoever knew the old password is logged out.
+
+use crate::{
+    errors,
+    mail::Message,
+    repo::{self, TokenPurpose, UserUpdate},
+    token::{hash_token, now, random_token},
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Seconds a reset token stays valid
+const TOKEN_LIFETIME: i64 = 60 * 60;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The token is unknown, was already used or has expired
+    InvalidToken,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidToken => write!(f, "invalid or expired reset token"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::InvalidToken => StatusCode::BAD_REQUEST,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ResetRequest {
+    email: String,
+}
+
+/// Mail a password reset token
+///
+/// The response is the same whether or not a user has this address.
+#[utoipa::path(
+    post,
+    path = "/api/users/password-reset",
+    tag = "users",
+    request_body = ResetRequest,
+    responses((status = 200, description = "A token was sent if the address is known")),
+)]
+pub async fn request_reset(
+    State(app): State<Arc<AppState>>,
+    Json(request): Json<ResetRequest>,
+) -> Result<(), Error> {
+    let Some(user) = app.repos.users.find_by_email(&request.email).await? else {
+        return Ok(());
+    };
+
+    let token = random_token();
+
+    app.repos
+        .one_time_tokens
+        .create(
+            user.id,
+            TokenPurpose::ResetPassword,
+            &hash_token(&token),
+            now() + TOKEN_LIFETIME,
+        )
+        .await?;
+
+    let message = Message {
+        to: user.email.clone(),
+        subject: String::from("Reset your password"),
+        body: format!(
+            "Hi {},\n\n\
+            someone asked to reset your password. If it was you, set a new one \
+            with this token:\n\n\
+            {}\n\n\
+            It is valid for one hour. Otherwise you can ignore this mail.\n",
+            user.username, token
+        ),
+    };
+
+    // Failing here would tell the caller that the address is known
+    if let Err(error) = app.mailer.send(message).await {
+        eprintln!("Could not send reset mail to {}: {}", user.email, error);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ResetConfirmation {
+    /// The token from the reset mail
+    token: String,
+    password: String,
+}
+
+/// Set a new password with a reset token
+#[utoipa::path(
+    post,
+    path = "/api/users/password-reset/confirm",
+    tag = "users",
+    request_body = ResetConfirmation,
+    responses(
+        (status = 200, description = "The password was changed and all sessions ended"),
+        (status = 400, description = "The token is invalid or expired"),
+    ),
+)]
+pub async fn confirm_reset(
+    State(app): State<Arc<AppState>>,
+    Json(confirmation): Json<ResetConfirmation>,
+) -> Result<(), Error> {
+    let user_id = app
+        .repos
+        .one_time_tokens
+        .consume(
+            TokenPurpose::ResetPassword,
+            &hash_token(&confirmation.token),
+            now(),
+        )
+        .await?
+        .ok_or(Error::InvalidToken)?;
+
+    app.repos
+        .users
+        .update(
+            user_id,
+            UserUpdate {
+                password: Some(confirmation.password),
+                ..UserUpdate::default()
+            },
+        )
+        .await?;
+    app.repos.users.revoke_tokens(user_id).await?;
+
+    Ok(())
+}
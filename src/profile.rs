@@ -1,118 +1,397 @@
-use crate::{auth::Auth, AppState};
+use crate::{
+    auth::Auth,
+    database::{self, User},
+    errors,
+    notifications::notify,
+    repo::{self, NewNotification, NotificationKind, Relation},
+    AppState,
+};
 use axum::{
     extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
 use serde::Serialize;
+use std::fmt;
 use std::sync::Arc;
+use utoipa::ToSchema;
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    /// The user acted on blocked them
+    Blocked,
+    /// Users cannot block or mute themselves
+    Yourself,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "no such user"),
+            Error::Blocked => write!(f, "blocked by the user"),
+            Error::Yourself => write!(f, "cannot be done to yourself"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
 
-#[derive(Debug, Serialize)]
-pub struct Profile {
-    pub username: String,
-    pub bio: Option<String>,
-    pub image: Option<String>,
-    pub following: bool,
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
 }
 
-#[derive(Debug, Serialize)]
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Blocked => StatusCode::FORBIDDEN,
+            Error::Yourself => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ResponseProfile {
-    profile: Profile,
+    profile: database::Profile,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseMultipleProfiles {
+    profiles: Vec<database::Profile>,
 }
 
+/// The profile of a user
+#[utoipa::path(
+    get,
+    path = "/api/profiles/{username}",
+    tag = "profiles",
+    params(("username" = String, Path)),
+    security((), ("token" = [])),
+    responses(
+        (status = 200, description = "The profile", body = ResponseProfile),
+        (status = 404, description = "There is no such user"),
+    ),
+)]
 pub async fn get_profile(
     State(app): State<Arc<AppState>>,
     authentication: Option<Auth>,
     Path(username): Path<String>,
-) -> Json<ResponseProfile> {
-    let profile = if let Some(authentication) = authentication {
-        let user_id = authentication.0;
-
-        sqlx::query_as::<_, crate::database::Profile>(
-            "
-            SELECT `username`, `bio`, `image`, (
-                SELECT COUNT(*)
-                FROM `follows`
-                WHERE `follows`.`source`=? AND `follows`.`target`=`users`.`id`
-            ) AS `following`
-            FROM `users`
-            WHERE `users`.`username`=?
-        ",
-        )
-        .bind(user_id)
-        .bind(&username)
-        .fetch_one(&app.db)
-        .await
-        .unwrap()
-    } else {
-        sqlx::query_as::<_, crate::database::Profile>(
-            "
-                SELECT `username`, `bio`, `image`, FALSE AS `following`
-                FROM `users`
-                WHERE `users`.`username`=?
-            ",
-        )
-        .bind(&username)
-        .fetch_one(&app.db)
-        .await
-        .unwrap()
-    };
+) -> Result<Json<ResponseProfile>, Error> {
+    let user = find_user(&app, &username).await?;
+    let profile = load_profile(&app, authentication.map(|auth| auth.0), user).await?;
 
-    Json(ResponseProfile {
-        profile: Profile {
-            username: profile.username,
-            bio: profile.bio,
-            image: profile.image,
-            following: profile.following,
-        },
-    })
+    Ok(Json(ResponseProfile { profile }))
 }
 
+/// Follow a user
+#[utoipa::path(
+    post,
+    path = "/api/profiles/{username}/follow",
+    tag = "profiles",
+    params(("username" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The followed profile", body = ResponseProfile),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such user"),
+        (status = 403, description = "The user blocked the authenticated user"),
+    ),
+)]
 pub async fn follow_user(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
     Path(username): Path<String>,
-) -> Json<ResponseProfile> {
-    sqlx::query(
-        "
-            INSERT INTO `follows`
-            (`source`, `target`)
-            VALUES
-            (?, (
-                SELECT `id`
-                FROM `users`
-                WHERE `username`=?
-            ))
-        ",
-    )
-    .bind(user_id)
-    .bind(&username)
-    .execute(&app.db)
-    .await
-    .unwrap();
+) -> Result<Json<ResponseProfile>, Error> {
+    let target = find_user(&app, &username).await?;
+
+    if is_blocked_by(&app, user_id, target.id).await? {
+        return Err(Error::Blocked);
+    }
+
+    if !app.repos.follows.is_following(user_id, target.id).await? {
+        app.repos.follows.follow(user_id, target.id).await?;
+        notify(
+            &app,
+            NewNotification {
+                user: target.id,
+                kind: NotificationKind::Follow,
+                actor: user_id,
+                article: None,
+                comment: None,
+            },
+        )
+        .await?;
+    }
 
     get_profile(State(app), Some(Auth(user_id)), Path(username)).await
 }
 
+/// Stop following a user
+#[utoipa::path(
+    delete,
+    path = "/api/profiles/{username}/follow",
+    tag = "profiles",
+    params(("username" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The unfollowed profile", body = ResponseProfile),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such user"),
+    ),
+)]
 pub async fn unfollow_user(
     State(app): State<Arc<AppState>>,
     Auth(user_id): Auth,
     Path(username): Path<String>,
-) -> Json<ResponseProfile> {
-    sqlx::query(
-        "
-            DELETE FROM `follows`
-            WHERE `source`=? AND `target`=(
-                SELECT `id`
-                FROM `users`
-                WHERE `username`=?
-            )
-        ",
-    )
-    .bind(user_id)
-    .bind(&username)
-    .execute(&app.db)
-    .await
-    .unwrap();
+) -> Result<Json<ResponseProfile>, Error> {
+    let target = find_user(&app, &username).await?;
+    app.repos.follows.unfollow(user_id, target.id).await?;
+
+    get_profile(State(app), Some(Auth(user_id)), Path(username)).await
+}
+
+/// Block a user, which also ends following in both directions
+#[utoipa::path(
+    post,
+    path = "/api/profiles/{username}/block",
+    tag = "profiles",
+    params(("username" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The blocked profile", body = ResponseProfile),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such user"),
+        (status = 422, description = "The user is the authenticated user"),
+    ),
+)]
+pub async fn block_user(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(username): Path<String>,
+) -> Result<Json<ResponseProfile>, Error> {
+    let target = find_other_user(&app, user_id, &username).await?;
+    app.repos
+        .relations
+        .add(Relation::Block, user_id, target.id)
+        .await?;
+    app.repos.follows.unfollow(user_id, target.id).await?;
+    app.repos.follows.unfollow(target.id, user_id).await?;
+
+    get_profile(State(app), Some(Auth(user_id)), Path(username)).await
+}
+
+/// Unblock a user
+#[utoipa::path(
+    delete,
+    path = "/api/profiles/{username}/block",
+    tag = "profiles",
+    params(("username" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The unblocked profile", body = ResponseProfile),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such user"),
+    ),
+)]
+pub async fn unblock_user(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(username): Path<String>,
+) -> Result<Json<ResponseProfile>, Error> {
+    remove_relation(&app, Relation::Block, user_id, &username).await?;
+
+    get_profile(State(app), Some(Auth(user_id)), Path(username)).await
+}
+
+/// Mute a user, hiding their articles and comments
+#[utoipa::path(
+    post,
+    path = "/api/profiles/{username}/mute",
+    tag = "profiles",
+    params(("username" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The muted profile", body = ResponseProfile),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such user"),
+        (status = 422, description = "The user is the authenticated user"),
+    ),
+)]
+pub async fn mute_user(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(username): Path<String>,
+) -> Result<Json<ResponseProfile>, Error> {
+    let target = find_other_user(&app, user_id, &username).await?;
+    app.repos
+        .relations
+        .add(Relation::Mute, user_id, target.id)
+        .await?;
 
     get_profile(State(app), Some(Auth(user_id)), Path(username)).await
 }
+
+/// Unmute a user
+#[utoipa::path(
+    delete,
+    path = "/api/profiles/{username}/mute",
+    tag = "profiles",
+    params(("username" = String, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The unmuted profile", body = ResponseProfile),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "There is no such user"),
+    ),
+)]
+pub async fn unmute_user(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(username): Path<String>,
+) -> Result<Json<ResponseProfile>, Error> {
+    remove_relation(&app, Relation::Mute, user_id, &username).await?;
+
+    get_profile(State(app), Some(Auth(user_id)), Path(username)).await
+}
+
+/// Users the authenticated user blocked
+#[utoipa::path(
+    get,
+    path = "/api/user/blocks",
+    tag = "profiles",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The blocked profiles", body = ResponseMultipleProfiles),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn list_blocks(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<Json<ResponseMultipleProfiles>, Error> {
+    Ok(Json(list_relation(&app, Relation::Block, user_id).await?))
+}
+
+/// Users the authenticated user muted
+#[utoipa::path(
+    get,
+    path = "/api/user/mutes",
+    tag = "profiles",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The muted profiles", body = ResponseMultipleProfiles),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn list_mutes(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<Json<ResponseMultipleProfiles>, Error> {
+    Ok(Json(list_relation(&app, Relation::Mute, user_id).await?))
+}
+
+async fn remove_relation(
+    app: &AppState,
+    relation: Relation,
+    user_id: i64,
+    username: &str,
+) -> Result<(), Error> {
+    let target = find_user(app, username).await?;
+    app.repos
+        .relations
+        .remove(relation, user_id, target.id)
+        .await?;
+
+    Ok(())
+}
+
+async fn list_relation(
+    app: &AppState,
+    relation: Relation,
+    user_id: i64,
+) -> repo::Result<ResponseMultipleProfiles> {
+    let targets = app.repos.relations.targets(relation, user_id).await?;
+    let mut profiles = Vec::with_capacity(targets.len());
+
+    for target in targets {
+        profiles.push(load_profile_by_id(app, Some(user_id), target).await?);
+    }
+
+    Ok(ResponseMultipleProfiles { profiles })
+}
+
+/// The user with `username`
+async fn find_user(app: &AppState, username: &str) -> Result<User, Error> {
+    app.repos
+        .users
+        .find_by_username(username)
+        .await?
+        .ok_or(Error::NotFound)
+}
+
+/// The user with `username`, unless it is `user_id`
+async fn find_other_user(app: &AppState, user_id: i64, username: &str) -> Result<User, Error> {
+    let user = find_user(app, username).await?;
+
+    if user.id == user_id {
+        return Err(Error::Yourself);
+    }
+
+    Ok(user)
+}
+
+/// Whether `author` blocked `user`
+pub(crate) async fn is_blocked_by(app: &AppState, user: i64, author: i64) -> repo::Result<bool> {
+    app.repos
+        .relations
+        .exists(Relation::Block, author, user)
+        .await
+}
+
+/// The profile of `user` as seen by `viewer`
+pub(crate) async fn load_profile(
+    app: &AppState,
+    viewer: Option<i64>,
+    user: User,
+) -> repo::Result<database::Profile> {
+    // Users who blocked the viewer never show up as followed
+    let following = if let Some(viewer) = viewer {
+        app.repos.follows.is_following(viewer, user.id).await?
+            && !is_blocked_by(app, viewer, user.id).await?
+    } else {
+        false
+    };
+
+    Ok(database::Profile {
+        username: user.username,
+        bio: user.bio,
+        image: user.image,
+        following,
+    })
+}
+
+/// Like [`load_profile`] for a user known only by `id`
+pub(crate) async fn load_profile_by_id(
+    app: &AppState,
+    viewer: Option<i64>,
+    user_id: i64,
+) -> repo::Result<database::Profile> {
+    // Whoever is referred to by `id` is there as long as the reference is
+    let user = app
+        .repos
+        .users
+        .find(user_id)
+        .await?
+        .ok_or(repo::Error::Database(sqlx::Error::RowNotFound))?;
+
+    load_profile(app, viewer, user).await
+}
@@ -0,0 +1,1493 @@
+use super::{
+    AccessToken, AccessTokenRepo, Article, ArticleFilter, ArticleRepo, ArticleUpdate, Attachment,
+    AttachmentRepo, Comment, CommentRepo, Delivery, DeliveryAttempt, DeliveryStatus, Error,
+    FollowRepo, IdentityRepo, LoginAttemptRepo, LoginFailure, NewAccessToken, NewArticle,
+    NewAttachment, NewLoginFailure, NewNotification, NewSession, Notification, NotificationFilter,
+    NotificationKind, NotificationRepo, OneTimeTokenRepo, PendingLogin, Relation, RelationRepo,
+    Result, Session, SessionRepo, TagRepo, Throttle, TokenPurpose, TwoFactor, TwoFactorRepo,
+    UserRepo, UserUpdate, Webhook, WebhookEvent, WebhookRepo,
+};
+use crate::database::{Role, User};
+use crate::token::{self, random_token};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Repositories which keep everything in memory and forget it when dropped
+///
+/// Behaves like [`super::SqlStore`] as far as the handlers can tell, so that
+/// they can be tested without a database.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+#[derive(Debug, Default)]
+struct Data {
+    /// The last `id` handed out per table, like `AUTOINCREMENT`
+    last_ids: BTreeMap<&'static str, i64>,
+    users: BTreeMap<i64, User>,
+    articles: BTreeMap<i64, StoredArticle>,
+    comments: BTreeMap<i64, Comment>,
+    tags: BTreeMap<i64, String>,
+    /// `(article, tag)`
+    taglist: BTreeSet<(i64, i64)>,
+    /// `(user, article)`
+    favorites: BTreeSet<(i64, i64)>,
+    /// `(source, target)`
+    follows: BTreeSet<(i64, i64)>,
+    /// `(relation, source, target)`
+    relations: BTreeSet<(Relation, i64, i64)>,
+    notifications: BTreeMap<i64, Notification>,
+    /// Kinds users turned on or off explicitly
+    notification_preferences: BTreeMap<(i64, NotificationKind), bool>,
+    webhooks: BTreeMap<i64, Webhook>,
+    deliveries: BTreeMap<i64, Delivery>,
+    attachments: BTreeMap<i64, Attachment>,
+    /// Sessions with the hashes of their refresh tokens
+    sessions: BTreeMap<i64, (Session, String)>,
+    /// Personal access tokens with their hashes
+    access_tokens: BTreeMap<i64, (AccessToken, String)>,
+    login_throttles: BTreeMap<String, Throttle>,
+    login_failures: BTreeMap<i64, LoginFailure>,
+    two_factor: BTreeMap<i64, TwoFactor>,
+    /// `(user, hash)`
+    recovery_codes: BTreeSet<(i64, String)>,
+    /// Users and expiry of the one-time tokens by purpose and hash
+    one_time_tokens: BTreeMap<(TokenPurpose, String), (i64, i64)>,
+    /// `(provider, subject)` to user
+    identities: BTreeMap<(String, String), i64>,
+    oidc_logins: BTreeMap<String, PendingLogin>,
+}
+
+#[derive(Debug, Clone)]
+struct StoredArticle {
+    slug: String,
+    title: String,
+    description: String,
+    body: String,
+    created_at: String,
+    updated_at: String,
+    author: i64,
+    published: bool,
+}
+
+impl Data {
+    fn next_id(&mut self, table: &'static str) -> i64 {
+        let id = self.last_ids.entry(table).or_default();
+        *id += 1;
+        *id
+    }
+
+    fn user_id(&self, username: &str) -> Option<i64> {
+        self.users
+            .values()
+            .find(|user| user.username == username)
+            .map(|user| user.id)
+    }
+
+    fn article(&self, viewer: Option<i64>, id: i64) -> Article {
+        let article = &self.articles[&id];
+
+        Article {
+            id,
+            slug: article.slug.clone(),
+            title: article.title.clone(),
+            description: article.description.clone(),
+            body: article.body.clone(),
+            tag_list: self
+                .taglist
+                .iter()
+                .filter(|(entry, _)| *entry == id)
+                .map(|(_, tag)| self.tags[tag].clone())
+                .collect(),
+            created_at: article.created_at.clone(),
+            updated_at: article.updated_at.clone(),
+            author: article.author,
+            published: article.published,
+            favorited: viewer.is_some_and(|user| self.favorites.contains(&(user, id))),
+            favorites_count: self
+                .favorites
+                .iter()
+                .filter(|(_, target)| *target == id)
+                .count() as i64,
+        }
+    }
+
+    fn delete_webhooks_of(&mut self, owner: i64) {
+        let webhooks = &mut self.webhooks;
+        webhooks.retain(|_, webhook| webhook.owner != Some(owner));
+        self.deliveries
+            .retain(|_, delivery| webhooks.contains_key(&delivery.webhook));
+    }
+
+    fn delete_article(&mut self, id: i64) {
+        self.notifications
+            .retain(|_, notification| notification.article != Some(id));
+        self.favorites.retain(|(_, target)| *target != id);
+        self.taglist.retain(|(article, _)| *article != id);
+        self.comments.retain(|_, comment| comment.article != id);
+        self.attachments
+            .retain(|_, attachment| attachment.article != id);
+        self.articles.remove(&id);
+    }
+}
+
+impl MemoryStore {
+    fn data(&self) -> std::sync::MutexGuard<'_, Data> {
+        self.data.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl UserRepo for MemoryStore {
+    async fn create(&self, email: &str, password: &str, username: &str) -> Result<i64> {
+        let mut data = self.data();
+
+        if data
+            .users
+            .values()
+            .any(|user| user.email == email || user.username == username)
+        {
+            return Err(Error::Conflict);
+        }
+
+        let id = data.next_id("users");
+        data.users.insert(
+            id,
+            User {
+                id,
+                email: email.to_owned(),
+                password: password.to_owned(),
+                username: username.to_owned(),
+                bio: None,
+                image: None,
+                role: Role::User,
+                verified: false,
+                token_version: 0,
+            },
+        );
+
+        Ok(id)
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<User>> {
+        Ok(self.data().users.get(&id).cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        let data = self.data();
+
+        Ok(data.user_id(username).map(|id| data.users[&id].clone()))
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        Ok(self
+            .data()
+            .users
+            .values()
+            .find(|user| user.email == email)
+            .cloned())
+    }
+
+    async fn find_by_credentials(&self, email: &str, password: &str) -> Result<Option<User>> {
+        Ok(self
+            .data()
+            .users
+            .values()
+            .find(|user| user.email == email && user.password == password)
+            .cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<User>> {
+        Ok(self.data().users.values().cloned().collect())
+    }
+
+    async fn update(&self, id: i64, update: UserUpdate) -> Result<()> {
+        let mut data = self.data();
+
+        if data.users.values().any(|user| {
+            user.id != id
+                && (update.email.as_ref() == Some(&user.email)
+                    || update.username.as_ref() == Some(&user.username))
+        }) {
+            return Err(Error::Conflict);
+        }
+
+        if let Some(user) = data.users.get_mut(&id) {
+            if let Some(email) = update.email {
+                user.email = email;
+            }
+            if let Some(password) = update.password {
+                user.password = password;
+            }
+            if let Some(username) = update.username {
+                user.username = username;
+            }
+            if let Some(bio) = update.bio {
+                user.bio = Some(bio);
+            }
+            if let Some(image) = update.image {
+                user.image = Some(image);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn set_role(&self, id: i64, role: Role) -> Result<()> {
+        if let Some(user) = self.data().users.get_mut(&id) {
+            user.role = role;
+        }
+
+        Ok(())
+    }
+
+    async fn set_verified(&self, id: i64, verified: bool) -> Result<()> {
+        if let Some(user) = self.data().users.get_mut(&id) {
+            user.verified = verified;
+        }
+
+        Ok(())
+    }
+
+    async fn revoke_tokens(&self, id: i64) -> Result<()> {
+        let mut data = self.data();
+
+        if let Some(user) = data.users.get_mut(&id) {
+            user.token_version += 1;
+        }
+
+        data.sessions.retain(|_, (session, _)| session.user != id);
+        data.access_tokens.retain(|_, (token, _)| token.user != id);
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        let mut data = self.data();
+
+        let articles: Vec<i64> = data
+            .articles
+            .iter()
+            .filter(|(_, article)| article.author == id)
+            .map(|(&article, _)| article)
+            .collect();
+
+        for article in articles {
+            data.delete_article(article);
+        }
+
+        data.notifications
+            .retain(|_, notification| notification.user != id && notification.actor != id);
+        data.notification_preferences
+            .retain(|(user, _), _| *user != id);
+        data.delete_webhooks_of(id);
+        data.attachments
+            .retain(|_, attachment| attachment.owner != id);
+        data.comments.retain(|_, comment| comment.author != id);
+        data.favorites.retain(|(source, _)| *source != id);
+        data.follows
+            .retain(|(source, target)| *source != id && *target != id);
+        data.relations
+            .retain(|(_, source, target)| *source != id && *target != id);
+        data.one_time_tokens.retain(|_, (user, _)| *user != id);
+        data.sessions.retain(|_, (session, _)| session.user != id);
+        data.access_tokens.retain(|_, (token, _)| token.user != id);
+        data.login_failures
+            .retain(|_, failure| failure.user != Some(id));
+        data.two_factor.remove(&id);
+        data.recovery_codes.retain(|(user, _)| *user != id);
+        data.identities.retain(|_, user| *user != id);
+        data.users.remove(&id);
+
+        Ok(())
+    }
+
+    async fn anonymize(&self, id: i64) -> Result<()> {
+        let mut data = self.data();
+
+        // The follows and favorites they were about are gone
+        data.notifications.retain(|_, notification| {
+            notification.user != id
+                && !(notification.actor == id && notification.kind != NotificationKind::Comment)
+        });
+        data.notification_preferences
+            .retain(|(user, _), _| *user != id);
+        data.delete_webhooks_of(id);
+
+        data.favorites.retain(|(source, _)| *source != id);
+        data.follows
+            .retain(|(source, target)| *source != id && *target != id);
+        data.relations
+            .retain(|(_, source, target)| *source != id && *target != id);
+        data.one_time_tokens.retain(|_, (user, _)| *user != id);
+        data.sessions.retain(|_, (session, _)| session.user != id);
+        data.access_tokens.retain(|_, (token, _)| token.user != id);
+        data.login_failures
+            .retain(|_, failure| failure.user != Some(id));
+        data.two_factor.remove(&id);
+        data.recovery_codes.retain(|(user, _)| *user != id);
+        data.identities.retain(|_, user| *user != id);
+
+        if let Some(user) = data.users.get_mut(&id) {
+            user.email = format!("deleted-{}@invalid", id);
+            // Nobody knows this password
+            user.password = random_token();
+            user.username = format!("deleted-{}", id);
+            user.bio = None;
+            user.image = None;
+            user.role = Role::User;
+            user.verified = false;
+            user.token_version += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ArticleRepo for MemoryStore {
+    async fn create(&self, author: i64, article: NewArticle) -> Result<i64> {
+        let mut data = self.data();
+
+        if data
+            .articles
+            .values()
+            .any(|stored| stored.slug == article.slug)
+        {
+            return Err(Error::Conflict);
+        }
+
+        let id = data.next_id("articles");
+        let now = now();
+        data.articles.insert(
+            id,
+            StoredArticle {
+                slug: article.slug,
+                title: article.title,
+                description: article.description,
+                body: article.body,
+                created_at: now.clone(),
+                updated_at: now,
+                author,
+                published: true,
+            },
+        );
+
+        // Tags are created in the same order as by the SQL store
+        let mut tag_list = article.tag_list;
+        tag_list.sort();
+        tag_list.dedup();
+
+        for name in tag_list {
+            let tag = match data.tags.iter().find(|(_, tag)| **tag == name) {
+                Some((&tag, _)) => tag,
+                None => {
+                    let tag = data.next_id("tags");
+                    data.tags.insert(tag, name);
+                    tag
+                }
+            };
+
+            data.taglist.insert((id, tag));
+        }
+
+        Ok(id)
+    }
+
+    async fn find_by_slug(&self, viewer: Option<i64>, slug: &str) -> Result<Option<Article>> {
+        let data = self.data();
+
+        Ok(data
+            .articles
+            .iter()
+            .find(|(_, article)| article.slug == slug)
+            .map(|(&id, _)| data.article(viewer, id)))
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Article>> {
+        let data = self.data();
+
+        Ok(data
+            .articles
+            .contains_key(&id)
+            .then(|| data.article(None, id)))
+    }
+
+    async fn list_by_author(&self, author: i64) -> Result<Vec<Article>> {
+        let data = self.data();
+
+        Ok(data
+            .articles
+            .iter()
+            .filter(|(_, article)| article.author == author)
+            .map(|(&id, _)| data.article(None, id))
+            .collect())
+    }
+
+    async fn list(&self, viewer: Option<i64>, filter: ArticleFilter) -> Result<Vec<Article>> {
+        let data = self.data();
+
+        // Unknown users match nothing
+        let author = filter.author.map(|name| data.user_id(&name).unwrap_or(-1));
+        let favorited = filter
+            .favorited
+            .map(|name| data.user_id(&name).unwrap_or(-1));
+
+        let mut articles: Vec<Article> = data
+            .articles
+            .keys()
+            .map(|&id| data.article(viewer, id))
+            .filter(|article| {
+                article.published
+                    && filter
+                        .tag
+                        .as_ref()
+                        .is_none_or(|tag| article.tag_list.contains(tag))
+                    && author.is_none_or(|author| article.author == author)
+                    && favorited.is_none_or(|user| data.favorites.contains(&(user, article.id)))
+                    && filter
+                        .followed_by
+                        .is_none_or(|user| data.follows.contains(&(user, article.author)))
+                    && filter.muted_by.is_none_or(|user| {
+                        !data
+                            .relations
+                            .contains(&(Relation::Mute, user, article.author))
+                    })
+            })
+            .collect();
+
+        articles.sort_by(|a, b| (&b.updated_at, b.id).cmp(&(&a.updated_at, a.id)));
+
+        Ok(articles
+            .into_iter()
+            .skip(filter.offset.unwrap_or(0).max(0) as usize)
+            .take(
+                filter
+                    .limit
+                    .map_or(usize::MAX, |limit| limit.max(0) as usize),
+            )
+            .collect())
+    }
+
+    async fn update(&self, id: i64, update: ArticleUpdate) -> Result<()> {
+        let mut data = self.data();
+
+        if let Some(ref slug) = update.slug {
+            if data
+                .articles
+                .iter()
+                .any(|(&other, article)| other != id && &article.slug == slug)
+            {
+                return Err(Error::Conflict);
+            }
+        }
+
+        if let Some(article) = data.articles.get_mut(&id) {
+            if let Some(slug) = update.slug {
+                article.slug = slug;
+            }
+            if let Some(title) = update.title {
+                article.title = title;
+            }
+            if let Some(description) = update.description {
+                article.description = description;
+            }
+            if let Some(body) = update.body {
+                article.body = body;
+            }
+            article.updated_at = now();
+        }
+
+        Ok(())
+    }
+
+    async fn set_published(&self, id: i64, published: bool) -> Result<()> {
+        if let Some(article) = self.data().articles.get_mut(&id) {
+            article.published = published;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        self.data().delete_article(id);
+
+        Ok(())
+    }
+
+    async fn favorite(&self, user: i64, article: i64) -> Result<()> {
+        self.data().favorites.insert((user, article));
+
+        Ok(())
+    }
+
+    async fn unfavorite(&self, user: i64, article: i64) -> Result<()> {
+        self.data().favorites.remove(&(user, article));
+
+        Ok(())
+    }
+
+    async fn favorites(&self, user: i64) -> Result<Vec<String>> {
+        let data = self.data();
+
+        Ok(data
+            .favorites
+            .iter()
+            .filter(|(source, _)| *source == user)
+            .filter_map(|(_, article)| data.articles.get(article))
+            .map(|article| article.slug.clone())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl CommentRepo for MemoryStore {
+    async fn create(&self, article: i64, author: i64, body: &str) -> Result<Comment> {
+        let mut data = self.data();
+
+        let id = data.next_id("comments");
+        let now = now();
+        let comment = Comment {
+            id,
+            article,
+            author,
+            body: body.to_owned(),
+            created_at: now.clone(),
+            updated_at: now,
+        };
+        data.comments.insert(id, comment.clone());
+
+        Ok(comment)
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Comment>> {
+        Ok(self.data().comments.get(&id).cloned())
+    }
+
+    async fn list(&self, article: i64) -> Result<Vec<Comment>> {
+        Ok(self
+            .data()
+            .comments
+            .values()
+            .filter(|comment| comment.article == article)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_by_author(&self, author: i64) -> Result<Vec<(String, Comment)>> {
+        let data = self.data();
+
+        Ok(data
+            .comments
+            .values()
+            .filter(|comment| comment.author == author)
+            .filter_map(|comment| {
+                let article = data.articles.get(&comment.article)?;
+
+                Some((article.slug.clone(), comment.clone()))
+            })
+            .collect())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        let mut data = self.data();
+        data.notifications
+            .retain(|_, notification| notification.comment != Some(id));
+        data.comments.remove(&id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FollowRepo for MemoryStore {
+    async fn follow(&self, source: i64, target: i64) -> Result<()> {
+        self.data().follows.insert((source, target));
+
+        Ok(())
+    }
+
+    async fn unfollow(&self, source: i64, target: i64) -> Result<()> {
+        self.data().follows.remove(&(source, target));
+
+        Ok(())
+    }
+
+    async fn is_following(&self, source: i64, target: i64) -> Result<bool> {
+        Ok(self.data().follows.contains(&(source, target)))
+    }
+
+    async fn following(&self, user: i64) -> Result<Vec<String>> {
+        let data = self.data();
+
+        Ok(data
+            .follows
+            .iter()
+            .filter(|(source, _)| *source == user)
+            .filter_map(|(_, target)| data.users.get(target))
+            .map(|user| user.username.clone())
+            .collect())
+    }
+
+    async fn followers(&self, user: i64) -> Result<Vec<String>> {
+        let data = self.data();
+        let mut users: Vec<i64> = data
+            .follows
+            .iter()
+            .filter(|(_, target)| *target == user)
+            .map(|(source, _)| *source)
+            .collect();
+        users.sort();
+
+        Ok(users
+            .iter()
+            .filter_map(|id| data.users.get(id))
+            .map(|user| user.username.clone())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl RelationRepo for MemoryStore {
+    async fn add(&self, relation: Relation, source: i64, target: i64) -> Result<()> {
+        self.data().relations.insert((relation, source, target));
+
+        Ok(())
+    }
+
+    async fn remove(&self, relation: Relation, source: i64, target: i64) -> Result<()> {
+        self.data().relations.remove(&(relation, source, target));
+
+        Ok(())
+    }
+
+    async fn exists(&self, relation: Relation, source: i64, target: i64) -> Result<bool> {
+        Ok(self.data().relations.contains(&(relation, source, target)))
+    }
+
+    async fn targets(&self, relation: Relation, source: i64) -> Result<Vec<i64>> {
+        Ok(self
+            .data()
+            .relations
+            .iter()
+            .filter(|(other, user, _)| *other == relation && *user == source)
+            .map(|(_, _, target)| *target)
+            .collect())
+    }
+
+    async fn target_names(&self, relation: Relation, source: i64) -> Result<Vec<String>> {
+        let data = self.data();
+
+        Ok(data
+            .relations
+            .iter()
+            .filter(|(other, user, _)| *other == relation && *user == source)
+            .filter_map(|(_, _, target)| data.users.get(target))
+            .map(|user| user.username.clone())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl TagRepo for MemoryStore {
+    async fn list(&self) -> Result<Vec<String>> {
+        Ok(self.data().tags.values().cloned().collect())
+    }
+
+    async fn find(&self, name: &str) -> Result<Option<i64>> {
+        Ok(self
+            .data()
+            .tags
+            .iter()
+            .find(|(_, tag)| *tag == name)
+            .map(|(&id, _)| id))
+    }
+
+    async fn rename(&self, id: i64, name: &str) -> Result<()> {
+        let mut data = self.data();
+
+        if data.tags.values().any(|tag| tag == name) {
+            return Err(Error::Conflict);
+        }
+
+        if let Some(tag) = data.tags.get_mut(&id) {
+            *tag = name.to_owned();
+        }
+
+        Ok(())
+    }
+
+    async fn merge(&self, source: i64, target: i64) -> Result<()> {
+        let mut data = self.data();
+
+        let articles: Vec<i64> = data
+            .taglist
+            .iter()
+            .filter(|(_, tag)| *tag == source)
+            .map(|(article, _)| *article)
+            .collect();
+
+        for article in articles {
+            data.taglist.remove(&(article, source));
+            data.taglist.insert((article, target));
+        }
+
+        data.tags.remove(&source);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NotificationRepo for MemoryStore {
+    async fn create(&self, notification: NewNotification) -> Result<Notification> {
+        let mut data = self.data();
+        let id = data.next_id("notifications");
+        let notification = Notification {
+            id,
+            user: notification.user,
+            kind: notification.kind,
+            actor: notification.actor,
+            article: notification.article,
+            comment: notification.comment,
+            read: false,
+            created_at: now(),
+        };
+        data.notifications.insert(id, notification.clone());
+
+        Ok(notification)
+    }
+
+    async fn list(&self, user: i64, filter: NotificationFilter) -> Result<Vec<Notification>> {
+        Ok(self
+            .data()
+            .notifications
+            .values()
+            .rev()
+            .filter(|notification| {
+                notification.user == user && !(filter.unread && notification.read)
+            })
+            .skip(filter.offset.unwrap_or(0).max(0) as usize)
+            .take(
+                filter
+                    .limit
+                    .map_or(usize::MAX, |limit| limit.max(0) as usize),
+            )
+            .cloned()
+            .collect())
+    }
+
+    async fn mark_read(&self, user: i64, id: i64) -> Result<bool> {
+        match self.data().notifications.get_mut(&id) {
+            Some(notification) if notification.user == user => {
+                notification.read = true;
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn mark_all_read(&self, user: i64) -> Result<()> {
+        for notification in self.data().notifications.values_mut() {
+            if notification.user == user {
+                notification.read = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn unread_count(&self, user: i64) -> Result<i64> {
+        Ok(self
+            .data()
+            .notifications
+            .values()
+            .filter(|notification| notification.user == user && !notification.read)
+            .count() as i64)
+    }
+
+    async fn is_enabled(&self, user: i64, kind: NotificationKind) -> Result<bool> {
+        Ok(self
+            .data()
+            .notification_preferences
+            .get(&(user, kind))
+            .copied()
+            .unwrap_or(true))
+    }
+
+    async fn set_enabled(&self, user: i64, kind: NotificationKind, enabled: bool) -> Result<()> {
+        self.data()
+            .notification_preferences
+            .insert((user, kind), enabled);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WebhookRepo for MemoryStore {
+    async fn create(
+        &self,
+        owner: Option<i64>,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+    ) -> Result<Webhook> {
+        let mut data = self.data();
+        let id = data.next_id("webhooks");
+        let webhook = Webhook {
+            id,
+            owner,
+            url: url.to_owned(),
+            secret: secret.to_owned(),
+            events: events.to_vec(),
+            created_at: now(),
+        };
+        data.webhooks.insert(id, webhook.clone());
+
+        Ok(webhook)
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Webhook>> {
+        Ok(self.data().webhooks.get(&id).cloned())
+    }
+
+    async fn list(&self, owner: Option<i64>) -> Result<Vec<Webhook>> {
+        Ok(self
+            .data()
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.owner == owner)
+            .cloned()
+            .collect())
+    }
+
+    async fn subscribed(&self, event: WebhookEvent, owner: i64) -> Result<Vec<Webhook>> {
+        Ok(self
+            .data()
+            .webhooks
+            .values()
+            .filter(|webhook| {
+                webhook.owner.is_none_or(|other| other == owner) && webhook.events.contains(&event)
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        let mut data = self.data();
+        data.deliveries.retain(|_, delivery| delivery.webhook != id);
+        data.webhooks.remove(&id);
+
+        Ok(())
+    }
+
+    async fn enqueue(
+        &self,
+        webhook: i64,
+        event: WebhookEvent,
+        payload: &str,
+        now: i64,
+    ) -> Result<()> {
+        let mut data = self.data();
+        let id = data.next_id("webhook_deliveries");
+        data.deliveries.insert(
+            id,
+            Delivery {
+                id,
+                webhook,
+                event,
+                payload: payload.to_owned(),
+                status: DeliveryStatus::Pending,
+                attempts: 0,
+                next_attempt_at: Some(now),
+                response_status: None,
+                error: None,
+                created_at: self::now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn due(&self, now: i64, limit: i64) -> Result<Vec<Delivery>> {
+        Ok(self
+            .data()
+            .deliveries
+            .values()
+            .filter(|delivery| {
+                delivery.status == DeliveryStatus::Pending
+                    && delivery.next_attempt_at.is_some_and(|at| at <= now)
+            })
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn record(&self, delivery: i64, attempt: DeliveryAttempt) -> Result<()> {
+        if let Some(delivery) = self.data().deliveries.get_mut(&delivery) {
+            delivery.status = attempt.status;
+            delivery.next_attempt_at = attempt.next_attempt_at;
+            delivery.response_status = attempt.response_status;
+            delivery.error = attempt.error;
+            delivery.attempts += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn deliveries(&self, webhook: i64) -> Result<Vec<Delivery>> {
+        Ok(self
+            .data()
+            .deliveries
+            .values()
+            .rev()
+            .filter(|delivery| delivery.webhook == webhook)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AttachmentRepo for MemoryStore {
+    async fn create(&self, attachment: NewAttachment, quota: i64) -> Result<Option<Attachment>> {
+        let mut data = self.data();
+
+        if data
+            .attachments
+            .values()
+            .any(|existing| existing.key == attachment.key)
+        {
+            return Err(Error::Conflict);
+        }
+
+        let usage: i64 = data
+            .attachments
+            .values()
+            .filter(|existing| existing.owner == attachment.owner)
+            .map(|existing| existing.size)
+            .sum();
+
+        if usage + attachment.size > quota {
+            return Ok(None);
+        }
+
+        let id = data.next_id("attachments");
+        let attachment = Attachment {
+            id,
+            article: attachment.article,
+            owner: attachment.owner,
+            key: attachment.key,
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            size: attachment.size,
+            created_at: now(),
+        };
+        data.attachments.insert(id, attachment.clone());
+
+        Ok(Some(attachment))
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Attachment>> {
+        Ok(self.data().attachments.get(&id).cloned())
+    }
+
+    async fn list(&self, article: i64) -> Result<Vec<Attachment>> {
+        Ok(self
+            .data()
+            .attachments
+            .values()
+            .filter(|attachment| attachment.article == article)
+            .cloned()
+            .collect())
+    }
+
+    async fn owned(&self, owner: i64) -> Result<Vec<Attachment>> {
+        Ok(self
+            .data()
+            .attachments
+            .values()
+            .filter(|attachment| attachment.owner == owner)
+            .cloned()
+            .collect())
+    }
+
+    async fn usage(&self, owner: i64) -> Result<i64> {
+        Ok(self
+            .data()
+            .attachments
+            .values()
+            .filter(|attachment| attachment.owner == owner)
+            .map(|attachment| attachment.size)
+            .sum())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        self.data().attachments.remove(&id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepo for MemoryStore {
+    async fn create(&self, session: NewSession) -> Result<Session> {
+        let mut data = self.data();
+        let started = token::now();
+
+        data.sessions
+            .retain(|_, (other, _)| other.user != session.user || other.expires_at >= started);
+
+        if data
+            .sessions
+            .values()
+            .any(|(_, hash)| *hash == session.refresh_hash)
+        {
+            return Err(Error::Conflict);
+        }
+
+        let id = data.next_id("sessions");
+        let stored = Session {
+            id,
+            user: session.user,
+            expires_at: session.expires_at,
+            last_used_at: started,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: now(),
+        };
+        data.sessions
+            .insert(id, (stored.clone(), session.refresh_hash));
+
+        Ok(stored)
+    }
+
+    async fn find(&self, id: i64, now: i64) -> Result<Option<Session>> {
+        Ok(self
+            .data()
+            .sessions
+            .get(&id)
+            .map(|(session, _)| session.clone())
+            .filter(|session| session.expires_at >= now))
+    }
+
+    async fn list(&self, user: i64, now: i64) -> Result<Vec<Session>> {
+        let mut sessions: Vec<Session> = self
+            .data()
+            .sessions
+            .values()
+            .map(|(session, _)| session)
+            .filter(|session| session.user == user && session.expires_at >= now)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse((session.last_used_at, session.id)));
+
+        Ok(sessions)
+    }
+
+    async fn refresh(
+        &self,
+        refresh_hash: &str,
+        new_hash: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<Option<Session>> {
+        let mut data = self.data();
+
+        let Some((session, hash)) = data
+            .sessions
+            .values_mut()
+            .find(|(session, hash)| hash == refresh_hash && session.expires_at >= now)
+        else {
+            return Ok(None);
+        };
+
+        session.expires_at = expires_at;
+        session.last_used_at = now;
+        *hash = new_hash.to_owned();
+
+        Ok(Some(session.clone()))
+    }
+
+    async fn touch(&self, id: i64, now: i64) -> Result<()> {
+        if let Some((session, _)) = self.data().sessions.get_mut(&id) {
+            session.last_used_at = now;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        self.data().sessions.remove(&id);
+
+        Ok(())
+    }
+
+    async fn delete_all(&self, user: i64) -> Result<()> {
+        self.data()
+            .sessions
+            .retain(|_, (session, _)| session.user != user);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AccessTokenRepo for MemoryStore {
+    async fn create(&self, token: NewAccessToken) -> Result<AccessToken> {
+        let mut data = self.data();
+
+        if data
+            .access_tokens
+            .values()
+            .any(|(_, hash)| *hash == token.hash)
+        {
+            return Err(Error::Conflict);
+        }
+
+        let id = data.next_id("access_tokens");
+        let stored = AccessToken {
+            id,
+            user: token.user,
+            name: token.name,
+            scopes: token.scopes,
+            expires_at: token.expires_at,
+            last_used_at: None,
+            created_at: now(),
+        };
+        data.access_tokens.insert(id, (stored.clone(), token.hash));
+
+        Ok(stored)
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<AccessToken>> {
+        Ok(self
+            .data()
+            .access_tokens
+            .get(&id)
+            .map(|(token, _)| token.clone()))
+    }
+
+    async fn find_by_hash(&self, hash: &str) -> Result<Option<AccessToken>> {
+        Ok(self
+            .data()
+            .access_tokens
+            .values()
+            .find(|(_, other)| other == hash)
+            .map(|(token, _)| token.clone()))
+    }
+
+    async fn list(&self, user: i64) -> Result<Vec<AccessToken>> {
+        Ok(self
+            .data()
+            .access_tokens
+            .values()
+            .rev()
+            .map(|(token, _)| token)
+            .filter(|token| token.user == user)
+            .cloned()
+            .collect())
+    }
+
+    async fn touch(&self, id: i64, now: i64) -> Result<()> {
+        if let Some((token, _)) = self.data().access_tokens.get_mut(&id) {
+            token.last_used_at = Some(now);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        self.data().access_tokens.remove(&id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepo for MemoryStore {
+    async fn throttle(&self, key: &str) -> Result<Option<Throttle>> {
+        Ok(self.data().login_throttles.get(key).copied())
+    }
+
+    async fn fail(&self, key: &str, now: i64, forget_before: i64) -> Result<()> {
+        let mut data = self.data();
+        let throttle = data
+            .login_throttles
+            .entry(key.to_owned())
+            .or_insert(Throttle {
+                failures: 0,
+                last_failure_at: now,
+            });
+
+        if throttle.last_failure_at < forget_before {
+            throttle.failures = 0;
+        }
+
+        throttle.failures += 1;
+        throttle.last_failure_at = now;
+
+        Ok(())
+    }
+
+    async fn attempt(
+        &self,
+        key: &str,
+        seen: Option<Throttle>,
+        now: i64,
+        forget_before: i64,
+    ) -> Result<bool> {
+        let mut data = self.data();
+
+        if data.login_throttles.get(key).copied() != seen {
+            return Ok(false);
+        }
+
+        let failures = match seen {
+            Some(seen) if seen.last_failure_at >= forget_before => seen.failures + 1,
+            _ => 1,
+        };
+        data.login_throttles.insert(
+            key.to_owned(),
+            Throttle {
+                failures,
+                last_failure_at: now,
+            },
+        );
+
+        Ok(true)
+    }
+
+    async fn forgive(&self, key: &str) -> Result<()> {
+        let mut data = self.data();
+
+        if let Some(throttle) = data.login_throttles.get_mut(key) {
+            throttle.failures -= 1;
+
+            if throttle.failures <= 0 {
+                data.login_throttles.remove(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reset(&self, key: &str) -> Result<()> {
+        self.data().login_throttles.remove(key);
+
+        Ok(())
+    }
+
+    async fn record(&self, failure: NewLoginFailure) -> Result<()> {
+        let mut data = self.data();
+        let id = data.next_id("login_failures");
+        data.login_failures.insert(
+            id,
+            LoginFailure {
+                id,
+                email: failure.email,
+                user: failure.user,
+                ip: failure.ip,
+                user_agent: failure.user_agent,
+                reason: failure.reason,
+                created_at: now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn failures(&self, limit: i64) -> Result<Vec<LoginFailure>> {
+        Ok(self
+            .data()
+            .login_failures
+            .values()
+            .rev()
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepo for MemoryStore {
+    async fn setup(&self, user: i64, secret: &str) -> Result<()> {
+        let mut data = self.data();
+
+        if !data
+            .two_factor
+            .get(&user)
+            .is_some_and(|two_factor| two_factor.enabled)
+        {
+            data.two_factor.insert(
+                user,
+                TwoFactor {
+                    user,
+                    secret: secret.to_owned(),
+                    enabled: false,
+                    last_step: None,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn find(&self, user: i64) -> Result<Option<TwoFactor>> {
+        Ok(self.data().two_factor.get(&user).cloned())
+    }
+
+    async fn enable(&self, user: i64, step: i64, recovery_hashes: &[String]) -> Result<()> {
+        let mut data = self.data();
+
+        if let Some(two_factor) = data.two_factor.get_mut(&user) {
+            two_factor.enabled = true;
+            two_factor.last_step = Some(step);
+        }
+
+        data.recovery_codes.retain(|(other, _)| *other != user);
+        data.recovery_codes
+            .extend(recovery_hashes.iter().map(|hash| (user, hash.clone())));
+
+        Ok(())
+    }
+
+    async fn use_step(&self, user: i64, step: i64) -> Result<bool> {
+        let mut data = self.data();
+
+        match data.two_factor.get_mut(&user) {
+            Some(two_factor) if two_factor.last_step.is_none_or(|last| last < step) => {
+                two_factor.last_step = Some(step);
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn use_recovery_code(&self, user: i64, hash: &str) -> Result<bool> {
+        Ok(self.data().recovery_codes.remove(&(user, hash.to_owned())))
+    }
+
+    async fn recovery_codes_left(&self, user: i64) -> Result<i64> {
+        Ok(self
+            .data()
+            .recovery_codes
+            .iter()
+            .filter(|(other, _)| *other == user)
+            .count() as i64)
+    }
+
+    async fn disable(&self, user: i64) -> Result<()> {
+        let mut data = self.data();
+        data.two_factor.remove(&user);
+        data.recovery_codes.retain(|(other, _)| *other != user);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IdentityRepo for MemoryStore {
+    async fn find(&self, provider: &str, subject: &str) -> Result<Option<i64>> {
+        Ok(self
+            .data()
+            .identities
+            .get(&(provider.to_owned(), subject.to_owned()))
+            .copied())
+    }
+
+    async fn link(&self, user: i64, provider: &str, subject: &str) -> Result<()> {
+        let mut data = self.data();
+        let key = (provider.to_owned(), subject.to_owned());
+
+        if data.identities.contains_key(&key) {
+            return Err(Error::Conflict);
+        }
+
+        data.identities.insert(key, user);
+
+        Ok(())
+    }
+
+    async fn start_login(&self, login: PendingLogin, now: i64) -> Result<()> {
+        let mut data = self.data();
+        data.oidc_logins.retain(|_, login| login.expires_at >= now);
+        data.oidc_logins.insert(login.hash.clone(), login);
+
+        Ok(())
+    }
+
+    async fn finish_login(&self, hash: &str, now: i64) -> Result<Option<PendingLogin>> {
+        Ok(self
+            .data()
+            .oidc_logins
+            .remove(hash)
+            .filter(|login| login.expires_at >= now))
+    }
+}
+
+#[async_trait]
+impl OneTimeTokenRepo for MemoryStore {
+    async fn create(
+        &self,
+        user: i64,
+        purpose: TokenPurpose,
+        hash: &str,
+        expires_at: i64,
+    ) -> Result<()> {
+        let mut data = self.data();
+
+        data.one_time_tokens
+            .retain(|(other, _), (owner, _)| *other != purpose || *owner != user);
+        data.one_time_tokens
+            .insert((purpose, hash.to_owned()), (user, expires_at));
+
+        Ok(())
+    }
+
+    async fn consume(&self, purpose: TokenPurpose, hash: &str, now: i64) -> Result<Option<i64>> {
+        Ok(self
+            .data()
+            .one_time_tokens
+            .remove(&(purpose, hash.to_owned()))
+            .filter(|&(_, expires_at)| expires_at >= now)
+            .map(|(user, _)| user))
+    }
+}
+
+/// The current time formatted like the timestamps of the SQL stores, e.g.
+/// `2025-01-28T17:43:41.000Z`
+fn now() -> String {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let seconds = elapsed.as_secs() as i64;
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // Convert days since 1970-01-01 to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        elapsed.subsec_millis()
+    )
+}
@@ -0,0 +1,848 @@
+//! Storage behind the HTTP handlers and the command-line interface
+//!
+//! Every kind of record has its own trait, so handlers only depend on what
+//! they use. [`SqlStore`] implements all of them on top of the configured
+//! database, [`MemoryStore`] keeps everything in memory for tests.
+
+mod memory;
+mod sql;
+
+pub use memory::MemoryStore;
+pub use sql::SqlStore;
+
+use crate::database::{Pool, Role, User};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum Error {
+    Database(sqlx::Error),
+    /// A value which has to be unique, such as a username, is already taken
+    Conflict,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Database(error) => write!(f, "database error: {}", error),
+            Error::Conflict => write!(f, "already exists"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        match error.as_database_error() {
+            Some(database_error) if database_error.is_unique_violation() => Error::Conflict,
+            _ => Error::Database(error),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Fields of a user to change, `None` keeps the current value
+#[derive(Debug, Default)]
+pub struct UserUpdate {
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub username: Option<String>,
+    pub bio: Option<String>,
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Article {
+    pub id: i64,
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub body: String,
+    pub tag_list: Vec<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub author: i64,
+    pub published: bool,
+    /// Whether the user the article was fetched for has favorited it
+    pub favorited: bool,
+    pub favorites_count: i64,
+}
+
+#[derive(Debug)]
+pub struct NewArticle {
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub body: String,
+    /// Tags which do not exist yet are created
+    pub tag_list: Vec<String>,
+}
+
+/// Fields of an article to change, `None` keeps the current value
+#[derive(Debug, Default)]
+pub struct ArticleUpdate {
+    pub slug: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub body: Option<String>,
+}
+
+/// Which published articles to list, newest first
+#[derive(Debug, Default)]
+pub struct ArticleFilter {
+    pub tag: Option<String>,
+    /// Username of the author
+    pub author: Option<String>,
+    /// Username of a user who favorited the article
+    pub favorited: Option<String>,
+    /// Only articles by users this user follows
+    pub followed_by: Option<i64>,
+    /// Leave out articles by users this user muted
+    pub muted_by: Option<i64>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// How one user shuts out another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Relation {
+    /// The target may neither follow the source nor comment on their
+    /// articles
+    Block,
+    /// Articles and comments of the target are hidden from the source
+    Mute,
+}
+
+/// What a token sent to a user allows them to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "kebab-case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+    /// The second step of logging in with two-factor authentication
+    TwoFactor,
+}
+
+/// What happened to make a user notified
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum NotificationKind {
+    /// Someone followed the user
+    Follow,
+    /// Someone favorited an article of the user
+    Favorite,
+    /// Someone commented on an article of the user
+    Comment,
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub id: i64,
+    /// The notified user
+    pub user: i64,
+    pub kind: NotificationKind,
+    /// The user who caused the notification
+    pub actor: i64,
+    pub article: Option<i64>,
+    pub comment: Option<i64>,
+    pub read: bool,
+    pub created_at: String,
+}
+
+#[derive(Debug)]
+pub struct NewNotification {
+    pub user: i64,
+    pub kind: NotificationKind,
+    pub actor: i64,
+    pub article: Option<i64>,
+    pub comment: Option<i64>,
+}
+
+/// Which notifications of a user to list, newest first
+#[derive(Debug, Default)]
+pub struct NotificationFilter {
+    pub unread: bool,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Something that happened to an article or comment, which webhooks
+/// subscribe to
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    utoipa::ToSchema,
+)]
+#[sqlx(type_name = "TEXT")]
+pub enum WebhookEvent {
+    #[serde(rename = "article.created")]
+    #[sqlx(rename = "article.created")]
+    ArticleCreated,
+    #[serde(rename = "article.updated")]
+    #[sqlx(rename = "article.updated")]
+    ArticleUpdated,
+    #[serde(rename = "article.deleted")]
+    #[sqlx(rename = "article.deleted")]
+    ArticleDeleted,
+    #[serde(rename = "comment.created")]
+    #[sqlx(rename = "comment.created")]
+    CommentCreated,
+    #[serde(rename = "comment.deleted")]
+    #[sqlx(rename = "comment.deleted")]
+    CommentDeleted,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::ArticleCreated,
+        WebhookEvent::ArticleUpdated,
+        WebhookEvent::ArticleDeleted,
+        WebhookEvent::CommentCreated,
+        WebhookEvent::CommentDeleted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::ArticleCreated => "article.created",
+            WebhookEvent::ArticleUpdated => "article.updated",
+            WebhookEvent::ArticleDeleted => "article.deleted",
+            WebhookEvent::CommentCreated => "comment.created",
+            WebhookEvent::CommentDeleted => "comment.deleted",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<WebhookEvent> {
+        WebhookEvent::ALL
+            .into_iter()
+            .find(|event| event.as_str() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub id: i64,
+    /// `None` for global webhooks, which receive events about everyone
+    pub owner: Option<i64>,
+    pub url: String,
+    /// Key of the HMAC signing the payloads
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+    pub created_at: String,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Waiting for its first or next attempt
+    Pending,
+    Delivered,
+    /// Given up after too many attempts
+    Failed,
+}
+
+/// One event sent, or still to be sent, to one webhook
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub id: i64,
+    pub webhook: i64,
+    pub event: WebhookEvent,
+    /// The JSON body which is posted
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    /// Seconds since the epoch, `None` once nothing is left to do
+    pub next_attempt_at: Option<i64>,
+    /// HTTP status of the last response
+    pub response_status: Option<i64>,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    pub created_at: String,
+}
+
+/// The outcome of trying to deliver
+#[derive(Debug)]
+pub struct DeliveryAttempt {
+    pub status: DeliveryStatus,
+    pub next_attempt_at: Option<i64>,
+    pub response_status: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// A file uploaded for use in an article
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub id: i64,
+    pub article: i64,
+    /// Who uploaded it
+    pub owner: i64,
+    /// Where the media store keeps the file
+    pub key: String,
+    /// The name of the file as it was uploaded
+    pub filename: String,
+    pub content_type: String,
+    /// In bytes
+    pub size: i64,
+    pub created_at: String,
+}
+
+#[derive(Debug)]
+pub struct NewAttachment {
+    pub article: i64,
+    pub owner: i64,
+    pub key: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+}
+
+/// A login, which lasts as long as its refresh token is used in time
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: i64,
+    pub user: i64,
+    /// Seconds since the epoch
+    pub expires_at: i64,
+    /// Seconds since the epoch
+    pub last_used_at: i64,
+    /// Of the client which logged in
+    pub user_agent: Option<String>,
+    /// Of the client which logged in
+    pub ip: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug)]
+pub struct NewSession {
+    pub user: i64,
+    pub refresh_hash: String,
+    pub expires_at: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// What a personal access token may be used for
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, utoipa::ToSchema,
+)]
+pub enum Scope {
+    /// Reading articles, comments, profiles and the own user
+    #[serde(rename = "read")]
+    Read,
+    /// Writing, deleting and favoriting articles, and their attachments
+    #[serde(rename = "articles:write")]
+    ArticlesWrite,
+    /// Writing and deleting comments
+    #[serde(rename = "comments:write")]
+    CommentsWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Read, Scope::ArticlesWrite, Scope::CommentsWrite];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::ArticlesWrite => "articles:write",
+            Scope::CommentsWrite => "comments:write",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Scope> {
+        Scope::ALL.into_iter().find(|scope| scope.as_str() == name)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A login which was sent to an identity provider and has not come back yet
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    /// The hash of the `state` parameter
+    pub hash: String,
+    pub provider: String,
+    /// The PKCE code verifier
+    pub verifier: String,
+    pub nonce: String,
+    /// Seconds since the epoch
+    pub expires_at: i64,
+}
+
+/// A token for scripts, which works without logging in until it expires
+#[derive(Debug, Clone)]
+pub struct AccessToken {
+    pub id: i64,
+    pub user: i64,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Seconds since the epoch
+    pub expires_at: i64,
+    /// Seconds since the epoch, `None` until the token is used
+    pub last_used_at: Option<i64>,
+    pub created_at: String,
+}
+
+#[derive(Debug)]
+pub struct NewAccessToken {
+    pub user: i64,
+    pub name: String,
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: i64,
+}
+
+/// Failed logins counted against an account or an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttle {
+    pub failures: i64,
+    /// Seconds since the epoch
+    pub last_failure_at: i64,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum LoginFailureReason {
+    /// The email address or the password is wrong
+    InvalidCredentials,
+    /// Too many logins failed before, the password was not checked
+    Throttled,
+    /// The password was right, but the code of the second factor was not
+    InvalidCode,
+}
+
+/// An entry in the audit trail of failed logins
+#[derive(Debug, Clone)]
+pub struct LoginFailure {
+    pub id: i64,
+    /// As entered
+    pub email: String,
+    /// The user with the email address, if there is one
+    pub user: Option<i64>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: LoginFailureReason,
+    pub created_at: String,
+}
+
+#[derive(Debug)]
+pub struct NewLoginFailure {
+    pub email: String,
+    pub user: Option<i64>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: LoginFailureReason,
+}
+
+/// The TOTP secret of a user, see RFC 6238
+#[derive(Debug, Clone)]
+pub struct TwoFactor {
+    pub user: i64,
+    /// Base32 encoded
+    pub secret: String,
+    /// Whether logging in takes a code, which is only once a code for the
+    /// secret was confirmed
+    pub enabled: bool,
+    /// The time step of the last code used
+    pub last_step: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub id: i64,
+    pub article: i64,
+    pub author: i64,
+    pub body: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    /// Returns the `id` of the new user
+    async fn create(&self, email: &str, password: &str, username: &str) -> Result<i64>;
+    async fn find(&self, id: i64) -> Result<Option<User>>;
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>>;
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>>;
+    async fn find_by_credentials(&self, email: &str, password: &str) -> Result<Option<User>>;
+    async fn list(&self) -> Result<Vec<User>>;
+    async fn update(&self, id: i64, update: UserUpdate) -> Result<()>;
+    async fn set_role(&self, id: i64, role: Role) -> Result<()>;
+    async fn set_verified(&self, id: i64, verified: bool) -> Result<()>;
+    /// Make every token issued to the user so far invalid, end all their
+    /// sessions and remove their personal access tokens
+    async fn revoke_tokens(&self, id: i64) -> Result<()>;
+    /// Remove a user together with everything they created or that refers
+    /// to them
+    async fn delete(&self, id: i64) -> Result<()>;
+    /// Strip a user of everything that identifies them and of their
+    /// relationships, but keep their articles and comments under a
+    /// placeholder name. The account cannot be logged into afterwards.
+    async fn anonymize(&self, id: i64) -> Result<()>;
+}
+
+#[async_trait]
+pub trait ArticleRepo: Send + Sync {
+    /// Returns the `id` of the new article
+    async fn create(&self, author: i64, article: NewArticle) -> Result<i64>;
+    /// `viewer` is the user `favorited` is determined for
+    async fn find_by_slug(&self, viewer: Option<i64>, slug: &str) -> Result<Option<Article>>;
+    async fn find(&self, id: i64) -> Result<Option<Article>>;
+    /// Unpublished articles are never listed
+    async fn list(&self, viewer: Option<i64>, filter: ArticleFilter) -> Result<Vec<Article>>;
+    /// Every article of the author, published or not, oldest first
+    async fn list_by_author(&self, author: i64) -> Result<Vec<Article>>;
+    async fn update(&self, id: i64, update: ArticleUpdate) -> Result<()>;
+    async fn set_published(&self, id: i64, published: bool) -> Result<()>;
+    /// Remove an article together with its tags, comments and favorites
+    async fn delete(&self, id: i64) -> Result<()>;
+    async fn favorite(&self, user: i64, article: i64) -> Result<()>;
+    async fn unfavorite(&self, user: i64, article: i64) -> Result<()>;
+    /// The slugs of the articles the user favorited
+    async fn favorites(&self, user: i64) -> Result<Vec<String>>;
+}
+
+#[async_trait]
+pub trait CommentRepo: Send + Sync {
+    async fn create(&self, article: i64, author: i64, body: &str) -> Result<Comment>;
+    async fn find(&self, id: i64) -> Result<Option<Comment>>;
+    /// All comments on an article, oldest first
+    async fn list(&self, article: i64) -> Result<Vec<Comment>>;
+    /// All comments the user wrote together with the slugs of the articles
+    /// they are on, oldest first
+    async fn list_by_author(&self, author: i64) -> Result<Vec<(String, Comment)>>;
+    async fn delete(&self, id: i64) -> Result<()>;
+}
+
+#[async_trait]
+pub trait FollowRepo: Send + Sync {
+    async fn follow(&self, source: i64, target: i64) -> Result<()>;
+    async fn unfollow(&self, source: i64, target: i64) -> Result<()>;
+    async fn is_following(&self, source: i64, target: i64) -> Result<bool>;
+    /// The usernames of the users `user` follows
+    async fn following(&self, user: i64) -> Result<Vec<String>>;
+    /// The usernames of the users who follow `user`
+    async fn followers(&self, user: i64) -> Result<Vec<String>>;
+}
+
+#[async_trait]
+pub trait RelationRepo: Send + Sync {
+    async fn add(&self, relation: Relation, source: i64, target: i64) -> Result<()>;
+    async fn remove(&self, relation: Relation, source: i64, target: i64) -> Result<()>;
+    async fn exists(&self, relation: Relation, source: i64, target: i64) -> Result<bool>;
+    /// The `id`s of the users `source` has this relation to
+    async fn targets(&self, relation: Relation, source: i64) -> Result<Vec<i64>>;
+    /// The usernames of the users `source` has this relation to
+    async fn target_names(&self, relation: Relation, source: i64) -> Result<Vec<String>>;
+}
+
+#[async_trait]
+pub trait TagRepo: Send + Sync {
+    async fn list(&self) -> Result<Vec<String>>;
+    /// Returns the `id` of the tag
+    async fn find(&self, name: &str) -> Result<Option<i64>>;
+    async fn rename(&self, id: i64, name: &str) -> Result<()>;
+    /// Move every article tagged with `source` over to `target` and remove
+    /// `source` afterwards
+    async fn merge(&self, source: i64, target: i64) -> Result<()>;
+}
+
+#[async_trait]
+pub trait NotificationRepo: Send + Sync {
+    async fn create(&self, notification: NewNotification) -> Result<Notification>;
+    async fn list(&self, user: i64, filter: NotificationFilter) -> Result<Vec<Notification>>;
+    /// Returns `false` if the user has no such notification
+    async fn mark_read(&self, user: i64, id: i64) -> Result<bool>;
+    async fn mark_all_read(&self, user: i64) -> Result<()>;
+    async fn unread_count(&self, user: i64) -> Result<i64>;
+    /// Kinds are enabled unless the user turned them off
+    async fn is_enabled(&self, user: i64, kind: NotificationKind) -> Result<bool>;
+    async fn set_enabled(&self, user: i64, kind: NotificationKind, enabled: bool) -> Result<()>;
+}
+
+/// Webhooks together with their queue of deliveries
+#[async_trait]
+pub trait WebhookRepo: Send + Sync {
+    async fn create(
+        &self,
+        owner: Option<i64>,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+    ) -> Result<Webhook>;
+    async fn find(&self, id: i64) -> Result<Option<Webhook>>;
+    /// The webhooks of `owner`, or the global ones for `None`
+    async fn list(&self, owner: Option<i64>) -> Result<Vec<Webhook>>;
+    /// The global webhooks and those of `owner` which subscribed to `event`
+    async fn subscribed(&self, event: WebhookEvent, owner: i64) -> Result<Vec<Webhook>>;
+    /// Remove a webhook together with its deliveries
+    async fn delete(&self, id: i64) -> Result<()>;
+    /// Queue a delivery whose first attempt is due at `now`
+    async fn enqueue(
+        &self,
+        webhook: i64,
+        event: WebhookEvent,
+        payload: &str,
+        now: i64,
+    ) -> Result<()>;
+    /// Up to `limit` pending deliveries whose next attempt is due at `now`,
+    /// oldest first
+    async fn due(&self, now: i64, limit: i64) -> Result<Vec<Delivery>>;
+    /// Count an attempt and store its outcome
+    async fn record(&self, delivery: i64, attempt: DeliveryAttempt) -> Result<()>;
+    /// Every delivery of the webhook, newest first
+    async fn deliveries(&self, webhook: i64) -> Result<Vec<Delivery>>;
+}
+
+/// Files uploaded for articles, which go together with the article
+#[async_trait]
+pub trait AttachmentRepo: Send + Sync {
+    /// `None` if the attachment would take what its owner uploaded above
+    /// `quota` bytes, which is checked together with storing it
+    async fn create(&self, attachment: NewAttachment, quota: i64) -> Result<Option<Attachment>>;
+    async fn find(&self, id: i64) -> Result<Option<Attachment>>;
+    /// The attachments of an article, oldest first
+    async fn list(&self, article: i64) -> Result<Vec<Attachment>>;
+    /// The attachments `owner` uploaded, oldest first
+    async fn owned(&self, owner: i64) -> Result<Vec<Attachment>>;
+    /// Total size in bytes of what `owner` uploaded
+    async fn usage(&self, owner: i64) -> Result<i64>;
+    async fn delete(&self, id: i64) -> Result<()>;
+}
+
+/// Personal access tokens, only their hashes are stored
+#[async_trait]
+pub trait AccessTokenRepo: Send + Sync {
+    async fn create(&self, token: NewAccessToken) -> Result<AccessToken>;
+    async fn find(&self, id: i64) -> Result<Option<AccessToken>>;
+    /// The token with the hash, even if it expired
+    async fn find_by_hash(&self, hash: &str) -> Result<Option<AccessToken>>;
+    /// The tokens of the user, newest first
+    async fn list(&self, user: i64) -> Result<Vec<AccessToken>>;
+    /// Record that the token was used at `now`
+    async fn touch(&self, id: i64, now: i64) -> Result<()>;
+    async fn delete(&self, id: i64) -> Result<()>;
+}
+
+/// Failed logins, counted per account and address to slow down guessing and
+/// kept as an audit trail
+#[async_trait]
+pub trait LoginAttemptRepo: Send + Sync {
+    /// The failures counted against `key`
+    async fn throttle(&self, key: &str) -> Result<Option<Throttle>>;
+    /// Count a failure at `now` against `key`, counting starts over if the
+    /// last one was before `forget_before`
+    async fn fail(&self, key: &str, now: i64, forget_before: i64) -> Result<()>;
+    /// Like [`fail`](Self::fail) for an attempt which is yet to be checked,
+    /// but only if the failures counted against `key` are still `seen`.
+    /// Returns whether it was counted, which it is not if another attempt
+    /// was counted in between.
+    async fn attempt(
+        &self,
+        key: &str,
+        seen: Option<Throttle>,
+        now: i64,
+        forget_before: i64,
+    ) -> Result<bool>;
+    /// Take back an attempt counted against `key` which succeeded
+    async fn forgive(&self, key: &str) -> Result<()>;
+    /// Forget the failures counted against `key`
+    async fn reset(&self, key: &str) -> Result<()>;
+    async fn record(&self, failure: NewLoginFailure) -> Result<()>;
+    /// The latest entries of the audit trail, newest first
+    async fn failures(&self, limit: i64) -> Result<Vec<LoginFailure>>;
+}
+
+/// TOTP secrets and recovery codes, only the hashes of the recovery codes are
+/// stored
+#[async_trait]
+pub trait TwoFactorRepo: Send + Sync {
+    /// Start enrolling the user with a new secret, replacing one which was
+    /// not confirmed yet
+    async fn setup(&self, user: i64, secret: &str) -> Result<()>;
+    async fn find(&self, user: i64) -> Result<Option<TwoFactor>>;
+    /// Require codes from now on and replace the recovery codes of the user,
+    /// `step` is the time step of the code which was confirmed
+    async fn enable(&self, user: i64, step: i64, recovery_hashes: &[String]) -> Result<()>;
+    /// Mark the time step of a code as used, unless it or a later one was
+    /// used before
+    async fn use_step(&self, user: i64, step: i64) -> Result<bool>;
+    /// Remove a recovery code, unless the user has no such code
+    async fn use_recovery_code(&self, user: i64, hash: &str) -> Result<bool>;
+    async fn recovery_codes_left(&self, user: i64) -> Result<i64>;
+    /// Remove the secret and the recovery codes of the user
+    async fn disable(&self, user: i64) -> Result<()>;
+}
+
+/// Accounts at identity providers which users are linked to, and the logins
+/// through them which are under way
+#[async_trait]
+pub trait IdentityRepo: Send + Sync {
+    /// The user linked to `subject` at `provider`
+    async fn find(&self, provider: &str, subject: &str) -> Result<Option<i64>>;
+    /// Link `subject` at `provider` to `user`, [`Error::Conflict`] if it is
+    /// linked already
+    async fn link(&self, user: i64, provider: &str, subject: &str) -> Result<()>;
+    /// Store a login which was sent to a provider, forgetting those which
+    /// expired before `now`
+    async fn start_login(&self, login: PendingLogin, now: i64) -> Result<()>;
+    /// Remove the login with the state `hash` and return it, unless it
+    /// expired before `now`
+    async fn finish_login(&self, hash: &str, now: i64) -> Result<Option<PendingLogin>>;
+}
+
+/// Tokens which are mailed to users and work only once, only their hashes
+/// are stored
+#[async_trait]
+pub trait OneTimeTokenRepo: Send + Sync {
+    /// Store a new token valid until `expires_at` (in seconds since the
+    /// epoch), earlier tokens of the user for the same purpose stop working
+    async fn create(
+        &self,
+        user: i64,
+        purpose: TokenPurpose,
+        hash: &str,
+        expires_at: i64,
+    ) -> Result<()>;
+    /// Remove the token and return its user, unless it expired before `now`
+    async fn consume(&self, purpose: TokenPurpose, hash: &str, now: i64) -> Result<Option<i64>>;
+}
+
+/// Logins of users, only the hashes of their refresh tokens are stored
+#[async_trait]
+pub trait SessionRepo: Send + Sync {
+    /// Start a session which ends at `expires_at` (in seconds since the
+    /// epoch) unless it is refreshed, sessions of the user which already
+    /// ended are removed
+    async fn create(&self, session: NewSession) -> Result<Session>;
+    /// The session, unless it ended before `now`
+    async fn find(&self, id: i64, now: i64) -> Result<Option<Session>>;
+    /// The sessions of the user which did not end before `now`, the most
+    /// recently used first
+    async fn list(&self, user: i64, now: i64) -> Result<Vec<Session>>;
+    /// Swap the refresh token of a session for a new one and move its end to
+    /// `expires_at`. Returns the session, unless the token is unknown or
+    /// the session ended before `now`.
+    async fn refresh(
+        &self,
+        refresh_hash: &str,
+        new_hash: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<Option<Session>>;
+    /// Record that the session was used at `now`
+    async fn touch(&self, id: i64, now: i64) -> Result<()>;
+    async fn delete(&self, id: i64) -> Result<()>;
+    /// End every session of the user
+    async fn delete_all(&self, user: i64) -> Result<()>;
+}
+
+/// One implementation of every repository, shared by the handlers
+#[derive(Clone)]
+pub struct Repos {
+    pub users: Arc<dyn UserRepo>,
+    pub articles: Arc<dyn ArticleRepo>,
+    pub comments: Arc<dyn CommentRepo>,
+    pub follows: Arc<dyn FollowRepo>,
+    pub relations: Arc<dyn RelationRepo>,
+    pub tags: Arc<dyn TagRepo>,
+    pub notifications: Arc<dyn NotificationRepo>,
+    pub webhooks: Arc<dyn WebhookRepo>,
+    pub attachments: Arc<dyn AttachmentRepo>,
+    pub sessions: Arc<dyn SessionRepo>,
+    pub access_tokens: Arc<dyn AccessTokenRepo>,
+    pub login_attempts: Arc<dyn LoginAttemptRepo>,
+    pub two_factor: Arc<dyn TwoFactorRepo>,
+    pub identities: Arc<dyn IdentityRepo>,
+    pub one_time_tokens: Arc<dyn OneTimeTokenRepo>,
+}
+
+impl Repos {
+    /// Use the same store for every kind of record
+    pub fn new<S>(store: S) -> Self
+    where
+        S: UserRepo
+            + ArticleRepo
+            + CommentRepo
+            + FollowRepo
+            + RelationRepo
+            + TagRepo
+            + NotificationRepo
+            + WebhookRepo
+            + AttachmentRepo
+            + SessionRepo
+            + AccessTokenRepo
+            + LoginAttemptRepo
+            + TwoFactorRepo
+            + IdentityRepo
+            + OneTimeTokenRepo
+            + 'static,
+    {
+        let store = Arc::new(store);
+
+        Repos {
+            users: store.clone(),
+            articles: store.clone(),
+            comments: store.clone(),
+            follows: store.clone(),
+            relations: store.clone(),
+            tags: store.clone(),
+            notifications: store.clone(),
+            webhooks: store.clone(),
+            attachments: store.clone(),
+            sessions: store.clone(),
+            access_tokens: store.clone(),
+            login_attempts: store.clone(),
+            two_factor: store.clone(),
+            identities: store.clone(),
+            one_time_tokens: store,
+        }
+    }
+
+    pub fn sql(db: Pool) -> Self {
+        Repos::new(SqlStore::new(db))
+    }
+
+    pub fn memory() -> Self {
+        Repos::new(MemoryStore::default())
+    }
+}
+
+impl fmt::Debug for Repos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Repos").finish_non_exhaustive()
+    }
+}
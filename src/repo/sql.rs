@@ -0,0 +1,2209 @@
+use super::{
+    AccessToken, AccessTokenRepo, Article, ArticleFilter, ArticleRepo, ArticleUpdate, Attachment,
+    AttachmentRepo, Comment, CommentRepo, Delivery, DeliveryAttempt, DeliveryStatus, FollowRepo,
+    IdentityRepo, LoginAttemptRepo, LoginFailure, LoginFailureReason, NewAccessToken, NewArticle,
+    NewAttachment, NewLoginFailure, NewNotification, NewSession, Notification, NotificationFilter,
+    NotificationKind, NotificationRepo, OneTimeTokenRepo, PendingLogin, Relation, RelationRepo,
+    Result, Scope, Session, SessionRepo, TagRepo, Throttle, TokenPurpose, TwoFactor, TwoFactorRepo,
+    UserRepo, UserUpdate, Webhook, WebhookEvent, WebhookRepo,
+};
+use crate::database::{timestamp, Db, Pool, Role, User};
+use crate::token::{now, random_token};
+use async_trait::async_trait;
+use sqlx::{FromRow, QueryBuilder};
+
+/// Deletes an article, everything referring to it goes first
+const DELETE_ARTICLE: [&str; 6] = [
+    r#"DELETE FROM "notifications" WHERE "article"=$1"#,
+    r#"DELETE FROM "favorites" WHERE "target"=$1"#,
+    r#"DELETE FROM "taglist" WHERE "article"=$1"#,
+    r#"DELETE FROM "comments" WHERE "article"=$1"#,
+    r#"DELETE FROM "attachments" WHERE "article"=$1"#,
+    r#"DELETE FROM "articles" WHERE "id"=$1"#,
+];
+
+/// Repositories backed by the database selected at compile time
+#[derive(Debug, Clone)]
+pub struct SqlStore {
+    db: Pool,
+}
+
+impl SqlStore {
+    pub fn new(db: Pool) -> Self {
+        SqlStore { db }
+    }
+}
+
+#[async_trait]
+impl UserRepo for SqlStore {
+    async fn create(&self, email: &str, password: &str, username: &str) -> Result<i64> {
+        let id = sqlx::query_scalar(
+            r#"
+                INSERT INTO "users"
+                ("email", "password", "username")
+                VALUES
+                ($1, $2, $3)
+                RETURNING "id"
+            "#,
+        )
+        .bind(email)
+        .bind(password)
+        .bind(username)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(r#"SELECT * FROM "users" WHERE "id"=$1"#)
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?;
+
+        Ok(user)
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(r#"SELECT * FROM "users" WHERE "username"=$1"#)
+            .bind(username)
+            .fetch_optional(&self.db)
+            .await?;
+
+        Ok(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(r#"SELECT * FROM "users" WHERE "email"=$1"#)
+            .bind(email)
+            .fetch_optional(&self.db)
+            .await?;
+
+        Ok(user)
+    }
+
+    async fn find_by_credentials(&self, email: &str, password: &str) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            r#"SELECT * FROM "users" WHERE "email"=$1 AND "password"=$2"#,
+        )
+        .bind(email)
+        .bind(password)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn list(&self) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(r#"SELECT * FROM "users" ORDER BY "id""#)
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(users)
+    }
+
+    async fn update(&self, id: i64, update: UserUpdate) -> Result<()> {
+        let fields = [
+            ("email", update.email),
+            ("password", update.password),
+            ("username", update.username),
+            ("bio", update.bio),
+            ("image", update.image),
+        ];
+
+        let mut sql: QueryBuilder<Db> = QueryBuilder::new(r#"UPDATE "users" SET "#);
+        let mut attributes = sql.separated(", ");
+        let mut changed = false;
+
+        for (name, value) in fields {
+            if let Some(value) = value {
+                attributes
+                    .push(format!(r#""{}"="#, name))
+                    .push_bind_unseparated(value);
+                changed = true;
+            }
+        }
+
+        if changed {
+            sql.push(r#" WHERE "id"="#).push_bind(id);
+            sql.build().execute(&self.db).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_role(&self, id: i64, role: Role) -> Result<()> {
+        sqlx::query(r#"UPDATE "users" SET "role"=$1 WHERE "id"=$2"#)
+            .bind(role)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_verified(&self, id: i64, verified: bool) -> Result<()> {
+        sqlx::query(r#"UPDATE "users" SET "verified"=$1 WHERE "id"=$2"#)
+            .bind(verified)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_tokens(&self, id: i64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        for sql in [
+            r#"UPDATE "users" SET "tokenVersion"="tokenVersion"+1 WHERE "id"=$1"#,
+            r#"DELETE FROM "sessions" WHERE "user"=$1"#,
+            r#"DELETE FROM "access_tokens" WHERE "user"=$1"#,
+        ] {
+            sqlx::query(sql).bind(id).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let articles: Vec<i64> =
+            sqlx::query_scalar(r#"SELECT "id" FROM "articles" WHERE "author"=$1"#)
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await?;
+
+        for article in articles {
+            for sql in DELETE_ARTICLE {
+                sqlx::query(sql).bind(article).execute(&mut *tx).await?;
+            }
+        }
+
+        // Nothing may be left referring to the user
+        for sql in [
+            r#"DELETE FROM "notifications" WHERE "user"=$1 OR "actor"=$1"#,
+            r#"DELETE FROM "notification_preferences" WHERE "user"=$1"#,
+            r#"DELETE FROM "webhook_deliveries"
+                WHERE "webhook" IN (SELECT "id" FROM "webhooks" WHERE "owner"=$1)"#,
+            r#"DELETE FROM "webhooks" WHERE "owner"=$1"#,
+            r#"DELETE FROM "attachments" WHERE "owner"=$1"#,
+            r#"DELETE FROM "comments" WHERE "author"=$1"#,
+            r#"DELETE FROM "favorites" WHERE "source"=$1"#,
+            r#"DELETE FROM "follows" WHERE "source"=$1 OR "target"=$1"#,
+            r#"DELETE FROM "blocks" WHERE "source"=$1 OR "target"=$1"#,
+            r#"DELETE FROM "mutes" WHERE "source"=$1 OR "target"=$1"#,
+            r#"DELETE FROM "one_time_tokens" WHERE "user"=$1"#,
+            r#"DELETE FROM "sessions" WHERE "user"=$1"#,
+            r#"DELETE FROM "access_tokens" WHERE "user"=$1"#,
+            r#"DELETE FROM "login_failures" WHERE "user"=$1"#,
+            r#"DELETE FROM "recovery_codes" WHERE "user"=$1"#,
+            r#"DELETE FROM "two_factor" WHERE "user"=$1"#,
+            r#"DELETE FROM "identities" WHERE "user"=$1"#,
+            r#"DELETE FROM "users" WHERE "id"=$1"#,
+        ] {
+            sqlx::query(sql).bind(id).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn anonymize(&self, id: i64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            r#"
+                UPDATE "users"
+                SET "email"=$1, "password"=$2, "username"=$3, "bio"=NULL, "image"=NULL,
+                    "role"='user', "verified"=FALSE, "tokenVersion"="tokenVersion"+1
+                WHERE "id"=$4
+            "#,
+        )
+        .bind(format!("deleted-{}@invalid", id))
+        // Nobody knows this password
+        .bind(random_token())
+        .bind(format!("deleted-{}", id))
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        for sql in [
+            r#"DELETE FROM "notifications" WHERE "user"=$1"#,
+            // The follows and favorites they were about are gone
+            r#"DELETE FROM "notifications" WHERE "actor"=$1 AND "kind" IN ('follow', 'favorite')"#,
+            r#"DELETE FROM "notification_preferences" WHERE "user"=$1"#,
+            r#"DELETE FROM "webhook_deliveries"
+                WHERE "webhook" IN (SELECT "id" FROM "webhooks" WHERE "owner"=$1)"#,
+            r#"DELETE FROM "webhooks" WHERE "owner"=$1"#,
+            r#"DELETE FROM "favorites" WHERE "source"=$1"#,
+            r#"DELETE FROM "follows" WHERE "source"=$1 OR "target"=$1"#,
+            r#"DELETE FROM "blocks" WHERE "source"=$1 OR "target"=$1"#,
+            r#"DELETE FROM "mutes" WHERE "source"=$1 OR "target"=$1"#,
+            r#"DELETE FROM "one_time_tokens" WHERE "user"=$1"#,
+            r#"DELETE FROM "sessions" WHERE "user"=$1"#,
+            r#"DELETE FROM "access_tokens" WHERE "user"=$1"#,
+            r#"DELETE FROM "login_failures" WHERE "user"=$1"#,
+            r#"DELETE FROM "recovery_codes" WHERE "user"=$1"#,
+            r#"DELETE FROM "two_factor" WHERE "user"=$1"#,
+            r#"DELETE FROM "identities" WHERE "user"=$1"#,
+        ] {
+            sqlx::query(sql).bind(id).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct ArticleRow {
+    id: i64,
+    slug: String,
+    title: String,
+    description: String,
+    body: String,
+    created_at: String,
+    updated_at: String,
+    author: i64,
+    published: bool,
+    favorited: bool,
+    favorites_count: i64,
+}
+
+impl SqlStore {
+    /// Start a query for articles as seen by `viewer`, to be continued with
+    /// conditions
+    fn select_articles<'a>(viewer: Option<i64>) -> QueryBuilder<'a, Db> {
+        let mut sql: QueryBuilder<Db> = QueryBuilder::new(format!(
+            r#"
+            SELECT "articles"."id", "slug", "title", "description", "body",
+                {} AS "createdAt",
+                {} AS "updatedAt",
+                "author", "published", EXISTS (
+                    SELECT *
+                    FROM "favorites"
+                    WHERE "favorites"."source"="#,
+            timestamp(r#""createdAt""#),
+            timestamp(r#""updatedAt""#)
+        ));
+
+        // Use an `id` which never exists if nobody is authenticated
+        sql.push_bind(viewer.unwrap_or(-1));
+        sql.push(
+            r#" AND "favorites"."target"="articles"."id"
+                ) AS "favorited", (
+                    SELECT COUNT(*)
+                    FROM "favorites"
+                    WHERE "target"="articles"."id"
+                ) AS "favoritesCount"
+            FROM "articles"
+            WHERE TRUE"#,
+        );
+
+        sql
+    }
+
+    /// Add the tags to each article
+    async fn with_tags(&self, rows: Vec<ArticleRow>) -> Result<Vec<Article>> {
+        let mut articles = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let tag_list: Vec<String> = sqlx::query_scalar(
+                r#"
+                    SELECT "name"
+                    FROM "taglist" INNER JOIN "tags" ON "taglist"."tag"="tags"."id"
+                    WHERE "article"=$1
+                "#,
+            )
+            .bind(row.id)
+            .fetch_all(&self.db)
+            .await?;
+
+            articles.push(Article {
+                id: row.id,
+                slug: row.slug,
+                title: row.title,
+                description: row.description,
+                body: row.body,
+                tag_list,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+                author: row.author,
+                published: row.published,
+                favorited: row.favorited,
+                favorites_count: row.favorites_count,
+            });
+        }
+
+        Ok(articles)
+    }
+}
+
+#[async_trait]
+impl ArticleRepo for SqlStore {
+    async fn create(&self, author: i64, article: NewArticle) -> Result<i64> {
+        let id: i64 = sqlx::query_scalar(
+            r#"
+                INSERT INTO "articles"
+                ("slug", "title", "description", "body", "author")
+                VALUES
+                ($1, $2, $3, $4, $5)
+                RETURNING "id"
+            "#,
+        )
+        .bind(article.slug)
+        .bind(article.title)
+        .bind(article.description)
+        .bind(article.body)
+        .bind(author)
+        .fetch_one(&self.db)
+        .await?;
+
+        let mut tag_list = article.tag_list;
+
+        if !tag_list.is_empty() {
+            // We only need each tag once
+            tag_list.sort();
+            tag_list.dedup();
+
+            // Tags which are used for the first time have to be created
+            let mut query: QueryBuilder<Db> = QueryBuilder::new(r#"INSERT INTO "tags" ("name") "#);
+
+            query.push_values(&tag_list, |mut query, tag| {
+                query.push_bind(tag);
+            });
+            query.push(" ON CONFLICT DO NOTHING");
+
+            query.build().execute(&self.db).await?;
+
+            let mut query: QueryBuilder<Db> =
+                QueryBuilder::new(r#"INSERT INTO "taglist" ("article", "tag") SELECT "#);
+            query.push_bind(id);
+            query.push(r#", "id" FROM "tags" WHERE "name" IN ("#);
+
+            let mut names = query.separated(", ");
+            for tag in tag_list {
+                names.push_bind(tag);
+            }
+            query.push(")");
+
+            query.build().execute(&self.db).await?;
+        }
+
+        Ok(id)
+    }
+
+    async fn find_by_slug(&self, viewer: Option<i64>, slug: &str) -> Result<Option<Article>> {
+        let mut sql = SqlStore::select_articles(viewer);
+        sql.push(r#" AND "slug"="#).push_bind(slug);
+
+        let row = sql
+            .build_query_as::<ArticleRow>()
+            .fetch_optional(&self.db)
+            .await?;
+
+        Ok(self.with_tags(row.into_iter().collect()).await?.pop())
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Article>> {
+        let mut sql = SqlStore::select_articles(None);
+        sql.push(r#" AND "articles"."id"="#).push_bind(id);
+
+        let row = sql
+            .build_query_as::<ArticleRow>()
+            .fetch_optional(&self.db)
+            .await?;
+
+        Ok(self.with_tags(row.into_iter().collect()).await?.pop())
+    }
+
+    async fn list_by_author(&self, author: i64) -> Result<Vec<Article>> {
+        let mut sql = SqlStore::select_articles(None);
+        sql.push(r#" AND "author"="#)
+            .push_bind(author)
+            .push(r#" ORDER BY "articles"."id""#);
+
+        let rows = sql
+            .build_query_as::<ArticleRow>()
+            .fetch_all(&self.db)
+            .await?;
+
+        self.with_tags(rows).await
+    }
+
+    async fn list(&self, viewer: Option<i64>, filter: ArticleFilter) -> Result<Vec<Article>> {
+        let mut sql = SqlStore::select_articles(viewer);
+        sql.push(r#" AND "published""#);
+
+        if let Some(name) = filter.tag {
+            sql.push(
+                r#"
+                AND EXISTS (
+                    SELECT *
+                    FROM "taglist"
+                    JOIN "tags" ON "tags"."id"="taglist"."tag"
+                    WHERE "taglist"."article"="articles"."id" AND "tags"."name"="#,
+            );
+            sql.push_bind(name);
+            sql.push(")");
+        }
+
+        if let Some(name) = filter.author {
+            sql.push(
+                r#"
+                AND "author" IN (
+                    SELECT "id"
+                    FROM "users"
+                    WHERE "username"="#,
+            );
+            sql.push_bind(name);
+            sql.push(")");
+        }
+
+        if let Some(name) = filter.favorited {
+            sql.push(
+                r#"
+                AND EXISTS (
+                    SELECT *
+                    FROM "favorites"
+                    JOIN "users" ON "users"."id"="favorites"."source"
+                    WHERE "favorites"."target"="articles"."id" AND "users"."username"="#,
+            );
+            sql.push_bind(name);
+            sql.push(")");
+        }
+
+        if let Some(user) = filter.followed_by {
+            sql.push(
+                r#"
+                AND "author" IN (
+                    SELECT "target"
+                    FROM "follows"
+                    WHERE "source"="#,
+            );
+            sql.push_bind(user);
+            sql.push(")");
+        }
+
+        if let Some(user) = filter.muted_by {
+            sql.push(
+                r#"
+                AND "author" NOT IN (
+                    SELECT "target"
+                    FROM "mutes"
+                    WHERE "source"="#,
+            );
+            sql.push_bind(user);
+            sql.push(")");
+        }
+
+        sql.push(
+            r#"
+            ORDER BY "updatedAt" DESC, "articles"."id" DESC"#,
+        );
+
+        if let Some(limit) = filter.limit {
+            sql.push(" LIMIT ").push_bind(limit);
+        }
+
+        if let Some(offset) = filter.offset {
+            sql.push(" OFFSET ").push_bind(offset);
+        }
+
+        let rows = sql
+            .build_query_as::<ArticleRow>()
+            .fetch_all(&self.db)
+            .await?;
+
+        self.with_tags(rows).await
+    }
+
+    async fn update(&self, id: i64, update: ArticleUpdate) -> Result<()> {
+        let fields = [
+            ("slug", update.slug),
+            ("title", update.title),
+            ("description", update.description),
+            ("body", update.body),
+        ];
+
+        let mut sql: QueryBuilder<Db> = QueryBuilder::new(r#"UPDATE "articles" SET "#);
+        let mut attributes = sql.separated(", ");
+
+        for (name, value) in fields {
+            if let Some(value) = value {
+                attributes
+                    .push(format!(r#""{}"="#, name))
+                    .push_bind_unseparated(value);
+            }
+        }
+
+        attributes.push(r#""updatedAt"=CURRENT_TIMESTAMP"#);
+
+        sql.push(r#" WHERE "id"="#).push_bind(id);
+        sql.build().execute(&self.db).await?;
+
+        Ok(())
+    }
+
+    async fn set_published(&self, id: i64, published: bool) -> Result<()> {
+        sqlx::query(r#"UPDATE "articles" SET "published"=$1 WHERE "id"=$2"#)
+            .bind(published)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        for sql in DELETE_ARTICLE {
+            sqlx::query(sql).bind(id).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn favorite(&self, user: i64, article: i64) -> Result<()> {
+        sqlx::query(r#"INSERT INTO "favorites" ("source", "target") VALUES ($1, $2)"#)
+            .bind(user)
+            .bind(article)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unfavorite(&self, user: i64, article: i64) -> Result<()> {
+        sqlx::query(r#"DELETE FROM "favorites" WHERE "source"=$1 AND "target"=$2"#)
+            .bind(user)
+            .bind(article)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn favorites(&self, user: i64) -> Result<Vec<String>> {
+        let articles = sqlx::query_scalar(
+            r#"
+                SELECT "articles"."slug"
+                FROM "favorites"
+                JOIN "articles" ON "articles"."id"="favorites"."target"
+                WHERE "favorites"."source"=$1
+                ORDER BY "favorites"."target"
+            "#,
+        )
+        .bind(user)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(articles)
+    }
+}
+
+impl SqlStore {
+    fn select_comments() -> String {
+        format!(
+            r#"
+                SELECT "id", "article", "author", "body",
+                    {} AS "createdAt",
+                    {} AS "updatedAt"
+                FROM "comments"
+            "#,
+            timestamp(r#""createdAt""#),
+            timestamp(r#""updatedAt""#)
+        )
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct CommentRow {
+    id: i64,
+    article: i64,
+    author: i64,
+    body: String,
+    created_at: String,
+    updated_at: String,
+}
+
+/// A comment with the slug of the article it is on
+#[derive(Debug, FromRow)]
+struct AuthoredCommentRow {
+    slug: String,
+    #[sqlx(flatten)]
+    comment: CommentRow,
+}
+
+impl From<CommentRow> for Comment {
+    fn from(row: CommentRow) -> Self {
+        Comment {
+            id: row.id,
+            article: row.article,
+            author: row.author,
+            body: row.body,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+#[async_trait]
+impl CommentRepo for SqlStore {
+    async fn create(&self, article: i64, author: i64, body: &str) -> Result<Comment> {
+        let id: i64 = sqlx::query_scalar(
+            r#"
+                INSERT INTO "comments" ("article", "body", "author")
+                VALUES ($1, $2, $3)
+                RETURNING "id"
+            "#,
+        )
+        .bind(article)
+        .bind(body)
+        .bind(author)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(CommentRepo::find(self, id).await?.unwrap())
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Comment>> {
+        let row = sqlx::query_as::<_, CommentRow>(&format!(
+            r#"{} WHERE "id"=$1"#,
+            SqlStore::select_comments()
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(Comment::from))
+    }
+
+    async fn list(&self, article: i64) -> Result<Vec<Comment>> {
+        let rows = sqlx::query_as::<_, CommentRow>(&format!(
+            r#"{} WHERE "article"=$1 ORDER BY "id""#,
+            SqlStore::select_comments()
+        ))
+        .bind(article)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(Comment::from).collect())
+    }
+
+    async fn list_by_author(&self, author: i64) -> Result<Vec<(String, Comment)>> {
+        let rows = sqlx::query_as::<_, AuthoredCommentRow>(&format!(
+            r#"
+                SELECT "comments"."id", "comments"."article", "comments"."author",
+                    "comments"."body", "articles"."slug",
+                    {} AS "createdAt",
+                    {} AS "updatedAt"
+                FROM "comments"
+                JOIN "articles" ON "articles"."id"="comments"."article"
+                WHERE "comments"."author"=$1
+                ORDER BY "comments"."id"
+            "#,
+            timestamp(r#""comments"."createdAt""#),
+            timestamp(r#""comments"."updatedAt""#)
+        ))
+        .bind(author)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.slug, Comment::from(row.comment)))
+            .collect())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        for sql in [
+            r#"DELETE FROM "notifications" WHERE "comment"=$1"#,
+            r#"DELETE FROM "comments" WHERE "id"=$1"#,
+        ] {
+            sqlx::query(sql).bind(id).execute(&self.db).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FollowRepo for SqlStore {
+    async fn follow(&self, source: i64, target: i64) -> Result<()> {
+        sqlx::query(r#"INSERT INTO "follows" ("source", "target") VALUES ($1, $2)"#)
+            .bind(source)
+            .bind(target)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unfollow(&self, source: i64, target: i64) -> Result<()> {
+        sqlx::query(r#"DELETE FROM "follows" WHERE "source"=$1 AND "target"=$2"#)
+            .bind(source)
+            .bind(target)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn is_following(&self, source: i64, target: i64) -> Result<bool> {
+        let following = sqlx::query_scalar(
+            r#"
+                SELECT EXISTS (
+                    SELECT *
+                    FROM "follows"
+                    WHERE "source"=$1 AND "target"=$2
+                )
+            "#,
+        )
+        .bind(source)
+        .bind(target)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(following)
+    }
+
+    async fn following(&self, user: i64) -> Result<Vec<String>> {
+        let users = sqlx::query_scalar(
+            r#"
+                SELECT "users"."username"
+                FROM "follows"
+                JOIN "users" ON "users"."id"="follows"."target"
+                WHERE "follows"."source"=$1
+                ORDER BY "follows"."target"
+            "#,
+        )
+        .bind(user)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(users)
+    }
+
+    async fn followers(&self, user: i64) -> Result<Vec<String>> {
+        let users = sqlx::query_scalar(
+            r#"
+                SELECT "users"."username"
+                FROM "follows"
+                JOIN "users" ON "users"."id"="follows"."source"
+                WHERE "follows"."target"=$1
+                ORDER BY "follows"."source"
+            "#,
+        )
+        .bind(user)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(users)
+    }
+}
+
+impl SqlStore {
+    fn relation_table(relation: Relation) -> &'static str {
+        match relation {
+            Relation::Block => "blocks",
+            Relation::Mute => "mutes",
+        }
+    }
+}
+
+#[async_trait]
+impl RelationRepo for SqlStore {
+    async fn add(&self, relation: Relation, source: i64, target: i64) -> Result<()> {
+        sqlx::query(&format!(
+            r#"INSERT INTO "{}" ("source", "target") VALUES ($1, $2) ON CONFLICT DO NOTHING"#,
+            SqlStore::relation_table(relation)
+        ))
+        .bind(source)
+        .bind(target)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, relation: Relation, source: i64, target: i64) -> Result<()> {
+        sqlx::query(&format!(
+            r#"DELETE FROM "{}" WHERE "source"=$1 AND "target"=$2"#,
+            SqlStore::relation_table(relation)
+        ))
+        .bind(source)
+        .bind(target)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn exists(&self, relation: Relation, source: i64, target: i64) -> Result<bool> {
+        let exists = sqlx::query_scalar(&format!(
+            r#"SELECT EXISTS (SELECT * FROM "{}" WHERE "source"=$1 AND "target"=$2)"#,
+            SqlStore::relation_table(relation)
+        ))
+        .bind(source)
+        .bind(target)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(exists)
+    }
+
+    async fn targets(&self, relation: Relation, source: i64) -> Result<Vec<i64>> {
+        let targets = sqlx::query_scalar(&format!(
+            r#"SELECT "target" FROM "{}" WHERE "source"=$1 ORDER BY "target""#,
+            SqlStore::relation_table(relation)
+        ))
+        .bind(source)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(targets)
+    }
+
+    async fn target_names(&self, relation: Relation, source: i64) -> Result<Vec<String>> {
+        let targets = sqlx::query_scalar(&format!(
+            r#"
+                SELECT "users"."username"
+                FROM "{0}"
+                JOIN "users" ON "users"."id"="{0}"."target"
+                WHERE "{0}"."source"=$1
+                ORDER BY "{0}"."target"
+            "#,
+            SqlStore::relation_table(relation)
+        ))
+        .bind(source)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(targets)
+    }
+}
+
+#[async_trait]
+impl TagRepo for SqlStore {
+    async fn list(&self) -> Result<Vec<String>> {
+        let tags = sqlx::query_scalar(r#"SELECT "name" FROM "tags""#)
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(tags)
+    }
+
+    async fn find(&self, name: &str) -> Result<Option<i64>> {
+        let id = sqlx::query_scalar(r#"SELECT "id" FROM "tags" WHERE "name"=$1"#)
+            .bind(name)
+            .fetch_optional(&self.db)
+            .await?;
+
+        Ok(id)
+    }
+
+    async fn rename(&self, id: i64, name: &str) -> Result<()> {
+        sqlx::query(r#"UPDATE "tags" SET "name"=$1 WHERE "id"=$2"#)
+            .bind(name)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn merge(&self, source: i64, target: i64) -> Result<()> {
+        // Articles which already carry both tags must not end up with `target` twice
+        sqlx::query(
+            r#"
+                UPDATE "taglist"
+                SET "tag"=$2
+                WHERE "tag"=$1 AND "article" NOT IN (
+                    SELECT "article"
+                    FROM "taglist"
+                    WHERE "tag"=$2
+                )
+            "#,
+        )
+        .bind(source)
+        .bind(target)
+        .execute(&self.db)
+        .await?;
+
+        for sql in [
+            r#"DELETE FROM "taglist" WHERE "tag"=$1"#,
+            r#"DELETE FROM "tags" WHERE "id"=$1"#,
+        ] {
+            sqlx::query(sql).bind(source).execute(&self.db).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct NotificationRow {
+    id: i64,
+    user: i64,
+    kind: NotificationKind,
+    actor: i64,
+    article: Option<i64>,
+    comment: Option<i64>,
+    read: bool,
+    created_at: String,
+}
+
+impl From<NotificationRow> for Notification {
+    fn from(row: NotificationRow) -> Self {
+        Notification {
+            id: row.id,
+            user: row.user,
+            kind: row.kind,
+            actor: row.actor,
+            article: row.article,
+            comment: row.comment,
+            read: row.read,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationRepo for SqlStore {
+    async fn create(&self, notification: NewNotification) -> Result<Notification> {
+        let row = sqlx::query_as::<_, NotificationRow>(&format!(
+            r#"
+                INSERT INTO "notifications" ("user", "kind", "actor", "article", "comment")
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING "id", "user", "kind", "actor", "article", "comment", "read",
+                    {} AS "createdAt"
+            "#,
+            timestamp(r#""createdAt""#)
+        ))
+        .bind(notification.user)
+        .bind(notification.kind)
+        .bind(notification.actor)
+        .bind(notification.article)
+        .bind(notification.comment)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    async fn list(&self, user: i64, filter: NotificationFilter) -> Result<Vec<Notification>> {
+        let mut sql = QueryBuilder::new(format!(
+            r#"
+                SELECT "id", "user", "kind", "actor", "article", "comment", "read",
+                    {} AS "createdAt"
+                FROM "notifications"
+                WHERE "user"="#,
+            timestamp(r#""createdAt""#)
+        ));
+        sql.push_bind(user);
+
+        if filter.unread {
+            sql.push(r#" AND NOT "read""#);
+        }
+
+        sql.push(r#" ORDER BY "id" DESC"#);
+
+        if let Some(limit) = filter.limit {
+            sql.push(" LIMIT ").push_bind(limit);
+        }
+
+        if let Some(offset) = filter.offset {
+            sql.push(" OFFSET ").push_bind(offset);
+        }
+
+        let rows = sql
+            .build_query_as::<NotificationRow>()
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(rows.into_iter().map(Notification::from).collect())
+    }
+
+    async fn mark_read(&self, user: i64, id: i64) -> Result<bool> {
+        let result =
+            sqlx::query(r#"UPDATE "notifications" SET "read"=TRUE WHERE "id"=$1 AND "user"=$2"#)
+                .bind(id)
+                .bind(user)
+                .execute(&self.db)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn mark_all_read(&self, user: i64) -> Result<()> {
+        sqlx::query(r#"UPDATE "notifications" SET "read"=TRUE WHERE "user"=$1"#)
+            .bind(user)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unread_count(&self, user: i64) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            r#"SELECT COUNT(*) FROM "notifications" WHERE "user"=$1 AND NOT "read""#,
+        )
+        .bind(user)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn is_enabled(&self, user: i64, kind: NotificationKind) -> Result<bool> {
+        let enabled: Option<bool> = sqlx::query_scalar(
+            r#"SELECT "enabled" FROM "notification_preferences" WHERE "user"=$1 AND "kind"=$2"#,
+        )
+        .bind(user)
+        .bind(kind)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(enabled.unwrap_or(true))
+    }
+
+    async fn set_enabled(&self, user: i64, kind: NotificationKind, enabled: bool) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO "notification_preferences" ("user", "kind", "enabled")
+                VALUES ($1, $2, $3)
+                ON CONFLICT ("user", "kind") DO UPDATE SET "enabled"=excluded."enabled"
+            "#,
+        )
+        .bind(user)
+        .bind(kind)
+        .bind(enabled)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl SqlStore {
+    fn select_webhooks() -> String {
+        format!(
+            r#"
+                SELECT "id", "owner", "url", "secret", "events", {} AS "createdAt"
+                FROM "webhooks"
+            "#,
+            timestamp(r#""createdAt""#)
+        )
+    }
+
+    fn select_deliveries() -> String {
+        format!(
+            r#"
+                SELECT "id", "webhook", "event", "payload", "status", "attempts",
+                    "nextAttemptAt", "responseStatus", "error", {} AS "createdAt"
+                FROM "webhook_deliveries"
+            "#,
+            timestamp(r#""createdAt""#)
+        )
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct WebhookRow {
+    id: i64,
+    owner: Option<i64>,
+    url: String,
+    secret: String,
+    events: String,
+    created_at: String,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            owner: row.owner,
+            url: row.url,
+            secret: row.secret,
+            events: row
+                .events
+                .split(',')
+                .filter_map(WebhookEvent::parse)
+                .collect(),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct DeliveryRow {
+    id: i64,
+    webhook: i64,
+    event: WebhookEvent,
+    payload: String,
+    status: DeliveryStatus,
+    attempts: i64,
+    next_attempt_at: Option<i64>,
+    response_status: Option<i64>,
+    error: Option<String>,
+    created_at: String,
+}
+
+impl From<DeliveryRow> for Delivery {
+    fn from(row: DeliveryRow) -> Self {
+        Delivery {
+            id: row.id,
+            webhook: row.webhook,
+            event: row.event,
+            payload: row.payload,
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at,
+            response_status: row.response_status,
+            error: row.error,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl WebhookRepo for SqlStore {
+    async fn create(
+        &self,
+        owner: Option<i64>,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+    ) -> Result<Webhook> {
+        let events: Vec<_> = events.iter().map(|event| event.as_str()).collect();
+        let id: i64 = sqlx::query_scalar(
+            r#"
+                INSERT INTO "webhooks" ("owner", "url", "secret", "events")
+                VALUES ($1, $2, $3, $4)
+                RETURNING "id"
+            "#,
+        )
+        .bind(owner)
+        .bind(url)
+        .bind(secret)
+        .bind(events.join(","))
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(WebhookRepo::find(self, id).await?.unwrap())
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Webhook>> {
+        let row = sqlx::query_as::<_, WebhookRow>(&format!(
+            r#"{} WHERE "id"=$1"#,
+            SqlStore::select_webhooks()
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(Webhook::from))
+    }
+
+    async fn list(&self, owner: Option<i64>) -> Result<Vec<Webhook>> {
+        let rows = match owner {
+            Some(owner) => {
+                sqlx::query_as::<_, WebhookRow>(&format!(
+                    r#"{} WHERE "owner"=$1 ORDER BY "id""#,
+                    SqlStore::select_webhooks()
+                ))
+                .bind(owner)
+                .fetch_all(&self.db)
+                .await?
+            }
+            None => {
+                sqlx::query_as::<_, WebhookRow>(&format!(
+                    r#"{} WHERE "owner" IS NULL ORDER BY "id""#,
+                    SqlStore::select_webhooks()
+                ))
+                .fetch_all(&self.db)
+                .await?
+            }
+        };
+
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    async fn subscribed(&self, event: WebhookEvent, owner: i64) -> Result<Vec<Webhook>> {
+        let rows = sqlx::query_as::<_, WebhookRow>(&format!(
+            r#"{} WHERE "owner" IS NULL OR "owner"=$1 ORDER BY "id""#,
+            SqlStore::select_webhooks()
+        ))
+        .bind(owner)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(Webhook::from)
+            .filter(|webhook| webhook.events.contains(&event))
+            .collect())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        for sql in [
+            r#"DELETE FROM "webhook_deliveries" WHERE "webhook"=$1"#,
+            r#"DELETE FROM "webhooks" WHERE "id"=$1"#,
+        ] {
+            sqlx::query(sql).bind(id).execute(&self.db).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn enqueue(
+        &self,
+        webhook: i64,
+        event: WebhookEvent,
+        payload: &str,
+        now: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO "webhook_deliveries" ("webhook", "event", "payload", "nextAttemptAt")
+                VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(webhook)
+        .bind(event)
+        .bind(payload)
+        .bind(now)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn due(&self, now: i64, limit: i64) -> Result<Vec<Delivery>> {
+        let rows = sqlx::query_as::<_, DeliveryRow>(&format!(
+            r#"{} WHERE "status"='pending' AND "nextAttemptAt"<=$1 ORDER BY "id" LIMIT $2"#,
+            SqlStore::select_deliveries()
+        ))
+        .bind(now)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(Delivery::from).collect())
+    }
+
+    async fn record(&self, delivery: i64, attempt: DeliveryAttempt) -> Result<()> {
+        sqlx::query(
+            r#"
+                UPDATE "webhook_deliveries"
+                SET "status"=$1, "nextAttemptAt"=$2, "responseStatus"=$3, "error"=$4,
+                    "attempts"="attempts"+1
+                WHERE "id"=$5
+            "#,
+        )
+        .bind(attempt.status)
+        .bind(attempt.next_attempt_at)
+        .bind(attempt.response_status)
+        .bind(attempt.error)
+        .bind(delivery)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn deliveries(&self, webhook: i64) -> Result<Vec<Delivery>> {
+        let rows = sqlx::query_as::<_, DeliveryRow>(&format!(
+            r#"{} WHERE "webhook"=$1 ORDER BY "id" DESC"#,
+            SqlStore::select_deliveries()
+        ))
+        .bind(webhook)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(Delivery::from).collect())
+    }
+}
+
+impl SqlStore {
+    fn select_attachments() -> String {
+        format!(
+            r#"
+                SELECT "id", "article", "owner", "key", "filename", "contentType", "size",
+                    {} AS "createdAt"
+                FROM "attachments"
+            "#,
+            timestamp(r#""createdAt""#)
+        )
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct AttachmentRow {
+    id: i64,
+    article: i64,
+    owner: i64,
+    key: String,
+    filename: String,
+    content_type: String,
+    size: i64,
+    created_at: String,
+}
+
+impl From<AttachmentRow> for Attachment {
+    fn from(row: AttachmentRow) -> Self {
+        Attachment {
+            id: row.id,
+            article: row.article,
+            owner: row.owner,
+            key: row.key,
+            filename: row.filename,
+            content_type: row.content_type,
+            size: row.size,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl AttachmentRepo for SqlStore {
+    async fn create(&self, attachment: NewAttachment, quota: i64) -> Result<Option<Attachment>> {
+        let mut tx = self.db.begin().await?;
+
+        // Locking the owner makes concurrent uploads of theirs wait, so each
+        // sees what the others stored
+        sqlx::query(r#"UPDATE "users" SET "id"="id" WHERE "id"=$1"#)
+            .bind(attachment.owner)
+            .execute(&mut *tx)
+            .await?;
+
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+                INSERT INTO "attachments"
+                ("article", "owner", "key", "filename", "contentType", "size")
+                SELECT $1, $2, $3, $4, $5, $6
+                WHERE (
+                    SELECT COALESCE(SUM("size"), 0) FROM "attachments" WHERE "owner"=$2
+                ) + $6 <= $7
+                RETURNING "id"
+            "#,
+        )
+        .bind(attachment.article)
+        .bind(attachment.owner)
+        .bind(attachment.key)
+        .bind(attachment.filename)
+        .bind(attachment.content_type)
+        .bind(attachment.size)
+        .bind(quota)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        match id {
+            Some(id) => Ok(AttachmentRepo::find(self, id).await?),
+            None => Ok(None),
+        }
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<Attachment>> {
+        let row = sqlx::query_as::<_, AttachmentRow>(&format!(
+            r#"{} WHERE "id"=$1"#,
+            SqlStore::select_attachments()
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(Attachment::from))
+    }
+
+    async fn list(&self, article: i64) -> Result<Vec<Attachment>> {
+        let rows = sqlx::query_as::<_, AttachmentRow>(&format!(
+            r#"{} WHERE "article"=$1 ORDER BY "id""#,
+            SqlStore::select_attachments()
+        ))
+        .bind(article)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(Attachment::from).collect())
+    }
+
+    async fn owned(&self, owner: i64) -> Result<Vec<Attachment>> {
+        let rows = sqlx::query_as::<_, AttachmentRow>(&format!(
+            r#"{} WHERE "owner"=$1 ORDER BY "id""#,
+            SqlStore::select_attachments()
+        ))
+        .bind(owner)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(Attachment::from).collect())
+    }
+
+    async fn usage(&self, owner: i64) -> Result<i64> {
+        // PostgreSQL sums up BIGINTs as NUMERIC
+        let usage = sqlx::query_scalar(
+            r#"SELECT CAST(COALESCE(SUM("size"), 0) AS BIGINT) FROM "attachments" WHERE "owner"=$1"#,
+        )
+        .bind(owner)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(usage)
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        sqlx::query(r#"DELETE FROM "attachments" WHERE "id"=$1"#)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl SqlStore {
+    fn select_sessions() -> String {
+        format!(
+            r#"
+                SELECT "id", "user", "expiresAt", "lastUsedAt", "userAgent", "ip",
+                    {} AS "createdAt"
+                FROM "sessions"
+            "#,
+            timestamp(r#""createdAt""#)
+        )
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct SessionRow {
+    id: i64,
+    user: i64,
+    expires_at: i64,
+    last_used_at: i64,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: String,
+}
+
+impl From<SessionRow> for Session {
+    fn from(row: SessionRow) -> Self {
+        Session {
+            id: row.id,
+            user: row.user,
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            user_agent: row.user_agent,
+            ip: row.ip,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl SessionRepo for SqlStore {
+    async fn create(&self, session: NewSession) -> Result<Session> {
+        let now = now();
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(r#"DELETE FROM "sessions" WHERE "user"=$1 AND "expiresAt"<$2"#)
+            .bind(session.user)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        let id: i64 = sqlx::query_scalar(
+            r#"
+                INSERT INTO "sessions"
+                ("user", "refreshHash", "expiresAt", "lastUsedAt", "userAgent", "ip")
+                VALUES
+                ($1, $2, $3, $4, $5, $6)
+                RETURNING "id"
+            "#,
+        )
+        .bind(session.user)
+        .bind(session.refresh_hash)
+        .bind(session.expires_at)
+        .bind(now)
+        .bind(session.user_agent)
+        .bind(session.ip)
+        .fetch_one(&mut *tx)
+        .await?;
+        let row = sqlx::query_as::<_, SessionRow>(&format!(
+            r#"{} WHERE "id"=$1"#,
+            SqlStore::select_sessions()
+        ))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+
+    async fn find(&self, id: i64, now: i64) -> Result<Option<Session>> {
+        let row = sqlx::query_as::<_, SessionRow>(&format!(
+            r#"{} WHERE "id"=$1 AND "expiresAt">=$2"#,
+            SqlStore::select_sessions()
+        ))
+        .bind(id)
+        .bind(now)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(Session::from))
+    }
+
+    async fn list(&self, user: i64, now: i64) -> Result<Vec<Session>> {
+        let rows = sqlx::query_as::<_, SessionRow>(&format!(
+            r#"
+                {} WHERE "user"=$1 AND "expiresAt">=$2
+                ORDER BY "lastUsedAt" DESC, "id" DESC
+            "#,
+            SqlStore::select_sessions()
+        ))
+        .bind(user)
+        .bind(now)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(Session::from).collect())
+    }
+
+    async fn refresh(
+        &self,
+        refresh_hash: &str,
+        new_hash: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<Option<Session>> {
+        // Only one of several requests with the same token gets through
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+                UPDATE "sessions"
+                SET "refreshHash"=$1, "expiresAt"=$2, "lastUsedAt"=$3
+                WHERE "refreshHash"=$4 AND "expiresAt">=$3
+                RETURNING "id"
+            "#,
+        )
+        .bind(new_hash)
+        .bind(expires_at)
+        .bind(now)
+        .bind(refresh_hash)
+        .fetch_optional(&self.db)
+        .await?;
+
+        match id {
+            Some(id) => SessionRepo::find(self, id, now).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn touch(&self, id: i64, now: i64) -> Result<()> {
+        sqlx::query(r#"UPDATE "sessions" SET "lastUsedAt"=$1 WHERE "id"=$2"#)
+            .bind(now)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        sqlx::query(r#"DELETE FROM "sessions" WHERE "id"=$1"#)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_all(&self, user: i64) -> Result<()> {
+        sqlx::query(r#"DELETE FROM "sessions" WHERE "user"=$1"#)
+            .bind(user)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl SqlStore {
+    fn select_access_tokens() -> String {
+        format!(
+            r#"
+                SELECT "id", "user", "name", "scopes", "expiresAt", "lastUsedAt",
+                    {} AS "createdAt"
+                FROM "access_tokens"
+            "#,
+            timestamp(r#""createdAt""#)
+        )
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct AccessTokenRow {
+    id: i64,
+    user: i64,
+    name: String,
+    scopes: String,
+    expires_at: i64,
+    last_used_at: Option<i64>,
+    created_at: String,
+}
+
+impl From<AccessTokenRow> for AccessToken {
+    fn from(row: AccessTokenRow) -> Self {
+        AccessToken {
+            id: row.id,
+            user: row.user,
+            name: row.name,
+            scopes: row.scopes.split(',').filter_map(Scope::parse).collect(),
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl AccessTokenRepo for SqlStore {
+    async fn create(&self, token: NewAccessToken) -> Result<AccessToken> {
+        let scopes: Vec<_> = token.scopes.iter().map(|scope| scope.as_str()).collect();
+        let id: i64 = sqlx::query_scalar(
+            r#"
+                INSERT INTO "access_tokens" ("user", "name", "hash", "scopes", "expiresAt")
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING "id"
+            "#,
+        )
+        .bind(token.user)
+        .bind(token.name)
+        .bind(token.hash)
+        .bind(scopes.join(","))
+        .bind(token.expires_at)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(AccessTokenRepo::find(self, id).await?.unwrap())
+    }
+
+    async fn find(&self, id: i64) -> Result<Option<AccessToken>> {
+        let row = sqlx::query_as::<_, AccessTokenRow>(&format!(
+            r#"{} WHERE "id"=$1"#,
+            SqlStore::select_access_tokens()
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(AccessToken::from))
+    }
+
+    async fn find_by_hash(&self, hash: &str) -> Result<Option<AccessToken>> {
+        let row = sqlx::query_as::<_, AccessTokenRow>(&format!(
+            r#"{} WHERE "hash"=$1"#,
+            SqlStore::select_access_tokens()
+        ))
+        .bind(hash)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(AccessToken::from))
+    }
+
+    async fn list(&self, user: i64) -> Result<Vec<AccessToken>> {
+        let rows = sqlx::query_as::<_, AccessTokenRow>(&format!(
+            r#"{} WHERE "user"=$1 ORDER BY "id" DESC"#,
+            SqlStore::select_access_tokens()
+        ))
+        .bind(user)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(AccessToken::from).collect())
+    }
+
+    async fn touch(&self, id: i64, now: i64) -> Result<()> {
+        sqlx::query(r#"UPDATE "access_tokens" SET "lastUsedAt"=$1 WHERE "id"=$2"#)
+            .bind(now)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<()> {
+        sqlx::query(r#"DELETE FROM "access_tokens" WHERE "id"=$1"#)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct LoginFailureRow {
+    id: i64,
+    email: String,
+    user: Option<i64>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    reason: LoginFailureReason,
+    created_at: String,
+}
+
+impl From<LoginFailureRow> for LoginFailure {
+    fn from(row: LoginFailureRow) -> Self {
+        LoginFailure {
+            id: row.id,
+            email: row.email,
+            user: row.user,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            reason: row.reason,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepo for SqlStore {
+    async fn throttle(&self, key: &str) -> Result<Option<Throttle>> {
+        let row: Option<(i64, i64)> = sqlx::query_as(
+            r#"SELECT "failures", "lastFailureAt" FROM "login_throttles" WHERE "key"=$1"#,
+        )
+        .bind(key)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(|(failures, last_failure_at)| Throttle {
+            failures,
+            last_failure_at,
+        }))
+    }
+
+    async fn fail(&self, key: &str, now: i64, forget_before: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO "login_throttles" ("key", "failures", "lastFailureAt")
+                VALUES ($1, 1, $2)
+                ON CONFLICT ("key") DO UPDATE SET
+                    "failures"=CASE
+                        WHEN "login_throttles"."lastFailureAt"<$3 THEN 1
+                        ELSE "login_throttles"."failures"+1
+                    END,
+                    "lastFailureAt"=$2
+            "#,
+        )
+        .bind(key)
+        .bind(now)
+        .bind(forget_before)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn attempt(
+        &self,
+        key: &str,
+        seen: Option<Throttle>,
+        now: i64,
+        forget_before: i64,
+    ) -> Result<bool> {
+        let result = match seen {
+            None => {
+                sqlx::query(
+                    r#"
+                        INSERT INTO "login_throttles" ("key", "failures", "lastFailureAt")
+                        VALUES ($1, 1, $2)
+                        ON CONFLICT ("key") DO NOTHING
+                    "#,
+                )
+                .bind(key)
+                .bind(now)
+                .execute(&self.db)
+                .await?
+            }
+            Some(seen) => {
+                sqlx::query(
+                    r#"
+                        UPDATE "login_throttles" SET
+                            "failures"=CASE WHEN "lastFailureAt"<$2 THEN 1 ELSE "failures"+1 END,
+                            "lastFailureAt"=$3
+                        WHERE "key"=$1 AND "failures"=$4 AND "lastFailureAt"=$5
+                    "#,
+                )
+                .bind(key)
+                .bind(forget_before)
+                .bind(now)
+                .bind(seen.failures)
+                .bind(seen.last_failure_at)
+                .execute(&self.db)
+                .await?
+            }
+        };
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn forgive(&self, key: &str) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(r#"DELETE FROM "login_throttles" WHERE "key"=$1 AND "failures"<=1"#)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"UPDATE "login_throttles" SET "failures"="failures"-1 WHERE "key"=$1"#)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn reset(&self, key: &str) -> Result<()> {
+        sqlx::query(r#"DELETE FROM "login_throttles" WHERE "key"=$1"#)
+            .bind(key)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn record(&self, failure: NewLoginFailure) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO "login_failures" ("email", "user", "ip", "userAgent", "reason")
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(failure.email)
+        .bind(failure.user)
+        .bind(failure.ip)
+        .bind(failure.user_agent)
+        .bind(failure.reason)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn failures(&self, limit: i64) -> Result<Vec<LoginFailure>> {
+        let rows = sqlx::query_as::<_, LoginFailureRow>(&format!(
+            r#"
+                SELECT "id", "email", "user", "ip", "userAgent", "reason",
+                    {} AS "createdAt"
+                FROM "login_failures"
+                ORDER BY "id" DESC
+                LIMIT $1
+            "#,
+            timestamp(r#""createdAt""#)
+        ))
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(LoginFailure::from).collect())
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct TwoFactorRow {
+    user: i64,
+    secret: String,
+    enabled: bool,
+    last_step: Option<i64>,
+}
+
+impl From<TwoFactorRow> for TwoFactor {
+    fn from(row: TwoFactorRow) -> Self {
+        TwoFactor {
+            user: row.user,
+            secret: row.secret,
+            enabled: row.enabled,
+            last_step: row.last_step,
+        }
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepo for SqlStore {
+    async fn setup(&self, user: i64, secret: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+                INSERT INTO "two_factor" ("user", "secret", "enabled")
+                VALUES ($1, $2, FALSE)
+                ON CONFLICT ("user") DO UPDATE SET "secret"=$2, "lastStep"=NULL
+                WHERE NOT "two_factor"."enabled"
+            "#,
+        )
+        .bind(user)
+        .bind(secret)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find(&self, user: i64) -> Result<Option<TwoFactor>> {
+        let row = sqlx::query_as::<_, TwoFactorRow>(
+            r#"SELECT "user", "secret", "enabled", "lastStep" FROM "two_factor" WHERE "user"=$1"#,
+        )
+        .bind(user)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(TwoFactor::from))
+    }
+
+    async fn enable(&self, user: i64, step: i64, recovery_hashes: &[String]) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(r#"UPDATE "two_factor" SET "enabled"=TRUE, "lastStep"=$1 WHERE "user"=$2"#)
+            .bind(step)
+            .bind(user)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM "recovery_codes" WHERE "user"=$1"#)
+            .bind(user)
+            .execute(&mut *tx)
+            .await?;
+
+        for hash in recovery_hashes {
+            sqlx::query(r#"INSERT INTO "recovery_codes" ("user", "hash") VALUES ($1, $2)"#)
+                .bind(user)
+                .bind(hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn use_step(&self, user: i64, step: i64) -> Result<bool> {
+        // Only one of several requests with the same code gets through
+        let result = sqlx::query(
+            r#"
+                UPDATE "two_factor" SET "lastStep"=$1
+                WHERE "user"=$2 AND ("lastStep" IS NULL OR "lastStep"<$1)
+            "#,
+        )
+        .bind(step)
+        .bind(user)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn use_recovery_code(&self, user: i64, hash: &str) -> Result<bool> {
+        let result = sqlx::query(r#"DELETE FROM "recovery_codes" WHERE "user"=$1 AND "hash"=$2"#)
+            .bind(user)
+            .bind(hash)
+            .execute(&self.db)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn recovery_codes_left(&self, user: i64) -> Result<i64> {
+        let count = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "recovery_codes" WHERE "user"=$1"#)
+            .bind(user)
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn disable(&self, user: i64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        for sql in [
+            r#"DELETE FROM "recovery_codes" WHERE "user"=$1"#,
+            r#"DELETE FROM "two_factor" WHERE "user"=$1"#,
+        ] {
+            sqlx::query(sql).bind(user).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow)]
+#[sqlx(rename_all = "camelCase")]
+struct PendingLoginRow {
+    hash: String,
+    provider: String,
+    verifier: String,
+    nonce: String,
+    expires_at: i64,
+}
+
+impl From<PendingLoginRow> for PendingLogin {
+    fn from(row: PendingLoginRow) -> Self {
+        PendingLogin {
+            hash: row.hash,
+            provider: row.provider,
+            verifier: row.verifier,
+            nonce: row.nonce,
+            expires_at: row.expires_at,
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityRepo for SqlStore {
+    async fn find(&self, provider: &str, subject: &str) -> Result<Option<i64>> {
+        let user = sqlx::query_scalar(
+            r#"SELECT "user" FROM "identities" WHERE "provider"=$1 AND "subject"=$2"#,
+        )
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn link(&self, user: i64, provider: &str, subject: &str) -> Result<()> {
+        sqlx::query(
+            r#"INSERT INTO "identities" ("user", "provider", "subject") VALUES ($1, $2, $3)"#,
+        )
+        .bind(user)
+        .bind(provider)
+        .bind(subject)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn start_login(&self, login: PendingLogin, now: i64) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(r#"DELETE FROM "oidc_logins" WHERE "expiresAt"<$1"#)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+                INSERT INTO "oidc_logins" ("hash", "provider", "verifier", "nonce", "expiresAt")
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(login.hash)
+        .bind(login.provider)
+        .bind(login.verifier)
+        .bind(login.nonce)
+        .bind(login.expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn finish_login(&self, hash: &str, now: i64) -> Result<Option<PendingLogin>> {
+        let login = sqlx::query_as::<_, PendingLoginRow>(
+            r#"
+                DELETE FROM "oidc_logins" WHERE "hash"=$1
+                RETURNING "hash", "provider", "verifier", "nonce", "expiresAt"
+            "#,
+        )
+        .bind(hash)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(login
+            .map(PendingLogin::from)
+            .filter(|login| login.expires_at >= now))
+    }
+}
+
+#[async_trait]
+impl OneTimeTokenRepo for SqlStore {
+    async fn create(
+        &self,
+        user: i64,
+        purpose: TokenPurpose,
+        hash: &str,
+        expires_at: i64,
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(r#"DELETE FROM "one_time_tokens" WHERE "user"=$1 AND "purpose"=$2"#)
+            .bind(user)
+            .bind(purpose)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+                INSERT INTO "one_time_tokens"
+                ("user", "purpose", "hash", "expiresAt")
+                VALUES
+                ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(user)
+        .bind(purpose)
+        .bind(hash)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn consume(&self, purpose: TokenPurpose, hash: &str, now: i64) -> Result<Option<i64>> {
+        let token: Option<(i64, i64)> = sqlx::query_as(
+            r#"
+                DELETE FROM "one_time_tokens"
+                WHERE "purpose"=$1 AND "hash"=$2
+                RETURNING "user", "expiresAt"
+            "#,
+        )
+        .bind(purpose)
+        .bind(hash)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(token
+            .filter(|&(_, expires_at)| expires_at >= now)
+            .map(|(user, _)| user))
+    }
+}
@@ -0,0 +1,557 @@
+//! Logins which outlast the access tokens issued for them
+//!
+//! Logging in starts a session and returns an access token together with a
+//! refresh token. Access tokens expire after `--access-token-ttl` seconds,
+//! `POST /api/users/refresh` trades the refresh token for a new pair. A
+//! session ends when its user logs out or when it was not refreshed for
+//! `--refresh-token-ttl` seconds, which also invalidates its access tokens.
+//!
+//! Users see their sessions together with the client which started them at
+//! `GET /api/user/sessions` and can end any or all of them.
+//!
+//! With `--cookie-sessions`, browsers get the tokens as `HttpOnly` cookies
+//! instead, which scripts cannot read. Since browsers send cookies along with
+//! requests which other sites make them send, requests authenticated by the
+//! cookie which change anything have to repeat the value of the CSRF cookie
+//! in the `X-CSRF-Token` header, which only scripts of the site can read.
+
+use crate::{
+    auth::{logged_in, CurrentSession, ResponseUser},
+    database::User,
+    errors,
+    repo::{self, NewSession, Session},
+    token::{create_token, hash_token, now, random_token},
+    AppState,
+};
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Path, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Seconds between updates of when a session was last used, so that not
+/// every request has to write to the database
+pub(crate) const TOUCH_INTERVAL: i64 = 60;
+
+/// User agents are cut off after this many characters
+const MAX_USER_AGENT: usize = 255;
+
+/// The cookie with the access token in cookie mode
+pub(crate) const TOKEN_COOKIE: &str = "realworld_token";
+
+/// The cookie with the refresh token in cookie mode, which is only sent to
+/// `POST /api/users/refresh`
+const REFRESH_COOKIE: &str = "realworld_refresh";
+
+/// The cookie with the CSRF token in cookie mode, which is not `HttpOnly`
+const CSRF_COOKIE: &str = "realworld_csrf";
+
+/// The header which has to repeat the CSRF cookie
+pub(crate) const CSRF_HEADER: &str = "x-csrf-token";
+
+const REFRESH_PATH: &str = "/api/users/refresh";
+
+/// `Set-Cookie` headers of a response, none unless in cookie mode
+pub(crate) type Cookies = AppendHeaders<Vec<(HeaderName, String)>>;
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "sessions")]
+pub struct Config {
+    /// Seconds an access token is valid
+    #[arg(
+        long = "access-token-ttl",
+        env = "ACCESS_TOKEN_TTL",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(i64).range(1..),
+        default_value_t = 15 * 60
+    )]
+    pub access_ttl: i64,
+
+    /// Seconds a session lasts without being refreshed
+    #[arg(
+        long = "refresh-token-ttl",
+        env = "REFRESH_TOKEN_TTL",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(i64).range(1..),
+        default_value_t = 30 * 24 * 60 * 60
+    )]
+    pub refresh_ttl: i64,
+
+    /// Take the address of clients from the last entry of `X-Forwarded-For`,
+    /// which only a reverse proxy in front of the server may set
+    #[arg(long = "trust-proxy", env = "TRUST_PROXY")]
+    pub trust_proxy: bool,
+
+    /// Set the tokens of sessions as cookies instead of returning them, and
+    /// accept the access token from its cookie
+    #[arg(long = "cookie-sessions", env = "COOKIE_SESSIONS")]
+    pub cookies: bool,
+
+    /// The `SameSite` attribute of the cookies
+    #[arg(
+        long = "cookie-same-site",
+        env = "COOKIE_SAME_SITE",
+        value_enum,
+        default_value_t = SameSite::Lax
+    )]
+    pub same_site: SameSite,
+
+    /// Leave out the `Secure` attribute of the cookies, so that browsers send
+    /// them over plain HTTP during development
+    #[arg(long = "insecure-cookies", env = "INSECURE_COOKIES")]
+    pub insecure_cookies: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SameSite {
+    /// Cookies are only sent with requests from the site itself
+    Strict,
+    /// Cookies are also sent when following links from other sites
+    Lax,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+        }
+    }
+}
+
+impl Config {
+    fn cookie(&self, name: &str, value: &str, path: &str, max_age: i64, http_only: bool) -> String {
+        let mut cookie = format!(
+            "{}={}; Path={}; Max-Age={}; SameSite={}",
+            name, value, path, max_age, self.same_site
+        );
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        if !self.insecure_cookies {
+            cookie.push_str("; Secure");
+        }
+
+        cookie
+    }
+
+    /// The cookies of a session with these tokens
+    fn set_cookies(&self, token: &str, refresh_token: &str) -> Cookies {
+        AppendHeaders(vec![
+            (
+                header::SET_COOKIE,
+                self.cookie(TOKEN_COOKIE, token, "/", self.access_ttl, true),
+            ),
+            (
+                header::SET_COOKIE,
+                self.cookie(CSRF_COOKIE, &csrf_token(token), "/", self.access_ttl, false),
+            ),
+            (
+                header::SET_COOKIE,
+                self.cookie(
+                    REFRESH_COOKIE,
+                    refresh_token,
+                    REFRESH_PATH,
+                    self.refresh_ttl,
+                    true,
+                ),
+            ),
+        ])
+    }
+
+    /// Cookies which remove those of a session, none unless in cookie mode
+    pub(crate) fn clear_cookies(&self) -> Cookies {
+        if !self.cookies {
+            return AppendHeaders(Vec::new());
+        }
+
+        AppendHeaders(
+            [
+                (TOKEN_COOKIE, "/"),
+                (CSRF_COOKIE, "/"),
+                (REFRESH_COOKIE, REFRESH_PATH),
+            ]
+            .into_iter()
+            .map(|(name, path)| (header::SET_COOKIE, self.cookie(name, "", path, 0, true)))
+            .collect(),
+        )
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            access_ttl: 15 * 60,
+            refresh_ttl: 30 * 24 * 60 * 60,
+            trust_proxy: false,
+            cookies: false,
+            same_site: SameSite::Lax,
+            insecure_cookies: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The refresh token is unknown, was already used or its session ended
+    InvalidToken,
+    /// The user has no session with this `id`
+    NotFound,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidToken => write!(f, "invalid or expired refresh token"),
+            Error::NotFound => write!(f, "no such session"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::InvalidToken => StatusCode::UNAUTHORIZED,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// What is recorded about the client which starts a session
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl FromRequestParts<Arc<AppState>> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT).collect());
+        // Every proxy appends the address it got the request from, so only
+        // the last one was not made up by the client
+        let forwarded = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .next_back()
+            .filter(|_| state.sessions.trust_proxy)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .map(|ip| ip.trim().to_owned());
+        // Only known when the server runs with connection info, unlike in
+        // the tests
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip().to_string());
+
+        Ok(Client {
+            user_agent,
+            ip: forwarded.or(peer),
+        })
+    }
+}
+
+/// Start a session for `user`, returns its access token and refresh token
+pub(crate) async fn start(
+    app: &AppState,
+    user: &User,
+    client: Client,
+) -> repo::Result<(String, String)> {
+    let refresh_token = random_token();
+    let session = app
+        .repos
+        .sessions
+        .create(NewSession {
+            user: user.id,
+            refresh_hash: hash_token(&refresh_token),
+            expires_at: now() + app.sessions.refresh_ttl,
+            user_agent: client.user_agent,
+            ip: client.ip,
+        })
+        .await?;
+    let token = create_token(
+        &app.keys,
+        user.id,
+        user.token_version,
+        session.id,
+        app.sessions.access_ttl,
+    );
+
+    Ok((token, refresh_token))
+}
+
+/// The response to starting or refreshing a session of `user`, which only
+/// has the tokens as cookies in cookie mode
+pub(crate) fn issue(
+    app: &AppState,
+    user: User,
+    token: String,
+    refresh_token: String,
+) -> (Cookies, ResponseUser) {
+    if !app.sessions.cookies {
+        return (
+            AppendHeaders(Vec::new()),
+            logged_in(user, token, Some(refresh_token)),
+        );
+    }
+
+    let cookies = app.sessions.set_cookies(&token, &refresh_token);
+
+    (cookies, logged_in(user, String::new(), None))
+}
+
+/// The CSRF token which goes with the access token `token`, which cannot be
+/// made up without knowing the access token
+pub(crate) fn csrf_token(token: &str) -> String {
+    hash_token(&format!("csrf:{}", token))
+}
+
+/// The value of the cookie `name` sent with a request
+pub(crate) fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Record that `session` was used at `now`, unless that was done recently
+pub(crate) async fn touch(app: &AppState, session: &Session, now: i64) -> repo::Result<()> {
+    if now - session.last_used_at >= TOUCH_INTERVAL {
+        app.repos.sessions.touch(session.id, now).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Refresh {
+    /// From logging in or the last refresh, taken from its cookie in cookie
+    /// mode if left out
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+/// Trade a refresh token for a new access token and refresh token
+///
+/// Every refresh token works once, the session is extended each time.
+#[utoipa::path(
+    post,
+    path = "/api/users/refresh",
+    tag = "users",
+    request_body = Refresh,
+    responses(
+        (status = 200, description = "The user with fresh tokens", body = ResponseUser),
+        (status = 401, description = "The refresh token is invalid or its session ended"),
+    ),
+)]
+pub async fn refresh(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(refresh): Json<Refresh>,
+) -> Result<(Cookies, Json<ResponseUser>), Error> {
+    let old_token = refresh
+        .refresh_token
+        .as_deref()
+        .or_else(|| cookie(&headers, REFRESH_COOKIE).filter(|_| app.sessions.cookies))
+        .ok_or(Error::InvalidToken)?;
+    let refresh_token = random_token();
+    let now = now();
+    let session = app
+        .repos
+        .sessions
+        .refresh(
+            &hash_token(old_token),
+            &hash_token(&refresh_token),
+            now,
+            now + app.sessions.refresh_ttl,
+        )
+        .await?
+        .ok_or(Error::InvalidToken)?;
+    let user = app
+        .repos
+        .users
+        .find(session.user)
+        .await?
+        .ok_or(Error::InvalidToken)?;
+    let token = create_token(
+        &app.keys,
+        user.id,
+        user.token_version,
+        session.id,
+        app.sessions.access_ttl,
+    );
+
+    let (cookies, response) = issue(&app, user, token, refresh_token);
+
+    Ok((cookies, Json(response)))
+}
+
+/// End the session of the access token
+///
+/// Its access tokens and its refresh token stop working, in cookie mode the
+/// cookies are removed.
+#[utoipa::path(
+    post,
+    path = "/api/users/logout",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The session ended"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn logout(
+    State(app): State<Arc<AppState>>,
+    CurrentSession { session_id, .. }: CurrentSession,
+) -> Result<Cookies, Error> {
+    app.repos.sessions.delete(session_id).await?;
+
+    Ok(app.sessions.clear_cookies())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseSessionBody {
+    id: i64,
+    created_at: String,
+    /// Seconds since the epoch, updated at most once a minute
+    last_used_at: i64,
+    /// Seconds since the epoch, unless the session is refreshed
+    expires_at: i64,
+    /// Of the client which logged in
+    user_agent: Option<String>,
+    /// Of the client which logged in
+    ip: Option<String>,
+    /// Whether the request was made with a token of this session
+    current: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseMultipleSessions {
+    sessions: Vec<ResponseSessionBody>,
+}
+
+/// The sessions of the authenticated user, the most recently used first
+#[utoipa::path(
+    get,
+    path = "/api/user/sessions",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The sessions", body = ResponseMultipleSessions),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn list_sessions(
+    State(app): State<Arc<AppState>>,
+    CurrentSession {
+        user_id,
+        session_id,
+    }: CurrentSession,
+) -> Result<Json<ResponseMultipleSessions>, Error> {
+    let sessions = app.repos.sessions.list(user_id, now()).await?;
+
+    Ok(Json(ResponseMultipleSessions {
+        sessions: sessions
+            .into_iter()
+            .map(|session| ResponseSessionBody {
+                current: session.id == session_id,
+                id: session.id,
+                created_at: session.created_at,
+                last_used_at: session.last_used_at,
+                expires_at: session.expires_at,
+                user_agent: session.user_agent,
+                ip: session.ip,
+            })
+            .collect(),
+    }))
+}
+
+/// End a session of the authenticated user
+#[utoipa::path(
+    delete,
+    path = "/api/user/sessions/{id}",
+    tag = "users",
+    params(("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The session ended"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The user has no such session"),
+    ),
+)]
+pub async fn delete_session(
+    State(app): State<Arc<AppState>>,
+    CurrentSession { user_id, .. }: CurrentSession,
+    Path(id): Path<i64>,
+) -> Result<(), Error> {
+    let session = app
+        .repos
+        .sessions
+        .find(id, now())
+        .await?
+        .filter(|session| session.user == user_id)
+        .ok_or(Error::NotFound)?;
+
+    app.repos.sessions.delete(session.id).await?;
+
+    Ok(())
+}
+
+/// Log out everywhere
+///
+/// Every session of the authenticated user ends, including the current one,
+/// in cookie mode the cookies are removed.
+#[utoipa::path(
+    delete,
+    path = "/api/user/sessions",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "All sessions ended"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn delete_all_sessions(
+    State(app): State<Arc<AppState>>,
+    CurrentSession { user_id, .. }: CurrentSession,
+) -> Result<Cookies, Error> {
+    app.repos.sessions.delete_all(user_id).await?;
+
+    Ok(app.sessions.clear_cookies())
+}
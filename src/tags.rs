@@ -2,22 +2,22 @@ use crate::AppState;
 use axum::{extract::State, Json};
 use serde::Serialize;
 use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ResponseTagList {
     tags: Vec<String>,
 }
 
+/// All tags
+#[utoipa::path(
+    get,
+    path = "/api/tags",
+    tag = "tags",
+    responses((status = 200, description = "Every tag in use", body = ResponseTagList)),
+)]
 pub async fn get_tags(State(app): State<Arc<AppState>>) -> Json<ResponseTagList> {
     Json(ResponseTagList {
-        tags: sqlx::query_scalar(
-            "
-            SELECT `name`
-            FROM `tags`
-        ",
-        )
-        .fetch_all(&app.db)
-        .await
-        .unwrap(),
+        tags: app.repos.tags.list().await.unwrap(),
     })
 }
@@ -1,38 +1,119 @@
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use crate::keys::KeyRing;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
 use serde::{Deserialize, Serialize};
-
-const SECRET: &[u8] = b"nuclear launch codes";
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Our claims struct, it needs to derive `Serialize` and/or `Deserialize`
 #[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    exp: usize,
-    user_id: i64,
+pub struct Claims {
+    /// Seconds since the epoch
+    pub exp: usize,
+    pub user_id: i64,
+    /// Has to match the token version of the user, tokens from before
+    /// versions existed have version 0
+    #[serde(default)]
+    pub version: i64,
+    /// The session the token was issued for, tokens from before sessions
+    /// existed have session 0, which never exists
+    #[serde(default)]
+    pub session: i64,
 }
 
-pub fn create_token(user_id: i64) -> String {
+/// An access token for the session which expires after `ttl` seconds,
+/// signed with the signing key of `keys`
+pub fn create_token(keys: &KeyRing, user_id: i64, version: i64, session: i64, ttl: i64) -> String {
     let my_claims = Claims {
-        exp: 10000000000,
-        user_id: user_id,
+        exp: (now() + ttl) as usize,
+        user_id,
+        version,
+        session,
+    };
+    let key = keys.signing_key();
+    let header = Header {
+        kid: Some(key.kid.clone()),
+        ..Header::new(key.algorithm)
     };
 
-    let token = encode(
-        &Header::new(Algorithm::HS256),
-        &my_claims,
-        &EncodingKey::from_secret(SECRET),
-    )
-    .unwrap();
+    // The signing key of a key ring always has its private half
+    encode(&header, &my_claims, key.encoding.as_ref().unwrap()).unwrap()
+}
+
+/// Why a token is not accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    /// The token was valid, but is past its `exp`
+    Expired,
+    /// The token was not signed by this server
+    BadSignature,
+    /// The token is not a JWT or lacks our claims
+    Malformed,
+    /// The `kid` of the token is none of our keys
+    UnknownKey,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Expired => write!(f, "the token has expired"),
+            TokenError::BadSignature => write!(f, "the token has an invalid signature"),
+            TokenError::Malformed => write!(f, "the token is malformed"),
+            TokenError::UnknownKey => write!(f, "the token was signed with an unknown key"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
 
-    token
+/// The claims of the token, if it was signed with one of `keys` and has not
+/// expired yet
+pub fn authenticate(keys: &KeyRing, token: &str) -> Result<Claims, TokenError> {
+    let header = decode_header(token).map_err(|_| TokenError::Malformed)?;
+    let key = keys
+        .find(header.kid.as_deref())
+        .ok_or(TokenError::UnknownKey)?;
+    // Tokens have to use the algorithm of their key, or else a public key
+    // could pass for an HMAC secret
+    let mut validation = Validation::new(key.algorithm);
+    // Only this server issues tokens, so there is no clock skew to allow for
+    validation.leeway = 0;
+
+    match decode::<Claims>(token, &key.decoding, &validation) {
+        Ok(token) => Ok(token.claims),
+        // The signature is checked before the expiry, so forged tokens
+        // never count as expired
+        Err(error) => Err(match error.kind() {
+            ErrorKind::ExpiredSignature => TokenError::Expired,
+            ErrorKind::InvalidSignature | ErrorKind::InvalidAlgorithm => TokenError::BadSignature,
+            _ => TokenError::Malformed,
+        }),
+    }
+}
+
+/// A random token to send to a user, e.g. to verify their email address
+pub fn random_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+
+    hex(&bytes)
 }
 
-pub fn authenticate(token: &str) -> i64 {
-    let token = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(SECRET),
-        &Validation::new(Algorithm::HS256),
-    )
-    .unwrap();
+/// What is stored of a token from [`random_token`], so that the tokens
+/// themselves are useless to anyone who can read the database
+pub fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Seconds since the epoch, which is what token expiry is measured in
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
 
-    token.claims.user_id
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
@@ -0,0 +1,464 @@
+//! Two-factor authentication with time-based one-time passwords, see RFC 6238
+//!
+//! `POST /api/user/2fa/setup` creates a secret for an authenticator app,
+//! which `POST /api/user/2fa/confirm` enables with a first code from the app.
+//! That returns recovery codes, each of which works once instead of a code
+//! when the app is lost. From then on, logging in with the password only
+//! returns a short-lived challenge token, and `POST /api/users/login/2fa`
+//! trades it and a code for the tokens of a session. Every code works once.
+
+use crate::{
+    auth::{Auth, ResponseUser},
+    errors,
+    http_client::percent_encode,
+    lockout,
+    repo::{self, TokenPurpose},
+    sessions::{self, Client, Cookies},
+    token::{hash_token, now, random_token},
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Shown by authenticator apps next to the codes
+const ISSUER: &str = "RealWorld";
+
+/// Seconds each code is valid for
+const STEP: i64 = 30;
+
+/// Digits of each code
+const DIGITS: u32 = 6;
+
+/// Codes of this many steps before or after the current one are accepted as
+/// well, to allow for clocks which are a little off
+const SKEW: i64 = 1;
+
+/// Bytes of each secret, the length of an HMAC-SHA1 key recommended by
+/// RFC 4226
+const SECRET_BYTES: usize = 20;
+
+/// Recovery codes issued when two-factor authentication is enabled
+const RECOVERY_CODES: usize = 10;
+
+/// Seconds a challenge token from logging in stays valid
+const CHALLENGE_LIFETIME: i64 = 5 * 60;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Two-factor authentication is enabled already and has to be disabled
+    /// before it can be set up again
+    AlreadyEnabled,
+    /// There is no secret to confirm, or nothing to disable
+    NotSetUp,
+    /// The code is wrong or was used before
+    InvalidCode,
+    /// The challenge token is unknown, was already used or has expired
+    InvalidChallenge,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AlreadyEnabled => write!(f, "two-factor authentication is enabled already"),
+            Error::NotSetUp => write!(f, "two-factor authentication is not set up"),
+            Error::InvalidCode => write!(f, "invalid code"),
+            Error::InvalidChallenge => write!(f, "invalid or expired challenge, log in again"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::AlreadyEnabled | Error::NotSetUp => StatusCode::CONFLICT,
+            Error::InvalidCode => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::InvalidChallenge => StatusCode::UNAUTHORIZED,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// `bytes` in the base32 alphabet of RFC 4648, without padding, which is what
+/// authenticator apps expect
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+
+    encoded
+}
+
+/// The bytes of base32 `encoded`, `None` if it is not base32
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for char in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32
+            .iter()
+            .position(|&other| other == char.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+/// The code for `secret` at time `step`, see RFC 4226
+fn code(secret: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let hash = hmac::sign(&key, &step.to_be_bytes());
+    let hash = hash.as_ref();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// The time step of the code among those accepted at `now` which is `code`
+fn matching_step(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let secret = base32_decode(secret)?;
+    let current = now / STEP;
+
+    (current - SKEW..=current + SKEW).find(|&step| self::code(&secret, step) == code)
+}
+
+/// What is stored of a recovery code, which is accepted with any case,
+/// dashes and spaces
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|char| *char != '-' && !char.is_whitespace())
+        .map(|char| char.to_ascii_lowercase())
+        .collect();
+
+    hash_token(&normalized)
+}
+
+/// Whether `code` is a code of the enabled second factor of `user_id` or one
+/// of their recovery codes, either of which is used up by this
+async fn verify(app: &AppState, user_id: i64, code: &str) -> repo::Result<bool> {
+    let Some(two_factor) = app.repos.two_factor.find(user_id).await? else {
+        return Ok(false);
+    };
+
+    if !two_factor.enabled {
+        return Ok(false);
+    }
+
+    let code = code.trim();
+    if code.len() == DIGITS as usize && code.bytes().all(|byte| byte.is_ascii_digit()) {
+        return match matching_step(&two_factor.secret, code, now()) {
+            Some(step) => app.repos.two_factor.use_step(user_id, step).await,
+            None => Ok(false),
+        };
+    }
+
+    app.repos
+        .two_factor
+        .use_recovery_code(user_id, &hash_recovery_code(code))
+        .await
+}
+
+/// Whether logging in as `user_id` takes a second factor
+pub(crate) async fn enabled(app: &AppState, user_id: i64) -> repo::Result<bool> {
+    Ok(app
+        .repos
+        .two_factor
+        .find(user_id)
+        .await?
+        .is_some_and(|two_factor| two_factor.enabled))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseChallengeBody {
+    /// Has to be sent to `POST /api/users/login/2fa` with a code
+    token: String,
+    /// Seconds since the epoch
+    expires_at: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseChallenge {
+    challenge: ResponseChallengeBody,
+}
+
+/// A challenge token for `user_id`, who entered their password but still has
+/// to enter a code
+pub(crate) async fn challenge(app: &AppState, user_id: i64) -> repo::Result<ResponseChallenge> {
+    let token = random_token();
+    let expires_at = now() + CHALLENGE_LIFETIME;
+
+    app.repos
+        .one_time_tokens
+        .create(
+            user_id,
+            TokenPurpose::TwoFactor,
+            &hash_token(&token),
+            expires_at,
+        )
+        .await?;
+
+    Ok(ResponseChallenge {
+        challenge: ResponseChallengeBody { token, expires_at },
+    })
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseSetup {
+    /// Base32 encoded, for entering it into an authenticator app by hand
+    secret: String,
+    /// The secret as an `otpauth://` URI, usually shown as a QR code
+    otpauth_uri: String,
+}
+
+/// Create a new secret for two-factor authentication
+///
+/// It takes confirming a code for the secret before logging in requires one.
+/// Setting up again before that replaces the secret.
+#[utoipa::path(
+    post,
+    path = "/api/user/2fa/setup",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The new secret", body = ResponseSetup),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "Two-factor authentication is enabled already"),
+    ),
+)]
+pub async fn setup_two_factor(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<Json<ResponseSetup>, Error> {
+    if enabled(&app, user_id).await? {
+        return Err(Error::AlreadyEnabled);
+    }
+
+    let user = app.repos.users.find(user_id).await?.unwrap();
+    let secret = base32_encode(&rand::random::<[u8; SECRET_BYTES]>());
+
+    app.repos.two_factor.setup(user_id, &secret).await?;
+
+    let otpauth_uri = format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(ISSUER),
+        percent_encode(&user.email),
+        secret,
+        percent_encode(ISSUER),
+        DIGITS,
+        STEP
+    );
+
+    Ok(Json(ResponseSetup {
+        secret,
+        otpauth_uri,
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RequestCode {
+    /// From the authenticator app, or a recovery code where that is accepted
+    code: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseRecoveryCodes {
+    /// Each works once instead of a code, they are not shown again
+    recovery_codes: Vec<String>,
+}
+
+/// Enable two-factor authentication with a code for the new secret
+#[utoipa::path(
+    post,
+    path = "/api/user/2fa/confirm",
+    tag = "users",
+    request_body = RequestCode,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "Enabled, with recovery codes", body = ResponseRecoveryCodes),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "There is no secret to confirm or it is enabled already"),
+        (status = 422, description = "The code is wrong"),
+    ),
+)]
+pub async fn confirm_two_factor(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Json(request): Json<RequestCode>,
+) -> Result<Json<ResponseRecoveryCodes>, Error> {
+    let two_factor = app
+        .repos
+        .two_factor
+        .find(user_id)
+        .await?
+        .ok_or(Error::NotSetUp)?;
+
+    if two_factor.enabled {
+        return Err(Error::AlreadyEnabled);
+    }
+
+    let step =
+        matching_step(&two_factor.secret, request.code.trim(), now()).ok_or(Error::InvalidCode)?;
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = random_token();
+
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+
+    app.repos.two_factor.enable(user_id, step, &hashes).await?;
+
+    Ok(Json(ResponseRecoveryCodes { recovery_codes }))
+}
+
+/// Disable two-factor authentication, which takes a code or a recovery code
+#[utoipa::path(
+    post,
+    path = "/api/user/2fa/disable",
+    tag = "users",
+    request_body = RequestCode,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "Disabled, the secret and recovery codes are deleted"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "Two-factor authentication is not enabled"),
+        (status = 422, description = "The code is wrong"),
+    ),
+)]
+pub async fn disable_two_factor(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Json(request): Json<RequestCode>,
+) -> Result<(), Error> {
+    if !enabled(&app, user_id).await? {
+        return Err(Error::NotSetUp);
+    }
+
+    if !verify(&app, user_id, &request.code).await? {
+        return Err(Error::InvalidCode);
+    }
+
+    app.repos.two_factor.disable(user_id).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondFactor {
+    /// From logging in with the password
+    challenge_token: String,
+    /// From the authenticator app, or a recovery code
+    code: String,
+}
+
+/// Finish logging in with a code
+///
+/// The challenge token works once, after a wrong code the password has to be
+/// entered again. Wrong codes count as failed logins.
+#[utoipa::path(
+    post,
+    path = "/api/users/login/2fa",
+    tag = "users",
+    request_body = SecondFactor,
+    responses(
+        (status = 200, description = "The user with the tokens of a new session", body = ResponseUser),
+        (status = 401, description = "The challenge token is invalid or expired, or the code is wrong"),
+    ),
+)]
+pub async fn login_two_factor(
+    State(app): State<Arc<AppState>>,
+    client: Client,
+    Json(request): Json<SecondFactor>,
+) -> Result<(Cookies, Json<ResponseUser>), Error> {
+    let user_id = app
+        .repos
+        .one_time_tokens
+        .consume(
+            TokenPurpose::TwoFactor,
+            &hash_token(&request.challenge_token),
+            now(),
+        )
+        .await?
+        .ok_or(Error::InvalidChallenge)?;
+    let user = app
+        .repos
+        .users
+        .find(user_id)
+        .await?
+        .ok_or(Error::InvalidChallenge)?;
+
+    if !verify(&app, user_id, &request.code).await? {
+        lockout::second_factor_failed(&app, &client, &user).await?;
+
+        return Err(Error::InvalidChallenge);
+    }
+
+    lockout::succeeded(&app, &user).await?;
+    let (token, refresh_token) = sessions::start(&app, &user, client).await?;
+    let (cookies, response) = sessions::issue(&app, user, token, refresh_token);
+
+    Ok((cookies, Json(response)))
+}
@@ -0,0 +1,186 @@
+//! Confirming that users own their email address
+//!
+//! Registration mails a token to the new address, which the user hands back
+//! to `POST /api/users/verify`. Unverified users can still log in, but the
+//! server can be configured to keep them from writing articles and comments.
+
+use crate::{
+    auth::Auth,
+    database::User,
+    errors,
+    mail::{self, Message},
+    repo::{self, TokenPurpose},
+    token::{hash_token, now, random_token},
+    AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Seconds a verification token stays valid
+const TOKEN_LIFETIME: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Default, clap::Args)]
+#[group(id = "verification")]
+pub struct Config {
+    /// Only let users who verified their email address write articles and
+    /// comments
+    #[arg(long = "require-verified-email", env = "REQUIRE_VERIFIED_EMAIL")]
+    pub required: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The token is unknown, was already used or has expired
+    InvalidToken,
+    AlreadyVerified,
+    Repo(repo::Error),
+    Mail(mail::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidToken => write!(f, "invalid or expired verification token"),
+            Error::AlreadyVerified => write!(f, "email address is already verified"),
+            Error::Repo(error) => write!(f, "{}", error),
+            Error::Mail(error) => write!(f, "could not send mail: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl From<mail::Error> for Error {
+    fn from(error: mail::Error) -> Self {
+        Error::Mail(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::InvalidToken => StatusCode::BAD_REQUEST,
+            Error::AlreadyVerified => StatusCode::CONFLICT,
+            Error::Repo(_) | Error::Mail(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// Mail a new verification token to `user`, earlier ones stop working
+pub(crate) async fn send_token(app: &AppState, user: &User) -> Result<(), Error> {
+    let token = random_token();
+
+    app.repos
+        .one_time_tokens
+        .create(
+            user.id,
+            TokenPurpose::VerifyEmail,
+            &hash_token(&token),
+            now() + TOKEN_LIFETIME,
+        )
+        .await?;
+
+    app.mailer
+        .send(Message {
+            to: user.email.clone(),
+            subject: String::from("Confirm your email address"),
+            body: format!(
+                "Hi {},\n\n\
+                please confirm your email address with this token:\n\n\
+                {}\n\n\
+                It is valid for 24 hours.\n",
+                user.username, token
+            ),
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Like [`send_token`] for handlers which must not fail because of the mail
+pub(crate) async fn try_send_token(app: &AppState, user: &User) {
+    if let Err(error) = send_token(app, user).await {
+        eprintln!(
+            "Could not send verification mail to {}: {}",
+            user.email, error
+        );
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Verification {
+    /// The token from the verification mail
+    token: String,
+}
+
+/// Confirm an email address
+#[utoipa::path(
+    post,
+    path = "/api/users/verify",
+    tag = "users",
+    request_body = Verification,
+    responses(
+        (status = 200, description = "The email address is verified"),
+        (status = 400, description = "The token is invalid or expired"),
+    ),
+)]
+pub async fn verify_email(
+    State(app): State<Arc<AppState>>,
+    Json(verification): Json<Verification>,
+) -> Result<(), Error> {
+    let user_id = app
+        .repos
+        .one_time_tokens
+        .consume(
+            TokenPurpose::VerifyEmail,
+            &hash_token(&verification.token),
+            now(),
+        )
+        .await?
+        .ok_or(Error::InvalidToken)?;
+
+    app.repos.users.set_verified(user_id, true).await?;
+
+    Ok(())
+}
+
+/// Mail a new verification token to the authenticated user
+#[utoipa::path(
+    post,
+    path = "/api/users/verify/resend",
+    tag = "users",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "A new token was sent"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "The email address is already verified"),
+    ),
+)]
+pub async fn resend_verification(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<(), Error> {
+    let user = app.repos.users.find(user_id).await?.unwrap();
+
+    if user.verified {
+        return Err(Error::AlreadyVerified);
+    }
+
+    send_token(&app, &user).await
+}
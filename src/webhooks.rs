@@ -0,0 +1,722 @@
+//! Telling other services about new and changed articles and comments
+//!
+//! Users register webhooks for events about their own articles and the
+//! comments on them, admins register global webhooks which receive the events
+//! about everyone's. Each event is stored as a delivery per subscribed
+//! webhook, and a worker posts the deliveries which are due, retrying failed
+//! ones with exponential backoff until `--webhook-attempts` are used up.
+//!
+//! The body is `{"event": "...", "data": {...}}`. `X-Webhook-Signature` is
+//! `sha256=` followed by the hex HMAC-SHA256 of the body, keyed with the
+//! secret returned when the webhook was created.
+//!
+//! Webhooks are only delivered to public addresses, the host of a URL is
+//! resolved when the webhook is created and again for every attempt, and
+//! loopback, private, link-local, reserved and unspecified addresses, as well
+//! as IPv6 addresses embedding such IPv4 ones, are refused unless the host is
+//! one of `--webhook-allow-host`.
+
+use crate::{
+    auth::{Admin, Auth},
+    errors, http_client,
+    repo::{self, Delivery, DeliveryAttempt, DeliveryStatus, Webhook, WebhookEvent},
+    token::{hex, now, random_token},
+    AppState,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, uri::Scheme, Method, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use utoipa::ToSchema;
+
+/// How often the worker looks for due deliveries
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "webhooks")]
+pub struct Config {
+    /// Attempts per delivery before it is given up
+    #[arg(
+        long = "webhook-attempts",
+        env = "WEBHOOK_ATTEMPTS",
+        value_parser = clap::value_parser!(i64).range(1..=100),
+        default_value_t = 6
+    )]
+    pub attempts: i64,
+
+    /// Seconds before the first retry of a delivery, doubling with every
+    /// further retry
+    #[arg(
+        long = "webhook-backoff",
+        env = "WEBHOOK_BACKOFF",
+        value_parser = clap::value_parser!(i64).range(1..=86_400),
+        default_value_t = 30
+    )]
+    pub backoff: i64,
+
+    /// Seconds to wait for a webhook to respond
+    #[arg(
+        long = "webhook-timeout",
+        env = "WEBHOOK_TIMEOUT",
+        value_parser = clap::value_parser!(u64).range(1..=300),
+        default_value_t = 10
+    )]
+    pub timeout: u64,
+
+    /// Deliveries the worker picks up at a time, the rest wait for the next
+    /// round
+    #[arg(
+        long = "webhook-batch-size",
+        env = "WEBHOOK_BATCH_SIZE",
+        value_parser = clap::value_parser!(i64).range(1..=10_000),
+        default_value_t = 100
+    )]
+    pub batch_size: i64,
+
+    /// Deliveries which are posted at the same time
+    #[arg(
+        long = "webhook-concurrency",
+        env = "WEBHOOK_CONCURRENCY",
+        value_parser = clap::value_parser!(u64).range(1..=1_000),
+        default_value_t = 16
+    )]
+    pub concurrency: u64,
+
+    /// Hosts which may be delivered to even though they resolve to
+    /// loopback, private or link-local addresses
+    #[arg(
+        long = "webhook-allow-host",
+        env = "WEBHOOK_ALLOW_HOSTS",
+        value_name = "HOST",
+        value_delimiter = ','
+    )]
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            attempts: 6,
+            backoff: 30,
+            timeout: 10,
+            batch_size: 100,
+            concurrency: 16,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Only absolute `http` and `https` URLs are accepted
+    InvalidUrl,
+    /// The host of the URL does not resolve, or not only to public addresses
+    PrivateAddress,
+    /// A webhook has to subscribe to at least one event
+    NoEvents,
+    /// The user has no webhook with this `id`
+    NotFound,
+    Repo(repo::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidUrl => write!(f, "the URL has to be an absolute http or https URL"),
+            Error::PrivateAddress => {
+                write!(f, "the host of the URL has to resolve to public addresses")
+            }
+            Error::NoEvents => write!(f, "no events to subscribe to"),
+            Error::NotFound => write!(f, "no such webhook"),
+            Error::Repo(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<repo::Error> for Error {
+    fn from(error: repo::Error) -> Self {
+        Error::Repo(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::InvalidUrl | Error::PrivateAddress | Error::NoEvents => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Repo(_) => return errors::internal(&self),
+        };
+
+        (status, errors::body(&self)).into_response()
+    }
+}
+
+/// Queue `event` for the webhooks interested in the content of `owner`
+pub(crate) async fn dispatch(
+    app: &AppState,
+    event: WebhookEvent,
+    owner: i64,
+    data: impl Serialize,
+) -> repo::Result<()> {
+    let webhooks = app.repos.webhooks.subscribed(event, owner).await?;
+
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let payload = json!({ "event": event, "data": data }).to_string();
+
+    for webhook in webhooks {
+        app.repos
+            .webhooks
+            .enqueue(webhook.id, event, &payload, now())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Deliver whatever is due in the background for as long as the server runs
+pub fn spawn(app: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            timer.tick().await;
+
+            if let Err(error) = deliver_due(&app, now()).await {
+                eprintln!("Delivering webhooks failed: {}", error);
+            }
+        }
+    });
+}
+
+/// Attempt up to `--webhook-batch-size` deliveries which are due at `now`
+/// (in seconds since the epoch) and return how many there were
+pub async fn deliver_due(app: &AppState, now: i64) -> repo::Result<usize> {
+    let config = &app.webhooks;
+    let due = app.repos.webhooks.due(now, config.batch_size).await?;
+    let count = due.len();
+
+    // A slow receiver must not hold up the others, but only so many are
+    // posted to at once
+    let mut posts = JoinSet::new();
+    let slots = Arc::new(Semaphore::new(config.concurrency as usize));
+
+    for delivery in due {
+        let Some(webhook) = app.repos.webhooks.find(delivery.webhook).await? else {
+            continue;
+        };
+        let config = config.clone();
+        let Ok(slot) = slots.clone().acquire_owned().await else {
+            continue;
+        };
+
+        posts.spawn(async move {
+            let _slot = slot;
+            let outcome = tokio::time::timeout(
+                Duration::from_secs(config.timeout),
+                post(&config, &webhook, &delivery),
+            )
+            .await
+            .unwrap_or_else(|_| Err(String::from("timed out")));
+
+            (delivery, outcome)
+        });
+    }
+
+    while let Some(posted) = posts.join_next().await {
+        let Ok((delivery, outcome)) = posted else {
+            continue;
+        };
+
+        let attempt = match outcome {
+            Ok(status) if (200..300).contains(&status) => DeliveryAttempt {
+                status: DeliveryStatus::Delivered,
+                next_attempt_at: None,
+                response_status: Some(status.into()),
+                error: None,
+            },
+            outcome => {
+                let (response_status, error) = match outcome {
+                    Ok(status) => (Some(status.into()), format!("responded with {}", status)),
+                    Err(error) => (None, error),
+                };
+                let attempts = delivery.attempts + 1;
+
+                if attempts >= config.attempts {
+                    DeliveryAttempt {
+                        status: DeliveryStatus::Failed,
+                        next_attempt_at: None,
+                        response_status,
+                        error: Some(error),
+                    }
+                } else {
+                    let backoff = config
+                        .backoff
+                        .saturating_mul(1 << (attempts - 1).clamp(0, 30));
+
+                    DeliveryAttempt {
+                        status: DeliveryStatus::Pending,
+                        next_attempt_at: Some(now.saturating_add(backoff)),
+                        response_status,
+                        error: Some(error),
+                    }
+                }
+            }
+        };
+
+        app.repos.webhooks.record(delivery.id, attempt).await?;
+    }
+
+    Ok(count)
+}
+
+/// Whether `ip` is reachable from anywhere, rather than only from this host
+/// or its networks
+fn is_public(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // "This network", shared address space, benchmarking and
+                // reserved
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64)
+                || (first == 198 && second & 0xfe == 18)
+                || first >= 240)
+        }
+        IpAddr::V6(ip) => {
+            // IPv4-compatible addresses reach the IPv4 address they embed
+            if let Some(embedded) = ip.to_ipv4() {
+                return is_public(IpAddr::V4(embedded));
+            }
+
+            let [first, second, ..] = ip.segments();
+
+            // NAT64 and 6to4 translate to IPv4 addresses, which may be
+            // private
+            !((first == 0x64 && second == 0xff9b)
+                || first == 0x2002
+                || ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local())
+        }
+    }
+}
+
+/// The address to deliver to `url` at, unless its host resolves to
+/// addresses which are not public and is not one of `--webhook-allow-host`
+async fn resolve(config: &Config, url: &str) -> Result<SocketAddr, String> {
+    let uri: Uri = url.parse().map_err(|error| format!("{}", error))?;
+    let host = uri.host().ok_or("the URL has no host")?;
+    // IPv6 addresses are in brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let https = uri.scheme() == Some(&Scheme::HTTPS);
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|error| error.to_string())?
+        .collect();
+    let allowed = config
+        .allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host));
+
+    if !allowed {
+        if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+            return Err(format!("{} is not a public address", address.ip()));
+        }
+    }
+
+    addresses
+        .first()
+        .copied()
+        .ok_or_else(|| format!("{} has no addresses", host))
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `payload` keyed with `secret`
+pub fn signature(secret: &str, payload: &str) -> String {
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+
+    format!("sha256={}", hex(&mac.finalize().into_bytes()))
+}
+
+/// Post a delivery and return the status of the response
+async fn post(config: &Config, webhook: &Webhook, delivery: &Delivery) -> Result<u16, String> {
+    // Checked again as the host may resolve elsewhere by now
+    let address = resolve(config, &webhook.url).await?;
+    let request = Request::builder()
+        .method(Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::USER_AGENT, "realworld-webhooks")
+        .header("x-webhook-event", delivery.event.as_str())
+        .header("x-webhook-delivery", delivery.id)
+        .header(
+            "x-webhook-signature",
+            signature(&webhook.secret, &delivery.payload),
+        );
+    let response = http_client::send_to(
+        &webhook.url,
+        Some(address),
+        request,
+        Bytes::from(delivery.payload.clone()),
+    )
+    .await?;
+
+    Ok(response.status().as_u16())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseWebhookBody {
+    id: i64,
+    url: String,
+    events: Vec<WebhookEvent>,
+    /// Only returned when the webhook is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    created_at: String,
+}
+
+impl From<Webhook> for ResponseWebhookBody {
+    fn from(webhook: Webhook) -> Self {
+        ResponseWebhookBody {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            secret: None,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseWebhook {
+    webhook: ResponseWebhookBody,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseMultipleWebhooks {
+    webhooks: Vec<ResponseWebhookBody>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWebhook {
+    url: String,
+    events: Vec<WebhookEvent>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RequestWebhook {
+    webhook: NewWebhook,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseDelivery {
+    id: i64,
+    event: WebhookEvent,
+    status: DeliveryStatus,
+    attempts: i64,
+    /// Seconds since the epoch, missing once nothing is left to do
+    next_attempt_at: Option<i64>,
+    /// HTTP status of the last response
+    response_status: Option<i64>,
+    /// Why the last attempt failed
+    error: Option<String>,
+    /// The body which is posted
+    payload: Value,
+    created_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResponseDeliveries {
+    deliveries: Vec<ResponseDelivery>,
+}
+
+async fn create(
+    app: &AppState,
+    owner: Option<i64>,
+    request: NewWebhook,
+) -> Result<Json<ResponseWebhook>, Error> {
+    let valid = request.url.parse::<Uri>().is_ok_and(|uri| {
+        matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some()
+    });
+
+    if !valid {
+        return Err(Error::InvalidUrl);
+    }
+
+    let mut events = request.events;
+    events.sort();
+    events.dedup();
+
+    if events.is_empty() {
+        return Err(Error::NoEvents);
+    }
+
+    if resolve(&app.webhooks, &request.url).await.is_err() {
+        return Err(Error::PrivateAddress);
+    }
+
+    let secret = random_token();
+    let webhook = app
+        .repos
+        .webhooks
+        .create(owner, &request.url, &secret, &events)
+        .await?;
+
+    Ok(Json(ResponseWebhook {
+        webhook: ResponseWebhookBody {
+            secret: Some(secret),
+            ..webhook.into()
+        },
+    }))
+}
+
+async fn list(app: &AppState, owner: Option<i64>) -> Result<Json<ResponseMultipleWebhooks>, Error> {
+    let webhooks = app.repos.webhooks.list(owner).await?;
+
+    Ok(Json(ResponseMultipleWebhooks {
+        webhooks: webhooks.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// The webhook, if it belongs to `owner`
+async fn find(app: &AppState, owner: Option<i64>, id: i64) -> Result<Webhook, Error> {
+    app.repos
+        .webhooks
+        .find(id)
+        .await?
+        .filter(|webhook| webhook.owner == owner)
+        .ok_or(Error::NotFound)
+}
+
+async fn delete(app: &AppState, owner: Option<i64>, id: i64) -> Result<(), Error> {
+    let webhook = find(app, owner, id).await?;
+    app.repos.webhooks.delete(webhook.id).await?;
+
+    Ok(())
+}
+
+async fn deliveries(
+    app: &AppState,
+    owner: Option<i64>,
+    id: i64,
+) -> Result<Json<ResponseDeliveries>, Error> {
+    let webhook = find(app, owner, id).await?;
+    let deliveries = app.repos.webhooks.deliveries(webhook.id).await?;
+
+    Ok(Json(ResponseDeliveries {
+        deliveries: deliveries
+            .into_iter()
+            .map(|delivery| ResponseDelivery {
+                id: delivery.id,
+                event: delivery.event,
+                status: delivery.status,
+                attempts: delivery.attempts,
+                next_attempt_at: delivery.next_attempt_at,
+                response_status: delivery.response_status,
+                error: delivery.error,
+                payload: serde_json::from_str(&delivery.payload).unwrap_or(Value::Null),
+                created_at: delivery.created_at,
+            })
+            .collect(),
+    }))
+}
+
+/// Register a webhook for events about the authenticated user's articles
+/// and the comments on them
+#[utoipa::path(
+    post,
+    path = "/api/user/webhooks",
+    tag = "webhooks",
+    security(("token" = [])),
+    request_body = RequestWebhook,
+    responses(
+        (status = 200, description = "The new webhook with its signing secret", body = ResponseWebhook),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 422, description = "Invalid or private URL, or no events"),
+    ),
+)]
+pub async fn create_webhook(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Json(request): Json<RequestWebhook>,
+) -> Result<Json<ResponseWebhook>, Error> {
+    create(&app, Some(user_id), request.webhook).await
+}
+
+/// The webhooks of the authenticated user
+#[utoipa::path(
+    get,
+    path = "/api/user/webhooks",
+    tag = "webhooks",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The webhooks", body = ResponseMultipleWebhooks),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+)]
+pub async fn list_webhooks(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+) -> Result<Json<ResponseMultipleWebhooks>, Error> {
+    list(&app, Some(user_id)).await
+}
+
+/// Remove a webhook of the authenticated user
+#[utoipa::path(
+    delete,
+    path = "/api/user/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The webhook and its deliveries are gone"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The user has no such webhook"),
+    ),
+)]
+pub async fn delete_webhook(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(id): Path<i64>,
+) -> Result<(), Error> {
+    delete(&app, Some(user_id), id).await
+}
+
+/// The deliveries of a webhook of the authenticated user, newest first
+#[utoipa::path(
+    get,
+    path = "/api/user/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The delivery log", body = ResponseDeliveries),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The user has no such webhook"),
+    ),
+)]
+pub async fn list_deliveries(
+    State(app): State<Arc<AppState>>,
+    Auth(user_id): Auth,
+    Path(id): Path<i64>,
+) -> Result<Json<ResponseDeliveries>, Error> {
+    deliveries(&app, Some(user_id), id).await
+}
+
+/// Register a webhook for events about everyone's articles and comments
+#[utoipa::path(
+    post,
+    path = "/api/admin/webhooks",
+    tag = "admin",
+    security(("token" = [])),
+    request_body = RequestWebhook,
+    responses(
+        (status = 200, description = "The new webhook with its signing secret", body = ResponseWebhook),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The user is not an admin"),
+        (status = 422, description = "Invalid or private URL, or no events"),
+    ),
+)]
+pub async fn create_global_webhook(
+    State(app): State<Arc<AppState>>,
+    _: Admin,
+    Json(request): Json<RequestWebhook>,
+) -> Result<Json<ResponseWebhook>, Error> {
+    create(&app, None, request.webhook).await
+}
+
+/// The global webhooks
+#[utoipa::path(
+    get,
+    path = "/api/admin/webhooks",
+    tag = "admin",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The webhooks", body = ResponseMultipleWebhooks),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The user is not an admin"),
+    ),
+)]
+pub async fn list_global_webhooks(
+    State(app): State<Arc<AppState>>,
+    _: Admin,
+) -> Result<Json<ResponseMultipleWebhooks>, Error> {
+    list(&app, None).await
+}
+
+/// Remove a global webhook
+#[utoipa::path(
+    delete,
+    path = "/api/admin/webhooks/{id}",
+    tag = "admin",
+    params(("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The webhook and its deliveries are gone"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The user is not an admin"),
+        (status = 404, description = "No such global webhook"),
+    ),
+)]
+pub async fn delete_global_webhook(
+    State(app): State<Arc<AppState>>,
+    _: Admin,
+    Path(id): Path<i64>,
+) -> Result<(), Error> {
+    delete(&app, None, id).await
+}
+
+/// The deliveries of a global webhook, newest first
+#[utoipa::path(
+    get,
+    path = "/api/admin/webhooks/{id}/deliveries",
+    tag = "admin",
+    params(("id" = i64, Path)),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The delivery log", body = ResponseDeliveries),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The user is not an admin"),
+        (status = 404, description = "No such global webhook"),
+    ),
+)]
+pub async fn list_global_deliveries(
+    State(app): State<Arc<AppState>>,
+    _: Admin,
+    Path(id): Path<i64>,
+) -> Result<Json<ResponseDeliveries>, Error> {
+    deliveries(&app, None, id).await
+}
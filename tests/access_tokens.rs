@@ -0,0 +1,257 @@
+//! Personal access tokens and the scopes which limit them
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use common::TestApp;
+use realworld::repo::{NewAccessToken, Scope};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// In a day
+fn tomorrow() -> i64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap();
+
+    now.as_secs() as i64 + 24 * 60 * 60
+}
+
+async fn create_token(app: &TestApp, token: &str, scopes: &[&str]) -> (StatusCode, Value) {
+    app.post(
+        "/api/user/tokens",
+        Some(token),
+        json!({
+            "accessToken": {
+                "name": "deploy script",
+                "scopes": scopes,
+                "expiresAt": tomorrow(),
+            }
+        }),
+    )
+    .await
+}
+
+/// The status and `WWW-Authenticate` header of a request with `token`
+async fn send_with(
+    app: &TestApp,
+    method: Method,
+    uri: &str,
+    token: &str,
+    body: Value,
+) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let (status, headers, _) = app.send_raw(request).await;
+    let challenge = headers
+        .get(header::WWW_AUTHENTICATE)
+        .map(|value| value.to_str().unwrap().to_owned())
+        .unwrap_or_default();
+
+    (status, challenge)
+}
+
+#[tokio::test]
+async fn scopes_limit_what_tokens_can_do() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.create_article(&token, "How to train your dragon", &[])
+        .await;
+
+    let (status, body) = create_token(&app, &token, &["read", "comments:write"]).await;
+    assert_eq!(status, StatusCode::OK);
+    let pat = body["accessToken"]["token"].as_str().unwrap().to_owned();
+    assert!(pat.starts_with("rwpat_"));
+    assert_eq!(
+        body["accessToken"]["scopes"],
+        json!(["read", "comments:write"])
+    );
+
+    let (status, body) = app.get("/api/user", Some(&pat)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+    let (status, _) = app
+        .get("/api/articles/how-to-train-your-dragon", Some(&pat))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .post(
+            "/api/articles/how-to-train-your-dragon/comments",
+            Some(&pat),
+            json!({ "comment": { "body": "Thank you so much!" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, challenge) = send_with(
+        &app,
+        Method::POST,
+        "/api/articles",
+        &pat,
+        json!({
+            "article": { "title": "Scripted", "description": "", "body": "" }
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        challenge,
+        r#"Bearer realm="realworld", error="insufficient_scope", scope="articles:write""#
+    );
+
+    // Changing the account and creating more tokens takes logging in
+    let (status, challenge) = send_with(
+        &app,
+        Method::PUT,
+        "/api/user",
+        &pat,
+        json!({ "user": { "bio": "Scripted" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        challenge,
+        r#"Bearer realm="realworld", error="insufficient_scope""#
+    );
+    let (status, _) = create_token(&app, &pat, &["read"]).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app.get("/api/user/sessions", Some(&pat)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn tokens_are_listed_without_secrets_and_can_be_revoked() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    let jane_token = app.register("jane").await;
+
+    let (_, body) = create_token(&app, &token, &["read", "read"]).await;
+    let pat = body["accessToken"]["token"].as_str().unwrap().to_owned();
+    let id = body["accessToken"]["id"].as_i64().unwrap();
+
+    let (status, body) = app.get("/api/user/tokens", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let tokens = body["accessTokens"].as_array().unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0]["name"], "deploy script");
+    assert_eq!(tokens[0]["scopes"], json!(["read"]));
+    assert_eq!(tokens[0]["lastUsedAt"], Value::Null);
+    assert_eq!(tokens[0].get("token"), None);
+
+    let (status, _) = app.get("/api/user", Some(&pat)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.get("/api/user/tokens", Some(&token)).await;
+    assert!(body["accessTokens"][0]["lastUsedAt"].is_i64());
+
+    let uri = format!("/api/user/tokens/{}", id);
+    let (status, _) = app.delete(&uri, Some(&jane_token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.delete(&uri, Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app.get("/api/user", Some(&pat)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_not_created() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    for token_request in [
+        json!({ "name": " ", "scopes": ["read"], "expiresAt": tomorrow() }),
+        json!({ "name": "script", "scopes": [], "expiresAt": tomorrow() }),
+        json!({ "name": "script", "scopes": ["read"], "expiresAt": 1 }),
+    ] {
+        let (status, _) = app
+            .post(
+                "/api/user/tokens",
+                Some(&token),
+                json!({ "accessToken": token_request }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let (status, _) = app
+        .post(
+            "/api/user/tokens",
+            Some(&token),
+            json!({
+                "accessToken": { "name": "script", "scopes": ["admin"], "expiresAt": tomorrow() }
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn expired_and_revoked_tokens_are_rejected() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    let user = app
+        .repos
+        .users
+        .find_by_username("jake")
+        .await
+        .unwrap()
+        .unwrap();
+
+    let secret = "rwpat_expired";
+    app.repos
+        .access_tokens
+        .create(NewAccessToken {
+            user: user.id,
+            name: String::from("old script"),
+            hash: format!("{:x}", Sha256::digest(secret.as_bytes())),
+            scopes: vec![Scope::Read],
+            expires_at: 1,
+        })
+        .await
+        .unwrap();
+    let (status, challenge) = send_with(&app, Method::GET, "/api/user", secret, json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(challenge.contains("expired"), "{}", challenge);
+
+    let (status, _) = send_with(&app, Method::GET, "/api/user", "rwpat_unknown", json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Revoking every token of the user includes these
+    let token = app.register("jane").await;
+    let (_, body) = create_token(&app, &token, &["read"]).await;
+    let pat = body["accessToken"]["token"].as_str().unwrap().to_owned();
+    let jane = app
+        .repos
+        .users
+        .find_by_username("jane")
+        .await
+        .unwrap()
+        .unwrap();
+    app.repos.users.revoke_tokens(jane.id).await.unwrap();
+
+    let (status, _) = app.get("/api/user", Some(&pat)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn database_errors_are_not_mistaken_for_revoked_tokens() {
+    let app = TestApp::new().await;
+    let session = app.register("jake").await;
+    let (_, body) = create_token(&app, &session, &["read"]).await;
+    let pat = body["accessToken"]["token"].as_str().unwrap().to_owned();
+    // Only the database can fail
+    let Some(db) = &app.state.db else {
+        return;
+    };
+    db.close().await;
+
+    let (status, challenge) = send_with(&app, Method::GET, "/api/user", &pat, json!({})).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(challenge, "");
+}
@@ -0,0 +1,156 @@
+//! Users deleting their account and exporting their data
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+
+/// jake wrote an article and commented on one by celeb, and both follow
+/// each other
+async fn community(app: &TestApp) -> (String, String) {
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.create_article(&jake, "How to train your dragon", &["dragons", "training"])
+        .await;
+    app.create_article(&celeb, "Dragon riding", &[]).await;
+
+    app.post(
+        "/api/articles/dragon-riding/comments",
+        Some(&jake),
+        json!({ "comment": { "body": "Thank you so much!" } }),
+    )
+    .await;
+    app.post(
+        "/api/articles/dragon-riding/favorite",
+        Some(&jake),
+        json!({}),
+    )
+    .await;
+    app.post("/api/profiles/celeb/follow", Some(&jake), json!({}))
+        .await;
+    app.post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+
+    (jake, celeb)
+}
+
+async fn delete_account(app: &TestApp, token: &str, password: &str, content: &str) -> StatusCode {
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            "/api/user",
+            Some(token),
+            Some(json!({ "password": password, "content": content })),
+        )
+        .await;
+
+    status
+}
+
+#[tokio::test]
+async fn the_export_contains_everything_about_the_user() {
+    let app = TestApp::new().await;
+    let (jake, _) = community(&app).await;
+    app.register("troll").await;
+    app.register("bore").await;
+    app.post("/api/profiles/troll/block", Some(&jake), json!({}))
+        .await;
+    app.post("/api/profiles/bore/mute", Some(&jake), json!({}))
+        .await;
+
+    let (status, export) = app.get("/api/user/export", Some(&jake)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(export["profile"]["username"], "jake");
+    assert_eq!(export["profile"]["email"], "jake@example.com");
+    assert_eq!(export["articles"].as_array().unwrap().len(), 1);
+    assert_eq!(export["articles"][0]["slug"], "how-to-train-your-dragon");
+    assert_eq!(
+        export["articles"][0]["tagList"],
+        json!(["dragons", "training"])
+    );
+    assert_eq!(export["comments"][0]["article"], "dragon-riding");
+    assert_eq!(export["comments"][0]["body"], "Thank you so much!");
+    assert_eq!(export["following"], json!(["celeb"]));
+    assert_eq!(export["followers"], json!(["celeb"]));
+    assert_eq!(export["favorites"], json!(["dragon-riding"]));
+    assert_eq!(export["blocking"], json!(["troll"]));
+    assert_eq!(export["muting"], json!(["bore"]));
+}
+
+#[tokio::test]
+async fn deleting_requires_the_password() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+
+    assert_eq!(
+        delete_account(&app, &jake, "wrong", "delete").await,
+        StatusCode::FORBIDDEN
+    );
+    let (status, _) = app.get("/api/user", Some(&jake)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn deleting_removes_the_content() {
+    let app = TestApp::new().await;
+    let (jake, celeb) = community(&app).await;
+
+    assert_eq!(
+        delete_account(&app, &jake, "password", "delete").await,
+        StatusCode::OK
+    );
+
+    let (status, _) = app.get("/api/user", Some(&jake)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (_, body) = app.get("/api/articles", None).await;
+    assert_eq!(body["articlesCount"], 1);
+    assert_eq!(body["articles"][0]["slug"], "dragon-riding");
+    assert_eq!(body["articles"][0]["favoritesCount"], 0);
+
+    let (_, body) = app.get("/api/articles/dragon-riding/comments", None).await;
+    assert_eq!(body["comments"], json!([]));
+
+    let (_, export) = app.get("/api/user/export", Some(&celeb)).await;
+    assert_eq!(export["followers"], json!([]));
+
+    // The name is free again
+    app.register("jake").await;
+}
+
+#[tokio::test]
+async fn anonymizing_keeps_the_content() {
+    let app = TestApp::new().await;
+    let (jake, celeb) = community(&app).await;
+
+    assert_eq!(
+        delete_account(&app, &jake, "password", "anonymize").await,
+        StatusCode::OK
+    );
+
+    let (status, _) = app.get("/api/user", Some(&jake)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (_, body) = app
+        .get("/api/articles/how-to-train-your-dragon", None)
+        .await;
+    let author = &body["article"]["author"];
+    assert!(author["username"].as_str().unwrap().starts_with("deleted-"));
+    assert_eq!(author["bio"], Value::Null);
+
+    let (_, body) = app.get("/api/articles/dragon-riding/comments", None).await;
+    assert_eq!(body["comments"][0]["body"], "Thank you so much!");
+    assert_eq!(body["comments"][0]["author"], *author);
+
+    let (_, body) = app.get("/api/articles/dragon-riding", None).await;
+    assert_eq!(body["article"]["favoritesCount"], 0);
+
+    let (_, export) = app.get("/api/user/export", Some(&celeb)).await;
+    assert_eq!(export["following"], json!([]));
+    assert_eq!(export["followers"], json!([]));
+
+    // The address can be registered again
+    app.register("jake").await;
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn backups_require_an_admin() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    let (status, _) = app.post("/api/admin/backups", None, json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app
+        .post("/api/admin/backups", Some(&token), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app.get("/api/admin/backups", Some(&token)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[cfg(not(feature = "postgres"))]
+#[tokio::test]
+async fn backups_are_rotated() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.create_article(&token, "How to train your dragon", &["dragons"])
+        .await;
+    sqlx::query(r#"UPDATE "users" SET "role"='admin'"#)
+        .execute(app.db())
+        .await
+        .unwrap();
+
+    let mut files = Vec::new();
+
+    for _ in 0..3 {
+        let (status, body) = app
+            .post("/api/admin/backups", Some(&token), json!({}))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["backup"]["size"].as_u64().unwrap() > 0);
+
+        files.push(body["backup"]["file"].as_str().unwrap().to_owned());
+    }
+
+    // Only the two newest backups are kept
+    let (status, body) = app.get("/api/admin/backups", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({ "backups": [
+            { "file": files[1], "size": std::fs::metadata(&files[1]).unwrap().len() },
+            { "file": files[2], "size": std::fs::metadata(&files[2]).unwrap().len() },
+        ]})
+    );
+
+    // A backup is a complete database on its own
+    let backup = realworld::database::connect(&format!("sqlite:{}", files[2]))
+        .await
+        .unwrap();
+    let slugs: Vec<String> = sqlx::query_scalar(r#"SELECT "slug" FROM "articles""#)
+        .fetch_all(&backup)
+        .await
+        .unwrap();
+    assert_eq!(slugs, ["how-to-train-your-dragon"]);
+}
+
+#[cfg(feature = "postgres")]
+#[tokio::test]
+async fn backups_are_not_supported() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    sqlx::query(r#"UPDATE "users" SET "role"='admin'"#)
+        .execute(app.db())
+        .await
+        .unwrap();
+
+    let (status, _) = app
+        .post("/api/admin/backups", Some(&token), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+}
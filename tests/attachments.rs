@@ -0,0 +1,232 @@
+//! Files attached to articles
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use common::TestApp;
+use image::{ImageFormat, Rgb, RgbImage};
+use serde_json::Value;
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+const BOUNDARY: &str = "realworld-boundary";
+
+fn png() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    RgbImage::from_pixel(8, 8, Rgb([255, 0, 0]))
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .unwrap();
+
+    bytes
+}
+
+/// Post `bytes` as the `file` field of a form
+async fn attach(
+    app: &TestApp,
+    slug: &str,
+    token: &str,
+    filename: &str,
+    content_type: &str,
+    bytes: &[u8],
+) -> (StatusCode, Value) {
+    let mut body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: {content_type}\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/api/articles/{}/attachments", slug))
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .header(header::AUTHORIZATION, format!("Token {}", token))
+        .body(Body::from(body))
+        .unwrap();
+
+    app.send(request).await
+}
+
+/// Status and content type of the file served at `url`
+async fn fetch(app: &TestApp, url: &str) -> (StatusCode, String) {
+    let path = url.strip_prefix("http://realworld.test").unwrap();
+    let (status, headers, _) = app
+        .send_raw(Request::get(path).body(Body::empty()).unwrap())
+        .await;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .map(|value| value.to_str().unwrap().to_owned())
+        .unwrap_or_default();
+
+    (status, content_type)
+}
+
+#[tokio::test]
+async fn attachments_belong_to_their_article() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &[])
+        .await;
+
+    let (status, body) = attach(&app, &slug, &jake, "../dragon.png", "image/png", &png()).await;
+    assert_eq!(status, StatusCode::OK);
+    let image = body["attachment"].clone();
+    assert_eq!(image["filename"], "dragon.png");
+    assert_eq!(image["contentType"], "image/png");
+    assert_eq!(image["size"], png().len());
+    let image_url = image["url"].as_str().unwrap();
+    assert!(image_url.starts_with("http://realworld.test/media/attachments/"));
+    assert_eq!(
+        fetch(&app, image_url).await,
+        (StatusCode::OK, String::from("image/png"))
+    );
+
+    let (status, body) = attach(&app, &slug, &jake, "notes.txt", "text/plain", b"Fire!").await;
+    assert_eq!(status, StatusCode::OK);
+    let notes_url = body["attachment"]["url"].as_str().unwrap().to_owned();
+    assert_eq!(
+        fetch(&app, &notes_url).await,
+        (StatusCode::OK, String::from("text/plain; charset=utf-8"))
+    );
+
+    // Only the author attaches files
+    let (status, _) = attach(&app, &slug, &celeb, "cat.png", "image/png", &png()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = app
+        .get(&format!("/api/articles/{}/attachments", slug), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let names: Vec<_> = body["attachments"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|attachment| &attachment["filename"])
+        .collect();
+    assert_eq!(names, ["dragon.png", "notes.txt"]);
+
+    let uri = format!("/api/articles/{}/attachments/{}", slug, image["id"]);
+    let (status, _) = app.delete(&uri, Some(&celeb)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.delete(&uri, Some(&jake)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetch(&app, image_url).await.0, StatusCode::NOT_FOUND);
+
+    // Deleting the article takes the rest along
+    app.delete(&format!("/api/articles/{}", slug), Some(&jake))
+        .await;
+    assert_eq!(fetch(&app, &notes_url).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn attachments_are_limited() {
+    let app = TestApp::configured(|state| {
+        state.attachments.max_size = 100;
+        state.attachments.quota = 150;
+        state.attachments.types = vec![String::from("text/plain"), String::from("image/png")];
+    })
+    .await;
+    let jake = app.register("jake").await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &[])
+        .await;
+
+    let (status, _) = attach(
+        &app,
+        &slug,
+        &jake,
+        "dragon.pdf",
+        "application/pdf",
+        b"%PDF-1.7",
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // The content has to match the type
+    let (status, _) = attach(&app, &slug, &jake, "dragon.png", "image/png", b"Fire!").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = attach(&app, &slug, &jake, "notes.txt", "text/plain", &[0xff, 0xfe]).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = attach(&app, &slug, &jake, "notes.txt", "text/plain", &[b'a'; 101]).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let (status, body) = attach(&app, &slug, &jake, "notes.txt", "text/plain", &[b'a'; 100]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Only 50 bytes of the quota are left
+    let (status, _) = attach(&app, &slug, &jake, "more.txt", "text/plain", &[b'a'; 51]).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let (status, _) = attach(&app, &slug, &jake, "more.txt", "text/plain", &[b'a'; 50]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Deleting frees the quota again
+    let uri = format!(
+        "/api/articles/{}/attachments/{}",
+        slug, body["attachment"]["id"]
+    );
+    app.delete(&uri, Some(&jake)).await;
+    let (status, _) = attach(&app, &slug, &jake, "more.txt", "text/plain", &[b'a'; 100]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, body) = app
+        .get(&format!("/api/articles/{}/attachments", slug), None)
+        .await;
+    assert_eq!(body["attachments"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        app.get("/api/articles/missing/attachments", None).await.0,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn concurrent_uploads_stay_within_the_quota() {
+    let app = Arc::new(
+        TestApp::configured(|state| {
+            state.attachments.max_size = 100;
+            state.attachments.quota = 150;
+            state.attachments.types = vec![String::from("text/plain")];
+        })
+        .await,
+    );
+    let jake = app.register("jake").await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &[])
+        .await;
+
+    let mut uploads = JoinSet::new();
+    for _ in 0..10 {
+        let (app, slug, jake) = (app.clone(), slug.clone(), jake.clone());
+        uploads.spawn(async move {
+            attach(&app, &slug, &jake, "notes.txt", "text/plain", &[b'a'; 100])
+                .await
+                .0
+        });
+    }
+    let statuses = uploads.join_all().await;
+
+    let stored = statuses
+        .iter()
+        .filter(|&&status| status == StatusCode::OK)
+        .count();
+    assert_eq!(stored, 1, "{:?}", statuses);
+    assert!(statuses
+        .iter()
+        .all(|&status| status == StatusCode::OK || status == StatusCode::PAYLOAD_TOO_LARGE));
+
+    // The files of the rejected uploads are gone as well
+    let article_dir = std::fs::read_dir(app.state.media.dir.join("attachments"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert_eq!(std::fs::read_dir(article_dir).unwrap().count(), 1);
+}
@@ -0,0 +1,123 @@
+//! Rejecting requests whose `Authorization` header does not hold a valid token
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use common::TestApp;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::json;
+
+/// `GET /api/user` with `authorization` as the header, returns the status
+/// and the `WWW-Authenticate` header of the response
+async fn current_user(app: &TestApp, authorization: Option<&str>) -> (StatusCode, String) {
+    let mut request = Request::get("/api/user");
+
+    if let Some(authorization) = authorization {
+        request = request.header(header::AUTHORIZATION, authorization);
+    }
+
+    let (status, headers, _) = app.send_raw(request.body(Body::empty()).unwrap()).await;
+    let challenge = headers
+        .get(header::WWW_AUTHENTICATE)
+        .map(|value| value.to_str().unwrap().to_owned())
+        .unwrap_or_default();
+
+    (status, challenge)
+}
+
+#[tokio::test]
+async fn both_schemes_are_accepted() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    for scheme in ["Token", "Bearer", "bearer"] {
+        let (status, _) = current_user(&app, Some(&format!("{} {}", scheme, token))).await;
+        assert_eq!(status, StatusCode::OK, "{}", scheme);
+    }
+
+    let (_, body) = app
+        .send(
+            Request::get("/api/user")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(body["user"]["token"], token.as_str());
+
+    let (status, challenge) = current_user(&app, Some(&format!("Basic {}", token))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        challenge,
+        r#"Bearer realm="realworld", error="invalid_request""#
+    );
+
+    let (status, challenge) = current_user(&app, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(challenge, r#"Bearer realm="realworld""#);
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_rejected_with_the_reason() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    let (status, challenge) = current_user(&app, Some("Token not.a.jwt")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(challenge.contains(r#"error="invalid_token""#));
+    assert!(challenge.contains("malformed"), "{}", challenge);
+
+    let claims = json!({ "exp": 10000000000u64, "user_id": 1, "version": 0, "session": 1 });
+    let forged = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(b"not the secret"),
+    )
+    .unwrap();
+    let (status, challenge) = current_user(&app, Some(&format!("Token {}", forged))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(challenge.contains("invalid signature"), "{}", challenge);
+
+    // Deleting the account also ends its sessions, the user is checked first
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            "/api/user",
+            Some(&token),
+            Some(json!({ "password": "password", "content": "delete" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, challenge) = current_user(&app, Some(&format!("Token {}", token))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(challenge.contains("does not exist"), "{}", challenge);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected_with_the_reason() {
+    let app = TestApp::configured(|state| {
+        state.sessions.access_ttl = -1;
+    })
+    .await;
+    let token = app.register("jake").await;
+
+    let (status, challenge) = current_user(&app, Some(&format!("Bearer {}", token))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(challenge.contains("expired"), "{}", challenge);
+}
+
+#[tokio::test]
+async fn database_errors_are_not_mistaken_for_invalid_tokens() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    // Only the database can fail
+    let Some(db) = &app.state.db else {
+        return;
+    };
+    db.close().await;
+
+    let (status, challenge) = current_user(&app, Some(&format!("Token {}", token))).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(challenge, "");
+}
@@ -0,0 +1,415 @@
+mod common;
+
+use common::TestDatabase;
+use realworld::database::Row;
+use serde_json::{json, Value};
+use std::process::{Command, Output};
+
+struct Database(TestDatabase);
+
+impl Database {
+    fn new(name: &str) -> Self {
+        Database(TestDatabase::new(name))
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_realworld"))
+            .arg("--database")
+            .arg(&self.0.url)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Run a command which is expected to succeed and decode its JSON output
+    fn json(&self, args: &[&str]) -> Value {
+        let output = self.run(&[&["--format", "json"], args].concat());
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+
+    /// Run SQL statements directly against the database
+    fn execute(&self, statements: &[&str]) {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let pool = realworld::database::connect(&self.0.url).await.unwrap();
+
+            for sql in statements {
+                sqlx::query(sql).execute(&pool).await.unwrap();
+            }
+        });
+    }
+
+    fn fetch_all<T>(&self, sql: &str) -> Vec<T>
+    where
+        T: Send + Unpin + for<'r> sqlx::FromRow<'r, Row>,
+    {
+        fetch_all(&self.0.url, sql)
+    }
+}
+
+fn fetch_all<T>(url: &str, sql: &str) -> Vec<T>
+where
+    T: Send + Unpin + for<'r> sqlx::FromRow<'r, Row>,
+{
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let pool = realworld::database::connect(url).await.unwrap();
+
+        sqlx::query_as(sql).fetch_all(&pool).await.unwrap()
+    })
+}
+
+#[test]
+fn manage_users() {
+    let db = Database::new("users");
+
+    let user = db.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+    assert_eq!(
+        user,
+        json!({ "id": 1, "username": "jake", "email": "jake@jake.jake", "role": "user" })
+    );
+
+    db.json(&[
+        "user",
+        "create",
+        "celeb",
+        "celeb@example.com",
+        "secret",
+        "--role",
+        "admin",
+    ]);
+    db.json(&["user", "set-role", "jake", "admin"]);
+    db.json(&["user", "delete", "celeb"]);
+
+    let users = db.json(&["user", "list"]);
+    assert_eq!(
+        users,
+        json!([{ "id": 1, "username": "jake", "email": "jake@jake.jake", "role": "admin" }])
+    );
+
+    let output = db.run(&["user", "list"]);
+    let table = String::from_utf8(output.stdout).unwrap();
+    assert!(table.lines().nth(1).unwrap().contains("jake@jake.jake"));
+}
+
+#[test]
+fn unlock_users() {
+    let db = Database::new("unlock");
+
+    db.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+    db.execute(&[
+        r#"INSERT INTO "login_throttles" ("key", "failures", "lastFailureAt")
+            VALUES ('account:jake@jake.jake', 5, 0), ('ip:192.0.2.7', 20, 0),
+                ('ip:192.0.2.8', 20, 0)"#,
+    ]);
+
+    db.json(&["user", "unlock", "jake", "--ip", "192.0.2.7"]);
+
+    let keys: Vec<(String,)> = db.fetch_all(r#"SELECT "key" FROM "login_throttles""#);
+    assert_eq!(keys, [(String::from("ip:192.0.2.8"),)]);
+}
+
+#[test]
+fn disable_two_factor_authentication() {
+    let db = Database::new("two-factor");
+
+    db.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+    db.execute(&[
+        r#"INSERT INTO "two_factor" ("user", "secret", "enabled") VALUES (1, 'SECRET', TRUE)"#,
+        r#"INSERT INTO "recovery_codes" ("user", "hash") VALUES (1, 'hash')"#,
+    ]);
+
+    db.json(&["user", "disable-two-factor", "jake"]);
+
+    let codes: Vec<(i64,)> = db.fetch_all(r#"SELECT "id" FROM "recovery_codes""#);
+    assert!(codes.is_empty());
+    let secrets: Vec<(i64,)> = db.fetch_all(r#"SELECT "user" FROM "two_factor""#);
+    assert!(secrets.is_empty());
+}
+
+#[test]
+fn duplicate_and_missing_users_fail() {
+    let db = Database::new("errors");
+
+    db.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+
+    let output = db.run(&["user", "create", "jake", "other@jake.jake", "jakejake"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+
+    let output = db.run(&["user", "reset-password", "nobody", "secret"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not exist"));
+}
+
+#[test]
+fn rename_and_merge_tags() {
+    let db = Database::new("tags");
+    db.execute(&[
+        r#"INSERT INTO "users" ("email", "password", "username") VALUES ('a@a.a', 'a', 'a')"#,
+        r#"INSERT INTO "articles" ("slug", "title", "description", "body", "author")
+            VALUES ('one', 'One', '', '', 1), ('two', 'Two', '', '', 1)"#,
+        r#"INSERT INTO "tags" ("name") VALUES ('rust'), ('rustlang'), ('typo')"#,
+        r#"INSERT INTO "taglist" ("article", "tag") VALUES (1, 1), (1, 2), (2, 2), (2, 3)"#,
+    ]);
+
+    db.json(&["tag", "merge", "rustlang", "rust"]);
+    db.json(&["tag", "rename", "typo", "types"]);
+
+    let output = db.run(&["tag", "rename", "types", "rust"]);
+    assert!(!output.status.success());
+
+    let taglist: Vec<(String, String)> = db.fetch_all(
+        r#"
+            SELECT "slug", "name"
+            FROM "taglist"
+            JOIN "articles" ON "articles"."id"="taglist"."article"
+            JOIN "tags" ON "tags"."id"="taglist"."tag"
+            ORDER BY "slug", "name"
+        "#,
+    );
+
+    assert_eq!(
+        taglist,
+        [("one", "rust"), ("two", "rust"), ("two", "types")]
+            .map(|(slug, name)| (slug.to_owned(), name.to_owned()))
+    );
+}
+
+#[test]
+fn moderate_articles() {
+    let db = Database::new("articles");
+    db.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+
+    db.execute(&[
+        r#"INSERT INTO "articles" ("slug", "title", "description", "body", "author")
+        VALUES ('one', 'One', '', '', 1), ('two', 'Two', '', '', 1)"#,
+    ]);
+
+    let article = db.json(&["article", "unpublish", "one"]);
+    assert_eq!(
+        article,
+        json!({ "id": 1, "slug": "one", "published": false })
+    );
+    db.json(&["article", "delete", "two"]);
+
+    let articles: Vec<(String, bool)> =
+        db.fetch_all(r#"SELECT "slug", "published" FROM "articles""#);
+
+    assert_eq!(articles, [(String::from("one"), false)]);
+}
+
+#[test]
+fn deleting_removes_stored_files() {
+    let db = Database::new("files");
+    db.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+    db.json(&["user", "create", "celeb", "celeb@example.com", "secret"]);
+
+    let dir = db.0.file("media");
+    let stem = "2-0123456789abcdef";
+    let keys = [
+        "attachments/one.txt",
+        "attachments/two.txt",
+        &format!("avatars/{}-256.png", stem),
+        &format!("avatars/{}-128.png", stem),
+        &format!("avatars/{}-64.png", stem),
+    ];
+
+    for key in keys {
+        let path = dir.join(key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "content").unwrap();
+    }
+
+    db.execute(&[
+        &format!(
+            r#"UPDATE "users" SET "image"='/media/avatars/{}-256.png' WHERE "id"=2"#,
+            stem
+        ),
+        r#"INSERT INTO "articles" ("slug", "title", "description", "body", "author")
+        VALUES ('one', 'One', '', '', 1), ('two', 'Two', '', '', 2)"#,
+        r#"INSERT INTO "attachments" ("article", "owner", "key", "filename", "contentType", "size")
+        VALUES (1, 1, 'attachments/one.txt', 'one.txt', 'text/plain', 7),
+            (2, 2, 'attachments/two.txt', 'two.txt', 'text/plain', 7)"#,
+    ]);
+
+    let media_dir = dir.to_str().unwrap();
+    db.json(&["article", "delete", "one", "--media-dir", media_dir]);
+    assert!(!dir.join(keys[0]).exists());
+    assert!(dir.join(keys[1]).exists());
+
+    db.json(&["user", "delete", "celeb", "--media-dir", media_dir]);
+    for key in &keys[1..] {
+        assert!(!dir.join(key).exists(), "{}", key);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn export_and_import() {
+    let source = Database::new("export");
+    source.json(&["user", "create", "removed", "removed@example.com", "secret"]);
+    source.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+    source.json(&["user", "create", "celeb", "celeb@example.com", "secret"]);
+    source.execute(&[
+        r#"INSERT INTO "articles" ("slug", "title", "description", "body", "author")
+            VALUES ('gone', 'Gone', '', '', 1), ('one', 'One', '', '', 2)"#,
+        r#"INSERT INTO "tags" ("name") VALUES ('gone'), ('rust')"#,
+        r#"INSERT INTO "taglist" ("article", "tag") VALUES (2, 2)"#,
+        r#"INSERT INTO "follows" ("source", "target") VALUES (3, 2)"#,
+        r#"INSERT INTO "mutes" ("source", "target") VALUES (2, 3)"#,
+        r#"INSERT INTO "favorites" ("source", "target") VALUES (3, 2)"#,
+        r#"INSERT INTO "comments" ("article", "body", "author") VALUES (2, 'Nice', 3)"#,
+        r#"DELETE FROM "tags" WHERE "name"='gone'"#,
+    ]);
+    // Leave gaps in the ids so that the import has to remap them
+    source.json(&["user", "delete", "removed"]);
+
+    let dump = source.run(&["export"]).stdout;
+    let mut lines = dump.split(|&b| b == b'\n');
+    assert_eq!(
+        serde_json::from_slice::<Value>(lines.next().unwrap()).unwrap(),
+        json!({ "format": "realworld", "version": 1 })
+    );
+
+    let target = Database::new("import");
+    let path = target.0.file("ndjson");
+    std::fs::write(&path, &dump).unwrap();
+
+    let summary = target.json(&["import", path.to_str().unwrap()]);
+    assert_eq!(
+        summary,
+        json!({
+            "users": 2,
+            "follows": 1,
+            "blocks": 0,
+            "mutes": 1,
+            "tags": 1,
+            "articles": 1,
+            "taglist": 1,
+            "favorites": 1,
+            "comments": 1,
+        })
+    );
+
+    let comments: Vec<(String, String, String)> = target.fetch_all(
+        r#"
+            SELECT "slug", "username", "comments"."body"
+            FROM "comments"
+            JOIN "articles" ON "articles"."id"="comments"."article"
+            JOIN "users" ON "users"."id"="comments"."author"
+        "#,
+    );
+    assert_eq!(
+        comments,
+        [("one".to_owned(), "celeb".to_owned(), "Nice".to_owned())]
+    );
+
+    // Importing twice would duplicate everything
+    let output = target.run(&["import", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("empty database"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn import_rejects_dangling_references() {
+    let db = Database::new("dangling");
+    let path = db.0.file("ndjson");
+    std::fs::write(
+        &path,
+        [
+            r#"{"format":"realworld","version":1}"#,
+            r#"{"type":"user","id":1,"email":"a@a.a","password":"a","username":"a","bio":null,"image":null,"role":"user"}"#,
+            r#"{"type":"follow","source":1,"target":2}"#,
+        ]
+        .join("\n"),
+    )
+    .unwrap();
+
+    let output = db.run(&["import", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 3: unknown user 2"));
+
+    // Nothing of the dump is kept
+    let users: Vec<(i64,)> = db.fetch_all(r#"SELECT "id" FROM "users""#);
+    assert!(users.is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn import_names_the_line_of_rejected_records() {
+    let db = Database::new("rejected");
+    let path = db.0.file("ndjson");
+    std::fs::write(
+        &path,
+        [
+            r#"{"format":"realworld","version":1}"#,
+            r#"{"type":"user","id":1,"email":"a@a.a","password":"a","username":"a","bio":null,"image":null,"role":"user"}"#,
+            r#"{"type":"user","id":2,"email":"b@b.b","password":"b","username":"b","bio":null,"image":null,"role":"user"}"#,
+            r#"{"type":"block","source":1,"target":2}"#,
+            r#"{"type":"block","source":1,"target":2}"#,
+        ]
+        .join("\n"),
+    )
+    .unwrap();
+
+    let output = db.run(&["import", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("line 5: "),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(not(feature = "postgres"))]
+#[test]
+fn backup() {
+    let db = Database::new("backup");
+    db.json(&["user", "create", "jake", "jake@jake.jake", "jakejake"]);
+
+    let dir = db.0.file("backups");
+    let backup = db.json(&["backup", "--backup-dir", dir.to_str().unwrap()]);
+
+    let users: Vec<(String,)> = fetch_all(
+        &format!("sqlite:{}", backup["file"].as_str().unwrap()),
+        r#"SELECT "username" FROM "users""#,
+    );
+    assert_eq!(users, [(String::from("jake"),)]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn arguments_are_unambiguous() {
+    use clap::CommandFactory;
+
+    realworld::cli::Cli::command().debug_assert();
+}
+
+#[test]
+fn webhook_settings_are_bounded() {
+    use clap::Parser;
+    use realworld::cli::Cli;
+
+    for (argument, value) in [
+        ("--webhook-attempts", "0"),
+        ("--webhook-backoff", "0"),
+        ("--webhook-backoff", "9223372036854775807"),
+        ("--webhook-timeout", "0"),
+    ] {
+        assert!(Cli::try_parse_from(["realworld", "serve", argument, value]).is_err());
+    }
+
+    assert!(Cli::try_parse_from(["realworld", "serve", "--webhook-backoff", "60"]).is_ok());
+}
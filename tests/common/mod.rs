@@ -0,0 +1,456 @@
+#![allow(dead_code)]
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use realworld::mail::MaildirMailer;
+use realworld::media::{self, LocalMediaStore};
+use realworld::{backup, database, database::Pool, repo::Repos, router, AppState};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// An empty database for a single test, removed again once the test is done
+///
+/// With the `postgres` feature, `DATABASE_URL` has to point to a server on
+/// which databases may be created, e.g. `postgres://postgres@localhost/postgres`.
+pub struct TestDatabase {
+    pub url: String,
+    /// Unique per test, also used to name files belonging to the test
+    pub name: String,
+}
+
+impl TestDatabase {
+    pub fn new(prefix: &str) -> Self {
+        static DATABASES: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "realworld_{}_{}_{}",
+            prefix,
+            std::process::id(),
+            DATABASES.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let database = TestDatabase {
+            url: String::new(),
+            name,
+        };
+        database.create()
+    }
+
+    /// Path of a file in the temporary directory which belongs to the test
+    pub fn file(&self, extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}.{}", self.name, extension))
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    fn create(mut self) -> Self {
+        let _ = std::fs::remove_file(self.file("db"));
+        self.url = format!("sqlite:{}", self.file("db").display());
+        self
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    fn remove(&self) {
+        let _ = std::fs::remove_file(self.file("db"));
+        // Left behind by commands which fail in the middle of a transaction
+        let _ = std::fs::remove_file(self.file("db-journal"));
+    }
+
+    #[cfg(feature = "postgres")]
+    fn create(mut self) -> Self {
+        let server = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let (base, _) = server.rsplit_once('/').unwrap();
+        self.url = format!("{}/{}", base, self.name);
+
+        server_execute(server, format!(r#"CREATE DATABASE "{}""#, self.name));
+        self
+    }
+
+    #[cfg(feature = "postgres")]
+    fn remove(&self) {
+        server_execute(
+            std::env::var("DATABASE_URL").unwrap(),
+            format!(r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#, self.name),
+        );
+    }
+}
+
+/// Run a statement on the database server outside of any test database.
+/// This happens on its own runtime, so it also works from `Drop` and from
+/// synchronous tests.
+#[cfg(feature = "postgres")]
+fn server_execute(url: String, sql: String) {
+    use sqlx::{Connection, Executor};
+
+    std::thread::spawn(move || {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut connection = sqlx::PgConnection::connect(&url).await.unwrap();
+            connection.execute(sql.as_str()).await.unwrap();
+        })
+    })
+    .join()
+    .unwrap();
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// The whole application running in-process on top of a fresh database
+pub struct TestApp {
+    router: Router,
+    /// For calling into the application directly, e.g. to run its workers
+    pub state: Arc<AppState>,
+    pub repos: Repos,
+    db: Option<Pool>,
+    /// Holds everything else the application writes
+    pub dir: PathBuf,
+    mailer: MaildirMailer,
+    database: Option<TestDatabase>,
+}
+
+impl TestApp {
+    /// Set `TEST_STORE=memory` to run the application on top of the
+    /// in-memory fake instead
+    pub async fn new() -> Self {
+        TestApp::configured(|_| {}).await
+    }
+
+    /// Like `new`, with changes to the state of the application such as its
+    /// configuration
+    pub async fn configured(configure: impl FnOnce(&mut AppState)) -> Self {
+        if std::env::var("TEST_STORE").as_deref() == Ok("memory") {
+            return TestApp::with_repos(Repos::memory(), None, None, configure);
+        }
+
+        let database = TestDatabase::new("test");
+        let db = database::connect(&database.url).await.unwrap();
+
+        TestApp::with_repos(Repos::sql(db.clone()), Some(db), Some(database), configure)
+    }
+
+    /// The application on top of the in-memory fake of the repositories
+    pub async fn in_memory() -> Self {
+        TestApp::with_repos(Repos::memory(), None, None, |_| {})
+    }
+
+    fn with_repos(
+        repos: Repos,
+        db: Option<Pool>,
+        database: Option<TestDatabase>,
+        configure: impl FnOnce(&mut AppState),
+    ) -> Self {
+        static APPS: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "realworld-app-{}-{}",
+            std::process::id(),
+            APPS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mailer = MaildirMailer::new(dir.join("mail"), "test@realworld.test".parse().unwrap());
+
+        let mut state = AppState {
+            repos: repos.clone(),
+            db: db.clone(),
+            backups: backup::Config {
+                dir: dir.join("backups"),
+                keep: 2,
+            },
+            mailer: Arc::new(mailer.clone()),
+            media: media::Config {
+                dir: dir.join("media"),
+                url: String::from("http://realworld.test/media"),
+                max_size: 1024 * 1024,
+            },
+            media_store: Arc::new(LocalMediaStore::new(dir.join("media"))),
+            attachments: Default::default(),
+            sessions: Default::default(),
+            keys: Default::default(),
+            lockout: Default::default(),
+            verification: Default::default(),
+            events: Default::default(),
+            webhooks: Default::default(),
+            oidc: Default::default(),
+        };
+        configure(&mut state);
+        let state = Arc::new(state);
+
+        TestApp {
+            router: router(state.clone()),
+            state,
+            repos,
+            db,
+            dir,
+            mailer,
+            database,
+        }
+    }
+
+    /// The database behind the application, not available in memory
+    pub fn db(&self) -> &Pool {
+        self.db.as_ref().expect("the application runs in memory")
+    }
+
+    /// Every mail the application sent, oldest first
+    pub fn mail(&self) -> Vec<String> {
+        self.mailer.messages().unwrap()
+    }
+
+    /// The token in the last mail the application sent
+    pub fn mailed_token(&self) -> String {
+        let mail = self.mail();
+        let message = mail.last().expect("no mail was sent");
+
+        message
+            .lines()
+            .map(str::trim)
+            .find(|line| line.len() == 64 && line.chars().all(|c| c.is_ascii_hexdigit()))
+            .expect("the mail contains no token")
+            .to_owned()
+    }
+
+    /// Send a request and return the status together with the decoded body.
+    /// Empty bodies decode to `null` and bodies which are not JSON are
+    /// returned as a string.
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        let request = if let Some(body) = body {
+            request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+        } else {
+            request.body(Body::empty())
+        }
+        .unwrap();
+
+        self.send(request).await
+    }
+
+    /// Send a prepared request, see `request`
+    pub async fn send(&self, request: Request<Body>) -> (StatusCode, Value) {
+        let (status, _, bytes) = self.send_raw(request).await;
+
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        };
+
+        (status, body)
+    }
+
+    /// Send a prepared request and return the response as it is
+    pub async fn send_raw(&self, request: Request<Body>) -> (StatusCode, HeaderMap, Bytes) {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, headers, bytes)
+    }
+
+    pub async fn get(&self, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        self.request(Method::GET, uri, token, None).await
+    }
+
+    pub async fn post(&self, uri: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+        self.request(Method::POST, uri, token, Some(body)).await
+    }
+
+    pub async fn put(&self, uri: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+        self.request(Method::PUT, uri, token, Some(body)).await
+    }
+
+    pub async fn delete(&self, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        self.request(Method::DELETE, uri, token, None).await
+    }
+
+    /// Register a user and return its token
+    pub async fn register(&self, username: &str) -> String {
+        let (status, body) = self
+            .post(
+                "/api/users",
+                None,
+                json!({
+                    "user": {
+                        "username": username,
+                        "email": format!("{}@example.com", username),
+                        "password": "password",
+                    }
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        body["user"]["token"].as_str().unwrap().to_owned()
+    }
+
+    /// Create an article as the owner of `token` and return its slug
+    pub async fn create_article(&self, token: &str, title: &str, tags: &[&str]) -> String {
+        let (status, body) = self
+            .post(
+                "/api/articles",
+                Some(token),
+                json!({
+                    "article": {
+                        "title": title,
+                        "description": "Ever wonder how?",
+                        "body": "Very carefully.",
+                        "tagList": tags,
+                    }
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        body["article"]["slug"].as_str().unwrap().to_owned()
+    }
+
+    /// Open a stream of Server-Sent Events, resuming after `last_event_id`
+    pub async fn events(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+        last_event_id: Option<&str>,
+    ) -> EventStream {
+        let mut request = Request::builder().uri(uri);
+
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        if let Some(id) = last_event_id {
+            request = request.header("last-event-id", id);
+        }
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        EventStream {
+            body: response.into_body(),
+            buffer: String::new(),
+        }
+    }
+}
+
+/// A response body read one Server-Sent Event at a time
+pub struct EventStream {
+    body: Body,
+    buffer: String,
+}
+
+/// `id`, `event` and the decoded `data` of a Server-Sent Event
+#[derive(Debug)]
+pub struct ServerEvent {
+    pub id: String,
+    pub event: String,
+    pub data: Value,
+}
+
+impl EventStream {
+    /// Wait for the next event, skipping keep-alive comments
+    pub async fn next(&mut self) -> ServerEvent {
+        loop {
+            if let Some((block, rest)) = self.buffer.split_once("\n\n") {
+                let block = block.to_owned();
+                self.buffer = rest.to_owned();
+                let field = |name: &str| {
+                    block
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                        .map(|value| value.trim_start().to_owned())
+                };
+
+                if let Some(data) = field("data") {
+                    return ServerEvent {
+                        id: field("id").unwrap_or_default(),
+                        event: field("event").unwrap_or_default(),
+                        data: serde_json::from_str(&data).unwrap(),
+                    };
+                }
+
+                continue;
+            }
+
+            let frame = tokio::time::timeout(Duration::from_secs(5), self.body.frame())
+                .await
+                .expect("no event within five seconds")
+                .expect("the stream ended")
+                .unwrap();
+
+            if let Ok(data) = frame.into_data() {
+                self.buffer.push_str(std::str::from_utf8(&data).unwrap());
+            }
+        }
+    }
+
+    /// Whether an event arrives within a short while
+    pub async fn is_quiet(&mut self) -> bool {
+        tokio::time::timeout(Duration::from_millis(200), self.next())
+            .await
+            .is_err()
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Replace values which change between runs so that payloads can be
+/// compared against snapshots. Timestamps are checked for the ISO 8601
+/// format required by the specification before they are redacted.
+#[macro_export]
+macro_rules! assert_payload {
+    ($value:expr) => {
+        insta::assert_json_snapshot!($value, {
+            ".**.token" => "[token]",
+            ".**.refreshToken" => "[token]",
+            ".**.createdAt" => insta::dynamic_redaction(common::timestamp),
+            ".**.updatedAt" => insta::dynamic_redaction(common::timestamp),
+        })
+    };
+}
+
+pub fn timestamp(value: insta::internals::Content, _: insta::internals::ContentPath) -> String {
+    let value = value.as_str().unwrap();
+
+    // e.g. `2025-01-28T17:43:41.000Z`
+    assert_eq!(value.len(), 24, "{} is not a timestamp", value);
+    assert_eq!(&value[10..11], "T", "{} is not a timestamp", value);
+    assert!(value.ends_with('Z'), "{} is not a timestamp", value);
+
+    String::from("[timestamp]")
+}
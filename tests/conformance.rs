@@ -0,0 +1,618 @@
+//! Port of the scenarios in the official RealWorld Postman collection
+//!
+//! Every test runs against its own temporary database and compares the
+//! payloads against the snapshots in `tests/snapshots`.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use serde_json::{json, Value};
+
+// Auth
+
+#[tokio::test]
+async fn register() {
+    let app = TestApp::new().await;
+
+    let (status, body) = app
+        .post(
+            "/api/users",
+            None,
+            json!({
+                "user": {
+                    "email": "jake@jake.jake",
+                    "password": "jakejake",
+                    "username": "jake",
+                }
+            }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn login() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+
+    let (status, body) = app
+        .post(
+            "/api/users/login",
+            None,
+            json!({
+                "user": {
+                    "email": "jake@example.com",
+                    "password": "password",
+                }
+            }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn current_user() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    let (status, body) = app.get("/api/user", Some(&token)).await;
+
+    assert_eq!(status, StatusCode::OK);
+    // The token in the response must be usable as is
+    assert_eq!(body["user"]["token"], token.as_str());
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn update_user() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    let (status, body) = app
+        .put(
+            "/api/user",
+            Some(&token),
+            json!({
+                "user": {
+                    "email": "jake@jake.jake",
+                    "bio": "I like to skateboard",
+                    "image": "https://i.stack.imgur.com/xHWG8.jpg",
+                }
+            }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+// Profiles
+
+#[tokio::test]
+async fn profile() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.register("celeb").await;
+
+    let (status, body) = app.get("/api/profiles/celeb", Some(&token)).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn follow_and_unfollow_profile() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.register("celeb").await;
+
+    let (status, body) = app
+        .post("/api/profiles/celeb/follow", Some(&token), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+
+    let (status, body) = app.delete("/api/profiles/celeb/follow", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+// Articles
+
+#[tokio::test]
+async fn create_article() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    let (status, body) = app
+        .post(
+            "/api/articles",
+            Some(&token),
+            json!({
+                "article": {
+                    "title": "How to train your dragon",
+                    "description": "Ever wonder how?",
+                    "body": "Very carefully.",
+                    "tagList": ["training", "dragons"],
+                }
+            }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn single_article_by_slug() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    let slug = app
+        .create_article(&token, "How to train your dragon", &["dragons"])
+        .await;
+
+    let (status, body) = app.get(&format!("/api/articles/{}", slug), None).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn update_article() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    let slug = app
+        .create_article(&token, "How to train your dragon", &["dragons"])
+        .await;
+
+    let (status, body) = app
+        .put(
+            &format!("/api/articles/{}", slug),
+            Some(&token),
+            json!({
+                "article": {
+                    "body": "With two hands",
+                }
+            }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn delete_article() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    let slug = app
+        .create_article(&token, "How to train your dragon", &["dragons"])
+        .await;
+
+    let (status, _) = app
+        .delete(&format!("/api/articles/{}", slug), Some(&token))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = app.get("/api/articles", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn all_articles() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.create_article(&token, "How to train your dragon", &["dragons", "training"])
+        .await;
+    app.create_article(&token, "How to feed your dragon", &["dragons"])
+        .await;
+
+    let (status, body) = app.get("/api/articles", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+
+    // Logged in users get the same list
+    let (status, authenticated) = app.get("/api/articles", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, authenticated);
+}
+
+#[tokio::test]
+async fn articles_by_author() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.create_article(&celeb, "How to tame your dragon", &[])
+        .await;
+
+    let (status, body) = app.get("/api/articles?author=jake", None).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn articles_by_tag() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.create_article(&token, "How to train your dragon", &["dragons", "training"])
+        .await;
+    app.create_article(&token, "How to feed your cat", &["cats"])
+        .await;
+
+    let (status, body) = app.get("/api/articles?tag=dragons", None).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn favorite_and_unfavorite_article() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let slug = app
+        .create_article(&celeb, "How to train your dragon", &["dragons"])
+        .await;
+
+    let (status, body) = app
+        .post(
+            &format!("/api/articles/{}/favorite", slug),
+            Some(&jake),
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+
+    let (status, body) = app.get("/api/articles?favorited=jake", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+
+    let (status, body) = app
+        .delete(&format!("/api/articles/{}/favorite", slug), Some(&jake))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+
+    let (status, body) = app.get("/api/articles?favorited=jake", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["articlesCount"], 0);
+}
+
+#[tokio::test]
+async fn feed() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.register("nobody").await;
+    app.create_article(&celeb, "How to train your dragon", &[])
+        .await;
+
+    let (status, body) = app.get("/api/articles/feed", Some(&jake)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["articlesCount"], 0);
+
+    app.post("/api/profiles/celeb/follow", Some(&jake), json!({}))
+        .await;
+
+    let (status, body) = app.get("/api/articles/feed", Some(&jake)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+#[tokio::test]
+async fn pagination() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.post("/api/profiles/jake/follow", Some(&token), json!({}))
+        .await;
+
+    for i in 1..=5 {
+        app.create_article(&token, &format!("Article {}", i), &[])
+            .await;
+    }
+
+    for uri in [
+        "/api/articles?limit=2",
+        "/api/articles?limit=2&offset=2",
+        "/api/articles/feed?limit=2&offset=4",
+    ] {
+        let (status, body) = app.get(uri, Some(&token)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let slugs: Vec<_> = body["articles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|article| article["slug"].clone())
+            .collect();
+
+        insta::assert_json_snapshot!(slugs);
+    }
+}
+
+// Comments
+
+#[tokio::test]
+async fn comments() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &[])
+        .await;
+    let uri = format!("/api/articles/{}/comments", slug);
+
+    let (status, body) = app
+        .post(
+            &uri,
+            Some(&celeb),
+            json!({
+                "comment": {
+                    "body": "Thank you so much!",
+                }
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+    let id = body["comment"]["id"].as_i64().unwrap();
+
+    app.post("/api/profiles/celeb/follow", Some(&jake), json!({}))
+        .await;
+
+    let (status, body) = app.get(&uri, Some(&jake)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+
+    let (status, body) = app.get(&uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+
+    let (status, _) = app.delete(&format!("{}/{}", uri, id), Some(&celeb)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = app.get(&uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "comments": [] }));
+}
+
+// Tags
+
+#[tokio::test]
+async fn all_tags() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.create_article(&token, "How to train your dragon", &["training", "dragons"])
+        .await;
+    app.create_article(&token, "How to feed your dragon", &["dragons", "food"])
+        .await;
+
+    let (status, body) = app.get("/api/tags", None).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_payload!(body);
+}
+
+// Errors
+
+#[tokio::test]
+async fn authentication_required() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    let slug = app
+        .create_article(&token, "How to train your dragon", &[])
+        .await;
+
+    let routes = [
+        ("GET", "/api/user".to_owned()),
+        ("PUT", "/api/user".to_owned()),
+        ("GET", "/api/articles/feed".to_owned()),
+        ("POST", "/api/articles".to_owned()),
+        ("PUT", format!("/api/articles/{}", slug)),
+        ("DELETE", format!("/api/articles/{}", slug)),
+        ("POST", format!("/api/articles/{}/favorite", slug)),
+        ("DELETE", format!("/api/articles/{}/favorite", slug)),
+        ("POST", format!("/api/articles/{}/comments", slug)),
+        ("DELETE", format!("/api/articles/{}/comments/1", slug)),
+        ("POST", "/api/profiles/jake/follow".to_owned()),
+        ("DELETE", "/api/profiles/jake/follow".to_owned()),
+    ];
+
+    for (method, uri) in routes {
+        let (status, _) = app.request(method.parse().unwrap(), &uri, None, None).await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+    }
+}
+
+#[tokio::test]
+async fn wrong_authorization_scheme() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+
+    let request = axum::http::Request::get("/api/articles")
+        .header("authorization", "Basic amFrZTpqYWtl")
+        .body(axum::body::Body::empty())
+        .unwrap();
+    let (status, _) = app.send(request).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// Whether `body` is an error in the shape the spec has for errors
+fn is_error(body: &Value) -> bool {
+    body["errors"]["body"]
+        .as_array()
+        .is_some_and(|messages| !messages.is_empty() && messages.iter().all(Value::is_string))
+}
+
+#[tokio::test]
+async fn duplicate_registration() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+
+    for user in [
+        json!({ "username": "jake", "email": "other@example.com", "password": "password" }),
+        json!({ "username": "other", "email": "jake@example.com", "password": "password" }),
+    ] {
+        let (status, body) = app.post("/api/users", None, json!({ "user": user })).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", user);
+        assert!(is_error(&body), "{}", body);
+    }
+
+    let token = app.register("jane").await;
+    let (status, body) = app
+        .put(
+            "/api/user",
+            Some(&token),
+            json!({ "user": { "username": "jake" } }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(is_error(&body), "{}", body);
+}
+
+#[tokio::test]
+async fn wrong_credentials() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+
+    for user in [
+        json!({ "email": "jake@example.com", "password": "wrong" }),
+        json!({ "email": "nobody@example.com", "password": "password" }),
+    ] {
+        let (status, body) = app
+            .post("/api/users/login", None, json!({ "user": user }))
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", user);
+        assert!(is_error(&body), "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn not_found() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    let routes = [
+        ("GET", "/api/articles/nothing"),
+        ("PUT", "/api/articles/nothing"),
+        ("DELETE", "/api/articles/nothing"),
+        ("POST", "/api/articles/nothing/favorite"),
+        ("DELETE", "/api/articles/nothing/favorite"),
+        ("GET", "/api/articles/nothing/comments"),
+        ("POST", "/api/articles/nothing/comments"),
+        ("DELETE", "/api/articles/nothing/comments/1"),
+        ("GET", "/api/profiles/nobody"),
+        ("POST", "/api/profiles/nobody/follow"),
+        ("DELETE", "/api/profiles/nobody/follow"),
+    ];
+    let body = json!({
+        "article": { "title": "Did you train your dragon?" },
+        "comment": { "body": "Thank you so much!" },
+    });
+
+    for (method, uri) in routes {
+        let (status, response) = app
+            .request(
+                method.parse().unwrap(),
+                uri,
+                Some(&token),
+                Some(body.clone()),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND, "{} {}", method, uri);
+        assert!(is_error(&response), "{} {}: {}", method, uri, response);
+    }
+}
+
+#[tokio::test]
+async fn changing_what_others_wrote() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let jane = app.register("jane").await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &[])
+        .await;
+    let (_, body) = app
+        .post(
+            &format!("/api/articles/{}/comments", slug),
+            Some(&jake),
+            json!({ "comment": { "body": "Thank you so much!" } }),
+        )
+        .await;
+    let comment = &body["comment"]["id"];
+
+    let (status, body) = app
+        .put(
+            &format!("/api/articles/{}", slug),
+            Some(&jane),
+            json!({ "article": { "body": "Mine now" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(is_error(&body), "{}", body);
+
+    let (status, body) = app
+        .delete(
+            &format!("/api/articles/{}/comments/{}", slug, comment),
+            Some(&jane),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(is_error(&body), "{}", body);
+}
+
+#[tokio::test]
+async fn duplicate_title() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    app.create_article(&token, "How to train your dragon", &[])
+        .await;
+
+    let (status, body) = app
+        .post(
+            "/api/articles",
+            Some(&token),
+            json!({
+                "article": {
+                    "title": "How to train your dragon",
+                    "description": "Ever wonder how?",
+                    "body": "Very carefully.",
+                }
+            }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(is_error(&body), "{}", body);
+}
+
+#[tokio::test]
+async fn database_errors_are_not_shown() {
+    let app = TestApp::new().await;
+    // Only the database can fail
+    let Some(db) = &app.state.db else {
+        return;
+    };
+    db.close().await;
+
+    let (status, body) = app.get("/api/articles", None).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, json!({ "errors": { "body": ["internal error"] } }));
+}
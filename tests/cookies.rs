@@ -0,0 +1,286 @@
+//! Sessions of browsers, which keep their tokens in cookies
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+async fn app() -> TestApp {
+    TestApp::configured(|state| state.sessions.cookies = true).await
+}
+
+/// The cookies a response sets, by name
+fn set_cookies(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|value| {
+            let value = value.to_str().unwrap();
+            let (name, _) = value.split_once('=').unwrap();
+
+            (name.to_owned(), value.to_owned())
+        })
+        .collect()
+}
+
+/// The value of a cookie from its `Set-Cookie` header
+fn value(set_cookie: &str) -> &str {
+    set_cookie
+        .split(';')
+        .next()
+        .unwrap()
+        .split_once('=')
+        .unwrap()
+        .1
+}
+
+/// Send a request like a browser with `cookies`, and a CSRF token if given
+async fn send(
+    app: &TestApp,
+    method: Method,
+    uri: &str,
+    cookies: &HashMap<String, String>,
+    csrf: Option<&str>,
+    body: Value,
+) -> (StatusCode, HeaderMap, Value) {
+    let cookie = cookies
+        .values()
+        .map(|set_cookie| set_cookie.split(';').next().unwrap())
+        .collect::<Vec<_>>()
+        .join("; ");
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::COOKIE, cookie);
+    if let Some(csrf) = csrf {
+        request = request.header("x-csrf-token", csrf);
+    }
+
+    let (status, headers, bytes) = app
+        .send_raw(request.body(Body::from(body.to_string())).unwrap())
+        .await;
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+
+    (status, headers, body)
+}
+
+/// Register jake, returns the cookies set
+async fn register(app: &TestApp) -> HashMap<String, String> {
+    let registration = json!({
+        "user": { "username": "jake", "email": "jake@example.com", "password": "password" }
+    });
+    let (status, headers, body) = send(
+        app,
+        Method::POST,
+        "/api/users",
+        &HashMap::new(),
+        None,
+        registration,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["token"], "");
+    assert_eq!(body["user"].get("refreshToken"), None);
+
+    set_cookies(&headers)
+}
+
+/// Log in as jake, returns the cookies set
+async fn login(app: &TestApp) -> HashMap<String, String> {
+    let (status, headers, _) = send(
+        app,
+        Method::POST,
+        "/api/users/login",
+        &HashMap::new(),
+        None,
+        json!({ "user": { "email": "jake@example.com", "password": "password" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    set_cookies(&headers)
+}
+
+#[tokio::test]
+async fn logging_in_sets_cookies() {
+    let app = app().await;
+    let cookies = register(&app).await;
+
+    let token = &cookies["realworld_token"];
+    assert!(token.contains("; Path=/;"));
+    assert!(token.contains("; Max-Age=900;"));
+    assert!(token.contains("; SameSite=Lax"));
+    assert!(token.contains("; HttpOnly"));
+    assert!(token.contains("; Secure"));
+    // Scripts read the CSRF token
+    assert!(!cookies["realworld_csrf"].contains("HttpOnly"));
+    assert!(cookies["realworld_refresh"].contains("; Path=/api/users/refresh;"));
+
+    let (status, _, body) = send(&app, Method::GET, "/api/user", &cookies, None, json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+    assert_eq!(body["user"]["token"], "");
+
+    let (status, headers, body) = send(
+        &app,
+        Method::POST,
+        "/api/users/login",
+        &HashMap::new(),
+        None,
+        json!({ "user": { "email": "jake@example.com", "password": "password" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["token"], "");
+    let (status, _, _) = send(
+        &app,
+        Method::GET,
+        "/api/user",
+        &set_cookies(&headers),
+        None,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn cookies_are_ignored_unless_enabled() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    let cookies = HashMap::from([(
+        String::from("realworld_token"),
+        format!("realworld_token={}", token),
+    )]);
+
+    let (status, _, _) = send(&app, Method::GET, "/api/user", &cookies, None, json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn changes_take_the_csrf_token() {
+    let app = app().await;
+    let cookies = register(&app).await;
+    let csrf = value(&cookies["realworld_csrf"]).to_owned();
+    let update = json!({ "user": { "bio": "I work at statefarm" } });
+
+    for wrong in [None, Some("made-up")] {
+        let (status, _, _) = send(
+            &app,
+            Method::PUT,
+            "/api/user",
+            &cookies,
+            wrong,
+            update.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    let (status, _, body) = send(
+        &app,
+        Method::PUT,
+        "/api/user",
+        &cookies,
+        Some(&csrf),
+        update,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["bio"], "I work at statefarm");
+
+    // The token of another session does not fit
+    let other = login(&app).await;
+    let (status, _, _) = send(
+        &app,
+        Method::POST,
+        "/api/profiles/jake/follow",
+        &cookies,
+        Some(value(&other["realworld_csrf"])),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn refreshing_and_logging_out_use_the_cookies() {
+    let app = app().await;
+    let cookies = register(&app).await;
+
+    let (status, headers, body) = send(
+        &app,
+        Method::POST,
+        "/api/users/refresh",
+        &cookies,
+        None,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+    let refreshed = set_cookies(&headers);
+    assert_ne!(
+        value(&refreshed["realworld_refresh"]),
+        value(&cookies["realworld_refresh"])
+    );
+
+    // Every refresh token works once
+    let (status, _, _) = send(
+        &app,
+        Method::POST,
+        "/api/users/refresh",
+        &cookies,
+        None,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let csrf = value(&refreshed["realworld_csrf"]).to_owned();
+    let (status, headers, _) = send(
+        &app,
+        Method::POST,
+        "/api/users/logout",
+        &refreshed,
+        Some(&csrf),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let cleared = set_cookies(&headers);
+    assert_eq!(cleared.len(), 3);
+    assert!(cleared
+        .values()
+        .all(|cookie| cookie.contains("; Max-Age=0;")));
+
+    let (status, _, _) = send(&app, Method::GET, "/api/user", &refreshed, None, json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_removes_the_cookies() {
+    let app = app().await;
+    let cookies = register(&app).await;
+    let csrf = value(&cookies["realworld_csrf"]).to_owned();
+
+    let (status, headers, _) = send(
+        &app,
+        Method::DELETE,
+        "/api/user/sessions",
+        &cookies,
+        Some(&csrf),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let cleared = set_cookies(&headers);
+    assert_eq!(cleared.len(), 3);
+    assert!(cleared
+        .values()
+        .all(|cookie| cookie.contains("; Max-Age=0;")));
+}
@@ -0,0 +1,125 @@
+//! Comments and notifications streamed as Server-Sent Events
+
+mod common;
+
+use common::TestApp;
+use serde_json::json;
+
+async fn comment(app: &TestApp, token: &str, body: &str) -> i64 {
+    let (_, response) = app
+        .post(
+            "/api/articles/how-to-train-your-dragon/comments",
+            Some(token),
+            json!({ "comment": { "body": body } }),
+        )
+        .await;
+
+    response["comment"]["id"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn new_and_deleted_comments_are_streamed() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.create_article(&jake, "Dragon riding", &[]).await;
+
+    let mut events = app
+        .events(
+            "/api/articles/how-to-train-your-dragon/comments/stream",
+            None,
+            None,
+        )
+        .await;
+
+    // Other articles do not show up
+    app.post(
+        "/api/articles/dragon-riding/comments",
+        Some(&celeb),
+        json!({ "comment": { "body": "Elsewhere" } }),
+    )
+    .await;
+    let id = comment(&app, &celeb, "Thank you so much!").await;
+
+    let event = events.next().await;
+    assert_eq!(event.event, "comment");
+    assert_eq!(event.data["comment"]["id"], id);
+    assert_eq!(event.data["comment"]["body"], "Thank you so much!");
+    assert_eq!(event.data["comment"]["author"]["username"], "celeb");
+
+    app.delete(
+        &format!("/api/articles/how-to-train-your-dragon/comments/{}", id),
+        Some(&celeb),
+    )
+    .await;
+
+    let event = events.next().await;
+    assert_eq!(event.event, "comment-deleted");
+    assert_eq!(event.data, json!({ "id": id }));
+}
+
+#[tokio::test]
+async fn streams_resume_after_the_last_event_id() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    let uri = "/api/articles/how-to-train-your-dragon/comments/stream";
+
+    let mut events = app.events(uri, None, None).await;
+    comment(&app, &jake, "First").await;
+    let first = events.next().await;
+    drop(events);
+
+    // Missed while disconnected
+    comment(&app, &jake, "Second").await;
+    comment(&app, &jake, "Third").await;
+
+    let mut events = app.events(uri, None, Some(&first.id)).await;
+    assert_eq!(events.next().await.data["comment"]["body"], "Second");
+    assert_eq!(events.next().await.data["comment"]["body"], "Third");
+    comment(&app, &jake, "Fourth").await;
+    assert_eq!(events.next().await.data["comment"]["body"], "Fourth");
+}
+
+#[tokio::test]
+async fn notifications_are_streamed_to_their_user() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let fan = app.register("fan").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.post("/api/profiles/fan/mute", Some(&jake), json!({}))
+        .await;
+
+    let mut jakes = app
+        .events("/api/user/notifications/stream", Some(&jake), None)
+        .await;
+    let mut celebs = app
+        .events("/api/user/notifications/stream", Some(&celeb), None)
+        .await;
+    let mut comments = app
+        .events(
+            "/api/articles/how-to-train-your-dragon/comments/stream",
+            Some(&jake),
+            None,
+        )
+        .await;
+
+    comment(&app, &fan, "Muted").await;
+    comment(&app, &celeb, "Thank you so much!").await;
+
+    let event = jakes.next().await;
+    assert_eq!(event.event, "notification");
+    assert_eq!(event.data["notification"]["kind"], "comment");
+    assert_eq!(event.data["notification"]["actor"]["username"], "celeb");
+
+    // The muted comment is left out of the stream as well
+    let event = comments.next().await;
+    assert_eq!(event.data["comment"]["body"], "Thank you so much!");
+
+    assert!(celebs.is_quiet().await);
+}
@@ -0,0 +1,164 @@
+//! Signing tokens with configured keys and publishing them as a JWKS
+
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use realworld::keys::{self, Error};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+fn key_file(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/keys")
+        .join(name)
+}
+
+fn config(keys: &[(&str, &str)], signing_key: Option<&str>) -> keys::Config {
+    keys::Config {
+        keys: keys
+            .iter()
+            .map(|&(kid, name)| (kid.to_owned(), key_file(name)))
+            .collect(),
+        signing_key: signing_key.map(str::to_owned),
+    }
+}
+
+async fn app(keys: &[(&str, &str)], signing_key: Option<&str>) -> TestApp {
+    let key_ring = config(keys, signing_key).key_ring().unwrap();
+
+    TestApp::configured(|state| state.keys = key_ring).await
+}
+
+/// The claims of `token` without checking its signature
+fn claims(token: &str) -> Value {
+    let mut validation = Validation::new(decode_header(token).unwrap().alg);
+    validation.insecure_disable_signature_validation();
+
+    decode::<Value>(token, &DecodingKey::from_secret(&[]), &validation)
+        .unwrap()
+        .claims
+}
+
+#[tokio::test]
+async fn others_can_verify_tokens_with_the_jwks() {
+    for (name, algorithm) in [
+        ("rsa.pem", Algorithm::RS256),
+        ("rsa-pkcs1.pem", Algorithm::RS256),
+        ("ed25519.pem", Algorithm::EdDSA),
+    ] {
+        let app = app(&[("current", name)], None).await;
+        let token = app.register("jake").await;
+
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.alg, algorithm);
+        assert_eq!(header.kid.as_deref(), Some("current"));
+
+        let (status, body) = app.get("/.well-known/jwks.json", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let jwks: JwkSet = serde_json::from_value(body).unwrap();
+        let jwk = jwks.find("current").unwrap();
+        let key = DecodingKey::from_jwk(jwk).unwrap();
+        let verified = decode::<Value>(&token, &key, &Validation::new(algorithm)).unwrap();
+        assert_eq!(verified.claims["user_id"], claims(&token)["user_id"]);
+
+        let (status, _) = app.get("/api/user", Some(&token)).await;
+        assert_eq!(status, StatusCode::OK, "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn retired_keys_still_verify() {
+    let app = app(
+        &[("2026-10", "rsa.pem"), ("2026-04", "ed25519.pub.pem")],
+        Some("2026-10"),
+    )
+    .await;
+    let token = app.register("jake").await;
+    assert_eq!(
+        decode_header(&token).unwrap().kid.as_deref(),
+        Some("2026-10")
+    );
+
+    let (_, body) = app.get("/.well-known/jwks.json", None).await;
+    let kids: Vec<_> = body["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|key| (key["kid"].clone(), key["kty"].clone(), key["alg"].clone()))
+        .collect();
+    assert_eq!(
+        kids,
+        [
+            (json!("2026-10"), json!("RSA"), json!("RS256")),
+            (json!("2026-04"), json!("OKP"), json!("EdDSA")),
+        ]
+    );
+
+    // As if it was issued before the rotation
+    let pem = std::fs::read(key_file("ed25519.pem")).unwrap();
+    let (_, der) = pem_rfc7468::decode_vec(&pem).unwrap();
+    let header = Header {
+        kid: Some(String::from("2026-04")),
+        ..Header::new(Algorithm::EdDSA)
+    };
+    let old = encode(&header, &claims(&token), &EncodingKey::from_ed_der(&der)).unwrap();
+    let (status, _) = app.get("/api/user", Some(&old)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Tokens signed with the development secret stop working
+    let header = Header::new(Algorithm::HS256);
+    let development = encode(
+        &header,
+        &claims(&token),
+        &EncodingKey::from_secret(b"nuclear launch codes"),
+    )
+    .unwrap();
+    let (status, body) = app.get("/api/user", Some(&development)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body["errors"]["body"][0]
+        .as_str()
+        .unwrap()
+        .contains("unknown key"));
+}
+
+#[tokio::test]
+async fn without_keys_nothing_is_published() {
+    let app = TestApp::new().await;
+
+    let (status, body) = app.get("/.well-known/jwks.json", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "keys": [] }));
+}
+
+#[test]
+fn key_rings_are_checked() {
+    let error = config(&[("old", "ed25519.pub.pem")], None)
+        .key_ring()
+        .unwrap_err();
+    assert!(matches!(error, Error::PublicSigningKey(_)), "{}", error);
+
+    let error = config(&[("current", "rsa.pem")], Some("next"))
+        .key_ring()
+        .unwrap_err();
+    assert!(matches!(error, Error::UnknownSigningKey(_)), "{}", error);
+
+    let error = config(&[("a", "rsa.pem"), ("a", "ed25519.pem")], None)
+        .key_ring()
+        .unwrap_err();
+    assert!(matches!(error, Error::DuplicateKid(_)), "{}", error);
+
+    let error = config(&[("current", "../auth.rs")], None)
+        .key_ring()
+        .unwrap_err();
+    assert!(matches!(error, Error::InvalidKey(_)), "{}", error);
+
+    let error = config(&[("current", "missing.pem")], None)
+        .key_ring()
+        .unwrap_err();
+    assert!(matches!(error, Error::Io(..)), "{}", error);
+}
@@ -0,0 +1,178 @@
+//! Throttling failed logins per account and per address
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use common::TestApp;
+use realworld::database::Role;
+use serde_json::{json, Value};
+
+/// Log in from `ip`, returns the status and the `Retry-After` header of the
+/// response
+async fn login(app: &TestApp, email: &str, password: &str, ip: &str) -> (StatusCode, i64) {
+    let request = Request::post("/api/users/login")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("x-forwarded-for", ip)
+        .body(Body::from(
+            json!({ "user": { "email": email, "password": password } }).to_string(),
+        ))
+        .unwrap();
+    let (status, headers, _) = app.send_raw(request).await;
+    let retry_after = headers
+        .get(header::RETRY_AFTER)
+        .map(|value| value.to_str().unwrap().parse().unwrap())
+        .unwrap_or_default();
+
+    (status, retry_after)
+}
+
+async fn app(attempts: i64, ip_attempts: i64, delay: i64) -> TestApp {
+    let app = TestApp::configured(|state| {
+        state.sessions.trust_proxy = true;
+        state.lockout.attempts = attempts;
+        state.lockout.ip_attempts = ip_attempts;
+        state.lockout.delay = delay;
+    })
+    .await;
+    app.register("jake").await;
+    app.register("jane").await;
+
+    app
+}
+
+const JAKE: &str = "jake@example.com";
+const JANE: &str = "jane@example.com";
+const IP: &str = "192.0.2.7";
+
+#[tokio::test]
+async fn failed_logins_are_delayed_progressively() {
+    let app = app(5, 20, 60).await;
+
+    let (status, _) = login(&app, JAKE, "wrong", IP).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Not even the right password gets through until the delay is over
+    let (status, retry_after) = login(&app, JAKE, "password", IP).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!((59..=60).contains(&retry_after), "{}", retry_after);
+
+    // Other accounts are not held back by a single failure from the address
+    let (status, _) = login(&app, JANE, "password", IP).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn accounts_are_locked_after_too_many_failures() {
+    let app = app(3, 20, 0).await;
+
+    // A successful login starts the count over
+    for password in ["wrong", "wrong", "password", "wrong", "wrong"] {
+        login(&app, JAKE, password, IP).await;
+    }
+    let (status, _) = login(&app, JAKE, "password", IP).await;
+    assert_eq!(status, StatusCode::OK);
+
+    for ip in ["192.0.2.1", "192.0.2.2", "192.0.2.3"] {
+        let (status, _) = login(&app, JAKE, "wrong", ip).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let (status, retry_after) = login(&app, JAKE, "password", "192.0.2.4").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(retry_after > 15 * 60 - 2, "{}", retry_after);
+
+    let (status, _) = login(&app, JANE, "password", "192.0.2.1").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn addresses_are_locked_after_too_many_failures() {
+    let app = app(5, 3, 0).await;
+
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        let (status, _) = login(&app, email, "password", IP).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    let (status, _) = login(&app, JAKE, "password", IP).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, _) = login(&app, JAKE, "password", "192.0.2.8").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn failed_logins_are_audited() {
+    let app = app(1, 20, 0).await;
+
+    login(&app, "nobody@example.com", "password", IP).await;
+    login(&app, JAKE, "wrong", IP).await;
+    login(&app, JAKE, "password", IP).await;
+
+    let token = app.register("admin").await;
+    let (status, _) = app.get("/api/admin/login-failures", Some(&token)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let admin = app
+        .repos
+        .users
+        .find_by_username("admin")
+        .await
+        .unwrap()
+        .unwrap();
+    app.repos
+        .users
+        .set_role(admin.id, Role::Admin)
+        .await
+        .unwrap();
+
+    let (status, body) = app.get("/api/admin/login-failures", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let failures: Vec<Value> = body["loginFailures"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|failure| {
+            json!([
+                failure["email"],
+                failure["knownUser"],
+                failure["ip"],
+                failure["reason"]
+            ])
+        })
+        .collect();
+    assert_eq!(
+        failures,
+        [
+            json!([JAKE, true, IP, "throttled"]),
+            json!([JAKE, true, IP, "invalid_credentials"]),
+            json!(["nobody@example.com", false, IP, "invalid_credentials"]),
+        ]
+    );
+
+    let (_, body) = app
+        .get("/api/admin/login-failures?limit=1", Some(&token))
+        .await;
+    assert_eq!(body["loginFailures"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn concurrent_logins_are_throttled_as_well() {
+    let app = std::sync::Arc::new(app(5, 20, 60).await);
+
+    let mut logins = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let app = app.clone();
+        logins.spawn(async move { login(&app, JAKE, "wrong", IP).await.0 });
+    }
+    let statuses = logins.join_all().await;
+
+    // Only one guess gets to the password, the others have to wait for it
+    let guessed = statuses
+        .iter()
+        .filter(|&&status| status == StatusCode::UNAUTHORIZED)
+        .count();
+    assert_eq!(guessed, 1, "{:?}", statuses);
+    assert!(statuses.iter().all(
+        |&status| status == StatusCode::UNAUTHORIZED || status == StatusCode::TOO_MANY_REQUESTS
+    ));
+}
@@ -0,0 +1,208 @@
+//! Avatar uploads and serving the stored files
+
+mod common;
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, Method, Request, StatusCode};
+use common::TestApp;
+use image::{ImageFormat, Rgb, RgbImage};
+use serde_json::{json, Value};
+use std::io::Cursor;
+
+const BOUNDARY: &str = "realworld-boundary";
+
+/// A `width` × `height` image encoded as `format`
+fn image(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 128]));
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), format)
+        .unwrap();
+
+    bytes
+}
+
+/// Post `bytes` as the field `name` of a form to `/api/user/image`
+async fn upload(
+    app: &TestApp,
+    token: Option<&str>,
+    name: &str,
+    content_type: &str,
+    bytes: &[u8],
+) -> (StatusCode, Value) {
+    let mut body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"avatar\"\r\nContent-Type: {content_type}\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/user/image")
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        );
+
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+    }
+
+    app.send(request.body(Body::from(body)).unwrap()).await
+}
+
+/// The file the server serves at `url`
+async fn fetch(app: &TestApp, url: &str) -> (StatusCode, String, Bytes) {
+    let path = url.strip_prefix("http://realworld.test").unwrap();
+    let request = Request::get(path).body(Body::empty()).unwrap();
+    let (status, headers, bytes) = app.send_raw(request).await;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .map(|value| value.to_str().unwrap().to_owned())
+        .unwrap_or_default();
+
+    (status, content_type, bytes)
+}
+
+fn dimensions(bytes: &[u8]) -> (u32, u32) {
+    let image = image::load_from_memory_with_format(bytes, ImageFormat::Png).unwrap();
+
+    (image.width(), image.height())
+}
+
+#[tokio::test]
+async fn avatars_are_resized_and_served() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+
+    let (status, body) = upload(
+        &app,
+        Some(&token),
+        "image",
+        "image/jpeg",
+        &image(400, 300, ImageFormat::Jpeg),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let url = body["user"]["image"].as_str().unwrap().to_owned();
+    assert!(url.starts_with("http://realworld.test/media/avatars/"));
+    assert!(url.ends_with("-256.png"));
+    assert_eq!(body["user"]["username"], "jake");
+
+    let (_, body) = app.get("/api/profiles/jake", None).await;
+    assert_eq!(body["profile"]["image"], url);
+
+    let (status, content_type, bytes) = fetch(&app, &url).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "image/png");
+    assert_eq!(dimensions(&bytes), (256, 256));
+
+    let small = url.replace("-256.png", "-64.png");
+    let (status, _, bytes) = fetch(&app, &small).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(dimensions(&bytes), (64, 64));
+
+    // A new avatar replaces the old files
+    let (status, body) = upload(
+        &app,
+        Some(&token),
+        "image",
+        "image/png",
+        &image(64, 64, ImageFormat::Png),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(body["user"]["image"], url);
+    let (status, _, _) = fetch(&app, &url).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _) = fetch(&app, &small).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn uploads_are_validated() {
+    let app = TestApp::new().await;
+    let token = app.register("jake").await;
+    let png = image(32, 32, ImageFormat::Png);
+
+    let (status, _) = upload(&app, None, "image", "image/png", &png).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = upload(&app, Some(&token), "image", "text/plain", &png).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let (status, _) = upload(&app, Some(&token), "avatar", "image/png", &png).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = upload(&app, Some(&token), "image", "image/png", b"not a png").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // The tests allow a mebibyte
+    let large = vec![0; 1024 * 1024 + 1];
+    let (status, _) = upload(&app, Some(&token), "image", "image/png", &large).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let (_, body) = app.get("/api/user", Some(&token)).await;
+    assert_eq!(body["user"]["image"], Value::Null);
+
+    let (status, _, _) = fetch(&app, "http://realworld.test/media/avatars/missing.png").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn only_own_avatars_are_deleted() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+
+    let (_, body) = upload(
+        &app,
+        Some(&jake),
+        "image",
+        "image/png",
+        &image(64, 64, ImageFormat::Png),
+    )
+    .await;
+    let url = body["user"]["image"].as_str().unwrap().to_owned();
+
+    // Neither replacing nor deleting the account of celeb touches the files
+    // of jake which celeb links to
+    let (status, _) = app
+        .put(
+            "/api/user",
+            Some(&celeb),
+            json!({ "user": { "image": url } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = upload(
+        &app,
+        Some(&celeb),
+        "image",
+        "image/png",
+        &image(64, 64, ImageFormat::Png),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    app.put(
+        "/api/user",
+        Some(&celeb),
+        json!({ "user": { "image": url } }),
+    )
+    .await;
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            "/api/user",
+            Some(&celeb),
+            Some(json!({ "password": "password", "content": "delete" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    for size in [256, 128, 64] {
+        let (status, _, _) = fetch(&app, &url.replace("-256.png", &format!("-{}.png", size))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
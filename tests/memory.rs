@@ -0,0 +1,129 @@
+//! Business rules checked against the in-memory fake of the repositories
+
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use realworld::repo::Error;
+use serde_json::json;
+
+#[tokio::test]
+async fn unpublished_articles_are_only_visible_to_their_author() {
+    let app = TestApp::in_memory().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &["dragons"])
+        .await;
+
+    let article = app
+        .repos
+        .articles
+        .find_by_slug(None, &slug)
+        .await
+        .unwrap()
+        .unwrap();
+    app.repos
+        .articles
+        .set_published(article.id, false)
+        .await
+        .unwrap();
+
+    let (status, body) = app.get("/api/articles", Some(&celeb)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["articlesCount"], 0);
+
+    // Nobody else gets to it through any other route either
+    let comment = json!({ "comment": { "body": "Thank you so much!" } });
+    let routes = [
+        ("GET", format!("/api/articles/{}", slug)),
+        ("POST", format!("/api/articles/{}/favorite", slug)),
+        ("DELETE", format!("/api/articles/{}/favorite", slug)),
+        ("GET", format!("/api/articles/{}/comments", slug)),
+        ("POST", format!("/api/articles/{}/comments", slug)),
+        ("GET", format!("/api/articles/{}/comments/stream", slug)),
+        ("GET", format!("/api/articles/{}/attachments", slug)),
+    ];
+    for token in [Some(celeb.as_str()), None] {
+        // The others take a token in the first place
+        for (method, uri) in routes
+            .iter()
+            .filter(|(method, _)| token.is_some() || *method == "GET")
+        {
+            let (status, _) = app
+                .request(method.parse().unwrap(), uri, token, Some(comment.clone()))
+                .await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{} {}", method, uri);
+        }
+    }
+
+    let (status, body) = app
+        .get(&format!("/api/articles/{}", slug), Some(&jake))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["article"]["slug"], json!(slug));
+
+    let (status, _) = app
+        .post(
+            &format!("/api/articles/{}/comments", slug),
+            Some(&jake),
+            comment,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn deleting_a_user_removes_their_content() {
+    let app = TestApp::in_memory().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &["dragons"])
+        .await;
+    app.create_article(&celeb, "Fame", &[]).await;
+
+    app.post(
+        &format!("/api/articles/{}/comments", slug),
+        Some(&celeb),
+        json!({ "comment": { "body": "Thank you so much!" } }),
+    )
+    .await;
+    app.post(
+        &format!("/api/articles/{}/favorite", slug),
+        Some(&celeb),
+        json!({}),
+    )
+    .await;
+    app.post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+
+    let user = app
+        .repos
+        .users
+        .find_by_username("jake")
+        .await
+        .unwrap()
+        .unwrap();
+    app.repos.users.delete(user.id).await.unwrap();
+
+    let (_, body) = app.get("/api/articles", Some(&celeb)).await;
+    assert_eq!(body["articlesCount"], 1);
+    assert_eq!(body["articles"][0]["slug"], "fame");
+
+    let (_, body) = app.get("/api/articles/feed", Some(&celeb)).await;
+    assert_eq!(body["articlesCount"], 0);
+}
+
+#[tokio::test]
+async fn usernames_are_unique() {
+    let app = TestApp::in_memory().await;
+    app.register("jake").await;
+
+    let result = app
+        .repos
+        .users
+        .create("other@example.com", "password", "jake")
+        .await;
+    assert!(matches!(result, Err(Error::Conflict)));
+}
@@ -0,0 +1,168 @@
+//! Notifications about follows, favorites and comments
+
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn following_favoriting_and_commenting_notify() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+
+    app.post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+    app.post(
+        "/api/articles/how-to-train-your-dragon/favorite",
+        Some(&celeb),
+        json!({}),
+    )
+    .await;
+    app.post(
+        "/api/articles/how-to-train-your-dragon/comments",
+        Some(&celeb),
+        json!({ "comment": { "body": "Thank you so much!" } }),
+    )
+    .await;
+    // Doing something twice or to oneself does not count
+    app.post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+    app.post(
+        "/api/articles/how-to-train-your-dragon/comments",
+        Some(&jake),
+        json!({ "comment": { "body": "You're welcome" } }),
+    )
+    .await;
+
+    let (_, body) = app.get("/api/user", Some(&jake)).await;
+    assert_eq!(body["user"]["unreadNotifications"], 3);
+
+    let (status, body) = app.get("/api/user/notifications", Some(&jake)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["unreadCount"], 3);
+    let notifications = body["notifications"].as_array().unwrap();
+    let kinds: Vec<_> = notifications.iter().map(|n| &n["kind"]).collect();
+    assert_eq!(kinds, ["comment", "favorite", "follow"]);
+    assert_eq!(notifications[0]["actor"]["username"], "celeb");
+    assert_eq!(notifications[0]["article"], "how-to-train-your-dragon");
+    assert_eq!(notifications[0]["read"], false);
+    assert_eq!(notifications[2]["article"], json!(null));
+
+    // Nobody else is notified
+    let (_, body) = app.get("/api/user/notifications", Some(&celeb)).await;
+    assert_eq!(body["notifications"], json!([]));
+}
+
+#[tokio::test]
+async fn notifications_are_marked_read() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+    app.post("/api/profiles/celeb/follow", Some(&jake), json!({}))
+        .await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.post(
+        "/api/articles/how-to-train-your-dragon/favorite",
+        Some(&celeb),
+        json!({}),
+    )
+    .await;
+
+    let (_, body) = app.get("/api/user/notifications", Some(&jake)).await;
+    let id = body["notifications"][0]["id"].as_i64().unwrap();
+
+    // Only the own notifications
+    let (status, _) = app
+        .post(
+            &format!("/api/user/notifications/{}/read", id),
+            Some(&celeb),
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app
+        .post(
+            &format!("/api/user/notifications/{}/read", id),
+            Some(&jake),
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, body) = app
+        .get("/api/user/notifications?unread=true", Some(&jake))
+        .await;
+    assert_eq!(body["unreadCount"], 1);
+    assert_eq!(body["notifications"][0]["kind"], "follow");
+
+    let (status, _) = app
+        .post("/api/user/notifications/read", Some(&jake), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.get("/api/user", Some(&jake)).await;
+    assert_eq!(body["user"]["unreadNotifications"], 0);
+    let (_, body) = app.get("/api/user/notifications", Some(&jake)).await;
+    assert_eq!(body["notifications"].as_array().unwrap().len(), 2);
+
+    // The notifications of others stay unread
+    let (_, body) = app.get("/api/user", Some(&celeb)).await;
+    assert_eq!(body["user"]["unreadNotifications"], 1);
+}
+
+#[tokio::test]
+async fn disabled_kinds_and_muted_users_do_not_notify() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let fan = app.register("fan").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+
+    let (status, body) = app
+        .put(
+            "/api/user/notifications/preferences",
+            Some(&jake),
+            json!({ "preferences": { "follow": false } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["preferences"],
+        json!({ "follow": false, "favorite": true, "comment": true })
+    );
+    app.post("/api/profiles/fan/mute", Some(&jake), json!({}))
+        .await;
+
+    app.post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+    app.post(
+        "/api/articles/how-to-train-your-dragon/favorite",
+        Some(&fan),
+        json!({}),
+    )
+    .await;
+    app.post(
+        "/api/articles/how-to-train-your-dragon/favorite",
+        Some(&celeb),
+        json!({}),
+    )
+    .await;
+
+    let (_, body) = app.get("/api/user/notifications", Some(&jake)).await;
+    assert_eq!(body["unreadCount"], 1);
+    assert_eq!(body["notifications"][0]["kind"], "favorite");
+    assert_eq!(body["notifications"][0]["actor"]["username"], "celeb");
+
+    let (_, body) = app
+        .get("/api/user/notifications/preferences", Some(&jake))
+        .await;
+    assert_eq!(body["preferences"]["follow"], false);
+}
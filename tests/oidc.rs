@@ -0,0 +1,504 @@
+//! Logging in with an OpenID Connect provider, played by a mock on a local
+//! port
+
+mod common;
+
+use axum::extract::{Form, Query, State};
+use axum::http::{header, HeaderMap, Method, Request, StatusCode, Uri};
+use axum::routing::{get, post};
+use axum::{body::Body, Json, Router};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use common::TestApp;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use realworld::oidc::{Provider, Providers};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+const REDIRECT_URL: &str = "http://frontend.test/oidc/callback";
+const CLIENT_ID: &str = "realworld";
+const CLIENT_SECRET: &str = "s3cret";
+
+/// What the mock hands out for a code
+struct Grant {
+    challenge: String,
+    nonce: String,
+    /// Claims of the ID token, besides those of every token
+    claims: Value,
+}
+
+/// An identity provider which logs in whoever the test says
+struct MockIdp {
+    issuer: String,
+    key: EncodingKey,
+    public_key: Vec<u8>,
+    grants: Mutex<HashMap<String, Grant>>,
+    codes: AtomicUsize,
+}
+
+impl MockIdp {
+    async fn start() -> Arc<MockIdp> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let idp = Arc::new(MockIdp {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            public_key: pair.public_key().as_ref().to_vec(),
+            grants: Mutex::new(HashMap::new()),
+            codes: AtomicUsize::new(0),
+        });
+
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(idp.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        idp
+    }
+
+    /// Log in at the authorization URL `location` as the account with
+    /// `claims`, returns the code and state the frontend gets back
+    fn authorize(&self, location: &str, claims: Value) -> (String, String) {
+        let uri: Uri = location.parse().unwrap();
+        assert_eq!(uri.path(), "/authorize");
+        let Query(query) = Query::<HashMap<String, String>>::try_from_uri(&uri).unwrap();
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["redirect_uri"], REDIRECT_URL);
+        assert_eq!(query["scope"], "openid email profile");
+        assert_eq!(query["code_challenge_method"], "S256");
+
+        let code = format!("code-{}", self.codes.fetch_add(1, Ordering::Relaxed));
+        self.grants.lock().unwrap().insert(
+            code.clone(),
+            Grant {
+                challenge: query["code_challenge"].clone(),
+                nonce: query["nonce"].clone(),
+                claims,
+            },
+        );
+
+        (code, query["state"].clone())
+    }
+}
+
+async fn discovery(State(idp): State<Arc<MockIdp>>) -> Json<Value> {
+    Json(json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+    }))
+}
+
+async fn jwks(State(idp): State<Arc<MockIdp>>) -> Json<Value> {
+    Json(json!({
+        "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": URL_SAFE_NO_PAD.encode(&idp.public_key),
+            "kid": "mock",
+            "alg": "EdDSA",
+            "use": "sig",
+        }]
+    }))
+}
+
+async fn token(
+    State(idp): State<Arc<MockIdp>>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let invalid = |error: &str| (StatusCode::BAD_REQUEST, Json(json!({ "error": error })));
+
+    let credentials = format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", CLIENT_ID, CLIENT_SECRET))
+    );
+    if headers
+        .get(header::AUTHORIZATION)
+        .map(|value| value.to_str().unwrap())
+        != Some(&credentials)
+    {
+        return Err(invalid("invalid_client"));
+    }
+
+    if form["grant_type"] != "authorization_code" || form["redirect_uri"] != REDIRECT_URL {
+        return Err(invalid("invalid_request"));
+    }
+
+    let grant = idp
+        .grants
+        .lock()
+        .unwrap()
+        .remove(&form["code"])
+        .ok_or_else(|| invalid("invalid_grant"))?;
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+    if challenge != grant.challenge {
+        return Err(invalid("invalid_grant"));
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut claims = json!({
+        "iss": idp.issuer,
+        "aud": CLIENT_ID,
+        "iat": now,
+        "exp": now + 60,
+        "nonce": grant.nonce,
+    });
+    claims
+        .as_object_mut()
+        .unwrap()
+        .extend(grant.claims.as_object().unwrap().clone());
+    let header = Header {
+        kid: Some(String::from("mock")),
+        ..Header::new(Algorithm::EdDSA)
+    };
+
+    Ok(Json(json!({
+        "access_token": "unused",
+        "token_type": "Bearer",
+        "id_token": encode(&header, &claims, &idp.key).unwrap(),
+    })))
+}
+
+async fn app(idp: &MockIdp) -> TestApp {
+    let issuer = idp.issuer.clone();
+
+    TestApp::configured(move |state| {
+        state.oidc = Providers {
+            redirect_url: String::from(REDIRECT_URL),
+            providers: vec![Provider {
+                name: String::from("corp"),
+                issuer,
+                client_id: String::from(CLIENT_ID),
+                client_secret: Some(String::from(CLIENT_SECRET)),
+                scopes: ["openid", "email", "profile"].map(String::from).to_vec(),
+            }],
+        }
+    })
+    .await
+}
+
+/// Where logging in with the provider sends the browser, and the cookie
+/// the browser has to send back with the code and state
+async fn authorization_url(app: &TestApp) -> (String, String) {
+    let request = Request::get("/api/users/oidc/corp/authorize")
+        .body(Body::empty())
+        .unwrap();
+    let (status, headers, _) = app.send_raw(request).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.starts_with("realworld_oidc_state="));
+    assert!(set_cookie.contains("; HttpOnly"));
+    assert!(set_cookie.contains("; SameSite=Lax"));
+    let cookie = set_cookie.split(';').next().unwrap().to_owned();
+
+    (
+        headers[header::LOCATION].to_str().unwrap().to_owned(),
+        cookie,
+    )
+}
+
+/// Send `body` with the code and state of a login as the browser with
+/// `cookie` would
+async fn send_callback(
+    app: &TestApp,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    cookie: &str,
+    body: Value,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::COOKIE, cookie);
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+    }
+
+    app.send(request.body(Body::from(body.to_string())).unwrap())
+        .await
+}
+
+/// Log in at the provider as the account with `claims`
+async fn login(app: &TestApp, idp: &MockIdp, claims: Value) -> (StatusCode, Value) {
+    let (location, cookie) = authorization_url(app).await;
+    let (code, state) = idp.authorize(&location, claims);
+
+    send_callback(
+        app,
+        Method::POST,
+        "/api/users/oidc/callback",
+        None,
+        &cookie,
+        json!({ "code": code, "state": state }),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn new_accounts_get_a_user() {
+    let idp = MockIdp::start().await;
+    let app = app(&idp).await;
+
+    let (status, body) = app.get("/api/users/oidc", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["providers"], json!(["corp"]));
+
+    let claims = json!({
+        "sub": "1234",
+        "email": "jake@corp.example",
+        "email_verified": true,
+        "preferred_username": "jake smith",
+    });
+    let (status, body) = login(&app, &idp, claims.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["user"]["username"], "jakesmith");
+    assert_eq!(body["user"]["email"], "jake@corp.example");
+
+    let token = body["user"]["token"].as_str().unwrap();
+    let (status, _) = app.get("/api/user", Some(token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let user = app
+        .repos
+        .users
+        .find_by_username("jakesmith")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(user.verified);
+
+    // The account logs in as the same user from now on
+    let (status, body) = login(&app, &idp, claims).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jakesmith");
+    assert_eq!(app.repos.users.list().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn usernames_of_new_users_are_unique() {
+    let idp = MockIdp::start().await;
+    let app = app(&idp).await;
+    app.register("jake").await;
+
+    let (status, body) = login(
+        &app,
+        &idp,
+        json!({ "sub": "1", "email": "jake@corp.example", "email_verified": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake2");
+}
+
+#[tokio::test]
+async fn accounts_are_linked_to_users_by_verified_email() {
+    let idp = MockIdp::start().await;
+    let app = app(&idp).await;
+    app.register("jake").await;
+    let jake = app
+        .repos
+        .users
+        .find_by_username("jake")
+        .await
+        .unwrap()
+        .unwrap();
+
+    let claims = json!({ "sub": "1", "email": "jake@example.com", "email_verified": true });
+
+    // Whoever registered the address may not own it
+    let (status, _) = login(&app, &idp, claims.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Neither may the account
+    app.repos.users.set_verified(jake.id, true).await.unwrap();
+    let (status, _) = login(
+        &app,
+        &idp,
+        json!({ "sub": "1", "email": "jake@example.com", "email_verified": false }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = login(&app, &idp, claims).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+
+    // Once linked, the address at the provider does not matter
+    let (status, body) = login(
+        &app,
+        &idp,
+        json!({ "sub": "1", "email": "other@example.com" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+}
+
+#[tokio::test]
+async fn invalid_logins_are_rejected() {
+    let idp = MockIdp::start().await;
+    let app = app(&idp).await;
+    let claims = json!({ "sub": "1", "email": "jake@corp.example", "email_verified": true });
+
+    let (status, _) = app.get("/api/users/oidc/other/authorize", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Every state works once
+    let (location, cookie) = authorization_url(&app).await;
+    let (code, state) = idp.authorize(&location, claims.clone());
+    let callback = json!({ "code": code, "state": state });
+    let uri = "/api/users/oidc/callback";
+    let (status, _) = send_callback(&app, Method::POST, uri, None, &cookie, callback.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send_callback(&app, Method::POST, uri, None, &cookie, callback).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The provider rejects codes of other logins
+    let (location, cookie) = authorization_url(&app).await;
+    let (_, state) = idp.authorize(&location, claims.clone());
+    let callback = json!({ "code": "stolen", "state": state });
+    let (status, _) = send_callback(&app, Method::POST, uri, None, &cookie, callback).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+    for overrides in [
+        json!({ "nonce": "replayed" }),
+        json!({ "aud": "another-client" }),
+        json!({ "iss": "http://impostor.test" }),
+        json!({ "exp": 1 }),
+    ] {
+        let mut claims = claims.clone();
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(overrides.as_object().unwrap().clone());
+
+        let (status, body) = login(&app, &idp, claims).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", overrides, body);
+    }
+}
+
+#[tokio::test]
+async fn provisioned_users_confirm_deleting_at_the_provider() {
+    let idp = MockIdp::start().await;
+    let app = app(&idp).await;
+    let claims = json!({ "sub": "1234", "email": "jake@corp.example", "email_verified": true });
+    let (_, body) = login(&app, &idp, claims.clone()).await;
+    let token = body["user"]["token"].as_str().unwrap().to_owned();
+
+    // They never had a password
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            "/api/user",
+            Some(&token),
+            Some(json!({ "content": "delete" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Logging in at the provider as someone else does not do either
+    let (location, cookie) = authorization_url(&app).await;
+    let (code, state) = idp.authorize(
+        &location,
+        json!({ "sub": "5678", "email": "celeb@corp.example", "email_verified": true }),
+    );
+    let deletion = json!({ "oidc": { "code": code, "state": state }, "content": "delete" });
+    let (status, _) = send_callback(
+        &app,
+        Method::DELETE,
+        "/api/user",
+        Some(&token),
+        &cookie,
+        deletion.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Every login at the provider confirms once
+    let (status, _) = send_callback(
+        &app,
+        Method::DELETE,
+        "/api/user",
+        Some(&token),
+        &cookie,
+        deletion,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (location, cookie) = authorization_url(&app).await;
+    let (code, state) = idp.authorize(&location, claims);
+    let (status, _) = send_callback(
+        &app,
+        Method::DELETE,
+        "/api/user",
+        Some(&token),
+        &cookie,
+        json!({ "oidc": { "code": code, "state": state }, "content": "delete" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(app.repos.users.list().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn logins_only_finish_in_the_browser_which_started_them() {
+    let idp = MockIdp::start().await;
+    let app = app(&idp).await;
+    let claims = json!({ "sub": "1", "email": "jake@corp.example", "email_verified": true });
+
+    // Someone starts a login and hands its code and state to another
+    // browser, which has no cookie or the one of its own login
+    let (location, _) = authorization_url(&app).await;
+    let (code, state) = idp.authorize(&location, claims.clone());
+    let callback = json!({ "code": code, "state": state });
+    let (status, _) = app
+        .post("/api/users/oidc/callback", None, callback.clone())
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (_, cookie) = authorization_url(&app).await;
+    let (status, _) = send_callback(
+        &app,
+        Method::POST,
+        "/api/users/oidc/callback",
+        None,
+        &cookie,
+        callback,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(app.repos.users.list().await.unwrap().is_empty());
+
+    // Finishing the login removes the cookie
+    let (location, cookie) = authorization_url(&app).await;
+    let (code, state) = idp.authorize(&location, claims);
+    let request = Request::post("/api/users/oidc/callback")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::COOKIE, cookie)
+        .body(Body::from(
+            json!({ "code": code, "state": state }).to_string(),
+        ))
+        .unwrap();
+    let (status, headers, _) = app.send_raw(request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers.get_all(header::SET_COOKIE).iter().any(|value| value
+        .to_str()
+        .unwrap()
+        .starts_with("realworld_oidc_state=;")));
+}
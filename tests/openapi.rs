@@ -0,0 +1,160 @@
+//! The OpenAPI document has to describe exactly the routes of the router
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use common::TestApp;
+use realworld::openapi::ApiDoc;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use utoipa::OpenApi;
+
+const METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::PATCH,
+];
+
+/// `(method, path)` of every operation in the document
+fn documented() -> BTreeSet<(String, String)> {
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let mut operations = BTreeSet::new();
+
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        for method in item.as_object().unwrap().keys() {
+            if METHODS
+                .iter()
+                .any(|known| known.as_str().eq_ignore_ascii_case(method))
+            {
+                operations.insert((method.to_uppercase(), path.clone()));
+            }
+        }
+    }
+
+    operations
+}
+
+/// `(method, path)` of every `.route(...)` below `/api` in `router()`
+fn routed() -> BTreeSet<(String, String)> {
+    let source = include_str!("../src/lib.rs");
+    let mut operations = BTreeSet::new();
+
+    for (_, rest) in source
+        .match_indices(".route(")
+        .map(|(i, m)| source.split_at(i + m.len()))
+    {
+        // rustfmt puts the arguments on their own lines if they are long
+        let Some(rest) = rest.trim_start().strip_prefix('"') else {
+            continue;
+        };
+        let (path, rest) = rest.split_once('"').unwrap();
+        // The method router ends where the parentheses of `.route(` close
+        let mut depth = 1;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .unwrap()
+            .0;
+        let methods = &rest[..end];
+
+        if !path.starts_with("/api/") {
+            continue;
+        }
+
+        for method in &METHODS {
+            let name = method.as_str().to_lowercase();
+
+            for (i, _) in methods.match_indices(&format!("{}(", name)) {
+                let before = methods[..i].trim_end().chars().last();
+
+                if matches!(before, Some(',') | Some('.')) {
+                    operations.insert((method.to_string(), path.to_owned()));
+                }
+            }
+        }
+    }
+
+    operations
+}
+
+#[test]
+fn every_route_is_documented() {
+    assert_eq!(routed(), documented());
+}
+
+#[tokio::test]
+async fn every_documented_operation_is_routed() {
+    let app = TestApp::in_memory().await;
+    let documented = documented();
+    let paths: BTreeSet<_> = documented.iter().map(|(_, path)| path).collect();
+
+    for path in paths {
+        let uri = path
+            .split('/')
+            .map(|segment| {
+                if segment.starts_with('{') {
+                    "drift"
+                } else {
+                    segment
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        for method in &METHODS {
+            // The malformed token and the missing body stop every request in
+            // its extractors, before any handler could run
+            let request = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .header(header::AUTHORIZATION, "Bearer drift")
+                .body(Body::empty())
+                .unwrap();
+            let (status, body) = app.send(request).await;
+
+            if documented.contains(&(method.to_string(), path.clone())) {
+                // Handlers which look up a path parameter explain their 404,
+                // unlike the router
+                let unrouted = status == StatusCode::NOT_FOUND && body.is_null();
+                assert!(
+                    !unrouted && status != StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {} is documented but not routed",
+                    method,
+                    path
+                );
+            } else {
+                assert_eq!(
+                    status,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {} is routed but not documented",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn specification_and_docs_are_served() {
+    let app = TestApp::in_memory().await;
+
+    let (status, body) = app.get("/api/openapi.json", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["openapi"].as_str().unwrap().starts_with("3.1"));
+    assert_eq!(body, serde_json::to_value(ApiDoc::openapi()).unwrap());
+
+    let (status, body) = app.get("/api/docs/", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(matches!(body, Value::String(html) if html.contains("swagger-ui")));
+}
@@ -0,0 +1,118 @@
+//! Recovering accounts with reset tokens sent by mail
+
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use realworld::repo::TokenPurpose;
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[tokio::test]
+async fn a_reset_sets_the_password_and_revokes_all_tokens() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+
+    let (status, _) = app
+        .post(
+            "/api/users/password-reset",
+            None,
+            json!({ "email": "jake@example.com" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let token = app.mailed_token();
+
+    let (status, _) = app
+        .post(
+            "/api/users/password-reset/confirm",
+            None,
+            json!({ "token": token, "password": "hunter2" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app.get("/api/user", Some(&jake)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = app
+        .post(
+            "/api/users/login",
+            None,
+            json!({ "user": { "email": "jake@example.com", "password": "hunter2" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.get("/api/user", body["user"]["token"].as_str()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Tokens work only once
+    let (status, _) = app
+        .post(
+            "/api/users/password-reset/confirm",
+            None,
+            json!({ "token": token, "password": "hunter3" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    let sent = app.mail().len();
+
+    let (status, _) = app
+        .post(
+            "/api/users/password-reset",
+            None,
+            json!({ "email": "nobody@example.com" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.mail().len(), sent);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    let jake = app
+        .repos
+        .users
+        .find_by_username("jake")
+        .await
+        .unwrap()
+        .unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let tokens = &app.repos.one_time_tokens;
+    tokens
+        .create(jake.id, TokenPurpose::ResetPassword, "expired", now - 1)
+        .await
+        .unwrap();
+    assert_eq!(
+        tokens
+            .consume(TokenPurpose::ResetPassword, "expired", now)
+            .await
+            .unwrap(),
+        None
+    );
+
+    tokens
+        .create(jake.id, TokenPurpose::ResetPassword, "valid", now + 60)
+        .await
+        .unwrap();
+    // Tokens for one purpose are useless for any other
+    assert_eq!(
+        tokens
+            .consume(TokenPurpose::VerifyEmail, "valid", now)
+            .await
+            .unwrap(),
+        None
+    );
+}
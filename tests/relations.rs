@@ -0,0 +1,144 @@
+//! Blocking and muting other users
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn blocked_users_can_neither_follow_nor_comment() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+
+    let (status, _) = app
+        .post("/api/profiles/celeb/block", Some(&jake), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Blocking ended the following
+    let (_, body) = app.get("/api/profiles/jake", Some(&celeb)).await;
+    assert_eq!(body["profile"]["following"], false);
+
+    let (status, body) = app
+        .post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        body,
+        json!({ "errors": { "body": ["blocked by the user"] } })
+    );
+
+    let (status, _) = app
+        .post(
+            "/api/articles/how-to-train-your-dragon/comments",
+            Some(&celeb),
+            json!({ "comment": { "body": "Thank you so much!" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (_, body) = app.get("/api/user/blocks", Some(&jake)).await;
+    assert_eq!(body["profiles"][0]["username"], "celeb");
+
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            "/api/profiles/celeb/block",
+            Some(&jake),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app
+        .post("/api/profiles/jake/follow", Some(&celeb), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["profile"]["following"], true);
+}
+
+#[tokio::test]
+async fn muted_users_are_hidden() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.create_article(&celeb, "Dragon riding", &[]).await;
+    app.post(
+        "/api/articles/how-to-train-your-dragon/comments",
+        Some(&celeb),
+        json!({ "comment": { "body": "Thank you so much!" } }),
+    )
+    .await;
+    app.post("/api/profiles/celeb/follow", Some(&jake), json!({}))
+        .await;
+
+    let (status, body) = app
+        .post("/api/profiles/celeb/mute", Some(&jake), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    // Muting is silent, the following stays
+    assert_eq!(body["profile"]["following"], true);
+
+    let (_, body) = app.get("/api/articles", Some(&jake)).await;
+    assert_eq!(body["articlesCount"], 1);
+    assert_eq!(body["articles"][0]["slug"], "how-to-train-your-dragon");
+
+    let (_, body) = app.get("/api/articles/feed", Some(&jake)).await;
+    assert_eq!(body["articlesCount"], 0);
+
+    let (_, body) = app
+        .get(
+            "/api/articles/how-to-train-your-dragon/comments",
+            Some(&jake),
+        )
+        .await;
+    assert_eq!(body["comments"], json!([]));
+
+    // Nothing changes for others
+    let (_, body) = app.get("/api/articles", None).await;
+    assert_eq!(body["articlesCount"], 2);
+    let (_, body) = app
+        .get("/api/articles/how-to-train-your-dragon/comments", None)
+        .await;
+    assert_eq!(body["comments"].as_array().unwrap().len(), 1);
+
+    let (_, body) = app.get("/api/user/mutes", Some(&jake)).await;
+    assert_eq!(body["profiles"][0]["username"], "celeb");
+
+    app.request(
+        Method::DELETE,
+        "/api/profiles/celeb/mute",
+        Some(&jake),
+        None,
+    )
+    .await;
+    let (_, body) = app.get("/api/articles/feed", Some(&jake)).await;
+    assert_eq!(body["articlesCount"], 1);
+}
+
+#[tokio::test]
+async fn users_cannot_block_or_mute_themselves() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+
+    for uri in ["/api/profiles/jake/block", "/api/profiles/jake/mute"] {
+        let (status, body) = app.post(uri, Some(&jake), json!({})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", uri);
+        assert_eq!(
+            body,
+            json!({ "errors": { "body": ["cannot be done to yourself"] } })
+        );
+    }
+
+    let (_, body) = app.get("/api/user/blocks", Some(&jake)).await;
+    assert_eq!(body["profiles"], json!([]));
+    let (_, body) = app.get("/api/user/mutes", Some(&jake)).await;
+    assert_eq!(body["profiles"], json!([]));
+}
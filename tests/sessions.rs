@@ -0,0 +1,244 @@
+//! Short-lived access tokens, refreshing them and logging out
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+
+/// Log in as `username` and return the access token and the refresh token
+async fn login(app: &TestApp, username: &str) -> (String, String) {
+    let (status, body) = app
+        .post(
+            "/api/users/login",
+            None,
+            json!({
+                "user": {
+                    "email": format!("{}@example.com", username),
+                    "password": "password",
+                }
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    tokens(&body)
+}
+
+fn tokens(body: &Value) -> (String, String) {
+    (
+        body["user"]["token"].as_str().unwrap().to_owned(),
+        body["user"]["refreshToken"].as_str().unwrap().to_owned(),
+    )
+}
+
+async fn refresh(app: &TestApp, refresh_token: &str) -> (StatusCode, Value) {
+    app.post(
+        "/api/users/refresh",
+        None,
+        json!({ "refreshToken": refresh_token }),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn refresh_tokens_work_once() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    let (token, refresh_token) = login(&app, "jake").await;
+
+    let (status, body) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+    let (new_token, new_refresh_token) = tokens(&body);
+    assert_ne!(new_refresh_token, refresh_token);
+
+    // Access tokens of the session keep working until they expire
+    let (status, _) = app.get("/api/user", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app.get("/api/user", Some(&new_token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["refreshToken"], Value::Null);
+
+    let (status, _) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = refresh(&app, "not a refresh token").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = refresh(&app, &new_refresh_token).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn logging_out_ends_only_the_session() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    let (token, refresh_token) = login(&app, "jake").await;
+    let (other_token, _) = login(&app, "jake").await;
+
+    let (status, _) = app.post("/api/users/logout", None, json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.post("/api/users/logout", Some(&token), json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app.get("/api/user", Some(&token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app.get("/api/user", Some(&other_token)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn expired_access_tokens_are_rejected() {
+    let app = TestApp::configured(|state| {
+        // Expired as soon as they are issued
+        state.sessions.access_ttl = -1;
+    })
+    .await;
+    app.register("jake").await;
+    let (token, refresh_token) = login(&app, "jake").await;
+
+    let (status, _) = app.get("/api/user", Some(&token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The session itself goes on
+    let (status, _) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn sessions_end_after_the_refresh_ttl() {
+    let app = TestApp::configured(|state| {
+        state.sessions.refresh_ttl = -1;
+    })
+    .await;
+    app.register("jake").await;
+    let (token, refresh_token) = login(&app, "jake").await;
+
+    let (status, _) = app.get("/api/user", Some(&token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn revoking_tokens_ends_every_session() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    let (_, refresh_token) = login(&app, "jake").await;
+    let user = app
+        .repos
+        .users
+        .find_by_username("jake")
+        .await
+        .unwrap()
+        .unwrap();
+
+    app.repos.users.revoke_tokens(user.id).await.unwrap();
+
+    let (status, _) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn sessions_record_the_client() {
+    let app = TestApp::configured(|state| state.sessions.trust_proxy = true).await;
+    app.register("jake").await;
+    let (status, body) = app
+        .send(
+            Request::post("/api/users/login")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::USER_AGENT, "Conduit/1.0")
+                .header("x-forwarded-for", "10.0.0.1, 192.0.2.7")
+                .body(Body::from(
+                    json!({
+                        "user": { "email": "jake@example.com", "password": "password" }
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (token, _) = tokens(&body);
+
+    let (status, body) = app.get("/api/user/sessions", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let sessions = body["sessions"].as_array().unwrap();
+    // The one from registering and the one from logging in
+    assert_eq!(sessions.len(), 2);
+    let current = sessions
+        .iter()
+        .find(|session| session["current"] == true)
+        .unwrap();
+    assert_eq!(current["userAgent"], "Conduit/1.0");
+    assert_eq!(current["ip"], "192.0.2.7");
+    assert!(current["lastUsedAt"].as_i64().unwrap() > 0);
+    assert!(current["expiresAt"].as_i64() > current["lastUsedAt"].as_i64());
+
+    let (status, _) = app.get("/api/user/sessions", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn sessions_can_be_ended_one_by_one() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    app.register("jane").await;
+    let (token, _) = login(&app, "jake").await;
+    let (other_token, other_refresh_token) = login(&app, "jake").await;
+    let (jane_token, _) = login(&app, "jane").await;
+
+    let (_, body) = app.get("/api/user/sessions", Some(&other_token)).await;
+    let other = body["sessions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|session| session["current"] == true)
+        .unwrap()["id"]
+        .as_i64()
+        .unwrap();
+    let uri = format!("/api/user/sessions/{}", other);
+
+    // Nobody else can end it
+    let (status, _) = app.delete(&uri, Some(&jane_token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.get("/api/user", Some(&other_token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app.delete(&uri, Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.get("/api/user", Some(&other_token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = refresh(&app, &other_refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.delete(&uri, Some(&token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, body) = app.get("/api/user/sessions", Some(&token)).await;
+    assert_eq!(body["sessions"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_ends_every_session() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+    let jane_token = app.register("jane").await;
+    let (token, refresh_token) = login(&app, "jake").await;
+    let (other_token, _) = login(&app, "jake").await;
+
+    let (status, _) = app.delete("/api/user/sessions", Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    for token in [&token, &other_token] {
+        let (status, _) = app.get("/api/user", Some(token)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let (status, _) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app.get("/api/user", Some(&jane_token)).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,264 @@
+//! Two-factor authentication with an authenticator app or recovery codes
+
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use realworld::repo::LoginFailureReason;
+use ring::hmac;
+use serde_json::{json, Value};
+
+const JAKE: &str = "jake@example.com";
+
+/// The bytes of the base32 `secret`
+fn decode(secret: &str) -> Vec<u8> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+
+    for char in secret.bytes() {
+        let value = ALPHABET.iter().position(|&other| other == char).unwrap();
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    bytes
+}
+
+/// The code of the authenticator app for `secret`, `offset` steps of 30
+/// seconds from now
+fn code(secret: &str, offset: i64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let step = now / 30 + offset;
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &decode(secret));
+    let hash = hmac::sign(&key, &step.to_be_bytes());
+    let hash = hash.as_ref();
+    let offset = (hash[19] & 0xf) as usize;
+    let value = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+
+    format!("{:06}", value % 1_000_000)
+}
+
+async fn app(attempts: i64) -> TestApp {
+    TestApp::configured(|state| {
+        state.lockout.attempts = attempts;
+        state.lockout.delay = 0;
+    })
+    .await
+}
+
+/// Enable two-factor authentication for the user with `token`, returns the
+/// secret and the recovery codes
+async fn enable(app: &TestApp, token: &str) -> (String, Vec<String>) {
+    let (status, body) = app
+        .post("/api/user/2fa/setup", Some(token), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = body["secret"].as_str().unwrap().to_owned();
+
+    let (status, body) = app
+        .post(
+            "/api/user/2fa/confirm",
+            Some(token),
+            json!({ "code": code(&secret, 0) }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let recovery_codes = body["recoveryCodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_owned())
+        .collect();
+
+    (secret, recovery_codes)
+}
+
+async fn login(app: &TestApp, email: &str) -> (StatusCode, Value) {
+    app.post(
+        "/api/users/login",
+        None,
+        json!({ "user": { "email": email, "password": "password" } }),
+    )
+    .await
+}
+
+/// Log in as jake, which has to return a challenge, and answer it with `code`
+async fn login_with_code(app: &TestApp, code: &str) -> (StatusCode, Value) {
+    let (status, body) = login(app, JAKE).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.get("user"), None);
+    let challenge = body["challenge"]["token"].as_str().unwrap();
+
+    app.post(
+        "/api/users/login/2fa",
+        None,
+        json!({ "challengeToken": challenge, "code": code }),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn logging_in_takes_a_code_once_enabled() {
+    let app = app(5).await;
+    let token = app.register("jake").await;
+
+    let (status, body) = app
+        .post("/api/user/2fa/setup", Some(&token), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = body["secret"].as_str().unwrap();
+    assert_eq!(
+        body["otpauthUri"],
+        format!(
+            "otpauth://totp/RealWorld:jake%40example.com?secret={}&issuer=RealWorld&algorithm=SHA1&digits=6&period=30",
+            secret
+        )
+    );
+
+    // Until a code is confirmed, the password is enough
+    let (status, body) = login(&app, JAKE).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+
+    let (status, _) = app
+        .post(
+            "/api/user/2fa/confirm",
+            Some(&token),
+            json!({ "code": "abcdef" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = app
+        .post(
+            "/api/user/2fa/confirm",
+            Some(&token),
+            json!({ "code": code(secret, 0) }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["recoveryCodes"].as_array().unwrap().len(), 10);
+
+    let (status, _) = app
+        .post("/api/user/2fa/setup", Some(&token), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // The code used to confirm does not work again
+    let (status, _) = login_with_code(&app, &code(secret, 0)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = login_with_code(&app, &code(secret, 1)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+    let token = body["user"]["token"].as_str().unwrap();
+    let (status, _) = app.get("/api/user", Some(token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Others still log in with their password only
+    app.register("jane").await;
+    let (status, body) = login(&app, "jane@example.com").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jane");
+}
+
+#[tokio::test]
+async fn recovery_codes_work_once() {
+    let app = app(5).await;
+    let token = app.register("jake").await;
+    let (_, recovery_codes) = enable(&app, &token).await;
+
+    let (status, body) = login_with_code(&app, &recovery_codes[0].to_uppercase()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+
+    let (status, _) = login_with_code(&app, &recovery_codes[0]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = login_with_code(&app, &recovery_codes[1]).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn wrong_codes_count_as_failed_logins() {
+    let app = app(2).await;
+    let token = app.register("jake").await;
+    let (secret, _) = enable(&app, &token).await;
+
+    let (status, body) = login(&app, JAKE).await;
+    assert_eq!(status, StatusCode::OK);
+    let challenge = body["challenge"]["token"].as_str().unwrap();
+    let second_factor = json!({ "challengeToken": challenge, "code": "not a code" });
+
+    let (status, _) = app.post("/api/users/login/2fa", None, second_factor).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The challenge ended with the wrong code
+    let second_factor = json!({ "challengeToken": challenge, "code": code(&secret, 1) });
+    let (status, _) = app.post("/api/users/login/2fa", None, second_factor).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let failures = app.repos.login_attempts.failures(10).await.unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].email, JAKE);
+    assert!(failures[0].user.is_some());
+    assert_eq!(failures[0].reason, LoginFailureReason::InvalidCode);
+
+    // Right passwords do not forgive wrong codes
+    let (status, _) = login_with_code(&app, "123").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = login(&app, JAKE).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn disabling_takes_a_code() {
+    let app = app(5).await;
+    let token = app.register("jake").await;
+
+    let (status, _) = app
+        .post(
+            "/api/user/2fa/disable",
+            Some(&token),
+            json!({ "code": "000000" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (_, recovery_codes) = enable(&app, &token).await;
+
+    let (status, _) = app
+        .post(
+            "/api/user/2fa/disable",
+            Some(&token),
+            json!({ "code": "wrong" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = app
+        .post(
+            "/api/user/2fa/disable",
+            Some(&token),
+            json!({ "code": recovery_codes[0] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = login(&app, JAKE).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], "jake");
+
+    // Setting up again starts over with a new secret
+    enable(&app, &token).await;
+    let (_, body) = login(&app, JAKE).await;
+    assert!(body["challenge"]["token"].is_string());
+}
@@ -0,0 +1,119 @@
+//! Verifying email addresses with tokens sent by mail
+
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use serde_json::json;
+
+async fn verified(app: &TestApp, username: &str) -> bool {
+    app.repos
+        .users
+        .find_by_username(username)
+        .await
+        .unwrap()
+        .unwrap()
+        .verified
+}
+
+#[tokio::test]
+async fn registration_mails_a_token_which_verifies_the_address() {
+    let app = TestApp::new().await;
+    app.register("jake").await;
+
+    let mail = app.mail();
+    assert_eq!(mail.len(), 1);
+    assert!(mail[0].contains("To: jake@example.com"));
+    assert!(!verified(&app, "jake").await);
+
+    let token = app.mailed_token();
+    let (status, _) = app
+        .post("/api/users/verify", None, json!({ "token": token }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(verified(&app, "jake").await);
+
+    // Tokens work only once
+    let (status, _) = app
+        .post("/api/users/verify", None, json!({ "token": token }))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn resending_replaces_the_token() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let first = app.mailed_token();
+
+    let (status, _) = app
+        .post("/api/users/verify/resend", Some(&jake), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.mail().len(), 2);
+    let second = app.mailed_token();
+
+    let (status, _) = app
+        .post("/api/users/verify", None, json!({ "token": first }))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .post("/api/users/verify", None, json!({ "token": second }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app
+        .post("/api/users/verify/resend", Some(&jake), json!({}))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn a_new_address_has_to_be_verified_again() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+    let token = app.mailed_token();
+    app.post("/api/users/verify", None, json!({ "token": token }))
+        .await;
+
+    let (status, _) = app
+        .put(
+            "/api/user",
+            Some(&jake),
+            json!({ "user": { "email": "jake@jake.jake" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!verified(&app, "jake").await);
+    assert!(app.mail().last().unwrap().contains("To: jake@jake.jake"));
+}
+
+#[tokio::test]
+async fn unverified_users_cannot_write_if_required() {
+    let app = TestApp::configured(|state| state.verification.required = true).await;
+    let jake = app.register("jake").await;
+    let article = json!({
+        "article": { "title": "Dragons", "description": "Ever wonder how?", "body": "Very carefully." }
+    });
+
+    let (status, _) = app
+        .post("/api/articles", Some(&jake), article.clone())
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let token = app.mailed_token();
+    app.post("/api/users/verify", None, json!({ "token": token }))
+        .await;
+    let (status, _) = app.post("/api/articles", Some(&jake), article).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let celeb = app.register("celeb").await;
+    let (status, _) = app
+        .post(
+            "/api/articles/dragons/comments",
+            Some(&celeb),
+            json!({ "comment": { "body": "Thank you so much!" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,477 @@
+//! Webhooks, delivered to a stand-in server on the loopback interface
+
+mod common;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use common::TestApp;
+use hmac::{Hmac, Mac};
+use realworld::{database::Role, repo::WebhookEvent, webhooks::deliver_due, AppState};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Records what is posted to it and answers with `status`
+#[derive(Clone)]
+struct StandIn {
+    requests: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+    status: Arc<Mutex<StatusCode>>,
+    url: String,
+}
+
+impl StandIn {
+    async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stand_in = StandIn {
+            requests: Default::default(),
+            status: Arc::new(Mutex::new(StatusCode::OK)),
+            url: format!("http://{}/hook", listener.local_addr().unwrap()),
+        };
+
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(stand_in): State<StandIn>, headers: HeaderMap, body: Bytes| async move {
+                        stand_in.requests.lock().unwrap().push((headers, body));
+                        *stand_in.status.lock().unwrap()
+                    },
+                ),
+            )
+            .with_state(stand_in.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        stand_in
+    }
+
+    fn respond_with(&self, status: StatusCode) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    fn requests(&self) -> Vec<(HeaderMap, Bytes)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// An app which delivers to stand-ins, which are on the loopback interface
+async fn app() -> TestApp {
+    TestApp::configured(allow_stand_ins).await
+}
+
+fn allow_stand_ins(state: &mut AppState) {
+    state.webhooks.allowed_hosts = vec![String::from("127.0.0.1")];
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Register a webhook and return its `id` and secret
+async fn register_webhook(
+    app: &TestApp,
+    uri: &str,
+    token: &str,
+    url: &str,
+    events: Value,
+) -> (i64, String) {
+    let (status, body) = app
+        .post(
+            uri,
+            Some(token),
+            json!({ "webhook": { "url": url, "events": events } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    (
+        body["webhook"]["id"].as_i64().unwrap(),
+        body["webhook"]["secret"].as_str().unwrap().to_owned(),
+    )
+}
+
+/// The newest delivery of a webhook of the owner of `token`
+async fn last_delivery(app: &TestApp, token: &str, id: i64) -> Value {
+    let (_, body) = app
+        .get(
+            &format!("/api/user/webhooks/{}/deliveries", id),
+            Some(token),
+        )
+        .await;
+
+    body["deliveries"][0].clone()
+}
+
+#[tokio::test]
+async fn deliveries_are_signed_and_logged() {
+    let app = app().await;
+    let stand_in = StandIn::start().await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let (id, secret) = register_webhook(
+        &app,
+        "/api/user/webhooks",
+        &jake,
+        &stand_in.url,
+        json!(["article.created", "comment.created"]),
+    )
+    .await;
+
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.put(
+        &format!("/api/articles/{}", slug),
+        Some(&jake),
+        json!({ "article": { "body": "With patience." } }),
+    )
+    .await;
+    app.post(
+        &format!("/api/articles/{}/comments", slug),
+        Some(&celeb),
+        json!({ "comment": { "body": "Thank you so much!" } }),
+    )
+    .await;
+    // Only about articles of the owner
+    app.create_article(&celeb, "How to tame your cat", &[])
+        .await;
+
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 2);
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 0);
+
+    // Deliveries are posted concurrently
+    let mut requests = stand_in.requests();
+    requests.sort_by_key(|(headers, _)| headers["x-webhook-event"].to_str().unwrap().to_owned());
+    assert_eq!(requests.len(), 2);
+
+    for (headers, body) in &requests {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        let signature = headers["x-webhook-signature"].to_str().unwrap();
+        let signature = signature.strip_prefix("sha256=").unwrap();
+        let signature: Vec<u8> = (0..signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).unwrap())
+            .collect();
+        mac.verify_slice(&signature).unwrap();
+        assert_eq!(headers["content-type"], "application/json");
+    }
+
+    let (headers, body) = &requests[0];
+    let body: Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(headers["x-webhook-event"], "article.created");
+    assert_eq!(body["event"], "article.created");
+    assert_eq!(body["data"]["article"]["slug"], slug);
+    assert_eq!(body["data"]["article"]["author"]["username"], "jake");
+
+    let (headers, body) = &requests[1];
+    let body: Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(headers["x-webhook-event"], "comment.created");
+    assert_eq!(body["data"]["article"]["slug"], slug);
+    assert_eq!(body["data"]["comment"]["body"], "Thank you so much!");
+    assert_eq!(body["data"]["comment"]["author"]["username"], "celeb");
+
+    let (status, body) = app
+        .get(
+            &format!("/api/user/webhooks/{}/deliveries", id),
+            Some(&jake),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let deliveries = body["deliveries"].as_array().unwrap();
+    let events: Vec<_> = deliveries.iter().map(|d| &d["event"]).collect();
+    assert_eq!(events, ["comment.created", "article.created"]);
+    assert_eq!(deliveries[0]["status"], "delivered");
+    assert_eq!(deliveries[0]["attempts"], 1);
+    assert_eq!(deliveries[0]["responseStatus"], 200);
+    assert_eq!(deliveries[0]["nextAttemptAt"], json!(null));
+    assert_eq!(deliveries[0]["payload"]["event"], "comment.created");
+
+    // The secret is only shown once
+    let (_, body) = app.get("/api/user/webhooks", Some(&jake)).await;
+    let webhooks = body["webhooks"].as_array().unwrap();
+    assert_eq!(webhooks.len(), 1);
+    assert_eq!(webhooks[0]["url"], stand_in.url);
+    assert_eq!(webhooks[0].get("secret"), None);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_with_backoff() {
+    let app = TestApp::configured(|state| {
+        allow_stand_ins(state);
+        state.webhooks.attempts = 3;
+        state.webhooks.backoff = 10;
+    })
+    .await;
+    let stand_in = StandIn::start().await;
+    stand_in.respond_with(StatusCode::INTERNAL_SERVER_ERROR);
+    let jake = app.register("jake").await;
+    let (id, _) = register_webhook(
+        &app,
+        "/api/user/webhooks",
+        &jake,
+        &stand_in.url,
+        json!(["article.deleted"]),
+    )
+    .await;
+    let slug = app
+        .create_article(&jake, "How to train your dragon", &[])
+        .await;
+    app.delete(&format!("/api/articles/{}", slug), Some(&jake))
+        .await;
+    let start = now();
+
+    assert_eq!(deliver_due(&app.state, start).await.unwrap(), 1);
+    let delivery = last_delivery(&app, &jake, id).await;
+    assert_eq!(delivery["status"], "pending");
+    assert_eq!(delivery["attempts"], 1);
+    assert_eq!(delivery["responseStatus"], 500);
+    assert_eq!(delivery["nextAttemptAt"], start + 10);
+    assert_eq!(delivery["payload"]["data"]["article"]["slug"], slug);
+
+    // Not due yet
+    assert_eq!(deliver_due(&app.state, start + 9).await.unwrap(), 0);
+
+    // The wait doubles
+    assert_eq!(deliver_due(&app.state, start + 10).await.unwrap(), 1);
+    assert_eq!(
+        last_delivery(&app, &jake, id).await["nextAttemptAt"],
+        start + 30
+    );
+
+    assert_eq!(deliver_due(&app.state, start + 30).await.unwrap(), 1);
+    let delivery = last_delivery(&app, &jake, id).await;
+    assert_eq!(delivery["status"], "failed");
+    assert_eq!(delivery["attempts"], 3);
+    assert_eq!(delivery["nextAttemptAt"], json!(null));
+    assert!(delivery["error"].as_str().unwrap().contains("500"));
+
+    assert_eq!(deliver_due(&app.state, start + 3600).await.unwrap(), 0);
+    assert_eq!(stand_in.requests().len(), 3);
+
+    // A server which is not there counts as a failure as well
+    let (id, _) = register_webhook(
+        &app,
+        "/api/user/webhooks",
+        &jake,
+        "http://127.0.0.1:1/hook",
+        json!(["article.created"]),
+    )
+    .await;
+    app.create_article(&jake, "How to tame your cat", &[]).await;
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 1);
+    let (_, body) = app
+        .get(
+            &format!("/api/user/webhooks/{}/deliveries", id),
+            Some(&jake),
+        )
+        .await;
+    assert_eq!(body["deliveries"][0]["status"], "pending");
+    assert_eq!(body["deliveries"][0]["responseStatus"], json!(null));
+    assert!(body["deliveries"][0]["error"].is_string());
+}
+
+#[tokio::test]
+async fn deliveries_are_picked_up_in_batches() {
+    let app = TestApp::configured(|state| {
+        allow_stand_ins(state);
+        state.webhooks.batch_size = 2;
+        state.webhooks.concurrency = 1;
+    })
+    .await;
+    let stand_in = StandIn::start().await;
+    let jake = app.register("jake").await;
+    register_webhook(
+        &app,
+        "/api/user/webhooks",
+        &jake,
+        &stand_in.url,
+        json!(["article.created"]),
+    )
+    .await;
+    for title in ["One", "Two", "Three"] {
+        app.create_article(&jake, title, &[]).await;
+    }
+
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 2);
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 1);
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 0);
+    assert_eq!(stand_in.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn global_webhooks_receive_every_event() {
+    let app = app().await;
+    let stand_in = StandIn::start().await;
+    let admin = app.register("admin").await;
+    let jake = app.register("jake").await;
+    let celeb = app.register("celeb").await;
+    let admin_id = app
+        .repos
+        .users
+        .find_by_username("admin")
+        .await
+        .unwrap()
+        .unwrap()
+        .id;
+    app.repos
+        .users
+        .set_role(admin_id, Role::Admin)
+        .await
+        .unwrap();
+
+    let body = json!({ "webhook": { "url": stand_in.url, "events": ["article.created"] } });
+    let (status, _) = app
+        .post("/api/admin/webhooks", Some(&jake), body.clone())
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (global, _) = register_webhook(
+        &app,
+        "/api/admin/webhooks",
+        &admin,
+        &stand_in.url,
+        json!(["article.created"]),
+    )
+    .await;
+    let (own, _) = register_webhook(
+        &app,
+        "/api/user/webhooks",
+        &jake,
+        &stand_in.url,
+        json!(["article.created"]),
+    )
+    .await;
+
+    app.create_article(&celeb, "How to train your dragon", &[])
+        .await;
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 1);
+
+    let (_, body) = app
+        .get(
+            &format!("/api/admin/webhooks/{}/deliveries", global),
+            Some(&admin),
+        )
+        .await;
+    assert_eq!(body["deliveries"].as_array().unwrap().len(), 1);
+    let (_, body) = app
+        .get(
+            &format!("/api/user/webhooks/{}/deliveries", own),
+            Some(&jake),
+        )
+        .await;
+    assert_eq!(body["deliveries"], json!([]));
+
+    // Webhooks are only visible to their owners
+    let (_, body) = app.get("/api/admin/webhooks", Some(&admin)).await;
+    assert_eq!(body["webhooks"].as_array().unwrap().len(), 1);
+    let (_, body) = app.get("/api/user/webhooks", Some(&admin)).await;
+    assert_eq!(body["webhooks"], json!([]));
+    let (status, _) = app
+        .delete(&format!("/api/user/webhooks/{}", own), Some(&celeb))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .delete(&format!("/api/user/webhooks/{}", global), Some(&admin))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app
+        .delete(&format!("/api/admin/webhooks/{}", global), Some(&admin))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.get("/api/admin/webhooks", Some(&admin)).await;
+    assert_eq!(body["webhooks"], json!([]));
+}
+
+#[tokio::test]
+async fn webhooks_are_validated() {
+    let app = TestApp::new().await;
+    let jake = app.register("jake").await;
+
+    for webhook in [
+        json!({ "url": "ftp://example.com/hook", "events": ["article.created"] }),
+        json!({ "url": "/hook", "events": ["article.created"] }),
+        json!({ "url": "https://example.com/hook", "events": [] }),
+    ] {
+        let (status, _) = app
+            .post(
+                "/api/user/webhooks",
+                Some(&jake),
+                json!({ "webhook": webhook }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
+
+#[tokio::test]
+async fn webhooks_are_only_delivered_to_public_addresses() {
+    let app = TestApp::new().await;
+    let stand_in = StandIn::start().await;
+    let jake = app.register("jake").await;
+
+    for url in [
+        stand_in.url.as_str(),
+        "http://localhost/hook",
+        "http://[::1]/hook",
+        "http://0.0.0.0/hook",
+        "http://10.0.0.1/hook",
+        "http://192.168.1.1/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[fe80::1]/hook",
+        "http://[::ffff:127.0.0.1]/hook",
+        "http://[::127.0.0.1]/hook",
+        "http://198.18.0.1/hook",
+        "http://240.0.0.1/hook",
+        "http://[64:ff9b::7f00:1]/hook",
+        "http://[2002:7f00:1::]/hook",
+    ] {
+        let (status, body) = app
+            .post(
+                "/api/user/webhooks",
+                Some(&jake),
+                json!({ "webhook": { "url": url, "events": ["article.created"] } }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", url);
+        assert!(body["errors"]["body"][0].is_string());
+    }
+
+    // Hosts are checked again when delivering
+    let jake_id = app
+        .repos
+        .users
+        .find_by_username("jake")
+        .await
+        .unwrap()
+        .unwrap()
+        .id;
+    let webhook = app
+        .repos
+        .webhooks
+        .create(
+            Some(jake_id),
+            &stand_in.url,
+            "secret",
+            &[WebhookEvent::ArticleCreated],
+        )
+        .await
+        .unwrap();
+    app.create_article(&jake, "How to train your dragon", &[])
+        .await;
+    assert_eq!(deliver_due(&app.state, now()).await.unwrap(), 1);
+
+    assert!(stand_in.requests().is_empty());
+    let delivery = last_delivery(&app, &jake, webhook.id).await;
+    assert_eq!(delivery["status"], "pending");
+    assert_eq!(delivery["error"], "127.0.0.1 is not a public address");
+}